@@ -3,8 +3,11 @@ set -eu
 
 APPS=(hello)
 SERVERS=(lx)
+TESTS=(vmspace)
 RELEASE=${RELEASE:-}
 ARCH=${ARCH:-x64}
+COMPRESS_INITFS=${COMPRESS_INITFS:-}
+KTEST=${KTEST:-}
 
 export CARGO_TERM_HYPERLINKS=false
 
@@ -23,10 +26,19 @@ fi
 
 echo -n > initfs.list
 mkdir -p initfs/servers
+printf 'servers\0' >> initfs.list
+
+# The key to sign server images. The kernel only loads images signed by it.
+SIGNING_KEY=${SIGNING_KEY:-signing-key.pem}
+if [[ ! -f "$SIGNING_KEY" ]]; then
+  tools/sign-server.sh keygen "$SIGNING_KEY"
+fi
+export FTL_SERVER_PUBKEY=$(tools/sign-server.sh pubkey "$SIGNING_KEY")
 
 # Build apps.
 mkdir -p initfs/bin
 zig cc -O2 -target x86_64-linux-musl -static -no-pie apps/hello/hello.c -o initfs/bin/hello
+printf 'bin\0bin/hello\0' >> initfs.list
 
 # Build servers.
 for server in "${SERVERS[@]}"; do
@@ -35,16 +47,51 @@ for server in "${SERVERS[@]}"; do
       --manifest-path servers/$server/Cargo.toml
 
   cp target/server/$target/lib$server.so initfs/servers/$server.elf
+  tools/sign-server.sh sign "$SIGNING_KEY" initfs/servers/$server.elf
   printf 'servers/%s.elf\0' "$server" >> initfs.list
+  printf 'servers/%s.elf.sig\0' "$server" >> initfs.list
 done
 
+# Build test servers for the kernel's test mode. See kernel/src/ktest.rs.
+KERNEL_FEATURES=
+if [[ -n "$KTEST" ]]; then
+  KERNEL_FEATURES=ktest
+  mkdir -p initfs/tests
+  printf 'tests\0' >> initfs.list
+  for test in "${TESTS[@]}"; do
+    FTL_LOG_PREFIX="[$(printf '%-10s' "$test")] " \
+      cargo build "${CARGOFLAGS[@]}" --target libs/rust/ftl_api/src/arch/$ARCH/server.json \
+        --manifest-path tests/$test/Cargo.toml
+
+    cp target/server/$target/libtest_$test.so initfs/tests/$test.elf
+    tools/sign-server.sh sign "$SIGNING_KEY" initfs/tests/$test.elf
+    printf 'tests/%s.elf\0' "$test" >> initfs.list
+    printf 'tests/%s.elf.sig\0' "$test" >> initfs.list
+  done
+fi
+
+# Service access policy.
+cp servers/policy.txt initfs/policy.txt
+printf 'policy.txt\0' >> initfs.list
+
 # Build initfs.
 pushd initfs
 cpio -o -H newc -0 < ../initfs.list > ../initfs.cpio
 popd
+if [[ -n "$COMPRESS_INITFS" ]]; then
+  gzip -9 -n -c initfs.cpio > initfs.cpio.gz
+  mv initfs.cpio.gz initfs.cpio
+fi
 
 # Build kernel.
-FTL_LOG_PREFIX="[$(printf '%-10s' "kernel")] " \
-  cargo build "${CARGOFLAGS[@]}" --target kernel/src/arch/$ARCH/kernel.json \
-    --manifest-path kernel/Cargo.toml
-cp target/kernel/$target/kernel ftl.elf
+build_kernel() {
+  FTL_LOG_PREFIX="[$(printf '%-10s' "kernel")] " FTL_SYMBOL_TABLE_SIZE="$1" \
+    cargo build "${CARGOFLAGS[@]}" --features "$KERNEL_FEATURES" \
+      --target kernel/src/arch/$ARCH/kernel.json --manifest-path kernel/Cargo.toml
+  cp target/kernel/$target/kernel ftl.elf
+}
+
+# Build it again with the space for the symbol table, and fill it.
+build_kernel 0
+build_kernel "$(tools/embed-symbols.py --size ftl.elf)"
+tools/embed-symbols.py ftl.elf
@@ -0,0 +1,12 @@
+#![no_main]
+
+use ftl_cpio::Reader;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    for entry in Reader::new(data) {
+        assert!(entry.data.len() <= data.len());
+    }
+
+    assert!(Reader::archive_len(data) <= data.len() + 3);
+});
@@ -0,0 +1,47 @@
+#![no_main]
+
+use ftl_elf::ET_DYN;
+use ftl_elf::ET_EXEC;
+use ftl_elf::Elf;
+use ftl_elf::SHT_DYNSYM;
+use ftl_elf::SHT_SYMTAB;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // The parser requires an 8-byte aligned buffer.
+    let mut words = vec![0u64; data.len().div_ceil(8)];
+    let buf = &mut as_bytes_mut(&mut words)[..data.len()];
+    buf.copy_from_slice(data);
+
+    for expected_type in [ET_EXEC, ET_DYN] {
+        let Ok(elf) = Elf::parse(buf, expected_type) else {
+            continue;
+        };
+
+        for segment in elf.segments() {
+            assert!(segment.data.len() <= segment.vaddr_range.len());
+        }
+
+        for _entry in elf.dynamic() {}
+        for _note in elf.notes() {}
+        let _ = elf.interp();
+
+        for shdr in elf.shdrs {
+            let _ = elf.section_data(shdr);
+            let _ = elf.section_name(shdr);
+        }
+
+        for sh_type in [SHT_SYMTAB, SHT_DYNSYM] {
+            if let Some(symbols) = elf.symbol_table(sh_type) {
+                for sym in symbols.iter() {
+                    let _ = symbols.name(&sym);
+                }
+            }
+        }
+    }
+});
+
+fn as_bytes_mut(words: &mut [u64]) -> &mut [u8] {
+    // SAFETY: u64 has no padding, and any byte pattern is a valid u64.
+    unsafe { std::slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, words.len() * 8) }
+}
@@ -0,0 +1,13 @@
+#![no_main]
+
+use ftl_bootinfo::multiboot2::Tag;
+use ftl_bootinfo::multiboot2::Tags;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    for tag in Tags::new(data) {
+        if let Tag::MemoryMap(mmap) = tag {
+            for _entry in mmap {}
+        }
+    }
+});
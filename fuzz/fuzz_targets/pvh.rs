@@ -0,0 +1,13 @@
+#![no_main]
+
+use ftl_bootinfo::pvh;
+use ftl_bootinfo::pvh::StartInfo;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = StartInfo::parse(data);
+
+    // The tables are separate from the start info: reuse the input.
+    for _module in pvh::modules(data) {}
+    for _entry in pvh::memory_map(data) {}
+});
@@ -0,0 +1,91 @@
+#!/usr/bin/env python3
+"""Generates the seed corpora in fuzz/corpus/<target>/seed-*."""
+import struct
+from pathlib import Path
+
+CORPUS_DIR = Path(__file__).resolve().parent.parent / "corpus"
+
+ET_EXEC = 2
+ET_DYN = 3
+EM_X86_64 = 62
+PT_LOAD = 1
+PF_R, PF_W, PF_X = 4, 2, 1
+
+
+def elf(e_type, segments, entry=0x400000):
+    """A 64-bit ELF file with a PT_LOAD segment for each (vaddr, flags, data, memsz)."""
+    ehdr_size, phdr_size = 64, 56
+    offset = ehdr_size + phdr_size * len(segments)
+    phdrs, body = b"", b""
+    for vaddr, flags, data, memsz in segments:
+        phdrs += struct.pack("<IIQQQQQQ", PT_LOAD, flags, offset + len(body),
+                             vaddr, vaddr, len(data), memsz, 0x1000)
+        body += data
+    ident = b"\x7fELF" + bytes([2, 1, 1]) + bytes(9)
+    ehdr = ident + struct.pack("<HHIQQQIHHHHHH", e_type, EM_X86_64, 1, entry,
+                               ehdr_size, 0, 0, ehdr_size, phdr_size,
+                               len(segments), 64, 0, 0)
+    return ehdr + phdrs + body
+
+
+def cpio_entry(name, data, mode=0o100644):
+    name = name.encode() + b"\0"
+    header = "070701" + "".join("%08x" % v for v in [
+        0, mode, 0, 0, 1, 0, len(data), 0, 0, 0, 0, len(name), 0])
+    out = header.encode() + name
+    out += bytes(-len(out) % 4) + data
+    return out + bytes(-len(out) % 4)
+
+
+def mb2_tag(type_, body):
+    tag = struct.pack("<II", type_, 8 + len(body)) + body
+    return tag + bytes(-len(tag) % 8)
+
+
+def multiboot2(tags):
+    body = b"".join(tags) + mb2_tag(0, b"")
+    return struct.pack("<II", 8 + len(body), 0) + body
+
+
+def pvh_start_info(nr_modules, memmap_entries):
+    magic = bytes([ord("x"), ord("E") | 0x80, ord("n"), ord("3")])
+    return magic + struct.pack("<IIIQQQQII", 1, 0, nr_modules, 0x10000, 0x20000, 0,
+                    0x30000, memmap_entries, 0)
+
+
+def write(target, name, data):
+    path = CORPUS_DIR / target / f"seed-{name}"
+    path.parent.mkdir(parents=True, exist_ok=True)
+    path.write_bytes(data)
+
+
+def main():
+    code = b"\x48\xc7\xc0\x3c\x00\x00\x00\x0f\x05"  # exit(...)
+    exec_elf = elf(ET_EXEC, [
+        (0x400000, PF_R | PF_X, code, len(code)),
+        (0x401000, PF_R | PF_W, b"hello\n", 0x2000),  # with .bss
+    ])
+    write("elf", "exec", exec_elf)
+    write("elf", "dyn", elf(ET_DYN, [(0, PF_R | PF_X, code, len(code))], entry=0))
+    write("lx_process_create", "exec", exec_elf)
+
+    write("cpio", "files", cpio_entry("hello.txt", b"hello\n")
+          + cpio_entry("bin", b"", mode=0o040755)
+          + cpio_entry("TRAILER!!!", b""))
+
+    mmap = struct.pack("<II", 24, 0) + \
+        struct.pack("<QQII", 0, 0x9fc00, 1, 0) + \
+        struct.pack("<QQII", 0x100000, 0x7f00000, 1, 0)
+    write("multiboot2", "qemu", multiboot2([
+        mb2_tag(1, b"log=debug\0"),
+        mb2_tag(3, struct.pack("<II", 0x200000, 0x300000) + b"initfs\0"),
+        mb2_tag(6, mmap),
+    ]))
+
+    modlist = struct.pack("<QQQQ", 0x200000, 0x100000, 0, 0)
+    memmap = struct.pack("<QQII", 0x100000, 0x7f00000, 1, 0)
+    write("pvh", "qemu", pvh_start_info(1, 1) + modlist + memmap)
+
+
+if __name__ == "__main__":
+    main()
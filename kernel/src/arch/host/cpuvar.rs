@@ -0,0 +1,38 @@
+use std::cell::Cell;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+thread_local! {
+    /// Each host thread is a CPU.
+    static CPU_VAR: Cell<Option<&'static crate::cpuvar::CpuVar>> = const { Cell::new(None) };
+}
+
+/// CPU-local variables.
+pub struct CpuVar {}
+
+impl CpuVar {
+    pub fn new(_cpu_id: usize) -> Self {
+        Self {}
+    }
+}
+
+/// Returns a CPU ID for a new host thread.
+pub(super) fn alloc_cpu_id() -> usize {
+    static NEXT_CPU_ID: AtomicUsize = AtomicUsize::new(0);
+    NEXT_CPU_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+pub fn get_cpuvar() -> &'static crate::cpuvar::CpuVar {
+    try_get_cpuvar().expect("CPU-local variables are not initialized")
+}
+
+/// Returns the CPU-local variables, or `None` if they're not initialized yet.
+pub fn try_get_cpuvar() -> Option<&'static crate::cpuvar::CpuVar> {
+    CPU_VAR.get()
+}
+
+pub fn set_cpuvar(_cpu_id: usize, value: crate::cpuvar::CpuVar) {
+    // Leak it: the CPU (host thread) may exit, but the kernel never frees
+    // CPU-local variables.
+    CPU_VAR.set(Some(Box::leak(Box::new(value))));
+}
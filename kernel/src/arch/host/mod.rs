@@ -1,100 +1,120 @@
+//! The host backend, which runs the kernel as a normal process for testing.
+//!
+//! Physical memory is a heap buffer, each host thread is a CPU, and user
+//! threads are simulated: see [`Thread`] and [`run_until_idle`].
 use std::ops::Range;
+use std::sync::Once;
+use std::sync::OnceLock;
+use std::time::Instant;
 
-use ftl_api::error::ErrorCode;
-use ftl_api::thread::ContextData;
-use ftl_api::thread::ContextKind;
-use ftl_api::vmspace::PageAttrs;
 use ftl_arrayvec::ArrayVec;
 
-use crate::address::PAddr;
-use crate::address::UAddr;
-use crate::address::VAddr;
+use crate::arch::ExitStatus;
 use crate::boot::BootInfo;
-
-pub const MIN_PAGE_SIZE: usize = 4096;
-pub const DIRECT_MAP_END: PAddr = PAddr::new(usize::MAX);
-
+use crate::boot::FreeRam;
+
+mod cpuvar;
+mod thread;
+mod vmspace;
+
+pub use cpuvar::CpuVar;
+pub use cpuvar::get_cpuvar;
+pub use cpuvar::set_cpuvar;
+pub use cpuvar::try_get_cpuvar;
+pub use thread::Thread;
+pub use vmspace::DIRECT_MAP_END;
+pub use vmspace::MIN_PAGE_SIZE;
+pub use vmspace::VmSpace;
+pub use vmspace::get_kernel_reserved_range;
+pub use vmspace::map_server_image;
+pub use vmspace::paddr2vaddr;
+pub use vmspace::protect_server_image;
+#[cfg(test)]
+pub use vmspace::server_image_attrs;
+pub use vmspace::unmap_server_image;
+
+/// The payload of the unwinding from [`idle`] to [`run_until_idle`].
+struct Idle;
+
+/// Enters the idle loop, that is, returns to [`run_until_idle`].
 pub fn idle() -> ! {
-    todo!()
+    std::panic::resume_unwind(Box::new(Idle));
 }
 
-pub fn console_write(_bytes: &[u8]) {}
-
-pub fn paddr2vaddr(_paddr: PAddr) -> VAddr {
-    todo!()
-}
-
-pub struct VmSpace {}
-
-impl VmSpace {
-    pub fn new() -> Result<Self, ErrorCode> {
-        todo!()
-    }
-
-    pub fn switch(&self) {
-        todo!()
-    }
-
-    pub fn map(
-        &self,
-        _uaddr: UAddr,
-        _paddr: PAddr,
-        _len: usize,
-        _attrs: PageAttrs,
-    ) -> Result<(), ErrorCode> {
-        todo!()
+/// Runs threads on the current host thread until no threads are runnable.
+///
+/// It requires `panic = "unwind"`, which is always the case in tests.
+pub fn run_until_idle() {
+    match std::panic::catch_unwind(|| crate::scheduler::return_to_user()) {
+        Err(payload) if payload.is::<Idle>() => {}
+        Err(payload) => std::panic::resume_unwind(payload),
     }
 }
 
-pub struct Thread {}
-
-impl Thread {
-    pub fn new() -> Self {
-        todo!()
-    }
-
-    pub fn read_context(&self, _kind: ContextKind, _regs: &mut ContextData) {
-        todo!()
-    }
+pub fn console_write(bytes: &[u8]) {
+    // Use print! instead of writing to stdout directly so that the test
+    // harness captures it.
+    print!("{}", String::from_utf8_lossy(bytes));
+}
 
-    pub fn write_context(&mut self, _kind: ContextKind, _regs: &ContextData) {
-        todo!()
-    }
+pub fn backtrace(_callback: impl FnMut(usize)) {}
 
-    pub fn enter(_thread: *const Thread) -> ! {
-        todo!()
-    }
+pub fn uptime_nanos() -> u64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_nanos() as u64
 }
 
-pub struct CpuVar {}
+pub fn num_cpus() -> usize {
+    1
+}
 
-impl CpuVar {
-    pub fn new(_cpu_id: usize) -> Self {
-        Self {}
-    }
+pub fn halt() -> ! {
+    std::process::exit(1);
 }
 
-pub fn get_cpuvar() -> &'static crate::cpuvar::CpuVar {
-    todo!()
+pub fn reboot() -> ! {
+    std::process::exit(0);
 }
 
-pub fn set_cpuvar(_cpu_id: usize, _cpuvar: crate::cpuvar::CpuVar) {
-    todo!()
+pub fn semihosting_exit(status: ExitStatus) -> ! {
+    // The same exit codes as QEMU's isa-debug-exit device.
+    match status {
+        ExitStatus::Success => std::process::exit(33),
+        ExitStatus::Failure => std::process::exit(71),
+    }
 }
 
-pub fn semihosting_exit() -> ! {
-    todo!()
+fn bootinfo(cmdline: &'static [u8]) -> BootInfo {
+    let mut free_rams = ArrayVec::new();
+    let Range { start, end } = vmspace::ram_range();
+    let ram = FreeRam {
+        addr: start,
+        size: end.as_usize() - start.as_usize(),
+    };
+    assert!(free_rams.try_push(ram).is_ok());
+
+    BootInfo {
+        cmdline,
+        free_rams,
+        modules: ArrayVec::new(),
+    }
 }
 
-pub fn get_kernel_reserved_range() -> Range<PAddr> {
-    todo!()
+/// Initializes the kernel for a test: the memory allocator once per process,
+/// and the CPU-local variables once per host thread.
+pub fn init_for_test() {
+    static MEMORY: Once = Once::new();
+    MEMORY.call_once(|| {
+        crate::memory::init(&bootinfo(b""));
+    });
+
+    if try_get_cpuvar().is_none() {
+        crate::cpuvar::init(cpuvar::alloc_cpu_id());
+    }
 }
 
+#[cfg(not(test))]
 #[unsafe(no_mangle)]
 pub fn main() -> ! {
-    crate::boot::boot(BootInfo {
-        cmdline: b"",
-        free_rams: ArrayVec::new(),
-        modules: ArrayVec::new(),
-    });
+    crate::boot::boot(bootinfo(b""));
 }
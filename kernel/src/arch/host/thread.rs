@@ -0,0 +1,161 @@
+use ftl_api::error::ErrorCode;
+use ftl_api::thread::ContextData;
+use ftl_api::thread::ContextKind;
+use ftl_api::thread::FpState;
+use ftl_api::thread::FsBase;
+use ftl_api::thread::GsBase;
+use ftl_api::thread::InitRegs;
+use ftl_api::thread::Regs;
+use ftl_api::thread::SyscallArgs;
+use ftl_api::thread::Sysret;
+
+/// A simulated user program.
+///
+/// The kernel calls it every time the thread enters the user mode, and
+/// handles a syscall when it returns. Set its address as the program
+/// counter in [`ContextKind::InitRegs`].
+pub type UserProgram = fn(&mut Thread);
+
+/// The registers of a simulated user thread.
+#[derive(Debug)]
+pub struct Thread {
+    pub pc: u64,
+    pub sp: u64,
+    /// The syscall number on a syscall, and the return value on return.
+    pub n: u64,
+    pub args: [u64; 6],
+    pub fsbase: u64,
+    pub gsbase: u64,
+    pub fp_state: FpState,
+    /// Other registers, only accessible through [`ContextKind::Regs`].
+    others: Regs,
+}
+
+impl Thread {
+    pub fn new() -> Self {
+        Self {
+            pc: 0,
+            sp: 0,
+            n: 0,
+            args: [0; 6],
+            fsbase: 0,
+            gsbase: 0,
+            fp_state: FpState::initial(),
+            others: Regs::zeroed(),
+        }
+    }
+
+    pub fn read_context(&self, kind: ContextKind, regs: &mut ContextData) {
+        match kind {
+            ContextKind::SyscallArgs => {
+                regs.syscall_args = SyscallArgs {
+                    n: self.n,
+                    arg0: self.args[0],
+                    arg1: self.args[1],
+                    arg2: self.args[2],
+                    arg3: self.args[3],
+                    arg4: self.args[4],
+                    arg5: self.args[5],
+                };
+            }
+            ContextKind::Sysret => {
+                regs.sysret = Sysret { retval: self.n };
+            }
+            ContextKind::InitRegs => {
+                regs.init_regs = InitRegs {
+                    pc: self.pc,
+                    sp: self.sp,
+                };
+            }
+            ContextKind::Fsbase => {
+                regs.fsbase = FsBase { base: self.fsbase };
+            }
+            ContextKind::FpState => {
+                regs.fp_state = self.fp_state;
+            }
+            ContextKind::Gsbase => {
+                regs.gsbase = GsBase { base: self.gsbase };
+            }
+            ContextKind::Regs => {
+                regs.regs = Regs {
+                    rip: self.pc,
+                    rsp: self.sp,
+                    rax: self.n,
+                    rdi: self.args[0],
+                    rsi: self.args[1],
+                    rdx: self.args[2],
+                    r10: self.args[3],
+                    r8: self.args[4],
+                    r9: self.args[5],
+                    fsbase: self.fsbase,
+                    gsbase: self.gsbase,
+                    ..self.others
+                };
+            }
+        }
+    }
+
+    /// Writes the registers. Unlike the x64 backend, values are not
+    /// validated.
+    pub fn write_context(
+        &mut self,
+        kind: ContextKind,
+        regs: &ContextData,
+    ) -> Result<(), ErrorCode> {
+        match kind {
+            ContextKind::SyscallArgs => {
+                let args = unsafe { regs.syscall_args };
+                self.n = args.n;
+                self.args = [
+                    args.arg0, args.arg1, args.arg2, args.arg3, args.arg4, args.arg5,
+                ];
+            }
+            ContextKind::Sysret => {
+                self.n = unsafe { regs.sysret.retval };
+            }
+            ContextKind::InitRegs => {
+                let init_regs = unsafe { regs.init_regs };
+                self.pc = init_regs.pc;
+                self.sp = init_regs.sp;
+            }
+            ContextKind::Fsbase => {
+                self.fsbase = unsafe { regs.fsbase }.base;
+            }
+            ContextKind::FpState => {
+                self.fp_state = unsafe { regs.fp_state };
+            }
+            ContextKind::Gsbase => {
+                self.gsbase = unsafe { regs.gsbase }.base;
+            }
+            ContextKind::Regs => {
+                let regs = unsafe { regs.regs };
+                self.pc = regs.rip;
+                self.sp = regs.rsp;
+                self.n = regs.rax;
+                self.args = [regs.rdi, regs.rsi, regs.rdx, regs.r10, regs.r8, regs.r9];
+                self.fsbase = regs.fsbase;
+                self.gsbase = regs.gsbase;
+                self.others = regs;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs the user program until it makes a syscall.
+    ///
+    /// Unlike real CPUs, the kernel stack is not reset: each syscall nests
+    /// the stack frames until [`super::run_until_idle`] returns.
+    pub fn enter(thread: *const Thread) -> ! {
+        // SAFETY: The thread is running on this CPU, as the x64 backend
+        //         overwrites the registers directly.
+        let thread = unsafe { &mut *(thread as *mut Thread) };
+        assert!(thread.pc != 0, "no user program is set");
+
+        // SAFETY: `pc` is set from a `UserProgram`.
+        let program: UserProgram = unsafe { core::mem::transmute(thread.pc as usize) };
+        program(thread);
+
+        crate::syscall::handle_syscall();
+    }
+}
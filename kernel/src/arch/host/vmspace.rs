@@ -0,0 +1,164 @@
+use std::alloc::Layout;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+
+use ftl_api::error::ErrorCode;
+use ftl_api::vmspace::PageAttrs;
+use ftl_utils::alignment::is_aligned;
+use ftl_utils::spinlock::SpinLock;
+
+use crate::address::PAddr;
+use crate::address::UAddr;
+use crate::address::VAddr;
+use crate::quota::Quota;
+use crate::shared_ref::SharedRef;
+
+pub const MIN_PAGE_SIZE: usize = 4096;
+
+/// The start of the simulated physical memory. It's not zero so that a
+/// zeroed PAddr is never valid.
+const RAM_START: usize = 0x100000;
+const RAM_SIZE: usize = 64 * 1024 * 1024;
+
+pub const DIRECT_MAP_END: PAddr = PAddr::new(RAM_START + RAM_SIZE);
+
+/// Returns the host address of the simulated physical memory.
+fn ram_base() -> usize {
+    static RAM: OnceLock<usize> = OnceLock::new();
+    *RAM.get_or_init(|| {
+        let layout = Layout::from_size_align(RAM_SIZE, MIN_PAGE_SIZE).unwrap();
+        // SAFETY: The layout is not zero-sized. The memory is never freed.
+        let ptr = unsafe { std::alloc::alloc_zeroed(layout) };
+        assert!(!ptr.is_null(), "failed to allocate the simulated RAM");
+        ptr as usize
+    })
+}
+
+pub(super) fn ram_range() -> Range<PAddr> {
+    PAddr::new(RAM_START)..DIRECT_MAP_END
+}
+
+pub fn paddr2vaddr(paddr: PAddr) -> VAddr {
+    let paddr = paddr.as_usize();
+    assert!(
+        (RAM_START..RAM_START + RAM_SIZE).contains(&paddr),
+        "{paddr:#x} is not in the simulated RAM"
+    );
+
+    VAddr::new(ram_base() + paddr - RAM_START)
+}
+
+/// The permissions of the server image pages currently mapped, indexed by
+/// the address. Kept to check them in tests.
+static IMAGE_PAGES: Mutex<BTreeMap<usize, PageAttrs>> = Mutex::new(BTreeMap::new());
+
+/// The image is already accessible in the simulated RAM.
+pub fn map_server_image(paddr: PAddr, len: usize) -> Result<VAddr, ErrorCode> {
+    let vaddr = paddr2vaddr(paddr);
+    let mut pages = IMAGE_PAGES.lock().unwrap();
+    for offset in (0..len).step_by(MIN_PAGE_SIZE) {
+        pages.insert(
+            vaddr.as_usize() + offset,
+            PageAttrs::READ | PageAttrs::WRITE,
+        );
+    }
+
+    Ok(vaddr)
+}
+
+/// Page protection is not enforced, but recorded.
+pub fn protect_server_image(vaddr: VAddr, len: usize, attrs: PageAttrs) {
+    let mut pages = IMAGE_PAGES.lock().unwrap();
+    for offset in (0..len).step_by(MIN_PAGE_SIZE) {
+        let page = vaddr.as_usize() + offset;
+        let entry = pages.get_mut(&page);
+        *entry.unwrap_or_else(|| panic!("{page:#x} is not a mapped server image")) = attrs;
+    }
+}
+
+pub fn unmap_server_image(vaddr: VAddr, len: usize) {
+    let mut pages = IMAGE_PAGES.lock().unwrap();
+    for offset in (0..len).step_by(MIN_PAGE_SIZE) {
+        let page = vaddr.as_usize() + offset;
+        let removed = pages.remove(&page);
+        assert!(removed.is_some(), "{page:#x} is not a mapped server image");
+    }
+}
+
+/// Returns the permissions of a server image page, or `None` if it's not
+/// mapped.
+#[cfg(test)]
+pub fn server_image_attrs(vaddr: VAddr) -> Option<PageAttrs> {
+    IMAGE_PAGES.lock().unwrap().get(&vaddr.as_usize()).copied()
+}
+
+/// A simulated page mapping.
+#[derive(Debug, Clone, Copy)]
+pub struct Mapping {
+    pub paddr: PAddr,
+    pub attrs: PageAttrs,
+}
+
+/// A virtual memory space. Page tables are not simulated: mappings are kept
+/// in a map, not charged to the quota.
+pub struct VmSpace {
+    /// The mappings indexed by the user page address.
+    pages: SpinLock<BTreeMap<usize, Mapping>>,
+}
+
+impl VmSpace {
+    pub fn new(_quota: SharedRef<Quota>) -> Result<Self, ErrorCode> {
+        Ok(Self {
+            pages: SpinLock::new(BTreeMap::new()),
+        })
+    }
+
+    pub fn switch(&self) {}
+
+    pub fn map(
+        &self,
+        uaddr: UAddr,
+        paddr: PAddr,
+        len: usize,
+        attrs: PageAttrs,
+    ) -> Result<(), ErrorCode> {
+        let uaddr = uaddr.as_usize();
+
+        if !is_aligned(uaddr, MIN_PAGE_SIZE)
+            || !paddr.is_aligned(MIN_PAGE_SIZE)
+            || !is_aligned(len, MIN_PAGE_SIZE)
+        {
+            return Err(ErrorCode::INVALID_ARG);
+        }
+
+        let mut pages = self.pages.lock();
+        for offset in (0..len).step_by(MIN_PAGE_SIZE) {
+            if pages.contains_key(&(uaddr + offset)) {
+                return Err(ErrorCode::ALREADY_EXISTS);
+            }
+        }
+
+        for offset in (0..len).step_by(MIN_PAGE_SIZE) {
+            let mapping = Mapping {
+                paddr: PAddr::new(paddr.as_usize() + offset),
+                attrs,
+            };
+            pages.insert(uaddr + offset, mapping);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the mapping of the page containing `uaddr`.
+    pub fn lookup(&self, uaddr: UAddr) -> Option<Mapping> {
+        let page = uaddr.as_usize() & !(MIN_PAGE_SIZE - 1);
+        self.pages.lock().get(&page).copied()
+    }
+}
+
+/// The kernel is not in the simulated RAM.
+pub fn get_kernel_reserved_range() -> Range<PAddr> {
+    PAddr::new(RAM_START)..PAddr::new(RAM_START)
+}
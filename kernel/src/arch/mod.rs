@@ -8,3 +8,10 @@ pub mod host;
 pub use host::*;
 #[cfg(target_os = "none")]
 pub use x64::*;
+
+/// The result passed to `semihosting_exit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    Success,
+    Failure,
+}
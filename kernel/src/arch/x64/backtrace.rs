@@ -0,0 +1,45 @@
+use core::arch::asm;
+
+use super::boot::KERNEL_STACK_SIZE;
+use super::boot::bsp_stack_top;
+
+/// The maximum number of frames to walk, in case the chain is broken.
+const MAX_FRAMES: usize = 64;
+
+/// Walks the stack frames of the caller by frame pointers, and calls
+/// `callback` with the return address of each frame.
+#[inline(never)]
+pub fn backtrace(mut callback: impl FnMut(usize)) {
+    let stack_top = bsp_stack_top() as usize;
+    let stack_bottom = stack_top - KERNEL_STACK_SIZE;
+
+    let mut fp: usize;
+    unsafe {
+        asm!("mov {}, rbp", out(reg) fp);
+    }
+
+    for _ in 0..MAX_FRAMES {
+        // Stop at the first frame outside the kernel stack, e.g. a frame
+        // pointer from the user.
+        if fp < stack_bottom || fp + 16 > stack_top || !fp.is_multiple_of(8) {
+            break;
+        }
+
+        // A frame record is the caller's frame pointer followed by the
+        // return address.
+        let frame = fp as *const usize;
+        let (next_fp, return_addr) = unsafe { (*frame, *frame.add(1)) };
+        if return_addr == 0 {
+            break;
+        }
+
+        callback(return_addr);
+
+        // The stack grows downwards: callers' frames are above.
+        if next_fp <= fp {
+            break;
+        }
+
+        fp = next_fp;
+    }
+}
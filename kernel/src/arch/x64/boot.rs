@@ -1,6 +1,9 @@
 use core::arch::asm;
 use core::arch::naked_asm;
+use core::arch::x86_64::__cpuid;
 
+use super::msr::rdmsr;
+use super::msr::wrmsr;
 use super::multiboot;
 use super::pvh;
 use super::vmspace::BOOT_PDPT;
@@ -11,13 +14,28 @@ use crate::address::PAddr;
 extern "C" fn rust_boot(multiboot_magic: u32, start_info: u32) -> ! {
     super::console::init();
 
+    // Logging reads the CPU-local variables by RDGSBASE. Enable it before
+    // anything is logged.
+    enable_fsgsbase();
+
     // SeaBIOS prints an escape sequence which disables line wrapping, and messes up
     // your terminal. Revert it.
     println!("\x1b[?7h");
 
+    let bootinfo = if multiboot_magic == 0x36d76289 {
+        multiboot::parse_multiboot2_info(PAddr::new(start_info as usize))
+    } else {
+        pvh::parse_start_info(PAddr::new(start_info as usize))
+    };
+
+    // Parse the command line first: it affects the following steps.
+    crate::cmdline::init(bootinfo.cmdline);
+
     trace!("Booting FTL...");
-    enable_fsgsbase();
     enable_sse();
+    super::fpu::init();
+    enable_page_protection();
+    super::vmspace::init();
 
     let cpu_id = 0;
     super::gdt::init(cpu_id);
@@ -27,12 +45,6 @@ extern "C" fn rust_boot(multiboot_magic: u32, start_info: u32) -> ! {
     super::mp_table::init();
     super::timer::init();
 
-    let bootinfo = if multiboot_magic == 0x36d76289 {
-        multiboot::parse_multiboot2_info(PAddr::new(start_info as usize))
-    } else {
-        pvh::parse_start_info(PAddr::new(start_info as usize))
-    };
-
     crate::boot::boot(bootinfo);
 }
 
@@ -66,6 +78,38 @@ fn enable_sse() {
     }
 }
 
+/// Makes read-only and no-execute pages effective in the kernel mode, for
+/// server images.
+fn enable_page_protection() {
+    const MSR_IA32_EFER: u32 = 0xc000_0080;
+    const EFER_NXE: u64 = 1 << 11;
+    const CPUID_EXT_MAX: u32 = 0x8000_0000;
+    const CPUID_EXT_FEATURES: u32 = 0x8000_0001;
+    const CPUID_EXT_EDX_NX: u32 = 1 << 20;
+
+    // Setting EFER.NXE without the feature causes #GP.
+    let nx_supported = __cpuid(CPUID_EXT_MAX).eax >= CPUID_EXT_FEATURES
+        && __cpuid(CPUID_EXT_FEATURES).edx & CPUID_EXT_EDX_NX != 0;
+    if nx_supported {
+        unsafe {
+            wrmsr(MSR_IA32_EFER, rdmsr(MSR_IA32_EFER) | EFER_NXE);
+        }
+
+        super::vmspace::enable_no_execute();
+    } else {
+        warn!("no-execute pages are not supported: server data is executable");
+    }
+
+    unsafe {
+        asm!(
+            "mov rax, cr0",
+            "or  rax, 1 << 16", // WP
+            "mov cr0, rax",
+            out("rax") _,
+        );
+    }
+}
+
 /// The per-CPU kernel stack size.
 pub(super) const KERNEL_STACK_SIZE: usize = 1024 * 1024;
 
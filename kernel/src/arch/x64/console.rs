@@ -3,6 +3,9 @@ use core::hint::spin_loop;
 use super::ioport::in8;
 use super::ioport::out8;
 
+/// The ISA IRQ of COM1.
+pub(super) const COM1_IRQ: u8 = 4;
+
 fn putchar(c: u8) {
     // Wait for the serial port to be ready to receive more data.
     while unsafe { in8(COM1_LSR) } & 0x20 == 0 {
@@ -36,6 +39,27 @@ const COM1_MCR: u16 = COM1_DATA + 4;
 /// Line Status Register.
 const COM1_LSR: u16 = COM1_DATA + 5;
 
+/// Reads the received bytes and passes them to the kernel console.
+pub(super) fn handle_interrupt() {
+    let mut buf = [0; 16];
+    loop {
+        // Read in chunks: the RX FIFO holds up to 16 bytes.
+        let mut len = 0;
+        while len < buf.len() && unsafe { in8(COM1_LSR) } & 0x01 != 0 {
+            buf[len] = unsafe { in8(COM1_DATA) };
+            len += 1;
+        }
+
+        if len == 0 {
+            break;
+        }
+
+        crate::console::push_input(&buf[..len]);
+    }
+
+    super::get_cpuvar().arch.local_apic.acknowledge_irq();
+}
+
 /// Initializes the serial port.
 pub(super) fn init() {
     // Based on "Initialization" section in https://wiki.osdev.org/Serial_Ports
@@ -51,7 +75,10 @@ pub(super) fn init() {
         out8(COM1_LCR, 0x03);
         // Enable FIFO, clear both TX/RX FIFOs, buffer 14 bytes in RX.
         out8(COM1_FCR, 0xc7);
-        // Enable Data Terminal Ready (DTR) and Request to Send (RTS).
-        out8(COM1_MCR, 0x03);
+        // Enable Data Terminal Ready (DTR), Request to Send (RTS), and OUT2,
+        // which connects the interrupt line.
+        out8(COM1_MCR, 0x0b);
+        // Enable the Received Data Available interrupt.
+        out8(COM1_IER, 0x01);
     }
 }
@@ -1,8 +1,11 @@
 use core::arch::asm;
+use core::cell::Cell;
 use core::mem::MaybeUninit;
+use core::ptr;
 
 use super::NUM_CPUS_MAX;
 use super::local_apic::LocalApic;
+use super::thread::Thread;
 
 const MAGIC: u64 = 0xc12c_12c1_2c12_c12c;
 
@@ -16,6 +19,8 @@ pub struct CpuVar {
     pub(super) scratch: u64,
     pub(super) kernel_rsp: u64,
     pub(super) local_apic: LocalApic,
+    /// The thread whose FPU state is in the registers. See `fpu.rs`.
+    pub(super) fpu_owner: Cell<*const Thread>,
 }
 
 impl CpuVar {
@@ -31,6 +36,7 @@ impl CpuVar {
             scratch: 0,
             kernel_rsp,
             local_apic: LocalApic::init(),
+            fpu_owner: Cell::new(ptr::null()),
         }
     }
 }
@@ -47,6 +53,20 @@ pub fn get_cpuvar() -> &'static crate::cpuvar::CpuVar {
     cpuvar
 }
 
+/// Returns the CPU-local variables, or `None` if they're not initialized yet.
+pub fn try_get_cpuvar() -> Option<&'static crate::cpuvar::CpuVar> {
+    let gsbase: u64;
+    unsafe {
+        asm!("rdgsbase {}", out(reg) gsbase);
+    }
+
+    if gsbase == 0 {
+        return None;
+    }
+
+    Some(get_cpuvar())
+}
+
 pub fn set_cpuvar(cpu_id: usize, value: crate::cpuvar::CpuVar) {
     assert!(cpu_id < NUM_CPUS_MAX);
     unsafe {
@@ -0,0 +1,322 @@
+//! FPU, SSE, and AVX state.
+//!
+//! The kernel and servers are built with soft-float, and never touch these
+//! registers. Thus, the registers keep the state of the last user thread which
+//! ran on the CPU (the *owner*) while the kernel is running. We save the state
+//! to the owner and restore the next thread's one only when entering another
+//! thread.
+//!
+//! We use XSAVE if available, and FXSAVE otherwise.
+//!
+//! This assumes that threads don't migrate between CPUs with the state in the
+//! registers. It holds for now because only the BSP runs threads.
+use core::arch::asm;
+use core::arch::x86_64::__cpuid;
+use core::arch::x86_64::__cpuid_count;
+use core::fmt;
+use core::ptr;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+
+use ftl_api::error::ErrorCode;
+use ftl_api::thread::FpState;
+
+use super::cpuvar::get_cpuvar;
+use super::cpuvar::try_get_cpuvar;
+use super::thread::Thread;
+
+/// The size of the save area. Large enough for x87, SSE, and AVX (832 bytes).
+const AREA_SIZE: usize = 1024;
+/// The alignment required by XSAVE. FXSAVE requires 16 bytes.
+const AREA_ALIGN: usize = 64;
+/// The size of the FXSAVE area, which is also the legacy region of XSAVE.
+const LEGACY_SIZE: usize = 512;
+/// The offset of MXCSR_MASK in the FXSAVE area.
+const MXCSR_MASK_OFFSET: usize = 28;
+/// The MXCSR bits supported if FXSAVE saves zero as MXCSR_MASK.
+const MXCSR_MASK_DEFAULT: u32 = 0xffbf;
+/// The offset of XSTATE_BV in the XSAVE header.
+const XSTATE_BV_OFFSET: usize = 512;
+/// The offset of the upper halves of YMM registers in the standard format.
+const YMM_HI_OFFSET: usize = 576;
+
+const XFEATURE_X87: u64 = 1 << 0;
+const XFEATURE_SSE: u64 = 1 << 1;
+const XFEATURE_AVX: u64 = 1 << 2;
+
+/// Whether XSAVE is enabled.
+static XSAVE: AtomicBool = AtomicBool::new(false);
+/// The state components enabled in XCR0.
+static XFEATURES: AtomicU64 = AtomicU64::new(0);
+/// The MXCSR bits the CPU supports. Setting others causes #GP in
+/// FXRSTOR/XRSTOR.
+static MXCSR_MASK: AtomicU32 = AtomicU32::new(MXCSR_MASK_DEFAULT);
+
+/// Enables XSAVE and AVX if available.
+///
+/// SSE is already enabled in `enable_sse`.
+pub(super) fn init() {
+    const CPUID1_ECX_XSAVE: u32 = 1 << 26;
+    const CPUID1_ECX_AVX: u32 = 1 << 28;
+
+    // The CPU reports the supported MXCSR bits in the FXSAVE image.
+    let mut probe = FpuArea::new();
+    probe.save();
+    let mxcsr_mask = read_u64(probe.area(), MXCSR_MASK_OFFSET) as u32;
+    if mxcsr_mask != 0 {
+        MXCSR_MASK.store(mxcsr_mask, Ordering::Relaxed);
+    }
+
+    let features = __cpuid(1);
+    if features.ecx & CPUID1_ECX_XSAVE == 0 {
+        trace!("XSAVE is not available, using FXSAVE");
+        return;
+    }
+
+    let mut xfeatures = XFEATURE_X87 | XFEATURE_SSE;
+    if features.ecx & CPUID1_ECX_AVX != 0 {
+        xfeatures |= XFEATURE_AVX;
+    }
+
+    unsafe {
+        asm!(
+            "mov rax, cr4",
+            "or  rax, 1 << 18", // OSXSAVE
+            "mov cr4, rax",
+            out("rax") _,
+        );
+        asm!(
+            "xsetbv",
+            in("ecx") 0, // XCR0
+            in("eax") xfeatures as u32,
+            in("edx") (xfeatures >> 32) as u32,
+        );
+    }
+
+    // The size of the save area for the features enabled in XCR0.
+    let size = __cpuid_count(0xd, 0).ebx as usize;
+    assert!(size <= AREA_SIZE, "XSAVE area is too large: {size} bytes");
+
+    XFEATURES.store(xfeatures, Ordering::Relaxed);
+    XSAVE.store(true, Ordering::Relaxed);
+}
+
+/// The saved FPU state of a thread.
+pub(super) struct FpuArea {
+    /// The save area, at the first 64-byte aligned offset. The area is not
+    /// aligned by the type because `arch::Thread` is packed.
+    buf: [u8; AREA_SIZE + AREA_ALIGN - 1],
+    /// Whether `buf` holds the state. If not, the thread has not run yet
+    /// and it has the initial state.
+    saved: bool,
+}
+
+impl FpuArea {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; AREA_SIZE + AREA_ALIGN - 1],
+            saved: false,
+        }
+    }
+
+    /// The offset of the save area in `buf`. It changes when `self` moves:
+    /// don't copy the area out of the thread.
+    fn offset(&self) -> usize {
+        self.buf.as_ptr().align_offset(AREA_ALIGN)
+    }
+
+    fn area(&self) -> &[u8] {
+        let offset = self.offset();
+        &self.buf[offset..offset + AREA_SIZE]
+    }
+
+    fn area_mut(&mut self) -> &mut [u8] {
+        let offset = self.offset();
+        &mut self.buf[offset..offset + AREA_SIZE]
+    }
+
+    /// Saves the registers.
+    fn save(&mut self) {
+        let area = self.area_mut().as_mut_ptr();
+        unsafe {
+            if XSAVE.load(Ordering::Relaxed) {
+                asm!("xsave64 [{}]", in(reg) area, in("eax") u32::MAX, in("edx") u32::MAX);
+            } else {
+                asm!("fxsave64 [{}]", in(reg) area);
+            }
+        }
+
+        self.saved = true;
+    }
+
+    /// Loads the registers.
+    fn restore(&mut self) {
+        if !self.saved {
+            self.fill(&FpState::initial());
+        }
+
+        let area = self.area().as_ptr();
+        unsafe {
+            if XSAVE.load(Ordering::Relaxed) {
+                asm!("xrstor64 [{}]", in(reg) area, in("eax") u32::MAX, in("edx") u32::MAX);
+            } else {
+                asm!("fxrstor64 [{}]", in(reg) area);
+            }
+        }
+    }
+
+    fn read(&self, state: &mut FpState) {
+        if !self.saved {
+            *state = FpState::initial();
+            return;
+        }
+
+        let area = self.area();
+        // SAFETY: The first 512 bytes of FpState are in the FXSAVE format.
+        unsafe {
+            ptr::copy_nonoverlapping(area.as_ptr(), state as *mut FpState as *mut u8, LEGACY_SIZE);
+        }
+
+        state.ymm_hi = [[0; 16]; 16];
+        if !XSAVE.load(Ordering::Relaxed) {
+            return;
+        }
+
+        // Components in the initial state may not be written by XSAVE.
+        let xstate_bv = read_u64(area, XSTATE_BV_OFFSET);
+        if xstate_bv & XFEATURE_X87 == 0 {
+            let initial = FpState::initial();
+            state.fcw = initial.fcw;
+            state.fsw = 0;
+            state.ftw = 0;
+            state.fop = 0;
+            state.fip = 0;
+            state.fdp = 0;
+            state.st = [[0; 16]; 8];
+        }
+
+        if xstate_bv & XFEATURE_SSE == 0 {
+            state.xmm = [[0; 16]; 16];
+        }
+
+        if xstate_bv & XFEATURE_AVX != 0 {
+            for (i, ymm_hi) in state.ymm_hi.iter_mut().enumerate() {
+                let offset = YMM_HI_OFFSET + i * 16;
+                ymm_hi.copy_from_slice(&area[offset..offset + 16]);
+            }
+        }
+    }
+
+    /// Returns [`ErrorCode::INVALID_ARG`] if MXCSR has unsupported bits.
+    fn write(&mut self, state: &FpState) -> Result<(), ErrorCode> {
+        if state.mxcsr & !MXCSR_MASK.load(Ordering::Relaxed) != 0 {
+            return Err(ErrorCode::INVALID_ARG);
+        }
+
+        self.fill(state);
+        Ok(())
+    }
+
+    /// Writes `state`, which has been validated, to the area.
+    fn fill(&mut self, state: &FpState) {
+        let area = self.area_mut();
+        area.fill(0);
+        // SAFETY: The first 512 bytes of FpState are in the FXSAVE format.
+        unsafe {
+            ptr::copy_nonoverlapping(
+                state as *const FpState as *const u8,
+                area.as_mut_ptr(),
+                LEGACY_SIZE,
+            );
+        }
+
+        if XSAVE.load(Ordering::Relaxed) {
+            // Load all enabled components from the area. XCOMP_BV and the
+            // rest of the header must be zero.
+            let xfeatures = XFEATURES.load(Ordering::Relaxed);
+            area[XSTATE_BV_OFFSET..XSTATE_BV_OFFSET + 8].copy_from_slice(&xfeatures.to_le_bytes());
+
+            if xfeatures & XFEATURE_AVX != 0 {
+                for (i, ymm_hi) in state.ymm_hi.iter().enumerate() {
+                    let offset = YMM_HI_OFFSET + i * 16;
+                    area[offset..offset + 16].copy_from_slice(ymm_hi);
+                }
+            }
+        }
+
+        self.saved = true;
+    }
+}
+
+impl fmt::Debug for FpuArea {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FpuArea")
+            .field("saved", &self.saved)
+            .finish_non_exhaustive()
+    }
+}
+
+fn read_u64(area: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(area[offset..offset + 8].try_into().unwrap())
+}
+
+/// Returns true if the CPU registers hold the state of `thread`.
+fn is_owner(thread: *const Thread) -> bool {
+    try_get_cpuvar().is_some_and(|cpuvar| cpuvar.arch.fpu_owner.get() == thread)
+}
+
+/// Loads the state of `next` into the CPU, saving the current owner's.
+///
+/// # Safety
+///
+/// `next` and the current owner must be valid, and no one else accesses
+/// their FPU areas.
+pub(super) unsafe fn switch_to(next: *mut Thread) {
+    let owner = &get_cpuvar().arch.fpu_owner;
+    let prev = owner.get() as *mut Thread;
+    if prev == next {
+        return;
+    }
+
+    unsafe {
+        if !prev.is_null() {
+            (*prev).fpu.save();
+        }
+
+        (*next).fpu.restore();
+    }
+
+    owner.set(next);
+}
+
+/// Reads the state of `thread`.
+pub(super) fn read(thread: &Thread, state: &mut FpState) {
+    if is_owner(thread) {
+        // The registers are newer than the area. Save them to a temporary
+        // area to keep `thread` intact.
+        let mut live = FpuArea::new();
+        live.save();
+        live.read(state);
+    } else {
+        thread.fpu.read(state);
+    }
+}
+
+/// Overwrites the state of `thread`.
+pub(super) fn write(thread: &mut Thread, state: &FpState) -> Result<(), ErrorCode> {
+    thread.fpu.write(state)?;
+
+    // Load the new state when entering the thread next time.
+    forget(thread);
+    Ok(())
+}
+
+/// Forgets the registers owned by `thread`, which is being modified or
+/// dropped.
+pub(super) fn forget(thread: *const Thread) {
+    if is_owner(thread) {
+        get_cpuvar().arch.fpu_owner.set(ptr::null());
+    }
+}
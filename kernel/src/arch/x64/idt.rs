@@ -3,8 +3,10 @@ use core::arch::global_asm;
 use core::arch::naked_asm;
 use core::mem::offset_of;
 
+use ftl_api::thread::vector;
 use ftl_utils::spinlock::SpinLock;
 
+use super::console::COM1_IRQ;
 use super::gdt::GDT_KERNEL_CS;
 use super::io_apic::IRQ_VECTOR_BASE;
 use super::thread::Thread;
@@ -142,7 +144,7 @@ extern "C" fn interrupt_entry() -> ! {
         // Pop IRET frame and save it to the thread.
         "pop rbx",
         "mov [rax + {rip_offset}], rbx",
-        "pop rbx", // Drop CS
+        "pop rdx", // CS
         "pop rbx",
         "mov [rax + {rflags_offset}], rbx",
         "pop rbx",
@@ -173,7 +175,8 @@ extern "C" fn interrupt_entry() -> ! {
     )
 }
 
-extern "C" fn handle_interrupt(vector: u8, error_code: u64) -> ! {
+extern "C" fn handle_interrupt(vector: u8, error_code: u64, cs: u64) -> ! {
+    let from_user = cs & 3 == 3;
     match vector {
         14 => {
             let cr2: u64;
@@ -188,10 +191,30 @@ extern "C" fn handle_interrupt(vector: u8, error_code: u64) -> ! {
             if irq == TIMER_IRQ {
                 // trace!("timer interrupt");
                 super::timer::handle_interrupt();
+            } else if irq == COM1_IRQ {
+                super::console::handle_interrupt();
             } else {
                 trace!("unhandled interrupt ({vector}), error_code={error_code:#x}");
             }
         }
+        vector::DE
+        | vector::DB
+        | vector::BP
+        | vector::OF
+        | vector::BR
+        | vector::UD
+        | vector::NM
+        | vector::TS
+        | vector::NP
+        | vector::SS
+        | vector::GP
+        | vector::MF
+        | vector::AC
+        | vector::XM
+            if from_user =>
+        {
+            crate::syscall::handle_exception(vector, error_code);
+        }
         _ => {
             panic!("unhandled exception ({vector}), error_code={error_code:#x}");
         }
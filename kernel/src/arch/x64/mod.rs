@@ -1,6 +1,8 @@
+mod backtrace;
 mod boot;
 mod console;
 mod cpuvar;
+mod fpu;
 mod gdt;
 mod idle;
 mod idt;
@@ -11,6 +13,7 @@ mod mp_table;
 mod msr;
 mod multiboot;
 mod pic;
+mod power;
 mod pvh;
 mod semihosting;
 mod syscall;
@@ -20,15 +23,24 @@ mod vmspace;
 
 pub const NUM_CPUS_MAX: usize = 8;
 
+pub use backtrace::backtrace;
 pub use console::console_write;
 pub use cpuvar::CpuVar;
 pub use cpuvar::get_cpuvar;
 pub use cpuvar::set_cpuvar;
+pub use cpuvar::try_get_cpuvar;
 pub use idle::idle;
-// pub use semihosting::semihosting_exit;
+pub use mp_table::num_cpus;
+pub use power::halt;
+pub use power::reboot;
+pub use semihosting::semihosting_exit;
 pub use thread::Thread;
+pub use timer::uptime_nanos;
 pub use vmspace::DIRECT_MAP_END;
 pub use vmspace::MIN_PAGE_SIZE;
 pub use vmspace::VmSpace;
 pub use vmspace::get_kernel_reserved_range;
+pub use vmspace::map_server_image;
 pub use vmspace::paddr2vaddr;
+pub use vmspace::protect_server_image;
+pub use vmspace::unmap_server_image;
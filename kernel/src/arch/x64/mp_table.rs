@@ -3,10 +3,22 @@
 //! <https://web.archive.org/web/20121002210153/http://download.intel.com/design/archives/processors/pro/docs/24201606.pdf>
 
 use core::ops::Range;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
 
+use super::NUM_CPUS_MAX;
+use super::console::COM1_IRQ;
 use super::timer::TIMER_IRQ;
 use crate::address::PAddr;
 
+/// The number of CPUs to use.
+static NUM_CPUS: AtomicUsize = AtomicUsize::new(1);
+
+/// Returns the number of usable CPUs, up to [`NUM_CPUS_MAX`].
+pub fn num_cpus() -> usize {
+    NUM_CPUS.load(Ordering::Relaxed)
+}
+
 /// The MP floating pointer table.
 #[derive(Debug)]
 #[repr(C, packed)]
@@ -235,12 +247,21 @@ pub fn init() {
     let mp_table = find_mpfp_table().expect("failed to locate MP floating pointer table");
     let iter = MpTableIter::new(mp_table);
 
+    let mut num_cpus = 0;
     for entry in iter.clone() {
         if let MpTableEntry::Processor(entry) = entry {
+            if entry.cpu_flags & 1 == 0 || num_cpus >= NUM_CPUS_MAX {
+                trace!("processor: {:x} (disabled)", entry.local_apic_id);
+                continue;
+            }
+
             trace!("processor: {:x}", entry.local_apic_id);
+            num_cpus += 1;
         }
     }
 
+    NUM_CPUS.store(num_cpus.max(1), Ordering::Relaxed);
+
     // Find the ISA bus and I/O APIC.
     let mut isa_bus = None;
     let mut ioapic = None;
@@ -263,26 +284,44 @@ pub fn init() {
     let isa_bus = isa_bus.expect("ISA bus not found");
     let ioapic = ioapic.expect("I/O APIC not found");
 
-    // Locate the interrupt mapping for PIT (timer).
+    // Locate the interrupt mappings for PIT (timer) and COM1.
     let mut timer_int_mapping = None;
+    let mut com1_int_mapping = None;
     for entry in iter.clone() {
         if let MpTableEntry::IoInterruptAssignment(entry) = entry
             && entry.source_bus_id == isa_bus.bus_id
-            && entry.source_bus_irq == 0
         {
-            assert!(timer_int_mapping.is_none(), "multiple timer IRQs found");
-            timer_int_mapping = Some(entry);
+            if entry.source_bus_irq == TIMER_IRQ {
+                assert!(timer_int_mapping.is_none(), "multiple timer IRQs found");
+                timer_int_mapping = Some(entry);
+            } else if entry.source_bus_irq == COM1_IRQ {
+                assert!(com1_int_mapping.is_none(), "multiple COM1 IRQs found");
+                com1_int_mapping = Some(entry);
+            }
         }
     }
 
     let timer_int_mapping = timer_int_mapping.expect("timer IRQ not found");
     assert_eq!(timer_int_mapping.dest_io_apic_id, ioapic.io_apic_id);
 
+    // The console works without input.
+    let com1_int_mapping =
+        com1_int_mapping.filter(|mapping| mapping.dest_io_apic_id == ioapic.io_apic_id);
+    if com1_int_mapping.is_none() {
+        warn!("COM1 IRQ not found, console input is disabled");
+    }
+
     let ioapic_address = PAddr::new(ioapic.io_apic_address as usize);
     super::io_apic::init(ioapic_address);
     super::io_apic::use_ioapic(|ioapic| {
         ioapic
             .enable_irq_at(timer_int_mapping.dest_io_apic_intin, TIMER_IRQ)
             .unwrap();
+
+        if let Some(mapping) = com1_int_mapping {
+            ioapic
+                .enable_irq_at(mapping.dest_io_apic_intin, COM1_IRQ)
+                .unwrap();
+        }
     });
 }
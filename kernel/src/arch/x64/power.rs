@@ -0,0 +1,27 @@
+use core::arch::asm;
+
+use super::ioport::out8;
+
+/// The keyboard controller's command port.
+const KBC_COMMAND_PORT: u16 = 0x64;
+/// The command to pulse the CPU reset line.
+const KBC_RESET_CPU: u8 = 0xfe;
+
+/// Stops the CPU forever.
+pub fn halt() -> ! {
+    loop {
+        unsafe {
+            asm!("cli; hlt");
+        }
+    }
+}
+
+/// Resets the machine.
+pub fn reboot() -> ! {
+    unsafe {
+        out8(KBC_COMMAND_PORT, KBC_RESET_CPU);
+    }
+
+    // The reset is not immediate, or no keyboard controller exists.
+    halt();
+}
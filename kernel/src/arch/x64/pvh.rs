@@ -3,6 +3,8 @@
 use core::slice;
 
 use ftl_arrayvec::ArrayVec;
+use ftl_bootinfo::pvh;
+use ftl_bootinfo::pvh::StartInfo;
 use ftl_utils::formatter::ByteSize;
 
 use super::vmspace::paddr2vaddr;
@@ -11,41 +13,6 @@ use crate::boot::BootInfo;
 use crate::boot::FreeRam;
 use crate::boot::Module;
 
-/// Xen boot information.
-///
-/// <https://xenbits.xen.org/docs/unstable/hypercall/x86_64/include,public,arch-x86,hvm,start_info.h.html>
-#[repr(C, packed)]
-struct HvmStartInfo {
-    magic: [u8; 4],
-    version: u32,
-    flags: u32,
-    nr_modules: u32,
-    modlist_paddr: u64,
-    cmdline_paddr: u64,
-    rsdp_paddr: u64,
-    memmap_paddr: u64,
-    memmap_entries: u32,
-    reserved: u32,
-}
-
-const HVM_MEMMAP_TYPE_RAM: u32 = 1;
-
-#[repr(C, packed)]
-struct HvmModuleEntry {
-    paddr: u64,
-    size: u64,
-    cmdline_paddr: u64,
-    reserved: u64,
-}
-
-#[repr(C, packed)]
-struct HvmMemoryMapEntry {
-    addr: u64,
-    size: u64,
-    type_: u32,
-    reserved: u32,
-}
-
 fn cstr2slice(paddr: PAddr) -> &'static [u8] {
     if paddr.as_usize() == 0 {
         return b"";
@@ -62,45 +29,53 @@ fn cstr2slice(paddr: PAddr) -> &'static [u8] {
     }
 }
 
-pub fn parse_start_info(start_info: PAddr) -> BootInfo {
-    let start_info = unsafe { &*(paddr2vaddr(start_info).as_usize() as *const HvmStartInfo) };
-    if start_info.magic != [b'x', b'E' | 0x80, b'n', b'3'] {
-        panic!(
-            "invalid magic number in PVH start info: {:x?}",
-            start_info.magic
-        );
+/// Returns the `len`-byte table at `paddr`.
+///
+/// # Safety
+///
+/// The table must be mapped and live as long as the kernel.
+unsafe fn table(paddr: u64, len: usize) -> &'static [u8] {
+    if len == 0 {
+        return b"";
     }
 
-    let cmdline = cstr2slice(PAddr::new(start_info.cmdline_paddr as usize));
+    let ptr: *const u8 = paddr2vaddr(PAddr::new(paddr as usize)).as_ptr();
+    unsafe { slice::from_raw_parts(ptr, len) }
+}
 
-    let modlist_paddr = PAddr::new(start_info.modlist_paddr as usize);
-    let modlist = unsafe {
-        core::slice::from_raw_parts(
-            paddr2vaddr(modlist_paddr).as_usize() as *const HvmModuleEntry,
-            start_info.nr_modules as usize,
-        )
+pub fn parse_start_info(start_info: PAddr) -> BootInfo {
+    let start_info = unsafe { table(start_info.as_usize() as u64, pvh::START_INFO_SIZE) };
+    let start_info = match StartInfo::parse(start_info) {
+        Ok(start_info) => start_info,
+        Err(err) => panic!("invalid PVH start info: {:x?}", err),
     };
 
+    let cmdline = cstr2slice(PAddr::new(start_info.cmdline_paddr as usize));
+
+    // The numbers of entries are u32, so the sizes won't overflow.
+    let modlist_len = start_info.nr_modules as usize * pvh::MODULE_ENTRY_SIZE;
+    let modlist = unsafe { table(start_info.modlist_paddr, modlist_len) };
+
     let mut modules = ArrayVec::<Module, 8>::new();
-    for module in modlist {
+    for module in pvh::modules(modlist) {
+        let Some(end) = module.paddr.checked_add(module.size) else {
+            trace!("invalid module: {:x} ({} bytes)", module.paddr, module.size);
+            continue;
+        };
+
         let start = PAddr::new(module.paddr as usize);
-        let end = PAddr::new(module.paddr as usize + module.size as usize);
+        let end = PAddr::new(end as usize);
         if modules.try_push(Module { start, end }).is_err() {
             trace!("too many modules: {start} - {end}");
         }
     }
 
-    let memmap_paddr = PAddr::new(start_info.memmap_paddr as usize);
-    let memmap = unsafe {
-        core::slice::from_raw_parts(
-            paddr2vaddr(memmap_paddr).as_usize() as *const HvmMemoryMapEntry,
-            start_info.memmap_entries as usize,
-        )
-    };
+    let memmap_len = start_info.memmap_entries as usize * pvh::MEMORY_MAP_ENTRY_SIZE;
+    let memmap = unsafe { table(start_info.memmap_paddr, memmap_len) };
 
     let mut free_rams = ArrayVec::<FreeRam, 8>::new();
-    for entry in memmap {
-        if entry.type_ == HVM_MEMMAP_TYPE_RAM {
+    for entry in pvh::memory_map(memmap) {
+        if entry.type_ == pvh::MEMMAP_TYPE_RAM {
             let addr = PAddr::new(entry.addr as usize);
             let size = entry.size as usize;
             if free_rams.try_push(FreeRam { addr, size }).is_err() {
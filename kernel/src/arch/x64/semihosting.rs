@@ -1,13 +1,19 @@
 use core::hint::spin_loop;
 
 use super::ioport::out32;
+use crate::arch::ExitStatus;
 
 /// QEMU's isa-debug-exit device.
 const ISA_DEBUG_EXIT_PORT: u16 = 0x501;
 
-#[allow(dead_code)]
-pub fn semihosting_exit() -> ! {
-    let value = 35; // Exit code will be (value << 1) | 1 = 71
+/// Exits QEMU. The exit code will be `(value << 1) | 1`: 33 on success, and
+/// 71 on failure.
+pub fn semihosting_exit(status: ExitStatus) -> ! {
+    let value = match status {
+        ExitStatus::Success => 16,
+        ExitStatus::Failure => 35,
+    };
+
     unsafe { out32(ISA_DEBUG_EXIT_PORT, value) };
 
     loop {
@@ -1,17 +1,49 @@
 use core::arch::asm;
+use core::fmt;
 use core::mem::offset_of;
 
+use ftl_api::error::ErrorCode;
 use ftl_api::thread::ContextData;
 use ftl_api::thread::ContextKind;
+use ftl_api::thread::FpState;
 use ftl_api::thread::FsBase;
+use ftl_api::thread::GsBase;
 use ftl_api::thread::InitRegs;
+use ftl_api::thread::Regs;
 use ftl_api::thread::SyscallArgs;
 use ftl_api::thread::Sysret;
 
+use super::fpu;
+use super::fpu::FpuArea;
 use super::gdt::GDT_USER_CS;
 use super::gdt::GDT_USER_DS;
 
-#[derive(Default, Debug)]
+/// RFLAGS bits which are always set: IF and the reserved bit 1.
+const RFLAGS_FIXED: u64 = (1 << 9) | (1 << 1);
+
+/// RFLAGS bits which user threads can change by themselves with POPF: CF,
+/// PF, AF, ZF, SF, TF, DF, OF, NT, RF, AC, and ID.
+const RFLAGS_USER: u64 = (1 << 0)
+    | (1 << 2)
+    | (1 << 4)
+    | (1 << 6)
+    | (1 << 7)
+    | (1 << 8)
+    | (1 << 10)
+    | (1 << 11)
+    | (1 << 14)
+    | (1 << 16)
+    | (1 << 18)
+    | (1 << 21);
+
+/// Returns true if `addr` is canonical with 48-bit virtual addresses.
+///
+/// IRETQ and WRFSBASE raise #GP in the kernel on non-canonical addresses.
+fn is_canonical(addr: u64) -> bool {
+    let upper = addr >> 47;
+    upper == 0 || upper == (1 << 17) - 1
+}
+
 #[repr(C, packed)]
 pub struct Thread {
     // IRET frame. The order is important!
@@ -38,15 +70,36 @@ pub struct Thread {
     pub(super) r15: u64,
     pub(super) gsbase: u64,
     pub(super) fsbase: u64,
+    /// FPU, SSE, and AVX registers.
+    pub(super) fpu: FpuArea,
 }
 
 impl Thread {
     pub fn new() -> Self {
         Self {
+            rip: 0,
             cs: GDT_USER_CS as u64,
-            rflags: 0x202, // interrupts enabled
+            rflags: RFLAGS_FIXED, // interrupts enabled
+            rsp: 0,
             ss: GDT_USER_DS as u64,
-            ..Default::default()
+            rax: 0,
+            rbx: 0,
+            rcx: 0,
+            rdx: 0,
+            rsi: 0,
+            rdi: 0,
+            rbp: 0,
+            r8: 0,
+            r9: 0,
+            r10: 0,
+            r11: 0,
+            r12: 0,
+            r13: 0,
+            r14: 0,
+            r15: 0,
+            gsbase: 0,
+            fsbase: 0,
+            fpu: FpuArea::new(),
         }
     }
 
@@ -75,10 +128,51 @@ impl Thread {
             ContextKind::Fsbase => {
                 regs.fsbase = FsBase { base: self.fsbase };
             }
+            ContextKind::FpState => {
+                // Borrowing a union field is unsafe. Assign it instead.
+                let mut state = FpState::zeroed();
+                fpu::read(self, &mut state);
+                regs.fp_state = state;
+            }
+            ContextKind::Gsbase => {
+                regs.gsbase = GsBase { base: self.gsbase };
+            }
+            ContextKind::Regs => {
+                regs.regs = Regs {
+                    rax: self.rax,
+                    rbx: self.rbx,
+                    rcx: self.rcx,
+                    rdx: self.rdx,
+                    rsi: self.rsi,
+                    rdi: self.rdi,
+                    rbp: self.rbp,
+                    rsp: self.rsp,
+                    r8: self.r8,
+                    r9: self.r9,
+                    r10: self.r10,
+                    r11: self.r11,
+                    r12: self.r12,
+                    r13: self.r13,
+                    r14: self.r14,
+                    r15: self.r15,
+                    rip: self.rip,
+                    rflags: self.rflags,
+                    fsbase: self.fsbase,
+                    gsbase: self.gsbase,
+                };
+            }
         }
     }
 
-    pub fn write_context(&mut self, kind: ContextKind, regs: &ContextData) {
+    /// Writes the registers.
+    ///
+    /// Returns [`ErrorCode::INVALID_ARG`] if the new registers would fault
+    /// in the kernel, or give the thread a privilege.
+    pub fn write_context(
+        &mut self,
+        kind: ContextKind,
+        regs: &ContextData,
+    ) -> Result<(), ErrorCode> {
         match kind {
             ContextKind::SyscallArgs => {
                 let args = unsafe { regs.syscall_args };
@@ -95,17 +189,71 @@ impl Thread {
             }
             ContextKind::InitRegs => {
                 let init_regs = unsafe { regs.init_regs };
+                if !is_canonical(init_regs.pc) {
+                    return Err(ErrorCode::INVALID_ARG);
+                }
+
                 self.rip = init_regs.pc;
                 self.rsp = init_regs.sp;
             }
             ContextKind::Fsbase => {
-                self.fsbase = unsafe { regs.fsbase }.base;
+                let base = unsafe { regs.fsbase }.base;
+                if !is_canonical(base) {
+                    return Err(ErrorCode::INVALID_ARG);
+                }
+
+                self.fsbase = base;
+            }
+            ContextKind::FpState => {
+                fpu::write(self, unsafe { &regs.fp_state })?;
+            }
+            ContextKind::Gsbase => {
+                let base = unsafe { regs.gsbase }.base;
+                if !is_canonical(base) {
+                    return Err(ErrorCode::INVALID_ARG);
+                }
+
+                self.gsbase = base;
+            }
+            ContextKind::Regs => {
+                let regs = unsafe { regs.regs };
+                if !is_canonical(regs.rip)
+                    || !is_canonical(regs.fsbase)
+                    || !is_canonical(regs.gsbase)
+                    || regs.rflags & !(RFLAGS_USER | RFLAGS_FIXED) != 0
+                {
+                    return Err(ErrorCode::INVALID_ARG);
+                }
+
+                self.rax = regs.rax;
+                self.rbx = regs.rbx;
+                self.rcx = regs.rcx;
+                self.rdx = regs.rdx;
+                self.rsi = regs.rsi;
+                self.rdi = regs.rdi;
+                self.rbp = regs.rbp;
+                self.rsp = regs.rsp;
+                self.r8 = regs.r8;
+                self.r9 = regs.r9;
+                self.r10 = regs.r10;
+                self.r11 = regs.r11;
+                self.r12 = regs.r12;
+                self.r13 = regs.r13;
+                self.r14 = regs.r14;
+                self.r15 = regs.r15;
+                self.rip = regs.rip;
+                self.rflags = regs.rflags | RFLAGS_FIXED;
+                self.fsbase = regs.fsbase;
+                self.gsbase = regs.gsbase;
             }
         }
+
+        Ok(())
     }
 
     pub fn enter(thread: *const Thread) -> ! {
         unsafe {
+            fpu::switch_to(thread as *mut Thread);
             asm!(
                 "mov rsp, {}",
                 "swapgs",
@@ -163,3 +311,40 @@ impl Thread {
         }
     }
 }
+
+impl Drop for Thread {
+    fn drop(&mut self) {
+        fpu::forget(self);
+    }
+}
+
+impl fmt::Debug for Thread {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Copy the registers: references to packed fields may be unaligned.
+        f.debug_struct("Thread")
+            .field("rip", &{ self.rip })
+            .field("cs", &{ self.cs })
+            .field("rflags", &{ self.rflags })
+            .field("rsp", &{ self.rsp })
+            .field("ss", &{ self.ss })
+            .field("rax", &{ self.rax })
+            .field("rbx", &{ self.rbx })
+            .field("rcx", &{ self.rcx })
+            .field("rdx", &{ self.rdx })
+            .field("rsi", &{ self.rsi })
+            .field("rdi", &{ self.rdi })
+            .field("rbp", &{ self.rbp })
+            .field("r8", &{ self.r8 })
+            .field("r9", &{ self.r9 })
+            .field("r10", &{ self.r10 })
+            .field("r11", &{ self.r11 })
+            .field("r12", &{ self.r12 })
+            .field("r13", &{ self.r13 })
+            .field("r14", &{ self.r14 })
+            .field("r15", &{ self.r15 })
+            .field("gsbase", &{ self.gsbase })
+            .field("fsbase", &{ self.fsbase })
+            .field("fpu", &self.fpu)
+            .finish()
+    }
+}
@@ -22,7 +22,14 @@ const DIVISOR: u16 = (PIT_HZ / TIMER_HZ) as u16;
 
 // The initial value is deliberately close to the max to test overflow handling
 // easily.
-static TICKS: AtomicU64 = AtomicU64::new(0xffff_ffff_ffff_0000);
+const INITIAL_TICKS: u64 = 0xffff_ffff_ffff_0000;
+static TICKS: AtomicU64 = AtomicU64::new(INITIAL_TICKS);
+
+/// Returns the time since the timer is initialized in nanoseconds.
+pub fn uptime_nanos() -> u64 {
+    let ticks = TICKS.load(Ordering::Relaxed).wrapping_sub(INITIAL_TICKS);
+    ticks.wrapping_mul(NANOS_PER_TICK)
+}
 
 pub(super) fn handle_interrupt() {
     TICKS.fetch_add(1, Ordering::Relaxed);
@@ -1,16 +1,22 @@
 use core::arch::asm;
 use core::ops::Range;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
 
 use ftl_api::error::ErrorCode;
 use ftl_api::vmspace::PageAttrs;
+use ftl_ktest_macro::ktest;
 use ftl_utils::alignment::is_aligned;
 use ftl_utils::spinlock::SpinLock;
+use ftl_utils::spinlock::SpinLockGuard;
 
 use crate::address::PAddr;
 use crate::address::UAddr;
 use crate::address::VAddr;
 use crate::memory::PAGE_ALLOCATOR;
 use crate::memory::PageType;
+use crate::quota::Quota;
+use crate::shared_ref::SharedRef;
 
 pub const MIN_PAGE_SIZE: usize = 4096;
 pub const KERNEL_BASE: usize = 0xffff_8000_0000_0000;
@@ -20,11 +26,21 @@ const GIGA_PAGE_SIZE: usize = 1024 * 1024 * 1024;
 const DIRECT_MAP_SIZE: usize = 4 * GIGA_PAGE_SIZE;
 pub const DIRECT_MAP_END: PAddr = PAddr::new(DIRECT_MAP_SIZE);
 
+/// The kernel-space window where server images are mapped with 4 KiB pages,
+/// so that each page can have its own permissions. A physical address `paddr`
+/// is mapped at `SERVER_IMAGE_BASE + paddr` like the direct map.
+const SERVER_IMAGE_BASE: usize = KERNEL_BASE + ENTRIES_PER_TABLE * GIGA_PAGE_SIZE;
+
 // Page table entry flags.
 const PTE_V: u64 = 1 << 0;
 const PTE_W: u64 = 1 << 1;
 const PTE_U: u64 = 1 << 2;
 const PTE_HUGE: u64 = 1 << 7;
+const PTE_NX: u64 = 1 << 63;
+
+/// `PTE_NX` if no-execute pages are enabled, or zero: the bit is reserved
+/// on CPUs without the feature.
+static NX_BIT: AtomicU64 = AtomicU64::new(0);
 
 /// The boot-time PML4. The boot code will populate this.
 pub(super) static mut BOOT_PML4: Table = Table([Pte(0); ENTRIES_PER_TABLE]);
@@ -44,6 +60,11 @@ pub(super) static BOOT_PDPT: Table = {
     pdpt
 };
 
+/// The PDPT for [`SERVER_IMAGE_BASE`], shared by all page tables. Modified
+/// only with `SERVER_IMAGE_LOCK` held.
+static mut SERVER_IMAGE_PDPT: Table = Table([Pte(0); ENTRIES_PER_TABLE]);
+static SERVER_IMAGE_LOCK: SpinLock<()> = SpinLock::new(());
+
 /// A page table, at any level (PML4, PDPT, PDT, PT).
 #[repr(align(4096))]
 pub(super) struct Table([Pte; ENTRIES_PER_TABLE]);
@@ -74,6 +95,103 @@ impl Pte {
     }
 }
 
+fn pdpt_pte(pdpt: *const Table) -> Pte {
+    let pdpt_paddr = vaddr2paddr(VAddr::new(pdpt as usize));
+    Pte::new(pdpt_paddr, PTE_V | PTE_W)
+}
+
+/// Sets up the page tables shared by all address spaces.
+pub(super) fn init() {
+    // SAFETY: This is called once before other CPUs and servers start.
+    unsafe {
+        let pml4 = &raw mut BOOT_PML4;
+        (*pml4).0[pml4_index(SERVER_IMAGE_BASE)] = pdpt_pte(&raw const SERVER_IMAGE_PDPT);
+    }
+}
+
+fn invalidate_tlb(vaddr: VAddr) {
+    // TODO: TLB shootdown on other CPUs.
+    unsafe {
+        asm!("invlpg [{}]", in(reg) vaddr.as_usize());
+    }
+}
+
+/// Returns the PTE for `vaddr` in the server image window, allocating page
+/// tables if they don't exist.
+fn server_image_pte(
+    _lock: &SpinLockGuard<'_, ()>,
+    vaddr: usize,
+) -> Result<&'static mut Pte, ErrorCode> {
+    // SAFETY: The caller holds `SERVER_IMAGE_LOCK`.
+    let pdpt = unsafe { (&raw mut SERVER_IMAGE_PDPT).as_mut().unwrap() };
+    let pdt = ensure_next_table(None, pdpt, pdpt_index(vaddr))?;
+    let pt = ensure_next_table(None, pdt, pdt_index(vaddr))?;
+    Ok(&mut pt.0[pt_index(vaddr)])
+}
+
+/// Returns the leaf PTE flags for `attrs`. User mappings add `PTE_U`.
+fn pte_flags(attrs: PageAttrs) -> u64 {
+    let mut flags = PTE_V;
+    if attrs.contains(PageAttrs::WRITE) {
+        flags |= PTE_W;
+    }
+    if !attrs.contains(PageAttrs::EXEC) {
+        flags |= NX_BIT.load(Ordering::Relaxed);
+    }
+    flags
+}
+
+/// Sets `PTE_NX` in non-executable mappings. Called once EFER.NXE is set.
+pub(super) fn enable_no_execute() {
+    NX_BIT.store(PTE_NX, Ordering::Relaxed);
+}
+
+/// Maps a server image at its address in the server image window. The pages
+/// are writable and not executable until [`protect_server_image`] is called.
+pub fn map_server_image(paddr: PAddr, len: usize) -> Result<VAddr, ErrorCode> {
+    debug_assert!(paddr.is_aligned(MIN_PAGE_SIZE) && is_aligned(len, MIN_PAGE_SIZE));
+
+    if paddr.as_usize() + len > DIRECT_MAP_SIZE {
+        return Err(ErrorCode::OUT_OF_BOUNDS);
+    }
+
+    let lock = SERVER_IMAGE_LOCK.lock();
+    for offset in (0..len).step_by(MIN_PAGE_SIZE) {
+        let vaddr = SERVER_IMAGE_BASE + paddr.as_usize() + offset;
+        let pte = server_image_pte(&lock, vaddr)?;
+        *pte = Pte::new(
+            PAddr::new(paddr.as_usize() + offset),
+            pte_flags(PageAttrs::READ | PageAttrs::WRITE),
+        );
+    }
+
+    Ok(VAddr::new(SERVER_IMAGE_BASE + paddr.as_usize()))
+}
+
+/// Changes the permissions of pages mapped by [`map_server_image`].
+pub fn protect_server_image(vaddr: VAddr, len: usize, attrs: PageAttrs) {
+    let lock = SERVER_IMAGE_LOCK.lock();
+    for offset in (0..len).step_by(MIN_PAGE_SIZE) {
+        let vaddr = vaddr.as_usize() + offset;
+        let pte = server_image_pte(&lock, vaddr).expect("server image not mapped");
+        debug_assert!(pte.is_present());
+        *pte = Pte::new(pte.paddr(), pte_flags(attrs));
+        invalidate_tlb(VAddr::new(vaddr));
+    }
+}
+
+/// Unmaps pages mapped by [`map_server_image`]. Page tables are kept for
+/// later images.
+pub fn unmap_server_image(vaddr: VAddr, len: usize) {
+    let lock = SERVER_IMAGE_LOCK.lock();
+    for offset in (0..len).step_by(MIN_PAGE_SIZE) {
+        let vaddr = vaddr.as_usize() + offset;
+        let pte = server_image_pte(&lock, vaddr).expect("server image not mapped");
+        *pte = Pte(0);
+        invalidate_tlb(VAddr::new(vaddr));
+    }
+}
+
 pub fn paddr2vaddr(paddr: PAddr) -> VAddr {
     VAddr::new(paddr.as_usize() | KERNEL_BASE)
 }
@@ -87,21 +205,32 @@ fn paddr_to_table_mut(paddr: PAddr) -> &'static mut Table {
     unsafe { &mut *(vaddr.as_usize() as *mut Table) }
 }
 
-fn alloc_table() -> Result<PAddr, ErrorCode> {
-    let paddr = PAGE_ALLOCATOR
-        .alloc(MIN_PAGE_SIZE, PageType::Zeroed)
-        .ok_or(ErrorCode::OUT_OF_MEMORY)?;
-
-    Ok(paddr)
-}
-
-fn ensure_next_table(table: &mut Table, index: usize) -> Result<&mut Table, ErrorCode> {
+/// Returns the next-level table, allocating it if it doesn't exist.
+///
+/// Tables for user mappings are charged to `quota`. If it's `None`, the table
+/// is for kernel mappings and is allocated from [`PAGE_ALLOCATOR`].
+fn ensure_next_table<'a>(
+    quota: Option<&Quota>,
+    table: &'a mut Table,
+    index: usize,
+) -> Result<&'a mut Table, ErrorCode> {
     let entry = &mut table.0[index];
     let next_table_paddr = if !entry.is_present() {
-        let paddr = alloc_table()?;
-        // User mappings require U/S at every page-table level.
-        *entry = Pte::new(paddr, PTE_V | PTE_W | PTE_U);
-        paddr
+        match quota {
+            Some(quota) => {
+                let paddr = quota.alloc(MIN_PAGE_SIZE, PageType::Zeroed)?;
+                // User mappings require U/S at every page-table level.
+                *entry = Pte::new(paddr, PTE_V | PTE_W | PTE_U);
+                paddr
+            }
+            None => {
+                let paddr = PAGE_ALLOCATOR
+                    .alloc(MIN_PAGE_SIZE, PageType::Zeroed)
+                    .ok_or(ErrorCode::OUT_OF_MEMORY)?;
+                *entry = Pte::new(paddr, PTE_V | PTE_W);
+                paddr
+            }
+        }
     } else {
         if entry.is_huge() {
             return Err(ErrorCode::UNSUPPORTED);
@@ -133,27 +262,52 @@ struct Mutable {
     pml4: VAddr,
 }
 
+/// Frees the page tables below `table` at `level` (4 for PML4). The leaf
+/// pages are owned by VM areas and are not freed here.
+fn free_tables(quota: &Quota, table: &Table, level: usize, entries: Range<usize>) {
+    if level == 1 {
+        return;
+    }
+
+    for entry in &table.0[entries] {
+        if entry.is_present() && !entry.is_huge() {
+            let next_paddr = entry.paddr();
+            free_tables(
+                quota,
+                paddr_to_table_mut(next_paddr),
+                level - 1,
+                0..ENTRIES_PER_TABLE,
+            );
+            quota.free(next_paddr, MIN_PAGE_SIZE);
+        }
+    }
+}
+
 pub struct VmSpace {
     mutable: SpinLock<Mutable>,
     cr3: u64,
+    /// The quota page tables are charged to.
+    quota: SharedRef<Quota>,
 }
 
 impl VmSpace {
-    pub fn new() -> Result<Self, ErrorCode> {
+    pub fn new(quota: SharedRef<Quota>) -> Result<Self, ErrorCode> {
         let pdpt_vaddr = VAddr::new(BOOT_PDPT.0.as_ptr() as usize);
         let pdpt_paddr = vaddr2paddr(pdpt_vaddr);
-        let pml4_paddr = PAGE_ALLOCATOR
-            .alloc(4096, PageType::Zeroed)
-            .ok_or(ErrorCode::OUT_OF_MEMORY)?;
+        let pml4_paddr = quota.alloc(MIN_PAGE_SIZE, PageType::Zeroed)?;
         let pml4_vaddr = paddr2vaddr(pml4_paddr);
         let pml4 = unsafe { &mut *(pml4_vaddr.as_usize() as *mut Table) };
 
         // Map KERNEL_BASE to BOOT_PDPT.
         pml4.0[256] = Pte::new(pdpt_paddr, PTE_V);
 
+        // Share the server image window.
+        pml4.0[pml4_index(SERVER_IMAGE_BASE)] = pdpt_pte(&raw const SERVER_IMAGE_PDPT);
+
         Ok(Self {
             cr3: pml4_paddr.as_u64(),
             mutable: SpinLock::new(Mutable { pml4: pml4_vaddr }),
+            quota,
         })
     }
 
@@ -195,18 +349,47 @@ impl VmSpace {
 
         let mutable = self.mutable.lock();
         let pml4 = unsafe { &mut *(mutable.pml4.as_usize() as *mut Table) };
-        let pdpt = ensure_next_table(pml4, pml4_index(uaddr))?;
-        let pdt = ensure_next_table(pdpt, pdpt_index(uaddr))?;
-        let pt = ensure_next_table(pdt, pdt_index(uaddr))?;
+        let pdpt = ensure_next_table(Some(&self.quota), pml4, pml4_index(uaddr))?;
+        let pdt = ensure_next_table(Some(&self.quota), pdpt, pdpt_index(uaddr))?;
+        let pt = ensure_next_table(Some(&self.quota), pdt, pdt_index(uaddr))?;
         let entry = &mut pt.0[pt_index(uaddr)];
 
         if entry.is_present() {
             return Err(ErrorCode::ALREADY_EXISTS);
         }
 
-        *entry = Pte::new(paddr, PTE_V | PTE_U | attrs.as_raw());
+        *entry = Pte::new(paddr, pte_flags(attrs) | PTE_U);
         Ok(())
     }
+
+    /// Returns the leaf PTE mapping `uaddr`, if any.
+    fn lookup(&self, uaddr: usize) -> Option<Pte> {
+        let mutable = self.mutable.lock();
+        let mut table = unsafe { &*(mutable.pml4.as_usize() as *const Table) };
+        for index in [pml4_index(uaddr), pdpt_index(uaddr), pdt_index(uaddr)] {
+            let entry = table.0[index];
+            if !entry.is_present() || entry.is_huge() {
+                return None;
+            }
+
+            table = paddr_to_table_mut(entry.paddr());
+        }
+
+        let entry = table.0[pt_index(uaddr)];
+        entry.is_present().then_some(entry)
+    }
+}
+
+impl Drop for VmSpace {
+    fn drop(&mut self) {
+        let mutable = self.mutable.lock();
+        let pml4 = unsafe { &*(mutable.pml4.as_usize() as *const Table) };
+
+        // Free the user-space page tables. The kernel half is shared.
+        free_tables(&self.quota, pml4, 4, 0..pml4_index(KERNEL_BASE));
+        self.quota
+            .free(PAddr::new(self.cr3 as usize), MIN_PAGE_SIZE);
+    }
 }
 
 unsafe extern "C" {
@@ -221,3 +404,93 @@ pub fn get_kernel_reserved_range() -> Range<PAddr> {
     let end_paddr = vaddr2paddr(end);
     start_paddr..end_paddr
 }
+
+#[ktest]
+fn map_sets_pte_permissions() {
+    let quota = Quota::new(None, None).unwrap();
+    let vmspace = VmSpace::new(quota.clone()).unwrap();
+    let uaddr = 0x10000;
+    let cases = [
+        (PageAttrs::READ, 0, NX_BIT.load(Ordering::Relaxed)),
+        (
+            PageAttrs::READ | PageAttrs::WRITE,
+            PTE_W,
+            NX_BIT.load(Ordering::Relaxed),
+        ),
+        (PageAttrs::READ | PageAttrs::EXEC, 0, 0),
+    ];
+
+    let pages_len = cases.len() * MIN_PAGE_SIZE;
+    let pages = quota.alloc(pages_len, PageType::Zeroed).unwrap();
+    for (i, (attrs, w, nx)) in cases.into_iter().enumerate() {
+        let paddr = PAddr::new(pages.as_usize() + i * MIN_PAGE_SIZE);
+        let page = UAddr::new(uaddr + i * MIN_PAGE_SIZE);
+        vmspace.map(page, paddr, MIN_PAGE_SIZE, attrs).unwrap();
+
+        let pte = vmspace.lookup(page.as_usize()).unwrap();
+        assert_eq!(pte.paddr(), paddr);
+        assert_eq!(
+            pte.0 & (PTE_V | PTE_U | PTE_W | PTE_NX),
+            PTE_V | PTE_U | w | nx
+        );
+    }
+
+    assert!(vmspace.lookup(uaddr + pages_len).is_none());
+    assert_eq!(
+        vmspace.map(
+            UAddr::new(uaddr),
+            PAddr::new(0),
+            MIN_PAGE_SIZE,
+            PageAttrs::READ
+        ),
+        Err(ErrorCode::ALREADY_EXISTS)
+    );
+    assert_eq!(
+        vmspace.map(
+            UAddr::new(KERNEL_BASE),
+            PAddr::new(0),
+            MIN_PAGE_SIZE,
+            PageAttrs::READ
+        ),
+        Err(ErrorCode::NOT_ALLOWED)
+    );
+
+    drop(vmspace);
+    quota.free(pages, pages_len);
+}
+
+#[ktest]
+fn server_image_text_is_not_writable() {
+    let len = 2 * MIN_PAGE_SIZE;
+    let paddr = PAGE_ALLOCATOR.alloc(len, PageType::Zeroed).unwrap();
+    let vaddr = map_server_image(paddr, len).unwrap();
+    let text = vaddr.as_usize();
+    let rodata = text + MIN_PAGE_SIZE;
+    protect_server_image(
+        VAddr::new(text),
+        MIN_PAGE_SIZE,
+        PageAttrs::READ | PageAttrs::EXEC,
+    );
+    protect_server_image(VAddr::new(rodata), MIN_PAGE_SIZE, PageAttrs::READ);
+
+    {
+        let lock = SERVER_IMAGE_LOCK.lock();
+        let text_pte = server_image_pte(&lock, text).unwrap();
+        assert_eq!(text_pte.paddr(), paddr);
+        assert_eq!(text_pte.0 & (PTE_V | PTE_U | PTE_W | PTE_NX), PTE_V);
+
+        let rodata_pte = server_image_pte(&lock, rodata).unwrap();
+        assert_eq!(
+            rodata_pte.0 & (PTE_V | PTE_U | PTE_W | PTE_NX),
+            PTE_V | NX_BIT.load(Ordering::Relaxed)
+        );
+    }
+
+    unmap_server_image(vaddr, len);
+    {
+        let lock = SERVER_IMAGE_LOCK.lock();
+        assert!(!server_image_pte(&lock, text).unwrap().is_present());
+    }
+
+    PAGE_ALLOCATOR.free(paddr, len);
+}
@@ -0,0 +1,170 @@
+//! Stack backtraces.
+//!
+//! Kernel addresses are symbolized by the symbol table embedded into the
+//! kernel image after linking (`tools/embed-symbols.py`). Addresses in
+//! servers are symbolized by their ELF symbols, retained by the loader.
+use core::fmt;
+
+use crate::arch;
+use crate::server;
+
+/// The size of the symbol table in bytes. `build.sh` builds the kernel
+/// again with the size `tools/embed-symbols.py` needs.
+const SYMBOL_TABLE_SIZE: usize = match option_env!("FTL_SYMBOL_TABLE_SIZE") {
+    Some(size) => parse_size(size),
+    None => 0,
+};
+
+/// The size of an entry: the address, and the offset and length of the name.
+const SYMBOL_ENTRY_SIZE: usize = 16;
+
+const fn parse_size(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    let mut size = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        assert!(bytes[i].is_ascii_digit(), "invalid FTL_SYMBOL_TABLE_SIZE");
+        size = size * 10 + (bytes[i] - b'0') as usize;
+        i += 1;
+    }
+    size
+}
+
+#[repr(C)]
+struct SymbolTable {
+    num_symbols: u64,
+    /// `num_symbols` entries sorted by address, followed by the names. All
+    /// integers are little-endian.
+    data: [u8; SYMBOL_TABLE_SIZE],
+}
+
+/// Filled by `tools/embed-symbols.py`, which locates it by the section. It's
+/// `static mut` and exported so that the compiler doesn't assume it's empty.
+#[unsafe(no_mangle)]
+#[unsafe(link_section = ".symbol_table")]
+static mut SYMBOL_TABLE: SymbolTable = SymbolTable {
+    num_symbols: 0,
+    data: [0; SYMBOL_TABLE_SIZE],
+};
+
+pub struct Symbol<'a> {
+    pub name: &'a str,
+    /// The offset from the beginning of the symbol.
+    pub offset: usize,
+}
+
+/// Returns the address, and the name of the `index`-th symbol.
+fn symbol_entry(data: &'static [u8], index: usize) -> Option<(usize, &'static [u8])> {
+    let start = index.checked_mul(SYMBOL_ENTRY_SIZE)?;
+    let entry = data.get(start..start.checked_add(SYMBOL_ENTRY_SIZE)?)?;
+    let addr = u64::from_le_bytes(entry[0..8].try_into().unwrap()) as usize;
+    let name_offset = u32::from_le_bytes(entry[8..12].try_into().unwrap()) as usize;
+    let name_len = u32::from_le_bytes(entry[12..16].try_into().unwrap()) as usize;
+    let name = data.get(name_offset..name_offset.checked_add(name_len)?)?;
+    Some((addr, name))
+}
+
+fn kernel_symbol(addr: usize) -> Option<Symbol<'static>> {
+    let table = &raw const SYMBOL_TABLE;
+    // SAFETY: The table is never modified at runtime.
+    let (num_symbols, data) = unsafe { ((*table).num_symbols, &(*table).data) };
+
+    // Find the last symbol at or before the address. Gives up if an entry
+    // is out of the table.
+    let mut low = 0;
+    let mut high = num_symbols as usize;
+    while low < high {
+        let mid = low + (high - low) / 2;
+        let (mid_addr, _) = symbol_entry(data, mid)?;
+        if mid_addr <= addr {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+
+    let (symbol_addr, name) = symbol_entry(data, low.checked_sub(1)?)?;
+    Some(Symbol {
+        name: core::str::from_utf8(name).unwrap_or("(non-UTF-8)"),
+        offset: addr - symbol_addr,
+    })
+}
+
+/// Formats a symbol name, demangling the legacy Rust mangling
+/// (`_ZN3foo3bar17h0123456789abcdefE` into `foo::bar`).
+struct Demangle<'a>(&'a str);
+
+impl fmt::Display for Demangle<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(mut rest) = self.0.strip_prefix("_ZN") else {
+            return write!(f, "{}", self.0);
+        };
+
+        let mut first = true;
+        while let Some(len_end) = rest.find(|c: char| !c.is_ascii_digit()) {
+            let Ok(len) = rest[..len_end].parse::<usize>() else {
+                break;
+            };
+
+            let Some(component) = rest.get(len_end..len_end + len) else {
+                return write!(f, "{}", self.0);
+            };
+
+            rest = &rest[len_end + len..];
+
+            // Skip the hash.
+            let is_hash = component.len() == 17
+                && component.starts_with('h')
+                && component[1..].bytes().all(|b| b.is_ascii_hexdigit());
+            if rest == "E" && is_hash {
+                break;
+            }
+
+            if !first {
+                write!(f, "::")?;
+            }
+
+            write!(f, "{}", component)?;
+            first = false;
+        }
+
+        Ok(())
+    }
+}
+
+/// Prints the stack frames of the caller with symbols.
+pub fn print_backtrace() {
+    println!("backtrace:");
+    let mut index = 0;
+    arch::backtrace(|addr| {
+        let found = server::symbolize(addr, |server, symbol| {
+            match symbol {
+                Some(symbol) => {
+                    println!(
+                        "    #{}: {:016x}  [{}] {}+0x{:x}",
+                        index,
+                        addr,
+                        server,
+                        Demangle(symbol.name),
+                        symbol.offset
+                    );
+                }
+                None => println!("    #{}: {:016x}  [{}] (unknown)", index, addr, server),
+            }
+        });
+
+        if !found {
+            match kernel_symbol(addr) {
+                Some(symbol) => {
+                    println!(
+                        "    #{}: {:016x}  {}+0x{:x}",
+                        index, addr, symbol.name, symbol.offset
+                    );
+                }
+                None => println!("    #{}: {:016x}  (unknown)", index, addr),
+            }
+        }
+
+        index += 1;
+    });
+}
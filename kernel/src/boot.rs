@@ -23,8 +23,19 @@ pub struct BootInfo {
 }
 
 pub fn boot(bootinfo: BootInfo) -> ! {
+    trace!("CPUs: {}", crate::arch::num_cpus());
     crate::memory::init(&bootinfo);
     crate::cpuvar::init(0);
-    crate::server::init(&bootinfo);
+    crate::console::init();
+    crate::initfs::init(&bootinfo);
+
+    #[cfg(feature = "ktest")]
+    crate::ktest::run_ktests();
+
+    crate::server::init();
+
+    #[cfg(feature = "ktest")]
+    crate::ktest::schedule_finish();
+
     crate::scheduler::return_to_user();
 }
@@ -0,0 +1,509 @@
+//! Inter-server message channels.
+use alloc::collections::vec_deque::VecDeque;
+use alloc::vec::Vec;
+use core::mem;
+use core::ops::BitOrAssign;
+
+use ftl_api::channel::HandleSlots;
+use ftl_api::channel::MESSAGE_DATA_LEN_MAX;
+use ftl_api::channel::MESSAGE_HANDLES_MAX;
+use ftl_api::channel::MESSAGE_QUEUE_LEN_MAX;
+use ftl_api::channel::MessageInfo;
+use ftl_api::channel::UpcallArg;
+use ftl_api::error::ErrorCode;
+use ftl_api::handle::HandleRight;
+use ftl_api::upcall::Upcall;
+use ftl_utils::spinlock::SpinLock;
+
+use crate::handle::AnyHandle;
+use crate::handle::HandleTable;
+use crate::shared_ref::Handleable;
+use crate::shared_ref::SharedRef;
+use crate::upcall;
+use crate::upcall::PendingUpcall;
+use crate::upcall::ServerUpcall;
+
+struct Message {
+    data: Vec<u8>,
+    handles: Vec<AnyHandle>,
+}
+
+/// A set of events to be delivered to the listener.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Events(u8);
+
+impl Events {
+    const NONE: Self = Self(0);
+    const READABLE: Self = Self(1 << 0);
+    const WRITABLE: Self = Self(1 << 1);
+    const PEER_CLOSED: Self = Self(1 << 2);
+    const CLOSED: Self = Self(1 << 3);
+
+    const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOrAssign for Events {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+struct Mutable {
+    /// Messages sent to the owner endpoint.
+    queue: VecDeque<Message>,
+    /// The upcall to deliver events. It's temporarily taken out while it's
+    /// being invoked.
+    listener: Option<ServerUpcall<UpcallArg>>,
+    /// Events not yet delivered to the listener.
+    events: Events,
+    /// Whether this port is in the pending upcall queue, or being delivered.
+    /// Always `false` if no listener is set.
+    scheduled: bool,
+    /// Whether the peer failed to send a message because the queue is full.
+    sender_waiting: bool,
+    /// Whether the owner endpoint has been closed.
+    closed: bool,
+    /// Whether the peer endpoint has been closed.
+    peer_closed: bool,
+}
+
+/// The receiving side of a channel endpoint.
+///
+/// This is separated from [`Channel`] so that endpoints don't reference each
+/// other: the peer endpoint references this port to send messages and
+/// notify events.
+struct Port {
+    mutable: SpinLock<Mutable>,
+}
+
+impl Port {
+    fn new() -> Result<SharedRef<Port>, ErrorCode> {
+        SharedRef::new(Port {
+            mutable: SpinLock::new(Mutable {
+                queue: VecDeque::new(),
+                listener: None,
+                events: Events::NONE,
+                scheduled: false,
+                sender_waiting: false,
+                closed: false,
+                peer_closed: false,
+            }),
+        })
+    }
+
+    /// Adds events to be delivered, and schedules the delivery if the port is
+    /// being listened.
+    fn notify(
+        self: &SharedRef<Self>,
+        mutable: &mut Mutable,
+        events: Events,
+    ) -> Result<(), ErrorCode> {
+        if mutable.scheduled || mutable.listener.is_none() {
+            // The listener will see the events when it's delivered (or set).
+            mutable.events |= events;
+            return Ok(());
+        }
+
+        upcall::schedule(self.clone())?;
+        mutable.events |= events;
+        mutable.scheduled = true;
+        Ok(())
+    }
+}
+
+impl PendingUpcall for Port {
+    fn deliver(&self) {
+        loop {
+            let (listener, events) = {
+                let mut mutable = self.mutable.lock();
+                if mutable.events == Events::NONE || mutable.listener.is_none() {
+                    mutable.scheduled = false;
+                    return;
+                }
+
+                let events = mem::replace(&mut mutable.events, Events::NONE);
+                let listener = mutable.listener.take().unwrap();
+                (listener, events)
+            };
+
+            // If the server panics, the listener is dropped and won't be set
+            // again.
+            let (listener, closed) = upcall::with_unwind(
+                || self.mutable.lock().scheduled = false,
+                || {
+                    upcall::lend(listener, |listener| {
+                        if events.contains(Events::CLOSED) {
+                            listener.invoke(UpcallArg::Closed);
+                            return true;
+                        }
+
+                        if events.contains(Events::READABLE) {
+                            listener.invoke(UpcallArg::Readable);
+                        }
+
+                        if events.contains(Events::WRITABLE) {
+                            listener.invoke(UpcallArg::Writable);
+                        }
+
+                        if events.contains(Events::PEER_CLOSED) {
+                            listener.invoke(UpcallArg::PeerClosed);
+                        }
+
+                        false
+                    })
+                },
+            );
+
+            if closed {
+                // This was the last upcall. The listener is dropped here, and
+                // won't be set again.
+                continue;
+            }
+
+            // Put back the listener. Events notified during the upcalls will
+            // be delivered in the next iteration.
+            self.mutable.lock().listener = Some(listener);
+        }
+    }
+}
+
+/// An endpoint of a bidirectional message channel.
+pub struct Channel {
+    /// The port to receive messages from the peer.
+    rx: SharedRef<Port>,
+    /// The peer's `rx`.
+    tx: SharedRef<Port>,
+}
+
+impl Channel {
+    /// Creates a pair of connected endpoints.
+    pub fn new_pair() -> Result<(SharedRef<Channel>, SharedRef<Channel>), ErrorCode> {
+        let port0 = Port::new()?;
+        let port1 = Port::new()?;
+        let ch0 = SharedRef::new(Channel {
+            rx: port0.clone(),
+            tx: port1.clone(),
+        })?;
+        let ch1 = SharedRef::new(Channel {
+            rx: port1,
+            tx: port0,
+        })?;
+
+        Ok((ch0, ch1))
+    }
+
+    /// Sets the upcall to deliver events on this endpoint.
+    pub fn listen(&self, upcall: Upcall<UpcallArg>) -> Result<(), ErrorCode> {
+        let mut mutable = self.rx.mutable.lock();
+        if mutable.closed {
+            return Err(ErrorCode::INVALID_STATE);
+        }
+
+        if mutable.listener.is_some() || mutable.scheduled {
+            return Err(ErrorCode::ALREADY_EXISTS);
+        }
+
+        mutable.listener = Some(ServerUpcall::new(upcall));
+
+        // Deliver the messages and events arrived before listening.
+        let mut events = Events::NONE;
+        if !mutable.queue.is_empty() {
+            events |= Events::READABLE;
+        }
+
+        if (events != Events::NONE || mutable.events != Events::NONE)
+            && let Err(err) = self.rx.notify(&mut mutable, events)
+        {
+            // The caller will free the upcall's user data on failure.
+            mutable.listener = None;
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    /// Sends a message to the peer, moving the handles in `handles` out of
+    /// the sender's handle table.
+    pub fn send(
+        &self,
+        table: &mut HandleTable,
+        data: &[u8],
+        handles: &mut HandleSlots,
+    ) -> Result<(), ErrorCode> {
+        let num_handles = handles.iter().filter(|handle| handle.is_some()).count();
+        if data.len() > MESSAGE_DATA_LEN_MAX || num_handles > MESSAGE_HANDLES_MAX {
+            return Err(ErrorCode::TOO_LARGE);
+        }
+
+        // Allocate the message before taking the lock.
+        let mut message_data = Vec::new();
+        if message_data.try_reserve_exact(data.len()).is_err() {
+            return Err(ErrorCode::OUT_OF_MEMORY);
+        }
+        message_data.extend_from_slice(data);
+
+        let mut message_handles = Vec::new();
+        if message_handles.try_reserve_exact(num_handles).is_err() {
+            return Err(ErrorCode::OUT_OF_MEMORY);
+        }
+
+        for (i, handle) in handles.iter().enumerate() {
+            let Some(handle) = handle else {
+                continue;
+            };
+
+            // The same handle can't be moved twice.
+            if handles[..i].iter().flatten().any(|h| h.id() == handle.id()) {
+                return Err(ErrorCode::INVALID_ARG);
+            }
+
+            let handle = table.get(handle)?;
+            if !handle.authorize(HandleRight::TRANSFER) {
+                return Err(ErrorCode::NOT_ALLOWED);
+            }
+
+            message_handles.push(handle.clone());
+        }
+
+        if self.rx.mutable.lock().closed {
+            return Err(ErrorCode::INVALID_STATE);
+        }
+
+        let mut peer = self.tx.mutable.lock();
+        if peer.closed {
+            return Err(ErrorCode::PEER_CLOSED);
+        }
+
+        if peer.queue.len() >= MESSAGE_QUEUE_LEN_MAX {
+            // Notify the sender (us) when the peer receives a message.
+            peer.sender_waiting = true;
+            return Err(ErrorCode::WOULD_BLOCK);
+        }
+
+        if peer.queue.try_reserve(1).is_err() {
+            return Err(ErrorCode::OUT_OF_MEMORY);
+        }
+
+        self.tx.notify(&mut peer, Events::READABLE)?;
+
+        // Nothing fails from here. Move the handles into the message.
+        for handle in handles.iter_mut() {
+            if let Some(handle) = handle.take() {
+                table.remove(&handle).unwrap();
+            }
+        }
+
+        peer.queue.push_back(Message {
+            data: message_data,
+            handles: message_handles,
+        });
+
+        Ok(())
+    }
+
+    /// Receives a message from the peer, moving the handles in the message
+    /// into the receiver's handle table.
+    pub fn recv(
+        &self,
+        table: &mut HandleTable,
+        data: &mut [u8],
+        handles: &mut HandleSlots,
+    ) -> Result<MessageInfo, ErrorCode> {
+        let (message, sender_waiting) = {
+            let mut mutable = self.rx.mutable.lock();
+            if mutable.closed {
+                return Err(ErrorCode::INVALID_STATE);
+            }
+
+            let Some(front) = mutable.queue.front() else {
+                if mutable.peer_closed {
+                    return Err(ErrorCode::PEER_CLOSED);
+                }
+
+                return Err(ErrorCode::WOULD_BLOCK);
+            };
+
+            if front.data.len() > data.len() || front.handles.len() > handles.len() {
+                return Err(ErrorCode::TOO_LARGE);
+            }
+
+            table.reserve(front.handles.len())?;
+
+            let message = mutable.queue.pop_front().unwrap();
+            let sender_waiting = mem::replace(&mut mutable.sender_waiting, false);
+            (message, sender_waiting)
+        };
+
+        if sender_waiting {
+            let mut peer = self.tx.mutable.lock();
+            if let Err(err) = self.tx.notify(&mut peer, Events::WRITABLE) {
+                warn!("failed to notify a channel sender: {:?}", err);
+            }
+        }
+
+        let info = MessageInfo {
+            data_len: message.data.len(),
+            num_handles: message.handles.len(),
+        };
+
+        data[..info.data_len].copy_from_slice(&message.data);
+        for (slot, handle) in handles.iter_mut().zip(message.handles) {
+            // We've reserved the space in the table.
+            *slot = Some(table.insert(handle).unwrap());
+        }
+
+        Ok(info)
+    }
+
+    /// Closes this endpoint.
+    ///
+    /// If listening, an upcall with [`UpcallArg::Closed`] will be delivered.
+    pub fn close(&self) -> Result<(), ErrorCode> {
+        let discarded = {
+            let mut mutable = self.rx.mutable.lock();
+            if mutable.closed {
+                return Err(ErrorCode::INVALID_STATE);
+            }
+
+            mutable.closed = true;
+            if let Err(err) = self.rx.notify(&mut mutable, Events::CLOSED) {
+                warn!("failed to notify a channel close: {:?}", err);
+            }
+
+            mem::take(&mut mutable.queue)
+        };
+
+        // Release the handles in the discarded messages. Do this without
+        // holding the lock because the messages may contain this channel.
+        drop(discarded);
+
+        let mut peer = self.tx.mutable.lock();
+        peer.peer_closed = true;
+        if let Err(err) = self.tx.notify(&mut peer, Events::PEER_CLOSED) {
+            warn!("failed to notify a channel close to the peer: {:?}", err);
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        // Notify the peer if the endpoint has not been closed explicitly.
+        let _ = self.close();
+    }
+}
+
+impl Handleable for Channel {
+    const DEFAULT_RIGHT: HandleRight = HandleRight::READ
+        .or(HandleRight::WRITE)
+        .or(HandleRight::TRANSFER)
+        .or(HandleRight::DUPLICATE);
+}
+
+#[cfg(test)]
+mod tests {
+    use ftl_api::handle::Handle;
+
+    use super::*;
+    use crate::arch;
+
+    fn new_channel_handle() -> AnyHandle {
+        let (ch0, _) = Channel::new_pair().unwrap();
+        AnyHandle::new(ch0).unwrap()
+    }
+
+    fn recv_handles(ch: &Channel) -> usize {
+        let mut table = HandleTable::new();
+        let mut data = [0; MESSAGE_DATA_LEN_MAX];
+        let mut handles: [Option<Handle>; MESSAGE_HANDLES_MAX] = Default::default();
+        ch.recv(&mut table, &mut data, &mut handles)
+            .unwrap()
+            .num_handles
+    }
+
+    #[test]
+    fn send_moves_handles() {
+        arch::init_for_test();
+        let (ch0, ch1) = Channel::new_pair().unwrap();
+        let mut table = HandleTable::new();
+        let handle = table.insert(new_channel_handle()).unwrap();
+        let id = handle.id();
+
+        let mut handles = [Some(handle)];
+        ch0.send(&mut table, b"hello", &mut handles).unwrap();
+        assert!(handles[0].is_none());
+        assert_eq!(
+            table.get(&Handle::new(id)).err(),
+            Some(ErrorCode::INVALID_HANDLE)
+        );
+
+        assert_eq!(recv_handles(&ch1), 1);
+    }
+
+    #[test]
+    fn send_requires_transfer_right() {
+        arch::init_for_test();
+        let (ch0, ch1) = Channel::new_pair().unwrap();
+        let mut table = HandleTable::new();
+        let movable = table.insert(new_channel_handle()).unwrap();
+        let pinned = new_channel_handle()
+            .duplicate(HandleRight::READ.or(HandleRight::WRITE))
+            .unwrap();
+        let pinned = table.insert(pinned).unwrap();
+        let (movable_id, pinned_id) = (movable.id(), pinned.id());
+
+        let mut handles = [Some(movable), Some(pinned)];
+        assert_eq!(
+            ch0.send(&mut table, &[], &mut handles).err(),
+            Some(ErrorCode::NOT_ALLOWED)
+        );
+
+        // Nothing is moved if any of the handles is rejected.
+        assert!(handles.iter().all(Option::is_some));
+        assert!(table.get(&Handle::new(movable_id)).is_ok());
+        assert!(table.get(&Handle::new(pinned_id)).is_ok());
+        let mut data = [0; 1];
+        assert_eq!(
+            ch1.recv(&mut table, &mut data, &mut []).err(),
+            Some(ErrorCode::WOULD_BLOCK)
+        );
+    }
+
+    #[test]
+    fn send_rejects_duplicate_slots() {
+        arch::init_for_test();
+        let (ch0, _ch1) = Channel::new_pair().unwrap();
+        let mut table = HandleTable::new();
+        let handle = table.insert(new_channel_handle()).unwrap();
+        let id = handle.id();
+
+        let mut handles = [Some(handle), Some(Handle::new(id))];
+        assert_eq!(
+            ch0.send(&mut table, &[], &mut handles).err(),
+            Some(ErrorCode::INVALID_ARG)
+        );
+        assert!(handles.iter().all(Option::is_some));
+        assert!(table.get(&Handle::new(id)).is_ok());
+    }
+
+    #[test]
+    fn send_keeps_handles_on_peer_closed() {
+        arch::init_for_test();
+        let (ch0, ch1) = Channel::new_pair().unwrap();
+        let mut table = HandleTable::new();
+        let handle = table.insert(new_channel_handle()).unwrap();
+        let id = handle.id();
+        ch1.close().unwrap();
+
+        let mut handles = [Some(handle)];
+        assert_eq!(
+            ch0.send(&mut table, &[], &mut handles).err(),
+            Some(ErrorCode::PEER_CLOSED)
+        );
+        assert!(handles[0].is_some());
+        assert!(table.get(&Handle::new(id)).is_ok());
+    }
+}
@@ -0,0 +1,216 @@
+//! The kernel command line.
+//!
+//! The command line is a space-separated list of `key=value` options:
+//!
+//! - `log=trace|info|warn`: The minimum level of logs to print. Errors are
+//!   always printed.
+//! - `servers=lx,tcpip`: The servers to load from initfs. All by default.
+//! - `init=/bin/sh`: The first program for lx to run.
+//! - `mem=512M`: The maximum amount of RAM to use.
+//! - `panic=reboot|halt|qemu-exit`: What to do on a kernel panic. `halt` by
+//!   default, or `qemu-exit` in the test mode.
+//! - `server_pubkey=<hex>`: The public key to verify server images, instead of
+//!   the built-in one.
+//!
+//! Servers can read any option by [`option`], including ones unknown to the
+//! kernel. If an option is given more than once, the last one wins.
+use ftl_api::print::LogLevel;
+use ftl_utils::formatter::ByteSize;
+use ftl_utils::spinlock::SpinLock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanicAction {
+    Halt,
+    Reboot,
+    /// Exits QEMU through its isa-debug-exit device.
+    QemuExit,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CmdLine {
+    pub log: LogLevel,
+    /// Comma-separated server names.
+    servers: Option<&'static str>,
+    pub init: Option<&'static str>,
+    pub mem: Option<usize>,
+    pub panic: PanicAction,
+    pub server_pubkey: Option<&'static str>,
+}
+
+impl CmdLine {
+    const fn new() -> Self {
+        Self {
+            log: LogLevel::Trace,
+            servers: None,
+            init: None,
+            mem: None,
+            // Report the failure in the test mode. See ktest.rs.
+            panic: if cfg!(feature = "ktest") {
+                PanicAction::QemuExit
+            } else {
+                PanicAction::Halt
+            },
+            server_pubkey: None,
+        }
+    }
+
+    /// Returns true if the server should be loaded at boot.
+    pub fn is_server_enabled(&self, name: &str) -> bool {
+        match self.servers {
+            Some(servers) => servers.split(',').any(|s| s == name),
+            None => true,
+        }
+    }
+}
+
+static RAW: SpinLock<&'static str> = SpinLock::new("");
+static CMDLINE: SpinLock<CmdLine> = SpinLock::new(CmdLine::new());
+
+fn options(raw: &'static str) -> impl Iterator<Item = (&'static str, &'static str)> {
+    raw.split_ascii_whitespace()
+        .map(|option| option.split_once('=').unwrap_or((option, "")))
+}
+
+/// Returns the value of an option, or `None` if it's not given.
+pub fn option(key: &str) -> Option<&'static str> {
+    find_option(*RAW.lock(), key)
+}
+
+fn find_option(raw: &'static str, key: &str) -> Option<&'static str> {
+    options(raw)
+        .filter(|(k, _)| *k == key)
+        .last()
+        .map(|(_, value)| value)
+}
+
+/// Returns the parsed command line.
+pub fn get() -> CmdLine {
+    *CMDLINE.lock()
+}
+
+/// Parses the command line. Called before memory is initialized: it must not
+/// allocate.
+pub fn init(cmdline: &'static [u8]) {
+    let Ok(raw) = core::str::from_utf8(cmdline) else {
+        warn!("cmdline: not a UTF-8 string, ignoring");
+        return;
+    };
+
+    let parsed = parse(raw);
+    crate::print::set_log_level(parsed.log);
+    *RAW.lock() = raw;
+    *CMDLINE.lock() = parsed;
+}
+
+/// Parses the options known to the kernel. Invalid values are ignored with
+/// a warning.
+fn parse(raw: &'static str) -> CmdLine {
+    let mut parsed = CmdLine::new();
+    for (key, value) in options(raw) {
+        match key {
+            "log" => {
+                parsed.log = match value {
+                    "trace" => LogLevel::Trace,
+                    "info" => LogLevel::Info,
+                    "warn" => LogLevel::Warn,
+                    _ => {
+                        warn!("cmdline: invalid log level \"{}\"", value);
+                        continue;
+                    }
+                };
+            }
+            "servers" => parsed.servers = Some(value),
+            "init" => parsed.init = Some(value),
+            "mem" => {
+                match ByteSize::parse(value) {
+                    Some(mem) => parsed.mem = Some(mem),
+                    None => warn!("cmdline: invalid memory size \"{}\"", value),
+                }
+            }
+            "panic" => {
+                parsed.panic = match value {
+                    "halt" => PanicAction::Halt,
+                    "reboot" => PanicAction::Reboot,
+                    "qemu-exit" => PanicAction::QemuExit,
+                    _ => {
+                        warn!("cmdline: invalid panic action \"{}\"", value);
+                        continue;
+                    }
+                };
+            }
+            "server_pubkey" => parsed.server_pubkey = Some(value),
+            _ => {
+                // Options for servers.
+            }
+        }
+    }
+
+    parsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults() {
+        let cmdline = parse("");
+        assert_eq!(cmdline.log, LogLevel::Trace);
+        assert!(cmdline.is_server_enabled("lx"));
+        assert_eq!(cmdline.init, None);
+        assert_eq!(cmdline.mem, None);
+        assert_eq!(cmdline.panic, PanicAction::Halt);
+        assert_eq!(cmdline.server_pubkey, None);
+    }
+
+    #[test]
+    fn parse_options() {
+        let cmdline = parse(
+            "log=warn servers=lx,tcpip init=/bin/sh mem=512M panic=reboot server_pubkey=abcd",
+        );
+        assert_eq!(cmdline.log, LogLevel::Warn);
+        assert!(cmdline.is_server_enabled("lx"));
+        assert!(cmdline.is_server_enabled("tcpip"));
+        assert!(!cmdline.is_server_enabled("tcp"));
+        assert_eq!(cmdline.init, Some("/bin/sh"));
+        assert_eq!(cmdline.mem, Some(512 * 1024 * 1024));
+        assert_eq!(cmdline.panic, PanicAction::Reboot);
+        assert_eq!(cmdline.server_pubkey, Some("abcd"));
+
+        assert_eq!(parse("log=info").log, LogLevel::Info);
+        assert_eq!(parse("panic=halt").panic, PanicAction::Halt);
+        assert_eq!(parse("panic=qemu-exit").panic, PanicAction::QemuExit);
+        assert_eq!(parse("mem=4096").mem, Some(4096));
+        assert_eq!(parse("mem=64K").mem, Some(64 * 1024));
+        assert_eq!(parse("mem=2G").mem, Some(2 * 1024 * 1024 * 1024));
+    }
+
+    #[test]
+    fn ignore_invalid_values() {
+        let cmdline = parse("log=debug mem=512X mem=M panic=explode");
+        assert_eq!(cmdline.log, LogLevel::Trace);
+        assert_eq!(cmdline.mem, None);
+        assert_eq!(cmdline.panic, PanicAction::Halt);
+
+        // An invalid value doesn't override a valid one.
+        assert_eq!(parse("mem=1M mem=1T").mem, Some(1024 * 1024));
+    }
+
+    #[test]
+    fn ignore_unknown_options() {
+        let cmdline = parse("cpus=0 quiet foo=bar");
+        assert_eq!(cmdline.log, LogLevel::Trace);
+        assert_eq!(find_option("cpus=0 quiet foo=bar", "foo"), Some("bar"));
+        assert_eq!(find_option("cpus=0 quiet foo=bar", "quiet"), Some(""));
+        assert_eq!(find_option("cpus=0 quiet foo=bar", "bar"), None);
+    }
+
+    #[test]
+    fn last_option_wins() {
+        let raw = "log=info init=/bin/a log=warn init=/bin/b";
+        let cmdline = parse(raw);
+        assert_eq!(cmdline.log, LogLevel::Warn);
+        assert_eq!(cmdline.init, Some("/bin/b"));
+        assert_eq!(find_option(raw, "init"), Some("/bin/b"));
+    }
+}
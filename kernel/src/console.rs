@@ -0,0 +1,278 @@
+//! The system console input.
+//!
+//! The arch layer pushes input bytes (e.g. from the serial port) from its
+//! interrupt handler, and servers read them through a console handle.
+use alloc::collections::vec_deque::VecDeque;
+
+use ftl_api::console::UpcallArg;
+use ftl_api::error::ErrorCode;
+use ftl_api::handle::HandleRight;
+use ftl_api::upcall::Upcall;
+use ftl_utils::spinlock::SpinLock;
+
+use crate::service;
+use crate::shared_ref::Handleable;
+use crate::shared_ref::SharedRef;
+use crate::upcall;
+use crate::upcall::PendingUpcall;
+use crate::upcall::ServerUpcall;
+
+/// The maximum number of input bytes buffered. Bytes arriving when it's
+/// full are dropped.
+const INPUT_BUFFER_LEN: usize = 4096;
+
+static CONSOLE: SpinLock<Option<SharedRef<Console>>> = SpinLock::new(None);
+
+struct Mutable {
+    /// Preallocated at boot with `INPUT_BUFFER_LEN` bytes.
+    input: VecDeque<u8>,
+    /// The upcall to notify new input. It's temporarily taken out while it's
+    /// being invoked. The listener of a stopped server is replaced by the
+    /// next one.
+    listener: Option<ServerUpcall<UpcallArg>>,
+    /// Whether new input has arrived since the last notification.
+    readable: bool,
+    /// Whether this console is in the pending upcall queue, or being
+    /// delivered.
+    scheduled: bool,
+}
+
+pub struct Console {
+    mutable: SpinLock<Mutable>,
+}
+
+impl Console {
+    /// Notifies the listener of new input.
+    fn notify(self: &SharedRef<Self>, mutable: &mut Mutable) -> Result<(), ErrorCode> {
+        mutable.readable = true;
+        if mutable.scheduled || mutable.listener.is_none() {
+            return Ok(());
+        }
+
+        upcall::schedule(self.clone())?;
+        mutable.scheduled = true;
+        Ok(())
+    }
+
+    /// Sets the upcall to notify new input.
+    pub fn listen(self: &SharedRef<Self>, upcall: Upcall<UpcallArg>) -> Result<(), ErrorCode> {
+        let mut mutable = self.mutable.lock();
+        let in_use = match &mutable.listener {
+            Some(listener) => listener.is_alive(),
+            // Taken out by `deliver`.
+            None => mutable.scheduled,
+        };
+        if in_use {
+            return Err(ErrorCode::ALREADY_EXISTS);
+        }
+
+        // Drops the listener of a stopped server, if any.
+        mutable.listener = Some(ServerUpcall::new(upcall));
+
+        // Deliver the input arrived before listening.
+        if !mutable.input.is_empty()
+            && let Err(err) = self.notify(&mut mutable)
+        {
+            // The caller will free the upcall's user data on failure.
+            mutable.listener = None;
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    /// Reads buffered input bytes.
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, ErrorCode> {
+        let mut mutable = self.mutable.lock();
+        if mutable.input.is_empty() {
+            return Err(ErrorCode::WOULD_BLOCK);
+        }
+
+        let len = buf.len().min(mutable.input.len());
+        for (dst, src) in buf.iter_mut().zip(mutable.input.drain(..len)) {
+            *dst = src;
+        }
+
+        Ok(len)
+    }
+}
+
+impl PendingUpcall for Console {
+    fn deliver(&self) {
+        loop {
+            let listener = {
+                let mut mutable = self.mutable.lock();
+                if !mutable.readable || mutable.listener.is_none() {
+                    mutable.scheduled = false;
+                    return;
+                }
+
+                mutable.readable = false;
+                mutable.listener.take().unwrap()
+            };
+
+            // If the server panics in the upcall, the listener is dropped
+            // on unwinding and `scheduled` is cleared.
+            let (listener, _) = upcall::with_unwind(
+                || self.mutable.lock().scheduled = false,
+                || upcall::lend(listener, |listener| listener.invoke(UpcallArg::Readable)),
+            );
+
+            // Put back the listener unless the server has been stopped. Input
+            // arrived during the upcall will be notified in the next
+            // iteration.
+            let mut mutable = self.mutable.lock();
+            if listener.is_alive() {
+                mutable.listener = Some(listener);
+            } else {
+                mutable.scheduled = false;
+                return;
+            }
+        }
+    }
+}
+
+impl Handleable for Console {
+    const DEFAULT_RIGHT: HandleRight = HandleRight::READ
+        .or(HandleRight::TRANSFER)
+        .or(HandleRight::DUPLICATE);
+}
+
+/// Buffers input bytes. Called from the arch's interrupt handler.
+pub fn push_input(bytes: &[u8]) {
+    let Some(console) = CONSOLE.lock().clone() else {
+        return;
+    };
+
+    let mut mutable = console.mutable.lock();
+    let room = INPUT_BUFFER_LEN - mutable.input.len();
+    if bytes.len() > room {
+        warn!(
+            "console: input buffer is full, dropping {} bytes",
+            bytes.len() - room
+        );
+    }
+
+    // Don't grow the buffer: a server which never reads input shouldn't
+    // exhaust the kernel memory.
+    mutable.input.extend(bytes.iter().take(room));
+    if let Err(err) = console.notify(&mut mutable) {
+        warn!("console: failed to notify input: {:?}", err);
+    }
+}
+
+/// Opens the console. Allowed if the current server can look up the
+/// service `kernel/console`.
+pub fn open() -> Result<SharedRef<Console>, ErrorCode> {
+    service::authorize_kernel("console")?;
+    CONSOLE.lock().clone().ok_or(ErrorCode::UNSUPPORTED)
+}
+
+pub fn init() {
+    let mut input = VecDeque::new();
+    input
+        .try_reserve_exact(INPUT_BUFFER_LEN)
+        .expect("failed to allocate the console input buffer");
+    let console = SharedRef::new(Console {
+        mutable: SpinLock::new(Mutable {
+            input,
+            listener: None,
+            readable: false,
+            scheduled: false,
+        }),
+    })
+    .expect("failed to allocate the console");
+
+    *CONSOLE.lock() = Some(console);
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::AtomicUsize;
+    use core::sync::atomic::Ordering;
+
+    use ftl_api::upcall::UpCallCtx;
+
+    use super::*;
+    use crate::arch;
+    use crate::server;
+    use crate::server::Server;
+
+    static NUM_READABLE: AtomicUsize = AtomicUsize::new(0);
+
+    extern "Rust" fn on_readable(_ctx: UpCallCtx, arg: UpcallArg) {
+        assert!(matches!(arg, UpcallArg::Readable));
+        NUM_READABLE.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn new_console() -> SharedRef<Console> {
+        SharedRef::new(Console {
+            mutable: SpinLock::new(Mutable {
+                input: VecDeque::new(),
+                listener: None,
+                readable: false,
+                scheduled: false,
+            }),
+        })
+        .unwrap()
+    }
+
+    /// Runs the pending upcalls until `done` returns true. Other tests may
+    /// deliver them on their host threads.
+    fn run_until(done: impl Fn() -> bool) {
+        while !done() {
+            arch::run_until_idle();
+            std::thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn listen_after_listener_stops() {
+        arch::init_for_test();
+        let console = new_console();
+        let old = Server::new_for_test("listen_after_listener_stops_old");
+        server::run_as(&old, || {
+            console.listen(Upcall::from_fn(on_readable)).unwrap();
+            assert_eq!(
+                console.listen(Upcall::from_fn(on_readable)),
+                Err(ErrorCode::ALREADY_EXISTS)
+            );
+        });
+
+        old.stop().unwrap();
+        run_until(|| !old.is_alive());
+
+        // The restarted server can listen again, and the old listener is
+        // released.
+        let new = Server::new_for_test("listen_after_listener_stops_new");
+        server::run_as(&new, || {
+            console.listen(Upcall::from_fn(on_readable)).unwrap();
+        });
+        assert_eq!(SharedRef::ref_count(&old), 1);
+
+        let before = NUM_READABLE.load(Ordering::SeqCst);
+        console.mutable.lock().input.push_back(b'a');
+        console.notify(&mut console.mutable.lock()).unwrap();
+        run_until(|| NUM_READABLE.load(Ordering::SeqCst) > before);
+        run_until(|| !console.mutable.lock().scheduled);
+        assert!(console.mutable.lock().listener.is_some());
+    }
+
+    #[test]
+    fn deliver_drops_stopped_listener() {
+        arch::init_for_test();
+        let console = new_console();
+        let old = Server::new_for_test("deliver_drops_stopped_listener");
+        server::run_as(&old, || {
+            console.listen(Upcall::from_fn(on_readable)).unwrap();
+        });
+
+        old.stop().unwrap();
+        run_until(|| !old.is_alive());
+
+        console.notify(&mut console.mutable.lock()).unwrap();
+        run_until(|| !console.mutable.lock().scheduled);
+        assert!(console.mutable.lock().listener.is_none());
+        assert_eq!(SharedRef::ref_count(&old), 1);
+    }
+}
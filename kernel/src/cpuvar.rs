@@ -1,11 +1,22 @@
+use core::cell::Cell;
+use core::ptr;
+
 use crate::arch;
+use crate::server::Server;
+use crate::shared_ref::SharedRef;
 use crate::thread::CurrentThread;
+use crate::upcall::Unwinder;
 
 pub struct CpuVar {
     pub arch: arch::CpuVar,
+    pub cpu_id: usize,
     // Note: Do not wrap this field. The assembly assumes it is pointer to
     //       `arch::Thread`.
     pub current_thread: CurrentThread,
+    /// The server whose code is running on this CPU. See [`crate::server::run_as`].
+    pub current_server: Cell<Option<SharedRef<Server>>>,
+    /// The innermost upcall in progress. See [`crate::upcall::with_unwind`].
+    pub unwinders: Cell<*const Unwinder<'static>>,
 }
 
 pub fn init(cpu_id: usize) {
@@ -13,7 +24,10 @@ pub fn init(cpu_id: usize) {
         cpu_id,
         CpuVar {
             arch: arch::CpuVar::new(cpu_id),
+            cpu_id,
             current_thread: CurrentThread::new(),
+            current_server: Cell::new(None),
+            unwinders: Cell::new(ptr::null()),
         },
     );
 }
@@ -0,0 +1,294 @@
+//! Per-server handle tables.
+//!
+//! A [`Handle`] held by a server is an index into the server's handle table,
+//! not a pointer to a kernel object. The kernel looks up the table on every
+//! supercall, so that a server can't forge handles, nor use objects beyond
+//! the rights granted to it.
+use alloc::vec::Vec;
+use core::any::Any;
+use core::mem;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering;
+
+use ftl_api::error::ErrorCode;
+use ftl_api::handle::Handle;
+use ftl_api::handle::HandleRight;
+
+use crate::server;
+use crate::shared_ref::Handleable;
+use crate::shared_ref::SharedRef;
+
+/// The maximum number of handles in a handle table.
+const HANDLES_MAX: usize = 4096;
+
+/// The maximum length of a chain of duplicated handles.
+///
+/// Without it, a server could grow a chain indefinitely by duplicating a
+/// handle and closing the original, making lookups slower, and overflowing
+/// the kernel stack when the chain is dropped recursively.
+const DUPLICATE_DEPTH_MAX: usize = 16;
+
+/// A flag to invalidate a handle and all handles derived from it at once.
+///
+/// Each handle has its own token, and a duplicated handle's token points to
+/// the original's token as its parent. A handle is revoked if any of the
+/// tokens in the chain is revoked.
+struct RevokeToken {
+    revoked: AtomicBool,
+    parent: Option<SharedRef<RevokeToken>>,
+    /// The number of ancestors in `parent`. At most [`DUPLICATE_DEPTH_MAX`].
+    depth: usize,
+}
+
+impl RevokeToken {
+    fn new(parent: Option<SharedRef<RevokeToken>>) -> Result<SharedRef<Self>, ErrorCode> {
+        let depth = match &parent {
+            Some(parent) => parent.depth + 1,
+            None => 0,
+        };
+
+        if depth > DUPLICATE_DEPTH_MAX {
+            return Err(ErrorCode::TOO_LARGE);
+        }
+
+        SharedRef::new(RevokeToken {
+            revoked: AtomicBool::new(false),
+            parent,
+            depth,
+        })
+    }
+
+    fn is_revoked(&self) -> bool {
+        let mut token = self;
+        loop {
+            if token.revoked.load(Ordering::Acquire) {
+                return true;
+            }
+
+            match &token.parent {
+                Some(parent) => token = parent,
+                None => return false,
+            }
+        }
+    }
+}
+
+/// A type-erased reference to a kernel object with rights.
+///
+/// This is what a handle table entry holds. It's also used to move handles
+/// between servers through channels.
+#[derive(Clone)]
+pub struct AnyHandle {
+    object: SharedRef<dyn Any + Send + Sync>,
+    right: HandleRight,
+    token: SharedRef<RevokeToken>,
+}
+
+impl AnyHandle {
+    pub fn new<T: Handleable>(object: SharedRef<T>) -> Result<Self, ErrorCode> {
+        Ok(Self {
+            object,
+            right: T::DEFAULT_RIGHT,
+            token: RevokeToken::new(None)?,
+        })
+    }
+
+    pub fn is_revoked(&self) -> bool {
+        self.token.is_revoked()
+    }
+
+    pub fn authorize(&self, action: HandleRight) -> bool {
+        self.right.contains(action)
+    }
+
+    /// Returns the object if it's a `T` and the handle allows `action`.
+    pub fn downcast<T: Handleable>(&self, action: HandleRight) -> Result<SharedRef<T>, ErrorCode> {
+        if !self.authorize(action) {
+            return Err(ErrorCode::NOT_ALLOWED);
+        }
+
+        self.object
+            .clone()
+            .downcast::<T>()
+            .map_err(|_| ErrorCode::INVALID_TYPE)
+    }
+
+    /// Creates a handle to the same object with rights narrowed by `mask`,
+    /// which will be revoked along with `self`.
+    ///
+    /// Returns [`ErrorCode::TOO_LARGE`] if `self` is a duplicate of a
+    /// duplicate of ... [`DUPLICATE_DEPTH_MAX`] times.
+    pub fn duplicate(&self, mask: HandleRight) -> Result<AnyHandle, ErrorCode> {
+        if !self.authorize(HandleRight::DUPLICATE) {
+            return Err(ErrorCode::NOT_ALLOWED);
+        }
+
+        Ok(Self {
+            object: self.object.clone(),
+            right: self.right & mask,
+            token: RevokeToken::new(Some(self.token.clone()))?,
+        })
+    }
+
+    /// Invalidates all handles derived from `self`, and gives `self` a fresh
+    /// token so that it stays valid.
+    fn revoke(&mut self) -> Result<(), ErrorCode> {
+        let new_token = RevokeToken::new(self.token.parent.clone())?;
+        self.token.revoked.store(true, Ordering::Release);
+        self.token = new_token;
+        Ok(())
+    }
+}
+
+pub struct HandleTable {
+    /// Handle entries. The handle ID is the index plus 1 so that 0 is never a
+    /// valid handle.
+    entries: Vec<Option<AnyHandle>>,
+}
+
+impl HandleTable {
+    pub const fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    fn index(handle: &Handle) -> Result<usize, ErrorCode> {
+        handle.id().checked_sub(1).ok_or(ErrorCode::INVALID_HANDLE)
+    }
+
+    /// Returns the entry for a handle, if it's not revoked.
+    pub fn get(&self, handle: &Handle) -> Result<&AnyHandle, ErrorCode> {
+        let index = Self::index(handle)?;
+        let entry = self
+            .entries
+            .get(index)
+            .and_then(Option::as_ref)
+            .ok_or(ErrorCode::INVALID_HANDLE)?;
+
+        if entry.is_revoked() {
+            return Err(ErrorCode::REVOKED);
+        }
+
+        Ok(entry)
+    }
+
+    fn get_mut(&mut self, handle: &Handle) -> Result<&mut AnyHandle, ErrorCode> {
+        self.get(handle)?;
+        let index = Self::index(handle)?;
+        Ok(self.entries[index].as_mut().unwrap())
+    }
+
+    /// Makes room for `n` more handles so that the following `n` inserts
+    /// won't fail.
+    pub fn reserve(&mut self, n: usize) -> Result<(), ErrorCode> {
+        let free = self.entries.iter().filter(|entry| entry.is_none()).count();
+        if n <= free {
+            return Ok(());
+        }
+
+        let additional = n - free;
+        if self.entries.len() + additional > HANDLES_MAX {
+            return Err(ErrorCode::OUT_OF_MEMORY);
+        }
+
+        self.entries
+            .try_reserve(additional)
+            .map_err(|_| ErrorCode::OUT_OF_MEMORY)
+    }
+
+    pub fn insert(&mut self, handle: AnyHandle) -> Result<Handle, ErrorCode> {
+        self.reserve(1)?;
+
+        let index = match self.entries.iter().position(Option::is_none) {
+            Some(index) => {
+                self.entries[index] = Some(handle);
+                index
+            }
+            None => {
+                self.entries.push(Some(handle));
+                self.entries.len() - 1
+            }
+        };
+
+        Ok(Handle::new(index + 1))
+    }
+
+    /// Removes all handles from the table.
+    pub fn take_all(&mut self) -> impl Iterator<Item = AnyHandle> + use<> {
+        mem::take(&mut self.entries).into_iter().flatten()
+    }
+
+    /// Removes a handle from the table. Revoked handles can be removed too.
+    pub fn remove(&mut self, handle: &Handle) -> Result<AnyHandle, ErrorCode> {
+        let index = Self::index(handle)?;
+        self.entries
+            .get_mut(index)
+            .and_then(Option::take)
+            .ok_or(ErrorCode::INVALID_HANDLE)
+    }
+}
+
+/// Closes a handle in the current server's handle table.
+pub fn close(handle: Handle) -> Result<(), ErrorCode> {
+    let server = server::current();
+    let removed = server.handles().lock().remove(&handle)?;
+
+    // Drop the object after releasing the lock: it might be the last
+    // reference to the object.
+    drop(removed);
+    Ok(())
+}
+
+/// Duplicates a handle in the current server's handle table. The new handle
+/// has the rights of `handle` narrowed by `mask`.
+pub fn duplicate(handle: &Handle, mask: HandleRight) -> Result<Handle, ErrorCode> {
+    let server = server::current();
+    let mut table = server.handles().lock();
+    let new_handle = table.get(handle)?.duplicate(mask)?;
+    table.insert(new_handle)
+}
+
+/// Revokes all handles derived from a handle in the current server's handle
+/// table.
+pub fn revoke(handle: &Handle) -> Result<(), ErrorCode> {
+    let server = server::current();
+    let mut table = server.handles().lock();
+    table.get_mut(handle)?.revoke()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch;
+    use crate::arch::MIN_PAGE_SIZE;
+    use crate::quota::Quota;
+    use crate::vmarea::VmArea;
+
+    fn new_handle() -> AnyHandle {
+        arch::init_for_test();
+        let quota = Quota::new(None, None).unwrap();
+        let vmarea = VmArea::new_anonymous(MIN_PAGE_SIZE, quota).unwrap();
+        AnyHandle::new(vmarea).unwrap()
+    }
+
+    #[test]
+    fn duplicate_depth_is_limited() {
+        let root = new_handle();
+        let mut handle = root.clone();
+        for _ in 0..DUPLICATE_DEPTH_MAX {
+            // Drop the original as a server would close it.
+            handle = handle.duplicate(HandleRight::ALL).unwrap();
+        }
+
+        assert_eq!(
+            handle.duplicate(HandleRight::ALL).err(),
+            Some(ErrorCode::TOO_LARGE)
+        );
+
+        let mut root = root;
+        root.revoke().unwrap();
+        assert!(handle.is_revoked());
+        assert!(!root.is_revoked());
+    }
+}
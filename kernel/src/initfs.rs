@@ -1,65 +1,85 @@
-use core::mem::offset_of;
+//! The initial file system in the boot image (newc CPIO archives).
+//!
+//! Like Linux's initramfs, each boot module may contain multiple archives
+//! concatenated, each of which may be gzip-compressed. Compressed archives
+//! are decompressed into memory at boot. A file in a later archive overrides
+//! the one with the same path in earlier archives.
+//!
+//! Servers allowed to look up the service `kernel/initfs` can read it through
+//! the read-only file API in `ftl_api::initfs`.
+//! Directories without their own entries in the archive, e.g. `servers` in
+//! `servers/lx.elf`, are synthesized from file paths.
+use alloc::collections::btree_map::BTreeMap;
+use alloc::vec::Vec;
 use core::slice;
 
-use ftl_utils::alignment::align_up;
+use ftl_api::error::ErrorCode;
+use ftl_api::initfs::FileStat;
+use ftl_api::initfs::S_IFDIR;
+use ftl_api::initfs::S_IFMT;
+use ftl_utils::formatter::ByteSize;
+use ftl_utils::spinlock::SpinLock;
 
 use crate::arch;
-use crate::boot::Module;
-
-/// New CPIO header format.
-///
-/// <https://man.archlinux.org/man/cpio.5.en#New_ASCII_Format>
-#[repr(C, packed)]
-struct CpioHeader {
-    magic: [u8; 6],
-    inode: [u8; 8],
-    mode: [u8; 8],
-    uid: [u8; 8],
-    gid: [u8; 8],
-    nlink: [u8; 8],
-    mtime: [u8; 8],
-    filesize: [u8; 8],
-    dev_major: [u8; 8],
-    dev_minor: [u8; 8],
-    rdev_major: [u8; 8],
-    rdev_minor: [u8; 8],
-    namesize: [u8; 8],
-    checksum: [u8; 8],
-}
-
-fn parse_hex(s: &[u8]) -> Option<usize> {
-    let mut value = 0;
-    for &byte in s {
-        let digit = match byte {
-            b'0'..=b'9' => byte - b'0',
-            b'a'..=b'f' => byte - b'a' + 10,
-            b'A'..=b'F' => byte - b'A' + 10,
-            _ => return None,
-        };
+use crate::boot::BootInfo;
+use crate::service;
 
-        value = value * 16 + (digit as usize);
-    }
+/// The mode of synthesized directories: `drwxr-xr-x`.
+const IMPLICIT_DIR_MODE: u32 = S_IFDIR | 0o755;
 
-    Some(value)
-}
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// How deep gzip-compressed archives can be nested, e.g. a gzip member in a
+/// gzip member.
+const GZIP_NESTING_MAX: usize = 2;
+
+/// The initial buffer size to decompress an archive into.
+const GUNZIP_CHUNK_LEN: usize = 64 * 1024;
+
+/// Uncompressed CPIO archives, in the order they appear in boot modules.
+static IMAGES: SpinLock<&'static [&'static [u8]]> = SpinLock::new(&[]);
+
+/// Sorted names of the entries in directories listed by [`readdir`], keyed
+/// by the normalized directory path. The archives never change after boot,
+/// so a listing is built once instead of on every `readdir` call.
+static LISTINGS: SpinLock<BTreeMap<Vec<u8>, Vec<&'static [u8]>>> = SpinLock::new(BTreeMap::new());
 
 pub struct File<'a> {
+    /// The path without leading `/` or `./`.
     pub name: &'a [u8],
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub mtime: u64,
     pub data: &'a [u8],
 }
 
+impl File<'_> {
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+
+    fn stat(&self) -> FileStat {
+        FileStat {
+            mode: self.mode,
+            uid: self.uid,
+            gid: self.gid,
+            size: if self.is_dir() { 0 } else { self.data.len() },
+            mtime: self.mtime,
+        }
+    }
+}
+
+/// Iterates over the files in a CPIO archive, with normalized paths.
 pub struct InitFsLoader<'a> {
-    file: &'a [u8],
-    offset: usize,
+    reader: ftl_cpio::Reader<'a>,
 }
 
 impl<'a> InitFsLoader<'a> {
-    pub fn new(module: &Module) -> Self {
-        let start: *const u8 = arch::paddr2vaddr(module.start).as_ptr();
-        let end: *const u8 = arch::paddr2vaddr(module.end).as_ptr();
-        let len = (end as usize).saturating_sub(start as usize);
-        let file = unsafe { slice::from_raw_parts(start, len) };
-        Self { file, offset: 0 }
+    pub fn new(file: &'a [u8]) -> Self {
+        Self {
+            reader: ftl_cpio::Reader::new(file),
+        }
     }
 }
 
@@ -67,35 +87,531 @@ impl<'a> Iterator for InitFsLoader<'a> {
     type Item = File<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let magic = self.file.get(self.offset..self.offset + 6)?;
-        if magic != b"070701" {
-            return None;
+        let entry = self.reader.next()?;
+        Some(File {
+            name: normalize(entry.name),
+            mode: entry.mode,
+            uid: entry.uid,
+            gid: entry.gid,
+            mtime: entry.mtime,
+            data: entry.data,
+        })
+    }
+}
+
+/// Strips leading `/` and `./`, and trailing `/` from a path.
+fn normalize(mut path: &[u8]) -> &[u8] {
+    loop {
+        if let Some(rest) = path.strip_prefix(b"/") {
+            path = rest;
+        } else if let Some(rest) = path.strip_prefix(b"./") {
+            path = rest;
+        } else {
+            break;
+        }
+    }
+
+    while let Some(rest) = path.strip_suffix(b"/") {
+        path = rest;
+    }
+
+    if path == b"." { b"" } else { path }
+}
+
+/// Returns the part of `name` after the directory `dir`, or `None` if it's
+/// not in the directory. `dir` is empty for the root directory.
+fn strip_dir<'a>(name: &'a [u8], dir: &[u8]) -> Option<&'a [u8]> {
+    if dir.is_empty() {
+        return Some(name);
+    }
+
+    name.strip_prefix(dir)?.strip_prefix(b"/")
+}
+
+/// Iterates over all files in all boot modules.
+pub fn files() -> impl Iterator<Item = File<'static>> {
+    let images: &'static [&'static [u8]] = *IMAGES.lock();
+    images
+        .iter()
+        .flat_map(|image| InitFsLoader::new(image))
+        .filter(|file| !file.name.is_empty())
+}
+
+/// Looks for a file or a directory entry by its path. If there are multiple
+/// entries with the path, the last one wins.
+pub fn lookup(path: &str) -> Option<File<'static>> {
+    let path = normalize(path.as_bytes());
+    files().filter(|file| file.name == path).last()
+}
+
+/// Returns `false` if `file` is overridden by a later entry with the same
+/// path.
+pub fn is_latest(file: &File<'static>) -> bool {
+    files()
+        .filter(|other| other.name == file.name)
+        .last()
+        .is_some_and(|latest| core::ptr::eq(latest.name, file.name))
+}
+
+pub fn stat(path: &str) -> Result<FileStat, ErrorCode> {
+    service::authorize_kernel("initfs")?;
+    stat_entry(path)
+}
+
+fn stat_entry(path: &str) -> Result<FileStat, ErrorCode> {
+    if let Some(file) = lookup(path) {
+        return Ok(file.stat());
+    }
+
+    let path = normalize(path.as_bytes());
+    let is_implicit_dir = path.is_empty()
+        || files().any(|file| strip_dir(file.name, path).is_some_and(|rest| !rest.is_empty()));
+    if !is_implicit_dir {
+        return Err(ErrorCode::NOT_FOUND);
+    }
+
+    Ok(FileStat {
+        mode: IMPLICIT_DIR_MODE,
+        uid: 0,
+        gid: 0,
+        size: 0,
+        mtime: 0,
+    })
+}
+
+/// Reads a file from `offset`. Returns the number of bytes read.
+pub fn read(path: &str, offset: usize, buf: &mut [u8]) -> Result<usize, ErrorCode> {
+    service::authorize_kernel("initfs")?;
+    let file = lookup(path).ok_or(ErrorCode::NOT_FOUND)?;
+    if file.is_dir() {
+        return Err(ErrorCode::INVALID_ARG);
+    }
+
+    let data = file.data.get(offset..).unwrap_or(&[]);
+    let len = buf.len().min(data.len());
+    buf[..len].copy_from_slice(&data[..len]);
+    Ok(len)
+}
+
+/// Returns the name of the `index`-th entry in a directory, sorted by name,
+/// or `None` if there are no more entries.
+pub fn readdir(path: &str, index: usize) -> Result<Option<&'static str>, ErrorCode> {
+    service::authorize_kernel("initfs")?;
+    readdir_entry(path, index)
+}
+
+fn readdir_entry(path: &str, index: usize) -> Result<Option<&'static str>, ErrorCode> {
+    if !stat_entry(path)?.is_dir() {
+        return Err(ErrorCode::INVALID_ARG);
+    }
+
+    let dir = normalize(path.as_bytes());
+    let cached = LISTINGS
+        .lock()
+        .get(dir)
+        .map(|children| children.get(index).copied());
+    let child = match cached {
+        Some(child) => child,
+        None => {
+            // Build the listing without holding the lock: it scans all
+            // files. If another thread has built it in the meantime, they
+            // are the same.
+            let children = list_dir(dir)?;
+            let child = children.get(index).copied();
+            let mut key = Vec::new();
+            if key.try_reserve_exact(dir.len()).is_ok() {
+                key.extend_from_slice(dir);
+                LISTINGS.lock().insert(key, children);
+            }
+
+            child
+        }
+    };
+
+    let Some(child) = child else {
+        return Ok(None);
+    };
+
+    let name = core::str::from_utf8(child).map_err(|_| ErrorCode::INVALID_ARG)?;
+    Ok(Some(name))
+}
+
+/// Returns the names of the entries in a directory, sorted by name.
+fn list_dir(dir: &[u8]) -> Result<Vec<&'static [u8]>, ErrorCode> {
+    let mut children: Vec<&'static [u8]> = Vec::new();
+    for file in files() {
+        let Some(rest) = strip_dir(file.name, dir) else {
+            continue;
+        };
+
+        let child = rest.split(|&b| b == b'/').next().unwrap_or(rest);
+        if child.is_empty() {
+            continue;
+        }
+
+        if children.try_reserve(1).is_err() {
+            return Err(ErrorCode::OUT_OF_MEMORY);
+        }
+
+        children.push(child);
+    }
+
+    // Each child appears once even if there are multiple files under it, or
+    // it's in multiple boot modules.
+    children.sort_unstable();
+    children.dedup();
+    Ok(children)
+}
+
+/// Decompresses a gzip member into memory. Returns the decompressed data and
+/// the length of the member in `input`.
+fn gunzip(input: &[u8]) -> Result<(Vec<u8>, usize), ftl_inflate::Error> {
+    let mut reader = ftl_inflate::GzipReader::new(input)?;
+    let mut data = Vec::new();
+    loop {
+        // Grow the buffer geometrically.
+        let additional = data.len().max(GUNZIP_CHUNK_LEN);
+        data.try_reserve_exact(additional)
+            .map_err(|_| ftl_inflate::Error::OutOfMemory)?;
+
+        let start = data.len();
+        data.resize(start + additional, 0);
+        let len = reader.read(&mut data[start..])?;
+        data.truncate(start + len);
+        if len < additional {
+            break;
         }
+    }
+
+    let member_len = reader
+        .member_len()
+        .ok_or(ftl_inflate::Error::UnexpectedEof)?;
+    Ok((data, member_len))
+}
+
+/// Splits a boot module into uncompressed CPIO archives. `depth` is the
+/// number of gzip members `module` is nested in.
+fn unpack(mut module: &'static [u8], images: &mut Vec<&'static [u8]>, depth: usize) {
+    loop {
+        // Skip zero padding between archives.
+        let padding_len = module.iter().take_while(|&&byte| byte == 0).count();
+        module = &module[padding_len..];
+        if module.is_empty() {
+            return;
+        }
+
+        if module.starts_with(ftl_cpio::MAGIC) {
+            let len = ftl_cpio::Reader::archive_len(module);
+            if len == 0 {
+                warn!("initfs: malformed archive, ignoring the rest of the module");
+                return;
+            }
+
+            images.push(&module[..len]);
+            module = &module[len..];
+        } else if ftl_inflate::is_gzip(module) {
+            if depth >= GZIP_NESTING_MAX {
+                warn!("initfs: too deeply nested gzip archives, ignoring the rest of the module");
+                return;
+            }
+
+            match gunzip(module) {
+                Ok((data, len)) => {
+                    trace!(
+                        "initfs: decompressed {} into {}",
+                        ByteSize(len),
+                        ByteSize(data.len())
+                    );
+
+                    // The decompressed data may also contain multiple archives.
+                    unpack(data.leak(), images, depth + 1);
+                    module = &module[len..];
+                }
+                Err(err) => {
+                    error!("initfs: failed to decompress an archive: {:?}", err);
+                    return;
+                }
+            }
+        } else if module.starts_with(ZSTD_MAGIC) {
+            // Not implemented: build the boot image with gzip instead.
+            error!("initfs: zstd-compressed archives are not supported");
+            return;
+        } else {
+            warn!("initfs: unknown archive format, ignoring the rest of the module");
+            return;
+        }
+    }
+}
+
+pub fn init(bootinfo: &BootInfo) {
+    let mut images = Vec::new();
+    for module in &bootinfo.modules {
+        let start: *const u8 = arch::paddr2vaddr(module.start).as_ptr();
+        let end: *const u8 = arch::paddr2vaddr(module.end).as_ptr();
+        let len = (end as usize).saturating_sub(start as usize);
+        let module = unsafe { slice::from_raw_parts(start, len) };
+        unpack(module, &mut images, 0);
+    }
+
+    *IMAGES.lock() = images.leak();
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+
+    use ftl_api::initfs::S_IFREG;
+    use ftl_utils::alignment::align_up;
+
+    use super::*;
+
+    fn push_entry(archive: &mut Vec<u8>, name: &[u8], mode: u32, data: &[u8]) {
+        let header = format!(
+            "070701{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}",
+            0,
+            mode,
+            0,
+            0,
+            1,
+            0,
+            data.len(),
+            0,
+            0,
+            0,
+            0,
+            name.len() + 1,
+            0
+        );
+        archive.extend_from_slice(header.as_bytes());
+        archive.extend_from_slice(name);
+        archive.push(0);
+        archive.resize(align_up(archive.len(), 4), 0);
+        archive.extend_from_slice(data);
+        archive.resize(align_up(archive.len(), 4), 0);
+    }
+
+    fn archive(entries: &[(&[u8], u32, &[u8])]) -> Vec<u8> {
+        let mut archive = Vec::new();
+        for (name, mode, data) in entries {
+            push_entry(&mut archive, name, *mode, data);
+        }
+        push_entry(&mut archive, b"TRAILER!!!", 0, b"");
+        archive
+    }
+
+    /// Sets up the archives shared by all tests. `servers/lx.elf` is in both
+    /// of them.
+    fn init_for_test() {
+        let mut images = IMAGES.lock();
+        if !images.is_empty() {
+            return;
+        }
+
+        let first = archive(&[
+            (b".", S_IFDIR | 0o755, b""),
+            (b"./servers/", S_IFDIR | 0o700, b""),
+            (b"./servers/lx.elf", S_IFREG | 0o644, b"lx"),
+            (b"/etc/policy", S_IFREG | 0o600, b"policy"),
+            (b"docs/a/b.txt", S_IFREG | 0o644, b"hello"),
+        ]);
+        let second = archive(&[
+            (b"servers/shell.elf", S_IFREG | 0o644, b"shell"),
+            (b"servers/lx.elf", S_IFREG | 0o644, b"lx2"),
+        ]);
+        *images = Vec::from([&*first.leak(), &*second.leak()]).leak();
+    }
+
+    fn list(path: &str) -> Result<Vec<&'static str>, ErrorCode> {
+        let mut names = Vec::new();
+        while let Some(name) = readdir_entry(path, names.len())? {
+            names.push(name);
+        }
+        Ok(names)
+    }
+
+    #[test]
+    fn normalize_paths() {
+        assert_eq!(normalize(b"servers/lx.elf"), b"servers/lx.elf");
+        assert_eq!(normalize(b"/servers/lx.elf"), b"servers/lx.elf");
+        assert_eq!(normalize(b"./servers/"), b"servers");
+        assert_eq!(normalize(b"/././/servers//"), b"servers");
+        assert_eq!(normalize(b"."), b"");
+        assert_eq!(normalize(b"./"), b"");
+        assert_eq!(normalize(b"/"), b"");
+        assert_eq!(normalize(b""), b"");
+        assert_eq!(normalize(b"..."), b"...");
+        assert_eq!(normalize(b".hidden"), b".hidden");
+    }
+
+    #[test]
+    fn strip_dir_prefix() {
+        assert_eq!(
+            strip_dir(b"servers/lx.elf", b""),
+            Some(&b"servers/lx.elf"[..])
+        );
+        assert_eq!(
+            strip_dir(b"servers/lx.elf", b"servers"),
+            Some(&b"lx.elf"[..])
+        );
+        assert_eq!(strip_dir(b"servers", b"servers"), None);
+        assert_eq!(strip_dir(b"servers2/lx.elf", b"servers"), None);
+        assert_eq!(strip_dir(b"docs/a/b.txt", b"docs/a"), Some(&b"b.txt"[..]));
+        assert_eq!(strip_dir(b"docs/a/b.txt", b"docs/a/b.txt"), None);
+    }
 
-        let namesize_offset = self.offset + offset_of!(CpioHeader, namesize);
-        let namesize_bytes = self.file.get(namesize_offset..namesize_offset + 8)?;
-        let namesize = parse_hex(namesize_bytes)?;
+    #[test]
+    fn stat_files_and_dirs() {
+        init_for_test();
 
-        let filesize_offset = self.offset + offset_of!(CpioHeader, filesize);
-        let filesize_bytes = self.file.get(filesize_offset..filesize_offset + 8)?;
-        let filesize = parse_hex(filesize_bytes)?;
+        let file = stat_entry("/etc/policy").unwrap();
+        assert_eq!(file.mode, S_IFREG | 0o600);
+        assert_eq!(file.size, b"policy".len());
 
-        let name_offset = self.offset + size_of::<CpioHeader>();
-        let mut name = self.file.get(name_offset..name_offset + namesize)?;
+        // Directories with their own entries.
+        assert_eq!(stat_entry("servers").unwrap().mode, S_IFDIR | 0o700);
+        assert_eq!(stat_entry("./servers/").unwrap().mode, S_IFDIR | 0o700);
 
-        // Remove the trailing null byte.
-        if name.ends_with(b"\0") {
-            name = &name[..name.len() - 1];
+        // Synthesized directories.
+        for path in ["", "/", ".", "docs", "docs/a", "/etc/"] {
+            let stat = stat_entry(path).unwrap();
+            assert_eq!(stat.mode, IMPLICIT_DIR_MODE, "{path}");
+            assert_eq!(stat.size, 0);
         }
 
-        if name == b"TRAILER!!!" {
-            return None;
+        for path in ["doc", "docs/a/b", "docs/a/b.txt/c", "nonexistent"] {
+            assert_eq!(stat_entry(path).err(), Some(ErrorCode::NOT_FOUND), "{path}");
         }
+    }
+
+    #[test]
+    fn readdir_sorted_and_deduplicated() {
+        init_for_test();
+
+        assert_eq!(list("/").unwrap(), ["docs", "etc", "servers"]);
+        assert_eq!(list("docs").unwrap(), ["a"]);
+        assert_eq!(list("docs/a").unwrap(), ["b.txt"]);
+
+        // Listed from the cache the second time, with the same result.
+        for path in ["servers", "./servers/", "servers"] {
+            assert_eq!(list(path).unwrap(), ["lx.elf", "shell.elf"]);
+        }
+        assert_eq!(readdir_entry("servers", 100), Ok(None));
+
+        assert_eq!(list("docs/a/b.txt"), Err(ErrorCode::INVALID_ARG));
+        assert_eq!(list("nonexistent"), Err(ErrorCode::NOT_FOUND));
+    }
+
+    #[test]
+    fn later_archives_override() {
+        init_for_test();
+
+        let lx = lookup("servers/lx.elf").unwrap();
+        assert_eq!(lx.data, b"lx2");
+        assert!(is_latest(&lx));
+
+        let shadowed = files().find(|file| file.name == b"servers/lx.elf").unwrap();
+        assert_eq!(shadowed.data, b"lx");
+        assert!(!is_latest(&shadowed));
+
+        assert_eq!(stat_entry("/servers/lx.elf").unwrap().size, b"lx2".len());
+    }
+
+    /// Wraps `data` in a gzip member with a stored (uncompressed) block.
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let len = data.len() as u16;
+        let mut member = Vec::from([0x1f, 0x8b, 0x08, 0x00, 0, 0, 0, 0, 0x00, 0xff]);
+        member.push(0x01);
+        member.extend_from_slice(&len.to_le_bytes());
+        member.extend_from_slice(&(!len).to_le_bytes());
+        member.extend_from_slice(data);
+        member.extend_from_slice(&ftl_inflate::crc32(data).to_le_bytes());
+        member.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        member
+    }
+
+    fn unpack_names(module: Vec<u8>) -> Vec<Vec<&'static [u8]>> {
+        let mut images = Vec::new();
+        unpack(module.leak(), &mut images, 0);
+        images
+            .into_iter()
+            .map(|image| InitFsLoader::new(image).map(|file| file.name).collect())
+            .collect()
+    }
+
+    fn file_archive(name: &[u8]) -> Vec<u8> {
+        archive(&[(name, S_IFREG | 0o644, b"data")])
+    }
+
+    /// An archive of a file whose data is cut off in the middle.
+    fn truncated_archive(name: &[u8]) -> Vec<u8> {
+        let mut archive = Vec::new();
+        push_entry(&mut archive, name, S_IFREG | 0o644, b"data");
+        archive.truncate(archive.len() - 2);
+        archive
+    }
+
+    #[test]
+    fn unpack_padded_archives() {
+        let mut module = Vec::new();
+        module.extend_from_slice(&file_archive(b"a"));
+        module.resize(module.len() + 512, 0);
+        module.extend_from_slice(&file_archive(b"b"));
+        module.resize(module.len() + 3, 0);
+
+        assert_eq!(unpack_names(module), [[b"a"], [b"b"]]);
+    }
+
+    #[test]
+    fn unpack_concatenated_cpio_and_gzip() {
+        let mut module = Vec::new();
+        module.extend_from_slice(&file_archive(b"a"));
+        module.extend_from_slice(&gzip(&file_archive(b"b")));
+        module.extend_from_slice(&[0; 4]);
+
+        // A gzip member may contain multiple archives too.
+        let mut inner = file_archive(b"c");
+        inner.extend_from_slice(&file_archive(b"d"));
+        module.extend_from_slice(&gzip(&inner));
+        module.extend_from_slice(&file_archive(b"e"));
+
+        assert_eq!(
+            unpack_names(module),
+            [[b"a"], [b"b"], [b"c"], [b"d"], [b"e"]]
+        );
+    }
+
+    #[test]
+    fn unpack_nesting_limit() {
+        let nested = gzip(&gzip(&file_archive(b"a")));
+        assert_eq!(unpack_names(nested.clone()), [[b"a"]]);
+
+        // Too deep: the rest of the innermost member is ignored, but not the
+        // rest of the module.
+        let mut module = gzip(&nested);
+        module.extend_from_slice(&file_archive(b"b"));
+        assert_eq!(unpack_names(module), [[b"b"]]);
+    }
+
+    #[test]
+    fn unpack_truncated_members() {
+        // A truncated gzip member.
+        let mut module = file_archive(b"a");
+        let member = gzip(&file_archive(b"b"));
+        module.extend_from_slice(&member[..member.len() - 4]);
+        assert_eq!(unpack_names(module), [[b"a"]]);
+
+        // A CPIO archive truncated in the middle of the file data.
+        let mut module = file_archive(b"a");
+        module.extend_from_slice(&truncated_archive(b"b"));
+        assert_eq!(unpack_names(module), [[b"a"]]);
 
-        let data_offset = align_up(self.offset + size_of::<CpioHeader>() + namesize, 4);
-        let data = self.file.get(data_offset..data_offset + filesize)?;
+        // A truncated CPIO archive in a gzip member.
+        let mut module = gzip(&truncated_archive(b"a"));
+        module.extend_from_slice(&file_archive(b"b"));
+        assert_eq!(unpack_names(module), [[b"b"]]);
 
-        self.offset = align_up(data_offset + filesize, 4);
-        Some(File { name, data })
+        // Unknown formats.
+        assert!(unpack_names(Vec::from(*b"garbage")).is_empty());
+        assert!(unpack_names(Vec::from(ZSTD_MAGIC)).is_empty());
     }
 }
@@ -0,0 +1,217 @@
+//! Syscall interceptors. See [`ftl_api::interceptor`] for the overview.
+use core::mem::ManuallyDrop;
+
+use ftl_api::error::ErrorCode;
+use ftl_api::handle::HandleRight;
+use ftl_api::interceptor::Action;
+use ftl_api::interceptor::MAX_INTERCEPTORS;
+use ftl_api::interceptor::UpcallArg;
+use ftl_api::thread::SyscallArgs;
+use ftl_api::thread::Sysret;
+use ftl_api::upcall::Upcall;
+use ftl_arrayvec::ArrayVec;
+use ftl_utils::spinlock::SpinLock;
+
+use crate::shared_ref::Handleable;
+use crate::shared_ref::SharedRef;
+use crate::upcall;
+use crate::upcall::PendingUpcall;
+use crate::upcall::ServerUpcall;
+
+/// Interceptors a syscall goes through: ones attached to the VmSpace and
+/// ones attached to the thread.
+pub type Chain = ArrayVec<SharedRef<Interceptor>, { 2 * MAX_INTERCEPTORS }>;
+
+struct Mutable {
+    /// The number of in-flight upcalls. The [`UpcallArg::Detached`] upcall,
+    /// which frees the upcall's user data, waits for them.
+    in_upcalls: usize,
+    detached: bool,
+}
+
+pub struct Interceptor {
+    /// Dropped in [`Drop::drop`], or moved into [`PendingDetach`].
+    upcall: ManuallyDrop<ServerUpcall<UpcallArg, Action>>,
+    mutable: SpinLock<Mutable>,
+}
+
+impl Interceptor {
+    pub fn new(upcall: Upcall<UpcallArg, Action>) -> Result<SharedRef<Self>, ErrorCode> {
+        SharedRef::new(Self {
+            upcall: ManuallyDrop::new(ServerUpcall::new(upcall)),
+            mutable: SpinLock::new(Mutable {
+                in_upcalls: 0,
+                detached: false,
+            }),
+        })
+    }
+
+    fn is_detached(&self) -> bool {
+        self.mutable.lock().detached
+    }
+
+    /// Invokes the upcall unless the interceptor is detached.
+    fn invoke(self: &SharedRef<Self>, arg: UpcallArg) -> Option<Action> {
+        {
+            let mut mutable = self.mutable.lock();
+            if mutable.detached {
+                return None;
+            }
+
+            mutable.in_upcalls += 1;
+        }
+
+        // If the server panics, it won't do the detach upcall either.
+        let action = upcall::with_unwind(
+            || self.mutable.lock().in_upcalls -= 1,
+            || self.upcall.invoke(arg),
+        );
+
+        let detach_now = {
+            let mut mutable = self.mutable.lock();
+            mutable.in_upcalls -= 1;
+            mutable.in_upcalls == 0 && mutable.detached
+        };
+
+        if detach_now {
+            self.upcall.invoke(UpcallArg::Detached);
+        }
+
+        action
+    }
+
+    /// Asks the interceptor what to do with a syscall. A detached interceptor
+    /// passes everything, and a stopped interceptor server kills the thread.
+    pub fn syscall(self: &SharedRef<Self>, args: SyscallArgs) -> Action {
+        match self.invoke(UpcallArg::Syscall(args)) {
+            Some(action) => action,
+            None if self.is_detached() => Action::Continue,
+            None => {
+                // Don't let the syscall bypass the interceptor.
+                trace!("interceptor server has stopped (n={})", args.n);
+                Action::Kill
+            }
+        }
+    }
+
+    /// Passes the return value to the interceptor, and returns the possibly
+    /// modified one.
+    pub fn sysret(self: &SharedRef<Self>, args: SyscallArgs, sysret: Sysret) -> Sysret {
+        match self.invoke(UpcallArg::Sysret(args, sysret)) {
+            Some(Action::Complete(new_sysret)) => new_sysret,
+            _ => sysret,
+        }
+    }
+
+    /// Stops intercepting syscalls on all threads and VmSpaces.
+    ///
+    /// The kernel will do an upcall with [`UpcallArg::Detached`] when no
+    /// upcalls are in flight. Like [`crate::thread::Thread::terminate`], the
+    /// upcall is deferred until the current supercall returns.
+    pub fn detach(self: &SharedRef<Self>) -> Result<(), ErrorCode> {
+        let mut mutable = self.mutable.lock();
+        if mutable.detached {
+            return Err(ErrorCode::INVALID_STATE);
+        }
+
+        // `invoke` does the upcall when the in-flight ones return.
+        if mutable.in_upcalls == 0 {
+            upcall::schedule(self.clone())?;
+        }
+
+        mutable.detached = true;
+        Ok(())
+    }
+}
+
+impl PendingUpcall for Interceptor {
+    fn deliver(&self) {
+        self.upcall.invoke(UpcallArg::Detached);
+    }
+}
+
+impl Drop for Interceptor {
+    fn drop(&mut self) {
+        // SAFETY: `upcall` is not used after this.
+        let upcall = unsafe { ManuallyDrop::take(&mut self.upcall) };
+        if self.mutable.lock().detached {
+            return;
+        }
+
+        // The server hasn't detached it, e.g. it has closed the handle in
+        // another way. Let the server free the user data later: the caller
+        // may hold locks.
+        let pending = match SharedRef::new(PendingDetach(upcall)) {
+            Ok(pending) => pending,
+            Err(err) => {
+                warn!("failed to detach a dropped interceptor: {:?}", err);
+                return;
+            }
+        };
+
+        if let Err(err) = upcall::schedule(pending) {
+            warn!("failed to detach a dropped interceptor: {:?}", err);
+        }
+    }
+}
+
+/// The [`UpcallArg::Detached`] upcall of a dropped interceptor.
+struct PendingDetach(ServerUpcall<UpcallArg, Action>);
+
+impl PendingUpcall for PendingDetach {
+    fn deliver(&self) {
+        self.0.invoke(UpcallArg::Detached);
+    }
+}
+
+impl Handleable for Interceptor {
+    const DEFAULT_RIGHT: HandleRight = HandleRight::READ
+        .or(HandleRight::TERMINATE)
+        .or(HandleRight::TRANSFER)
+        .or(HandleRight::DUPLICATE);
+}
+
+/// Interceptors attached to a thread or a VmSpace.
+pub struct InterceptorList {
+    interceptors: SpinLock<ArrayVec<SharedRef<Interceptor>, MAX_INTERCEPTORS>>,
+}
+
+impl InterceptorList {
+    pub const fn new() -> Self {
+        Self {
+            interceptors: SpinLock::new(ArrayVec::new()),
+        }
+    }
+
+    /// Appends an interceptor to the end of the list.
+    pub fn attach(&self, interceptor: SharedRef<Interceptor>) -> Result<(), ErrorCode> {
+        if interceptor.is_detached() {
+            return Err(ErrorCode::INVALID_STATE);
+        }
+
+        let mut interceptors = self.interceptors.lock();
+        interceptors.retain(|attached| !attached.is_detached());
+        if interceptors
+            .iter()
+            .any(|attached| SharedRef::eq(attached, &interceptor))
+        {
+            return Err(ErrorCode::ALREADY_EXISTS);
+        }
+
+        interceptors
+            .try_push(interceptor)
+            .map_err(|_| ErrorCode::TOO_LARGE)
+    }
+
+    /// Appends the attached interceptors to `chain`, except detached ones.
+    pub fn collect_into(&self, chain: &mut Chain) {
+        for interceptor in self.interceptors.lock().iter() {
+            if interceptor.is_detached() {
+                continue;
+            }
+
+            // The chain has room for both the VmSpace and the thread.
+            let _ = chain.try_push(interceptor.clone());
+        }
+    }
+}
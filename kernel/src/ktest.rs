@@ -0,0 +1,135 @@
+//! In-kernel tests, enabled by the `ktest` feature.
+//!
+//! In the test mode, the kernel runs the tests below at boot, prints the
+//! results in the TAP format to the serial port, and exits QEMU through its
+//! isa-debug-exit device (exit code 33 if all tests passed, 71 otherwise):
+//!
+//! - Functions marked with `#[ktest]`, in the link order. A test fails if it
+//!   panics, and the remaining tests are not run.
+//! - Test servers, that is, `tests/<name>.elf` in initfs. A test server
+//!   passes if its `start` returns without panicking. Servers in `servers/`
+//!   are loaded as usual.
+//!
+//! `KTEST=1 ./run.sh` builds the kernel and test servers, and runs them.
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+
+use ftl_utils::spinlock::SpinLock;
+
+use crate::arch;
+use crate::arch::ExitStatus;
+use crate::shared_ref::SharedRef;
+use crate::upcall;
+use crate::upcall::PendingUpcall;
+
+/// A test registered by `#[ktest]`.
+pub struct KTest {
+    pub name: &'static str,
+    pub func: fn(),
+}
+
+unsafe extern "C" {
+    static __ktests: u8;
+    static __ktests_end: u8;
+}
+
+/// The number of reported tests.
+static NUM_TESTS: AtomicUsize = AtomicUsize::new(0);
+static NUM_FAILED: AtomicUsize = AtomicUsize::new(0);
+/// The `#[ktest]` function being run.
+static CURRENT: SpinLock<Option<&'static str>> = SpinLock::new(None);
+/// Test servers which have not finished yet.
+static SERVERS: SpinLock<Vec<String>> = SpinLock::new(Vec::new());
+
+fn ktests() -> &'static [KTest] {
+    // SAFETY: The linker script places the tests between the symbols.
+    unsafe {
+        let start = &raw const __ktests as *const KTest;
+        let end = &raw const __ktests_end as *const KTest;
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    }
+}
+
+fn report(passed: bool, name: &str) {
+    let n = NUM_TESTS.fetch_add(1, Ordering::Relaxed) + 1;
+    if passed {
+        println!("ok {} - {}", n, name);
+    } else {
+        NUM_FAILED.fetch_add(1, Ordering::Relaxed);
+        println!("not ok {} - {}", n, name);
+    }
+}
+
+/// Runs `#[ktest]` functions.
+pub fn run_ktests() {
+    println!("TAP version 13");
+    for test in ktests() {
+        *CURRENT.lock() = Some(test.name);
+        (test.func)();
+        *CURRENT.lock() = None;
+        report(true, test.name);
+    }
+}
+
+/// Registers a test server, loaded from `tests/<name>.elf`.
+pub fn add_server(name: &str) {
+    SERVERS.lock().push(name.to_string());
+}
+
+/// Reports the result of a server's start. Returns false if it's not a test
+/// server.
+pub fn server_finished(name: &str, passed: bool) -> bool {
+    let mut servers = SERVERS.lock();
+    let Some(index) = servers.iter().position(|s| s == name) else {
+        return false;
+    };
+
+    servers.remove(index);
+    drop(servers);
+    report(passed, name);
+    true
+}
+
+/// Schedules the end of the tests, after starting the servers loaded so far.
+pub fn schedule_finish() {
+    let finish = SharedRef::new(Finish).expect("failed to allocate the finish upcall");
+    upcall::schedule(finish).expect("failed to schedule the finish upcall");
+}
+
+struct Finish;
+
+impl PendingUpcall for Finish {
+    fn deliver(&self) {
+        // Test servers which failed to load.
+        let servers = core::mem::take(&mut *SERVERS.lock());
+        for name in servers {
+            report(false, &name);
+        }
+
+        let failed = NUM_FAILED.load(Ordering::Relaxed);
+        println!("1..{}", NUM_TESTS.load(Ordering::Relaxed));
+        if failed > 0 {
+            println!("# {} test(s) failed", failed);
+            arch::semihosting_exit(ExitStatus::Failure);
+        }
+
+        println!("# all tests passed");
+        arch::semihosting_exit(ExitStatus::Success);
+    }
+}
+
+/// Called on a kernel panic: reports the running test as failed. The kernel
+/// then exits QEMU, the default `panic=` action in the test mode.
+pub fn bail_out() {
+    // Don't deadlock if the panic happened while holding the lock.
+    if let Ok(current) = CURRENT.try_lock()
+        && let Some(name) = *current
+    {
+        report(false, name);
+    }
+
+    println!("Bail out! kernel panic");
+}
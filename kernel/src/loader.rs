@@ -1,146 +1,1026 @@
+use alloc::vec::Vec;
+use core::mem::align_of;
 use core::mem::size_of;
+use core::ops::Range;
 use core::slice;
 
+use ftl_api::Spec;
 use ftl_api::start::StartInfo;
-use ftl_elf::DT_NULL;
+use ftl_api::vmspace::PageAttrs;
+use ftl_elf::DT_GNU_HASH;
+use ftl_elf::DT_HASH;
+use ftl_elf::DT_JMPREL;
+use ftl_elf::DT_PLTREL;
+use ftl_elf::DT_PLTRELSZ;
 use ftl_elf::DT_RELA;
+use ftl_elf::DT_RELAENT;
 use ftl_elf::DT_RELASZ;
-use ftl_elf::Dyn;
+use ftl_elf::DT_STRSZ;
+use ftl_elf::DT_STRTAB;
+use ftl_elf::DT_SYMENT;
+use ftl_elf::DT_SYMTAB;
 use ftl_elf::Elf;
+use ftl_elf::PF_W;
+use ftl_elf::PF_X;
 use ftl_elf::PhdrType;
+#[cfg(target_arch = "x86_64")]
+use ftl_elf::R_X86_64_64;
+#[cfg(target_arch = "x86_64")]
+use ftl_elf::R_X86_64_GLOB_DAT;
+#[cfg(target_arch = "x86_64")]
+use ftl_elf::R_X86_64_JUMP_SLOT;
+#[cfg(target_arch = "x86_64")]
+use ftl_elf::R_X86_64_NONE;
+#[cfg(target_arch = "x86_64")]
+use ftl_elf::R_X86_64_RELATIVE;
 use ftl_elf::Rela;
+use ftl_elf::SHT_DYNSYM;
+use ftl_elf::SHT_SYMTAB;
+use ftl_elf::STB_LOCAL;
+use ftl_elf::STB_WEAK;
+use ftl_elf::STT_FUNC;
+use ftl_elf::Sym;
+use ftl_utils::alignment::align_down;
 use ftl_utils::alignment::align_up;
+use ftl_utils::alignment::is_aligned;
 
+use crate::address::PAddr;
+use crate::address::VAddr;
 use crate::arch;
 use crate::arch::MIN_PAGE_SIZE;
+use crate::backtrace::Symbol;
 use crate::memory::PAGE_ALLOCATOR;
 use crate::memory::PageType;
 
-pub type EntryFn = extern "Rust" fn(start_info: *const StartInfo);
+/// The maximum size of a server image in memory.
+const MAX_IMAGE_SIZE: usize = 256 * 1024 * 1024;
+
+pub type EntryFn = extern "Rust" fn(start_info: *const StartInfo) -> &'static Spec;
 
 pub struct LoadedElf {
-    pub image: *const u8,
+    /// The address where the image is mapped in the kernel space.
+    pub image_vaddr: VAddr,
+    /// The physical address of the image, to be freed when the server is
+    /// unloaded.
+    pub image_paddr: PAddr,
+    pub image_len: usize,
     pub entry_fn: EntryFn,
+    /// The symbol table in the ELF file, to symbolize backtraces.
+    pub symbols: Option<ElfSymbols>,
 }
 
 #[derive(Debug)]
 pub enum Error {
     ParseElf,
     OutOfMemory,
+    BadDynamic,
     BadRelocType,
     BadRelocOffset,
     BadRelocSize,
+    BadSymbol,
+    UndefinedSymbol,
+    /// A segment out of the image, or an empty image.
+    BadSegment,
 }
 
 pub fn load_elf(elf_file: &[u8]) -> Result<LoadedElf, Error> {
-    let elf = Elf::parse(elf_file, ftl_elf::ET_DYN).map_err(|_| Error::ParseElf)?;
+    // Files in initfs are aligned only to 4 bytes. Copy the file if needed
+    // because Elf::parse reads the headers in place.
+    let mut aligned = Vec::new();
+    let headers = if is_aligned(elf_file.as_ptr() as usize, align_of::<u64>()) {
+        elf_file
+    } else {
+        let num_words = elf_file.len().div_ceil(size_of::<u64>());
+        if aligned.try_reserve_exact(num_words).is_err() {
+            return Err(Error::OutOfMemory);
+        }
+
+        aligned.resize(num_words, 0u64);
+        let bytes =
+            unsafe { slice::from_raw_parts_mut(aligned.as_mut_ptr() as *mut u8, elf_file.len()) };
+        bytes.copy_from_slice(elf_file);
+        bytes
+    };
+
+    let elf = Elf::parse(headers, ftl_elf::ET_DYN).map_err(|_| Error::ParseElf)?;
 
     // Find the end of the image to calculate the size of the memory it needs.
-    let mut image_size = 0;
-    for phdr in elf.phdrs {
-        if phdr.p_type == PhdrType::Load as u32 {
-            image_size = image_size.max(phdr.p_vaddr + phdr.p_memsz);
+    let image_size = elf
+        .load_segments()
+        .map(|segment| segment.vaddr_range.end)
+        .max()
+        .unwrap_or(0);
+
+    if image_size == 0 || image_size > MAX_IMAGE_SIZE {
+        return Err(Error::BadSegment);
+    }
+
+    if elf.ehdr.e_entry as usize >= image_size {
+        return Err(Error::ParseElf);
+    }
+
+    // The dynamic section and the RELRO range must be in the image too.
+    for segment in elf.segments() {
+        let p_type = segment.phdr.p_type;
+        if (p_type == PhdrType::Dynamic as u32 || p_type == PhdrType::GnuRelro as u32)
+            && segment.vaddr_range.end > image_size
+        {
+            return Err(Error::BadSegment);
         }
     }
 
-    let image_size = align_up(image_size as usize, MIN_PAGE_SIZE);
+    let image_size = align_up(image_size, MIN_PAGE_SIZE);
     let image_paddr = PAGE_ALLOCATOR
         .alloc(image_size, PageType::Zeroed)
         .ok_or(Error::OutOfMemory)?;
 
+    let image_vaddr = match arch::map_server_image(image_paddr, image_size) {
+        Ok(vaddr) => vaddr,
+        Err(_) => {
+            PAGE_ALLOCATOR.free(image_paddr, image_size);
+            return Err(Error::OutOfMemory);
+        }
+    };
+
+    // Write the image through the direct map: the server image mapping
+    // becomes read-only in part.
     let image_ptr: *mut u8 = arch::paddr2vaddr(image_paddr).as_mut_ptr();
     let image = unsafe { slice::from_raw_parts_mut(image_ptr, image_size) };
 
-    // Load the segments into the allocated memory.
-    for phdr in elf.phdrs {
-        if phdr.p_type != PhdrType::Load as u32 {
-            continue;
+    if let Err(err) = load_image(&elf, image, image_vaddr) {
+        unload_elf(image_vaddr, image_paddr, image_size);
+        return Err(err);
+    }
+
+    protect_image(&elf, image_vaddr, image_size);
+
+    let entry_fn = unsafe {
+        let entry_ptr = image_vaddr.as_ptr::<u8>().add(elf.ehdr.e_entry as usize);
+        core::mem::transmute::<*const u8, EntryFn>(entry_ptr)
+    };
+
+    Ok(LoadedElf {
+        image_vaddr,
+        image_paddr,
+        image_len: image_size,
+        entry_fn,
+        symbols: ElfSymbols::find(&elf),
+    })
+}
+
+/// Unmaps and frees an image loaded by [`load_elf`].
+pub fn unload_elf(image_vaddr: VAddr, image_paddr: PAddr, image_len: usize) {
+    arch::unmap_server_image(image_vaddr, image_len);
+    PAGE_ALLOCATOR.free(image_paddr, image_len);
+}
+
+/// Returns `count` elements of `T` at `offset` in the image.
+fn image_slice<T>(image: &[u8], offset: usize, count: usize) -> Result<&[T], Error> {
+    let len = count.checked_mul(size_of::<T>()).ok_or(Error::BadDynamic)?;
+    let end = offset.checked_add(len).ok_or(Error::BadDynamic)?;
+    if end > image.len() || !(image.as_ptr() as usize + offset).is_multiple_of(align_of::<T>()) {
+        return Err(Error::BadDynamic);
+    }
+
+    // SAFETY: The range is in the image and aligned.
+    Ok(unsafe { slice::from_raw_parts(image.as_ptr().add(offset) as *const T, count) })
+}
+
+/// The dynamic symbol table (`.dynsym` and `.dynstr`).
+struct SymbolTable<'a> {
+    symbols: &'a [Sym],
+    strtab: &'a [u8],
+}
+
+impl<'a> SymbolTable<'a> {
+    fn name(&self, sym: &Sym) -> Result<&'a [u8], Error> {
+        let start = sym.st_name as usize;
+        let rest = self.strtab.get(start..).ok_or(Error::BadSymbol)?;
+        let len = rest.iter().position(|&b| b == 0).ok_or(Error::BadSymbol)?;
+        Ok(&rest[..len])
+    }
+
+    /// Returns the offset of a symbol in the image. Undefined symbols are
+    /// resolved by name against the defined symbols in the same table.
+    fn resolve(&self, index: usize) -> Result<Option<u64>, Error> {
+        let sym = self.symbols.get(index).ok_or(Error::BadSymbol)?;
+        if sym.is_defined() {
+            return Ok(Some(sym.st_value));
         }
 
-        // Copy the file contents to the allocated memory.
-        let src_off = phdr.p_offset as usize;
-        let dst_off = phdr.p_vaddr as usize;
-        let copy_len = phdr.p_filesz as usize;
-        let src = &elf_file[src_off..src_off + copy_len];
-        let dst = &mut image[dst_off..dst_off + copy_len];
-        dst.copy_from_slice(src);
+        let name = self.name(sym)?;
+        for other in self.symbols {
+            if other.is_defined() && other.binding() != STB_LOCAL && self.name(other)? == name {
+                return Ok(Some(other.st_value));
+            }
+        }
 
-        // Zero the remaining memory.
-        let zeroed_off = dst_off + copy_len;
-        let zeroed_len = phdr.p_memsz as usize - copy_len;
-        if zeroed_len > 0 {
-            let zeroed_range = (zeroed_off)..(zeroed_off + zeroed_len);
-            image[zeroed_range].fill(0);
+        if sym.binding() == STB_WEAK {
+            // An undefined weak symbol is resolved to zero.
+            return Ok(None);
         }
+
+        warn!(
+            "undefined symbol: {}",
+            core::str::from_utf8(name).unwrap_or("(non-UTF-8)")
+        );
+        Err(Error::UndefinedSymbol)
     }
+}
 
-    // Find the relocation table.
-    let mut rela_addr = 0;
-    let mut rela_size = 0;
-    for phdr in elf.phdrs {
-        if phdr.p_type != PhdrType::Dynamic as u32 {
-            continue;
+/// Returns the number of symbols from the `DT_HASH` or `DT_GNU_HASH` table.
+fn count_symbols(
+    image: &[u8],
+    hash: Option<usize>,
+    gnu_hash: Option<usize>,
+) -> Result<usize, Error> {
+    if let Some(hash) = hash {
+        // nbucket, nchain, ... where nchain equals the number of symbols.
+        let header = image_slice::<u32>(image, hash, 2)?;
+        return Ok(header[1] as usize);
+    }
+
+    let Some(gnu_hash) = gnu_hash else {
+        return Ok(0);
+    };
+
+    // nbuckets, symoffset, bloom_size, bloom_shift, bloom[], buckets[], chains[]
+    let header = image_slice::<u32>(image, gnu_hash, 4)?;
+    let nbuckets = header[0] as usize;
+    let symoffset = header[1] as usize;
+    let bloom_size = header[2] as usize;
+    let buckets_off = gnu_hash + 16 + bloom_size * size_of::<u64>();
+    let buckets = image_slice::<u32>(image, buckets_off, nbuckets)?;
+
+    // The last symbol is in the chain of the largest bucket.
+    let Some(&max_bucket) = buckets.iter().max() else {
+        return Ok(symoffset);
+    };
+
+    if (max_bucket as usize) < symoffset {
+        return Ok(symoffset);
+    }
+
+    let chains_off = buckets_off + nbuckets * size_of::<u32>();
+    let mut index = max_bucket as usize;
+    loop {
+        let chain = image_slice::<u32>(image, chains_off + (index - symoffset) * 4, 1)?[0];
+        if chain & 1 != 0 {
+            return Ok(index + 1);
+        }
+
+        index += 1;
+    }
+}
+
+/// Computes the value to be written by a relocation.
+#[cfg(target_arch = "x86_64")]
+fn relocation_value(rela: &Rela, base: u64, symbols: &SymbolTable) -> Result<Option<u64>, Error> {
+    let symbol = || -> Result<u64, Error> {
+        match symbols.resolve(rela.r_sym() as usize)? {
+            Some(offset) => Ok(base.wrapping_add(offset)),
+            None => Ok(0),
         }
+    };
+
+    let value = match rela.r_type() {
+        R_X86_64_NONE => return Ok(None),
+        R_X86_64_RELATIVE => base.wrapping_add(rela.r_addend as u64),
+        R_X86_64_64 => symbol()?.wrapping_add(rela.r_addend as u64),
+        R_X86_64_GLOB_DAT | R_X86_64_JUMP_SLOT => symbol()?,
+        _ => return Err(Error::BadRelocType),
+    };
 
-        let dynamic = unsafe {
-            slice::from_raw_parts(
-                image.as_ptr().add(phdr.p_vaddr as usize) as *const Dyn,
-                phdr.p_memsz as usize / size_of::<Dyn>(),
-            )
+    Ok(Some(value))
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn relocation_value(
+    _rela: &Rela,
+    _base: u64,
+    _symbols: &SymbolTable,
+) -> Result<Option<u64>, Error> {
+    Err(Error::BadRelocType)
+}
+
+fn apply_relocations(
+    image: &mut [u8],
+    relocations: &[Rela],
+    base: u64,
+    symbols: &SymbolTable,
+) -> Result<(), Error> {
+    for rela in relocations {
+        let Some(value) = relocation_value(rela, base, symbols)? else {
+            continue;
         };
 
-        for entry in dynamic {
-            match entry.d_tag {
-                DT_NULL => break,
-                DT_RELA => rela_addr = entry.d_val as usize,
-                DT_RELASZ => rela_size = entry.d_val as usize,
-                _ => {}
-            }
+        let target_off = rela.r_offset as usize;
+        let target_end = match target_off.checked_add(size_of::<u64>()) {
+            Some(end) if end <= image.len() => end,
+            _ => return Err(Error::BadRelocOffset),
+        };
+
+        image[target_off..target_end].copy_from_slice(&value.to_le_bytes());
+    }
+
+    Ok(())
+}
+
+/// Copies the segments into `image`, and applies relocations for the image
+/// mapped at `image_vaddr`.
+fn load_image(elf: &Elf, image: &mut [u8], image_vaddr: VAddr) -> Result<(), Error> {
+    // Load the segments into the allocated memory. The memory ranges have
+    // been checked in load_elf.
+    for segment in elf.load_segments() {
+        let (copied, zeroed) = image[segment.vaddr_range].split_at_mut(segment.data.len());
+        copied.copy_from_slice(segment.data);
+
+        // Zero the remaining memory.
+        zeroed.fill(0);
+    }
+
+    // Read the dynamic section.
+    let mut rela = (0, 0);
+    let mut jmprel = (0, 0);
+    let mut symtab = None;
+    let mut strtab = None;
+    let mut strsz = 0;
+    let mut hash = None;
+    let mut gnu_hash = None;
+    for entry in elf.dynamic() {
+        let value = entry.d_val as usize;
+        match entry.d_tag {
+            DT_RELA => rela.0 = value,
+            DT_RELASZ => rela.1 = value,
+            DT_RELAENT if value != size_of::<Rela>() => return Err(Error::BadRelocSize),
+            DT_JMPREL => jmprel.0 = value,
+            DT_PLTRELSZ => jmprel.1 = value,
+            DT_PLTREL if entry.d_val != DT_RELA as u64 => return Err(Error::BadRelocType),
+            DT_SYMTAB => symtab = Some(value),
+            DT_SYMENT if value != size_of::<Sym>() => return Err(Error::BadDynamic),
+            DT_STRTAB => strtab = Some(value),
+            DT_STRSZ => strsz = value,
+            DT_HASH => hash = Some(value),
+            DT_GNU_HASH => gnu_hash = Some(value),
+            _ => {}
         }
     }
 
+    // Copy the symbol table to apply relocations in `image`: it's usually in
+    // a read-only segment, which relocations don't touch.
+    let num_symbols = count_symbols(image, hash, gnu_hash)?;
+    let mut symbols = Vec::new();
+    let mut strings = Vec::new();
+    if let (Some(symtab), Some(strtab)) = (symtab, strtab) {
+        if symbols.try_reserve_exact(num_symbols).is_err()
+            || strings.try_reserve_exact(strsz).is_err()
+        {
+            return Err(Error::OutOfMemory);
+        }
+
+        symbols.extend_from_slice(image_slice::<Sym>(image, symtab, num_symbols)?);
+        strings.extend_from_slice(image_slice::<u8>(image, strtab, strsz)?);
+    }
+
+    let symbols = SymbolTable {
+        symbols: &symbols,
+        strtab: &strings,
+    };
+
     // Apply relocations.
-    if rela_addr != 0 && rela_size > 0 {
-        if rela_size % size_of::<Rela>() != 0 {
+    let base = image_vaddr.as_usize() as u64;
+    for (addr, size) in [rela, jmprel] {
+        if addr == 0 || size == 0 {
+            continue;
+        }
+
+        if size % size_of::<Rela>() != 0 {
             return Err(Error::BadRelocSize);
         }
 
-        let relocations = unsafe {
-            slice::from_raw_parts(
-                image.as_ptr().add(rela_addr) as *const Rela,
-                rela_size / size_of::<Rela>(),
-            )
-        };
+        let mut relocations = Vec::new();
+        let count = size / size_of::<Rela>();
+        if relocations.try_reserve_exact(count).is_err() {
+            return Err(Error::OutOfMemory);
+        }
+
+        relocations.extend_from_slice(image_slice::<Rela>(image, addr, count)?);
+        apply_relocations(image, &relocations, base, &symbols)?;
+    }
 
-        for rela in relocations {
-            let target_off = rela.r_offset as usize;
-            if rela.r_sym() != 0 {
-                return Err(Error::BadRelocType);
+    Ok(())
+}
+
+/// Applies the segment permissions to the image mapping, and makes the
+/// `PT_GNU_RELRO` range read-only.
+fn protect_image(elf: &Elf, image_vaddr: VAddr, image_len: usize) {
+    for page_off in (0..image_len).step_by(MIN_PAGE_SIZE) {
+        let page_end = page_off + MIN_PAGE_SIZE;
+        let mut writable = false;
+        let mut executable = false;
+        for segment in elf.load_segments() {
+            let Range { start, end } = segment.vaddr_range;
+            if start < page_end && page_off < end {
+                writable |= segment.phdr.p_flags & PF_W != 0;
+                executable |= segment.phdr.p_flags & PF_X != 0;
             }
+        }
+
+        let mut attrs = PageAttrs::READ;
+        if writable {
+            attrs = attrs | PageAttrs::WRITE;
+        }
+        if executable {
+            attrs = attrs | PageAttrs::EXEC;
+        }
+
+        let vaddr = VAddr::new(image_vaddr.as_usize() + page_off);
+        arch::protect_server_image(vaddr, MIN_PAGE_SIZE, attrs);
+    }
+
+    for segment in elf.segments() {
+        if segment.phdr.p_type != PhdrType::GnuRelro as u32 {
+            continue;
+        }
+
+        // Like ld.so, leave the partial page at the end writable.
+        let start = align_down(segment.vaddr_range.start, MIN_PAGE_SIZE);
+        let end = align_down(segment.vaddr_range.end, MIN_PAGE_SIZE);
+        if start < end {
+            let vaddr = VAddr::new(image_vaddr.as_usize() + start);
+            arch::protect_server_image(vaddr, end - start, PageAttrs::READ);
+        }
+    }
+}
 
-            #[cfg(target_arch = "x86_64")]
-            if rela.r_type() != ftl_elf::R_X86_64_RELATIVE {
-                return Err(Error::BadRelocType);
+/// The location of the symbol table in an ELF file.
+pub struct ElfSymbols {
+    symtab: Range<usize>,
+    strtab: Range<usize>,
+}
+
+impl ElfSymbols {
+    /// Looks for `.symtab`, or `.dynsym` if the file is stripped.
+    fn find(elf: &Elf) -> Option<Self> {
+        let symtab = elf
+            .shdrs
+            .iter()
+            .find(|shdr| shdr.sh_type == SHT_SYMTAB)
+            .or_else(|| elf.shdrs.iter().find(|shdr| shdr.sh_type == SHT_DYNSYM))?;
+        let strtab = elf.shdrs.get(symtab.sh_link as usize)?;
+
+        // The ranges are validated by Elf::parse.
+        Some(Self {
+            symtab: symtab.file_range()?,
+            strtab: strtab.file_range()?,
+        })
+    }
+
+    /// Returns the function containing `offset` in the image.
+    pub fn lookup<'a>(&self, elf_file: &'a [u8], offset: usize) -> Option<Symbol<'a>> {
+        let symbols = ftl_elf::SymbolTable::new(
+            elf_file.get(self.symtab.clone())?,
+            elf_file.get(self.strtab.clone())?,
+        );
+
+        let mut found: Option<Sym> = None;
+        for sym in symbols.iter() {
+            let start = sym.st_value as usize;
+            let size = sym.st_size as usize;
+            if sym.sym_type() != STT_FUNC
+                || !sym.is_defined()
+                || offset < start
+                || (size > 0 && start.checked_add(size).is_none_or(|end| offset >= end))
+            {
+                continue;
             }
 
-            let target_end = match target_off.checked_add(size_of::<u64>()) {
-                Some(end) if end <= image.len() => end,
-                _ => return Err(Error::BadRelocOffset),
+            // Prefer the closest one if there are symbols without sizes.
+            if found.is_none_or(|found| found.st_value < sym.st_value) {
+                found = Some(sym);
+            }
+        }
+
+        let sym = found?;
+        Some(Symbol {
+            name: core::str::from_utf8(symbols.name(&sym)?).ok()?,
+            offset: offset - sym.st_value as usize,
+        })
+    }
+}
+
+// Relocations are implemented only for x86_64.
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+    use ftl_elf::DT_NULL;
+    use ftl_elf::Dyn;
+    use ftl_elf::Ehdr;
+    use ftl_elf::PF_R;
+    use ftl_elf::Phdr;
+    use ftl_elf::STB_GLOBAL;
+
+    use super::*;
+
+    const EM_X86_64: u16 = 62;
+    /// The offset of the word patched by the relocation.
+    const RELOC_TARGET: usize = 0x100;
+    const RELOC_ADDEND: i64 = 0x1234;
+    const ENTRY: u64 = 0x10;
+    /// The size of the image: the rest of the file is `.bss`.
+    const MEMSZ: usize = 0x2000;
+
+    fn push<T>(bytes: &mut Vec<u8>, value: &T) {
+        // SAFETY: `T` is a plain old data type.
+        let value =
+            unsafe { slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+        bytes.extend_from_slice(value);
+    }
+
+    /// Builds a shared object with a `PT_LOAD` segment covering the whole
+    /// file plus `.bss`, and a `PT_DYNAMIC` segment with a relocation at
+    /// `reloc_offset`.
+    fn build(reloc_offset: u64) -> Vec<u64> {
+        const NUM_PHDRS: usize = 2;
+        let dynamic_offset = size_of::<Ehdr>() + NUM_PHDRS * size_of::<Phdr>();
+        let dynamic_len = 4 * size_of::<Dyn>();
+        let rela_offset = dynamic_offset + dynamic_len;
+        let file_len = RELOC_TARGET + size_of::<u64>();
+
+        let mut e_ident = [0; 16];
+        e_ident[..4].copy_from_slice(b"\x7fELF");
+        let ehdr = Ehdr {
+            e_ident,
+            e_type: ftl_elf::ET_DYN,
+            e_machine: EM_X86_64,
+            e_version: 1,
+            e_entry: ENTRY,
+            e_phoff: size_of::<Ehdr>() as u64,
+            e_shoff: 0,
+            e_flags: 0,
+            e_ehsize: size_of::<Ehdr>() as u16,
+            e_phentsize: size_of::<Phdr>() as u16,
+            e_phnum: NUM_PHDRS as u16,
+            e_shentsize: 0,
+            e_shnum: 0,
+            e_shstrndx: 0,
+        };
+        let load = Phdr {
+            p_type: PhdrType::Load as u32,
+            p_flags: PF_R | PF_W | PF_X,
+            p_offset: 0,
+            p_vaddr: 0,
+            p_paddr: 0,
+            p_filesz: file_len as u64,
+            p_memsz: MEMSZ as u64,
+            p_align: MIN_PAGE_SIZE as u64,
+        };
+        let dynamic = Phdr {
+            p_type: PhdrType::Dynamic as u32,
+            p_flags: PF_R | PF_W,
+            p_offset: dynamic_offset as u64,
+            p_vaddr: dynamic_offset as u64,
+            p_paddr: 0,
+            p_filesz: dynamic_len as u64,
+            p_memsz: dynamic_len as u64,
+            p_align: 8,
+        };
+
+        let mut bytes = Vec::new();
+        push(&mut bytes, &ehdr);
+        push(&mut bytes, &load);
+        push(&mut bytes, &dynamic);
+        for (d_tag, d_val) in [
+            (DT_RELA, rela_offset),
+            (DT_RELASZ, size_of::<Rela>()),
+            (DT_RELAENT, size_of::<Rela>()),
+            (DT_NULL, 0),
+        ] {
+            push(
+                &mut bytes,
+                &Dyn {
+                    d_tag,
+                    d_val: d_val as u64,
+                },
+            );
+        }
+        push(
+            &mut bytes,
+            &Rela {
+                r_offset: reloc_offset,
+                r_info: R_X86_64_RELATIVE as u64,
+                r_addend: RELOC_ADDEND,
+            },
+        );
+
+        // Garbage to be overwritten by the relocation.
+        bytes.resize(file_len, 0xaa);
+
+        let mut words = alloc::vec![0u64; bytes.len().div_ceil(8)];
+        // SAFETY: The buffer is at least `bytes.len()` long.
+        unsafe {
+            slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, bytes.len())
+                .copy_from_slice(&bytes);
+        }
+        words
+    }
+
+    /// Offsets in the image built by [`build_with_symbols`]. The file is
+    /// mapped as is: offsets in the file and the image are the same.
+    mod layout {
+        pub const SYMTAB: usize = 0x200;
+        pub const STRTAB: usize = 0x300;
+        pub const HASH: usize = 0x400;
+        pub const RELA: usize = 0x500;
+        pub const JMPREL: usize = 0x600;
+        /// A function in `.text`, which is the entry point.
+        pub const FUNC: usize = 0x800;
+        /// The start of the writable segment, and `.dynamic`.
+        pub const DATA: usize = 0x1000;
+        /// GOT entries in the RELRO range.
+        pub const GOT_DATA: usize = 0x1100;
+        pub const GOT_FUNC: usize = 0x1108;
+        pub const GOT_WEAK: usize = 0x1110;
+        /// The end of the RELRO range: the page containing it stays
+        /// writable.
+        pub const RELRO_END: usize = 0x2010;
+        /// A `.got.plt` entry after the RELRO range.
+        pub const PLT_FUNC: usize = 0x2008;
+        /// A variable in `.bss`.
+        pub const BSS_DATA: usize = 0x2100;
+        pub const FILE_LEN: usize = RELRO_END;
+        pub const IMAGE_LEN: usize = 0x3000;
+    }
+
+    /// The hash table to count the symbols from.
+    #[derive(Clone, Copy)]
+    enum HashKind {
+        Sysv,
+        Gnu,
+    }
+
+    const DATA_ADDEND: i64 = 8;
+    const STRINGS: &[u8] = b"\0hidden\0func\0weak_missing\0data\0";
+
+    fn put<T>(bytes: &mut [u8], offset: usize, value: &T) {
+        let mut buf = Vec::new();
+        push(&mut buf, value);
+        bytes[offset..offset + buf.len()].copy_from_slice(&buf);
+    }
+
+    fn sym(name: &[u8], binding: u8, shndx: u16, value: usize) -> Sym {
+        let st_name = if name.is_empty() {
+            0
+        } else {
+            STRINGS.windows(name.len()).position(|w| w == name).unwrap()
+        };
+
+        Sym {
+            st_name: st_name as u32,
+            st_info: (binding << 4) | STT_FUNC,
+            st_other: 0,
+            st_shndx: shndx,
+            st_value: value as u64,
+            st_size: 0,
+        }
+    }
+
+    fn rela(offset: usize, sym: u32, r_type: u32, addend: i64) -> Rela {
+        Rela {
+            r_offset: offset as u64,
+            r_info: ((sym as u64) << 32) | r_type as u64,
+            r_addend: addend,
+        }
+    }
+
+    /// Builds a shared object with a read-only executable segment, and a
+    /// writable segment whose first page is RELRO. The GOT entries refer to
+    /// symbols in `.dynsym`: `weak_missing` is an undefined weak symbol
+    /// unless `missing_is_weak` is false.
+    fn build_with_symbols(hash: HashKind, missing_is_weak: bool) -> Vec<u64> {
+        use layout::*;
+
+        let mut e_ident = [0; 16];
+        e_ident[..4].copy_from_slice(b"\x7fELF");
+        let phdrs = [
+            Phdr {
+                p_type: PhdrType::Load as u32,
+                p_flags: PF_R | PF_X,
+                p_offset: 0,
+                p_vaddr: 0,
+                p_paddr: 0,
+                p_filesz: DATA as u64,
+                p_memsz: DATA as u64,
+                p_align: MIN_PAGE_SIZE as u64,
+            },
+            Phdr {
+                p_type: PhdrType::Load as u32,
+                p_flags: PF_R | PF_W,
+                p_offset: DATA as u64,
+                p_vaddr: DATA as u64,
+                p_paddr: 0,
+                p_filesz: (FILE_LEN - DATA) as u64,
+                p_memsz: (IMAGE_LEN - DATA) as u64,
+                p_align: MIN_PAGE_SIZE as u64,
+            },
+            Phdr {
+                p_type: PhdrType::Dynamic as u32,
+                p_flags: PF_R | PF_W,
+                p_offset: DATA as u64,
+                p_vaddr: DATA as u64,
+                p_paddr: 0,
+                p_filesz: (GOT_DATA - DATA) as u64,
+                p_memsz: (GOT_DATA - DATA) as u64,
+                p_align: 8,
+            },
+            Phdr {
+                p_type: PhdrType::GnuRelro as u32,
+                p_flags: PF_R,
+                p_offset: DATA as u64,
+                p_vaddr: DATA as u64,
+                p_paddr: 0,
+                p_filesz: (RELRO_END - DATA) as u64,
+                p_memsz: (RELRO_END - DATA) as u64,
+                p_align: 1,
+            },
+        ];
+        let ehdr = Ehdr {
+            e_ident,
+            e_type: ftl_elf::ET_DYN,
+            e_machine: EM_X86_64,
+            e_version: 1,
+            e_entry: FUNC as u64,
+            e_phoff: size_of::<Ehdr>() as u64,
+            e_shoff: 0,
+            e_flags: 0,
+            e_ehsize: size_of::<Ehdr>() as u16,
+            e_phentsize: size_of::<Phdr>() as u16,
+            e_phnum: phdrs.len() as u16,
+            e_shentsize: 0,
+            e_shnum: 0,
+            e_shstrndx: 0,
+        };
+
+        let mut bytes = alloc::vec![0u8; FILE_LEN];
+        put(&mut bytes, 0, &ehdr);
+        for (i, phdr) in phdrs.iter().enumerate() {
+            put(&mut bytes, size_of::<Ehdr>() + i * size_of::<Phdr>(), phdr);
+        }
+
+        // Undefined symbols first, and then defined ones, which DT_GNU_HASH
+        // covers.
+        let missing_binding = if missing_is_weak {
+            STB_WEAK
+        } else {
+            STB_GLOBAL
+        };
+        let symbols = [
+            sym(b"", STB_LOCAL, 0, 0),
+            sym(b"hidden", STB_LOCAL, 1, FUNC),
+            sym(b"func", STB_GLOBAL, 0, 0),
+            sym(b"weak_missing", missing_binding, 0, 0),
+            sym(b"func", STB_GLOBAL, 1, FUNC),
+            sym(b"data", STB_GLOBAL, 2, BSS_DATA),
+        ];
+        for (i, sym) in symbols.iter().enumerate() {
+            put(&mut bytes, SYMTAB + i * size_of::<Sym>(), sym);
+        }
+        bytes[STRTAB..STRTAB + STRINGS.len()].copy_from_slice(STRINGS);
+
+        let hash_table: &[u32] = match hash {
+            // nbucket, nchain, buckets, chains
+            HashKind::Sysv => &[1, symbols.len() as u32, 0, 0, 0, 0, 0, 0, 0],
+            // nbuckets, symoffset, bloom_size, bloom_shift, bloom (u64),
+            // buckets, chains (the last one ends with 1).
+            HashKind::Gnu => &[1, 4, 1, 0, 0, 0, 4, 2, 3],
+        };
+        for (i, word) in hash_table.iter().enumerate() {
+            put(&mut bytes, HASH + i * 4, word);
+        }
+
+        let relocations = [
+            rela(GOT_DATA, 5, R_X86_64_64, DATA_ADDEND),
+            // The undefined `func` is resolved to the defined one.
+            rela(GOT_FUNC, 2, R_X86_64_GLOB_DAT, 0),
+            rela(GOT_WEAK, 3, R_X86_64_GLOB_DAT, 0),
+        ];
+        for (i, rela) in relocations.iter().enumerate() {
+            put(&mut bytes, RELA + i * size_of::<Rela>(), rela);
+        }
+        put(
+            &mut bytes,
+            JMPREL,
+            &rela(PLT_FUNC, 4, R_X86_64_JUMP_SLOT, 0),
+        );
+
+        let hash_tag = match hash {
+            HashKind::Sysv => DT_HASH,
+            HashKind::Gnu => DT_GNU_HASH,
+        };
+        let dynamic = [
+            (DT_SYMTAB, SYMTAB),
+            (DT_SYMENT, size_of::<Sym>()),
+            (DT_STRTAB, STRTAB),
+            (DT_STRSZ, STRINGS.len()),
+            (hash_tag, HASH),
+            (DT_RELA, RELA),
+            (DT_RELASZ, relocations.len() * size_of::<Rela>()),
+            (DT_RELAENT, size_of::<Rela>()),
+            (DT_JMPREL, JMPREL),
+            (DT_PLTRELSZ, size_of::<Rela>()),
+            (DT_PLTREL, DT_RELA as usize),
+            (DT_NULL, 0),
+        ];
+        assert!(dynamic.len() * size_of::<Dyn>() <= GOT_DATA - DATA);
+        for (i, (d_tag, d_val)) in dynamic.into_iter().enumerate() {
+            let entry = Dyn {
+                d_tag,
+                d_val: d_val as u64,
             };
+            put(&mut bytes, DATA + i * size_of::<Dyn>(), &entry);
+        }
 
-            let base = image.as_ptr() as u64;
-            let value = base.wrapping_add(rela.r_addend as u64);
-            image[target_off..target_end].copy_from_slice(&value.to_le_bytes());
+        // Garbage to be overwritten by the relocations.
+        for offset in [GOT_DATA, GOT_FUNC, GOT_WEAK, PLT_FUNC] {
+            put(&mut bytes, offset, &0xaaaa_aaaa_aaaa_aaaau64);
         }
+
+        let mut words = alloc::vec![0u64; bytes.len().div_ceil(8)];
+        // SAFETY: The buffer is at least `bytes.len()` long.
+        unsafe {
+            slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, bytes.len())
+                .copy_from_slice(&bytes);
+        }
+        words
     }
 
-    let entry_fn = unsafe {
-        let entry_ptr = image.as_ptr().add(elf.ehdr.e_entry as usize);
-        core::mem::transmute::<*const u8, extern "Rust" fn(start_info: *const StartInfo)>(entry_ptr)
-    };
+    fn read_word(loaded: &LoadedElf, offset: usize) -> u64 {
+        let image =
+            unsafe { slice::from_raw_parts(loaded.image_vaddr.as_ptr::<u8>(), loaded.image_len) };
+        u64::from_le_bytes(image[offset..offset + 8].try_into().unwrap())
+    }
 
-    Ok(LoadedElf {
-        image: image.as_ptr(),
-        entry_fn,
-    })
+    /// Returns the attributes of the page containing `offset`.
+    fn page_attrs(loaded: &LoadedElf, offset: usize) -> PageAttrs {
+        let page = loaded.image_vaddr.as_usize() + align_down(offset, MIN_PAGE_SIZE);
+        arch::server_image_attrs(VAddr::new(page)).unwrap()
+    }
+
+    fn as_bytes(words: &[u64]) -> &[u8] {
+        unsafe { slice::from_raw_parts(words.as_ptr() as *const u8, words.len() * 8) }
+    }
+
+    #[test]
+    fn load_and_relocate() {
+        arch::init_for_test();
+        let elf_file = build(RELOC_TARGET as u64);
+        let loaded = load_elf(as_bytes(&elf_file)).unwrap();
+        assert_eq!(loaded.image_len, MEMSZ);
+        assert_eq!(
+            loaded.entry_fn as usize,
+            loaded.image_vaddr.as_usize() + ENTRY as usize
+        );
+        assert!(loaded.symbols.is_none());
+
+        let image =
+            unsafe { slice::from_raw_parts(loaded.image_vaddr.as_ptr::<u8>(), loaded.image_len) };
+        let word = &image[RELOC_TARGET..RELOC_TARGET + size_of::<u64>()];
+        assert_eq!(
+            u64::from_le_bytes(word.try_into().unwrap()),
+            loaded.image_vaddr.as_usize() as u64 + RELOC_ADDEND as u64
+        );
+
+        // .bss is zeroed.
+        assert!(
+            image[RELOC_TARGET + size_of::<u64>()..]
+                .iter()
+                .all(|&b| b == 0)
+        );
+
+        unload_elf(loaded.image_vaddr, loaded.image_paddr, loaded.image_len);
+    }
+
+    #[test]
+    fn reject_relocation_out_of_image() {
+        arch::init_for_test();
+        let elf_file = build((MEMSZ - 4) as u64);
+        assert!(matches!(
+            load_elf(as_bytes(&elf_file)),
+            Err(Error::BadRelocOffset)
+        ));
+    }
+
+    #[test]
+    fn reject_unaligned_and_truncated_files() {
+        arch::init_for_test();
+        let elf_file = build(RELOC_TARGET as u64);
+
+        // An unaligned copy is accepted, like files in initfs.
+        let bytes = as_bytes(&elf_file);
+        let mut unaligned = alloc::vec![0u8; bytes.len() + 4];
+        unaligned[4..].copy_from_slice(bytes);
+        let loaded = load_elf(&unaligned[4..]).unwrap();
+        unload_elf(loaded.image_vaddr, loaded.image_paddr, loaded.image_len);
+
+        assert!(matches!(
+            load_elf(&bytes[..size_of::<Ehdr>()]),
+            Err(Error::ParseElf)
+        ));
+    }
+
+    #[test]
+    fn resolve_symbols() {
+        arch::init_for_test();
+        for hash in [HashKind::Sysv, HashKind::Gnu] {
+            let elf_file = build_with_symbols(hash, true);
+            let loaded = load_elf(as_bytes(&elf_file)).unwrap();
+            let base = loaded.image_vaddr.as_usize() as u64;
+            assert_eq!(loaded.image_len, layout::IMAGE_LEN);
+
+            assert_eq!(
+                read_word(&loaded, layout::GOT_DATA),
+                base + layout::BSS_DATA as u64 + DATA_ADDEND as u64
+            );
+            assert_eq!(
+                read_word(&loaded, layout::GOT_FUNC),
+                base + layout::FUNC as u64
+            );
+            assert_eq!(
+                read_word(&loaded, layout::PLT_FUNC),
+                base + layout::FUNC as u64
+            );
+
+            // An undefined weak symbol is resolved to zero.
+            assert_eq!(read_word(&loaded, layout::GOT_WEAK), 0);
+
+            unload_elf(loaded.image_vaddr, loaded.image_paddr, loaded.image_len);
+        }
+    }
+
+    #[test]
+    fn reject_undefined_symbol() {
+        arch::init_for_test();
+        let elf_file = build_with_symbols(HashKind::Gnu, false);
+        assert!(matches!(
+            load_elf(as_bytes(&elf_file)),
+            Err(Error::UndefinedSymbol)
+        ));
+    }
+
+    #[test]
+    fn protect_segments() {
+        arch::init_for_test();
+        let elf_file = build_with_symbols(HashKind::Sysv, true);
+        let loaded = load_elf(as_bytes(&elf_file)).unwrap();
+
+        // .text is executable and not writable.
+        let text = page_attrs(&loaded, 0);
+        assert!(text.contains(PageAttrs::READ | PageAttrs::EXEC));
+        assert!(!text.contains(PageAttrs::WRITE));
+
+        // The RELRO range is read-only after relocations, except the last
+        // partial page.
+        let relro = page_attrs(&loaded, layout::DATA);
+        assert!(relro.contains(PageAttrs::READ));
+        assert!(!relro.contains(PageAttrs::WRITE));
+        assert!(!relro.contains(PageAttrs::EXEC));
+
+        let data = page_attrs(&loaded, layout::RELRO_END - 1);
+        assert!(data.contains(PageAttrs::READ | PageAttrs::WRITE));
+        assert!(!data.contains(PageAttrs::EXEC));
+
+        unload_elf(loaded.image_vaddr, loaded.image_paddr, loaded.image_len);
+        assert!(arch::server_image_attrs(loaded.image_vaddr).is_none());
+    }
+
+    #[test]
+    fn count_symbols_from_hash_tables() {
+        let hash_table = |words: &[u32]| -> Vec<u64> {
+            words
+                .chunks(2)
+                .map(|pair| pair[0] as u64 | (pair.get(1).copied().unwrap_or(0) as u64) << 32)
+                .collect()
+        };
+
+        // DT_HASH: nchain is the number of symbols, and is preferred over
+        // DT_GNU_HASH.
+        let image = hash_table(&[3, 7]);
+        assert_eq!(
+            count_symbols(as_bytes(&image), Some(0), Some(0)).unwrap(),
+            7
+        );
+        assert_eq!(count_symbols(as_bytes(&image), None, None).unwrap(), 0);
+
+        // DT_GNU_HASH: 2 buckets starting from the symbol 5, and the chain
+        // of the last bucket ends at the symbol 8.
+        let image = hash_table(&[2, 5, 1, 0, 0, 0, 5, 7, 10, 11, 20, 31]);
+        assert_eq!(count_symbols(as_bytes(&image), None, Some(0)).unwrap(), 9);
+
+        // All buckets are empty: no symbols after `symoffset`.
+        let image = hash_table(&[2, 5, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(count_symbols(as_bytes(&image), None, Some(0)).unwrap(), 5);
+
+        // A chain running off the table.
+        let image = hash_table(&[2, 5, 1, 0, 0, 0, 7, 0, 0, 0, 0, 0]);
+        assert!(count_symbols(as_bytes(&image), None, Some(0)).is_err());
+    }
 }
@@ -0,0 +1,302 @@
+//! The kernel log buffer.
+//!
+//! Logs at all levels are recorded here, and printed on the console if
+//! they're at or above the console log level ([`crate::print::enabled`]).
+//! The console shows the recorded message, which is truncated to
+//! [`ftl_api::log::LOG_MESSAGE_LEN`] bytes.
+//!
+//! The buffer is lock-free: a writer reserves a sequence number, and writes
+//! the record into its slot guarded by a per-slot sequence lock. The first
+//! [`NUM_EARLY_RECORDS`] records are kept in a separate area which is never
+//! overwritten, for post-mortem inspection of boot failures.
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::ptr;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+use core::sync::atomic::fence;
+
+use ftl_api::error::ErrorCode;
+use ftl_api::log::LogRecord;
+use ftl_api::print::LogLevel;
+
+use crate::arch;
+use crate::server;
+use crate::service;
+
+const NUM_EARLY_RECORDS: usize = 128;
+const NUM_RING_RECORDS: usize = 1024;
+
+/// The state of a slot: 0 if empty, `seq * 2 + 1` while `seq` is being
+/// written, and `seq * 2 + 2` once it's written.
+struct Slot {
+    state: AtomicU64,
+    record: UnsafeCell<LogRecord>,
+}
+
+// SAFETY: Accesses to `record` are guarded by `state`.
+unsafe impl Sync for Slot {}
+
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            state: AtomicU64::new(0),
+            record: UnsafeCell::new(LogRecord::new(0, 0, 0, LogLevel::Trace)),
+        }
+    }
+
+    fn write(&self, record: &LogRecord) {
+        let seq = record.seq;
+        let prev = self.state.load(Ordering::Relaxed);
+        if prev > seq * 2 {
+            // A newer record has taken over the slot. Drop this one.
+            return;
+        }
+
+        if self
+            .state
+            .compare_exchange(prev, seq * 2 + 1, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            return;
+        }
+
+        fence(Ordering::Release);
+        unsafe { ptr::write_volatile(self.record.get(), *record) };
+        self.state.store(seq * 2 + 2, Ordering::Release);
+    }
+
+    /// Reads the record `seq`. Returns `None` if it's not written yet, or
+    /// has been overwritten.
+    fn read(&self, seq: u64) -> Option<LogRecord> {
+        let before = self.state.load(Ordering::Acquire);
+        if before != seq * 2 + 2 {
+            return None;
+        }
+
+        // The record may be torn by a concurrent writer. Don't interpret it,
+        // e.g. the `LogLevel` enum, until the state says it's intact.
+        let record =
+            unsafe { ptr::read_volatile(self.record.get().cast::<MaybeUninit<LogRecord>>()) };
+        fence(Ordering::Acquire);
+        let after = self.state.load(Ordering::Relaxed);
+        if before != after {
+            return None;
+        }
+
+        // SAFETY: The state hasn't changed, that is, the record is written
+        //         completely and not overwritten during the read.
+        Some(unsafe { record.assume_init() })
+    }
+}
+
+struct LogBuffer {
+    next_seq: AtomicU64,
+    early_records: [Slot; NUM_EARLY_RECORDS],
+    ring_records: [Slot; NUM_RING_RECORDS],
+}
+
+impl LogBuffer {
+    const fn new() -> Self {
+        Self {
+            next_seq: AtomicU64::new(0),
+            early_records: [const { Slot::new() }; NUM_EARLY_RECORDS],
+            ring_records: [const { Slot::new() }; NUM_RING_RECORDS],
+        }
+    }
+
+    /// Reserves the sequence number of a new record.
+    fn reserve(&self) -> u64 {
+        self.next_seq.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn slot(&self, seq: u64) -> &Slot {
+        let seq = seq as usize;
+        if seq < NUM_EARLY_RECORDS {
+            &self.early_records[seq]
+        } else {
+            &self.ring_records[(seq - NUM_EARLY_RECORDS) % NUM_RING_RECORDS]
+        }
+    }
+
+    /// Returns the oldest available record at or after `seq`.
+    fn read(&self, seq: u64) -> Option<LogRecord> {
+        let next_seq = self.next_seq.load(Ordering::Acquire);
+        let ring_start = next_seq.saturating_sub(NUM_RING_RECORDS as u64);
+        let mut seq = seq;
+        while seq < next_seq {
+            if seq >= NUM_EARLY_RECORDS as u64 && seq < ring_start {
+                // Overwritten.
+                seq = ring_start;
+                continue;
+            }
+
+            // A record may be still being written, or have been overwritten
+            // meanwhile. Skip it.
+            if let Some(record) = self.slot(seq).read(seq) {
+                return Some(record);
+            }
+
+            seq += 1;
+        }
+
+        None
+    }
+}
+
+static LOG_BUFFER: LogBuffer = LogBuffer::new();
+
+fn level_label(level: LogLevel) -> &'static str {
+    match level {
+        LogLevel::Trace => "\x1b[2mTRACE\x1b[0m ",
+        LogLevel::Info => "INFO  ",
+        LogLevel::Warn => "\x1b[33mWARN\x1b[0m  ",
+        LogLevel::Error => "\x1b[31mERROR\x1b[0m ",
+    }
+}
+
+fn record(source: &str, level: LogLevel, args: fmt::Arguments<'_>) {
+    let seq = LOG_BUFFER.reserve();
+    let cpu_id = arch::try_get_cpuvar().map_or(0, |cpuvar| cpuvar.cpu_id as u32);
+    let mut record = LogRecord::new(seq, arch::uptime_nanos(), cpu_id, level);
+    record.set_source(source);
+    fmt::write(&mut record, args).ok();
+    LOG_BUFFER.slot(seq).write(&record);
+
+    // Print the formatted message, truncated as in the buffer, instead of
+    // formatting `args` again.
+    if crate::print::enabled(level) {
+        println!(
+            "[{:<10}] {}{}",
+            source,
+            level_label(level),
+            record.message()
+        );
+    }
+}
+
+/// Records a log message from the kernel.
+pub fn log(level: LogLevel, args: fmt::Arguments<'_>) {
+    record("kernel", level, args);
+}
+
+/// Records a log message from the current server.
+pub fn log_from_server(level: LogLevel, args: fmt::Arguments<'_>) {
+    let server = server::current();
+    record(server.name(), level, args);
+}
+
+/// Returns the oldest available record at or after `seq`.
+pub fn read(seq: u64) -> Result<Option<LogRecord>, ErrorCode> {
+    service::authorize_kernel("log")?;
+    Ok(LOG_BUFFER.read(seq))
+}
+
+/// Changes the console log level.
+pub fn set_console_level(level: LogLevel) -> Result<(), ErrorCode> {
+    service::authorize_kernel("log")?;
+    crate::print::set_log_level(level);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+
+    use super::*;
+
+    fn write(buffer: &LogBuffer) -> u64 {
+        let seq = buffer.reserve();
+        let mut record = LogRecord::new(seq, seq * 10, 0, LogLevel::Info);
+        fmt::write(&mut record, format_args!("record {seq}")).unwrap();
+        buffer.slot(seq).write(&record);
+        seq
+    }
+
+    fn read_seq(buffer: &LogBuffer, seq: u64) -> Option<u64> {
+        buffer.read(seq).map(|record| record.seq)
+    }
+
+    #[test]
+    fn slot_keeps_newer_record() {
+        let slot = Slot::new();
+        let old = LogRecord::new(3, 0, 0, LogLevel::Info);
+        let new = LogRecord::new(3 + NUM_RING_RECORDS as u64, 0, 0, LogLevel::Warn);
+
+        slot.write(&old);
+        assert_eq!(slot.read(3).map(|record| record.seq), Some(3));
+
+        slot.write(&new);
+        assert!(slot.read(3).is_none());
+        assert_eq!(slot.read(new.seq).map(|record| record.seq), Some(new.seq));
+
+        // A slow writer of an older record doesn't overwrite the newer one.
+        slot.write(&old);
+        assert_eq!(slot.read(new.seq).map(|record| record.seq), Some(new.seq));
+    }
+
+    #[test]
+    fn ring_wraps_around() {
+        let buffer = Box::new(LogBuffer::new());
+        let total = (NUM_EARLY_RECORDS + 2 * NUM_RING_RECORDS + 5) as u64;
+        for _ in 0..total {
+            write(&buffer);
+        }
+
+        // Early records are never overwritten.
+        for seq in 0..NUM_EARLY_RECORDS as u64 {
+            let record = buffer.read(seq).unwrap();
+            assert_eq!(record.seq, seq);
+            assert_eq!(record.message(), alloc::format!("record {seq}"));
+        }
+
+        // The ring has the latest records.
+        let ring_start = total - NUM_RING_RECORDS as u64;
+        for seq in ring_start..total {
+            let record = buffer.read(seq).unwrap();
+            assert_eq!(record.seq, seq);
+            assert_eq!(record.timestamp, seq * 10);
+        }
+
+        assert_eq!(read_seq(&buffer, total), None);
+    }
+
+    #[test]
+    fn read_skips_overwritten_records() {
+        let buffer = Box::new(LogBuffer::new());
+        let total = (NUM_EARLY_RECORDS + NUM_RING_RECORDS + 5) as u64;
+        for _ in 0..total {
+            write(&buffer);
+        }
+
+        // Reading past the early records jumps to the oldest record in the
+        // ring.
+        let ring_start = total - NUM_RING_RECORDS as u64;
+        assert_eq!(ring_start, NUM_EARLY_RECORDS as u64 + 5);
+        for seq in NUM_EARLY_RECORDS as u64..=ring_start {
+            assert_eq!(read_seq(&buffer, seq), Some(ring_start));
+        }
+
+        // A record overwritten by a writer in the next lap of the ring after
+        // the reader has loaded `next_seq`.
+        let overwritten = ring_start;
+        let lapped = overwritten + NUM_RING_RECORDS as u64;
+        assert!(ptr::eq(buffer.slot(overwritten), buffer.slot(lapped)));
+        buffer
+            .slot(lapped)
+            .write(&LogRecord::new(lapped, 0, 0, LogLevel::Info));
+        assert_eq!(read_seq(&buffer, overwritten), Some(overwritten + 1));
+    }
+
+    #[test]
+    fn read_skips_unwritten_records() {
+        let buffer = Box::new(LogBuffer::new());
+        write(&buffer);
+        let reserved = buffer.reserve();
+        let written = write(&buffer);
+
+        assert_eq!(read_seq(&buffer, reserved), Some(written));
+        assert_eq!(read_seq(&buffer, written + 1), None);
+    }
+}
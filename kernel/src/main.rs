@@ -1,5 +1,5 @@
 #![cfg_attr(target_os = "none", no_std)]
-#![no_main]
+#![cfg_attr(not(test), no_main)]
 #![feature(coerce_unsized)]
 #![feature(unsize)]
 #![feature(unsafe_cell_access)]
@@ -13,16 +13,30 @@ mod print;
 
 mod address;
 mod arch;
+mod backtrace;
 mod boot;
+mod channel;
+mod cmdline;
+mod console;
 mod cpuvar;
+mod handle;
 mod initfs;
+mod interceptor;
+#[cfg(feature = "ktest")]
+mod ktest;
 mod loader;
+mod log;
 mod memory;
 mod panic;
+mod policy;
+mod quota;
 mod scheduler;
 mod server;
+mod service;
 mod shared_ref;
+mod signature;
 mod syscall;
 mod thread;
+mod upcall;
 mod vmarea;
 mod vmspace;
@@ -5,7 +5,9 @@ use core::ops::Range;
 
 use ftl_arrayvec::ArrayVec;
 use ftl_bump_allocator::BumpAllocator;
+use ftl_ktest_macro::ktest;
 use ftl_malloc::LinkedListAllocator;
+use ftl_utils::alignment::align_down;
 use ftl_utils::alignment::is_aligned;
 use ftl_utils::formatter::ByteSize;
 use ftl_utils::spinlock::SpinLock;
@@ -16,10 +18,12 @@ use crate::arch::MIN_PAGE_SIZE;
 use crate::boot::BootInfo;
 use crate::boot::FreeRam;
 use crate::boot::NUM_MODULES_MAX;
+use crate::cmdline;
 
 const MALLOC_CHUNK_SIZE: usize = 128 * 1024; // 128 KB
 
-#[global_allocator]
+/// The host backend uses the host's allocator instead.
+#[cfg_attr(target_os = "none", global_allocator)]
 static GLOBAL_ALLOCATOR: GlobalAllocator = GlobalAllocator::new();
 
 struct GlobalAllocator {
@@ -94,14 +98,121 @@ pub enum PageType {
     Zeroed,
 }
 
+/// The header of a freed memory block, stored at the beginning of the block.
+struct FreeBlock {
+    len: usize,
+    next: Option<PAddr>,
+}
+
+/// A singly-linked list of freed memory blocks.
+///
+/// The list doesn't allocate any memory by itself: each block holds its
+/// header in the block.
+struct FreeList {
+    head: Option<PAddr>,
+}
+
+impl FreeList {
+    const fn new() -> Self {
+        Self { head: None }
+    }
+
+    fn block(paddr: PAddr) -> *mut FreeBlock {
+        arch::paddr2vaddr(paddr).as_mut_ptr()
+    }
+
+    /// Adds a freed block, merging it with the adjacent free blocks. The list
+    /// is sorted by address to find them.
+    fn push(&mut self, paddr: PAddr, len: usize) {
+        let mut prev: Option<PAddr> = None;
+        let mut next = self.head;
+        while let Some(next_paddr) = next
+            && next_paddr < paddr
+        {
+            prev = next;
+            // SAFETY: Blocks in the list are not used by anyone.
+            next = unsafe { (*Self::block(next_paddr)).next };
+        }
+
+        let mut len = len;
+        if let Some(next_paddr) = next
+            && paddr.as_usize() + len == next_paddr.as_usize()
+        {
+            // SAFETY: Blocks in the list are not used by anyone.
+            let next_block = unsafe { &*Self::block(next_paddr) };
+            len += next_block.len;
+            next = next_block.next;
+        }
+
+        match prev {
+            Some(prev_paddr) => {
+                // SAFETY: Blocks in the list are not used by anyone.
+                let prev_block = unsafe { &mut *Self::block(prev_paddr) };
+                if prev_paddr.as_usize() + prev_block.len == paddr.as_usize() {
+                    prev_block.len += len;
+                    prev_block.next = next;
+                    return;
+                }
+
+                prev_block.next = Some(paddr);
+            }
+            None => {
+                self.head = Some(paddr);
+            }
+        }
+
+        // SAFETY: The block is no longer used by anyone.
+        unsafe {
+            Self::block(paddr).write(FreeBlock { len, next });
+        }
+    }
+
+    /// Allocates a block of `len` bytes from the first block large enough.
+    fn alloc(&mut self, len: usize) -> Option<PAddr> {
+        let mut prev: Option<PAddr> = None;
+        let mut current = self.head;
+        while let Some(paddr) = current {
+            // SAFETY: Blocks in the list are not used by anyone.
+            let block = unsafe { &mut *Self::block(paddr) };
+            if block.len == len {
+                // Remove the block from the list.
+                match prev {
+                    Some(prev) => unsafe { (*Self::block(prev)).next = block.next },
+                    None => self.head = block.next,
+                }
+
+                return Some(paddr);
+            }
+
+            if block.len > len {
+                // Split the block: take the tail part so that we don't need
+                // to update the list.
+                block.len -= len;
+                return Some(PAddr::new(paddr.as_usize() + block.len));
+            }
+
+            prev = current;
+            current = block.next;
+        }
+
+        None
+    }
+}
+
+// SAFETY: The free blocks are accessed only through the page allocator's
+//         lock.
+unsafe impl Send for FreeList {}
+
 pub struct PageAllocator {
     regions: SpinLock<ArrayVec<BumpAllocator, 8>>,
+    free_list: SpinLock<FreeList>,
 }
 
 impl PageAllocator {
     const fn new() -> Self {
         Self {
             regions: SpinLock::new(ArrayVec::new()),
+            free_list: SpinLock::new(FreeList::new()),
         }
     }
 
@@ -122,29 +233,39 @@ impl PageAllocator {
         debug_assert!(len > 0);
         debug_assert!(is_aligned(len, MIN_PAGE_SIZE));
 
-        let mut regions = self.regions.lock();
-        for region in regions.iter_mut() {
-            if let Some(addr) = region.alloc(len, MIN_PAGE_SIZE) {
-                let paddr = PAddr::new(addr);
-
-                match page_type {
-                    PageType::Dirty => {
-                        // Do nothing.
-                    }
-                    PageType::Zeroed => {
-                        let vaddr = arch::paddr2vaddr(paddr);
-                        let ptr = vaddr.as_usize() as *mut u8;
-                        unsafe {
-                            core::ptr::write_bytes(ptr, 0, len);
-                        }
-                    }
+        let paddr = self.free_list.lock().alloc(len).or_else(|| {
+            let mut regions = self.regions.lock();
+            regions
+                .iter_mut()
+                .find_map(|region| region.alloc(len, MIN_PAGE_SIZE))
+                .map(PAddr::new)
+        })?;
+
+        match page_type {
+            PageType::Dirty => {
+                // Do nothing.
+            }
+            PageType::Zeroed => {
+                let vaddr = arch::paddr2vaddr(paddr);
+                let ptr = vaddr.as_usize() as *mut u8;
+                unsafe {
+                    core::ptr::write_bytes(ptr, 0, len);
                 }
-
-                return Some(paddr);
             }
         }
 
-        None
+        Some(paddr)
+    }
+
+    /// Frees a memory block allocated by [`PageAllocator::alloc`].
+    ///
+    /// `len` must be the same as the one passed to `alloc`.
+    pub fn free(&self, paddr: PAddr, len: usize) {
+        debug_assert!(len > 0);
+        debug_assert!(is_aligned(len, MIN_PAGE_SIZE));
+        debug_assert!(paddr.is_aligned(MIN_PAGE_SIZE));
+
+        self.free_list.lock().push(paddr, len);
     }
 }
 
@@ -223,6 +344,9 @@ pub fn init(bootinfo: &BootInfo) {
 
     bubble_sort(reserved_regions.as_slice_mut(), |a, b| a.start > b.start);
 
+    // The amount of RAM to use, limited by the `mem` option.
+    let mut remaining = cmdline::get().mem.unwrap_or(usize::MAX);
+
     // Visit the free RAM regions and add them to the page allocator.
     for FreeRam { addr, size } in &bootinfo.free_rams {
         let Some(end) = addr.as_usize().checked_add(*size).map(PAddr::new) else {
@@ -233,9 +357,49 @@ pub fn init(bootinfo: &BootInfo) {
         // QEMU does not exclude module regions from the free RAM regions. Exclude
         // them manually so that the kernel won't try to allocate from them.
         visit_unused_regions(*addr, end, reserved_regions.as_slice(), |addr, end| {
-            let size = end.as_usize() - addr.as_usize();
+            let size = min(end.as_usize() - addr.as_usize(), remaining);
+            let size = align_down(size, MIN_PAGE_SIZE);
+            if size == 0 {
+                return;
+            }
+
+            let end = PAddr::new(addr.as_usize() + size);
             trace!("RAM: {addr} - {end} ({})", ByteSize(size));
             PAGE_ALLOCATOR.add_region(addr, end);
+            remaining -= size;
         });
     }
 }
+
+#[ktest]
+fn alloc_zeroed_pages() {
+    let len = 2 * MIN_PAGE_SIZE;
+    let paddr = PAGE_ALLOCATOR.alloc(len, PageType::Dirty).unwrap();
+    unsafe {
+        core::ptr::write_bytes(arch::paddr2vaddr(paddr).as_mut_ptr::<u8>(), 0xaa, len);
+    }
+    PAGE_ALLOCATOR.free(paddr, len);
+
+    // The freed pages are likely to be reused here.
+    let paddr = PAGE_ALLOCATOR.alloc(len, PageType::Zeroed).unwrap();
+    let pages =
+        unsafe { core::slice::from_raw_parts(arch::paddr2vaddr(paddr).as_ptr::<u8>(), len) };
+    assert!(pages.iter().all(|b| *b == 0));
+    PAGE_ALLOCATOR.free(paddr, len);
+}
+
+#[ktest]
+fn free_merges_adjacent_blocks() {
+    let len = 3 * MIN_PAGE_SIZE;
+    let paddr = PAGE_ALLOCATOR.alloc(len, PageType::Dirty).unwrap();
+    let page = |i: usize| PAddr::new(paddr.as_usize() + i * MIN_PAGE_SIZE);
+
+    let mut free_list = FreeList::new();
+    free_list.push(page(2), MIN_PAGE_SIZE);
+    free_list.push(page(0), MIN_PAGE_SIZE);
+    free_list.push(page(1), MIN_PAGE_SIZE);
+    assert_eq!(free_list.alloc(len), Some(paddr));
+    assert_eq!(free_list.head, None);
+
+    PAGE_ALLOCATOR.free(paddr, len);
+}
@@ -1,6 +1,20 @@
 #[cfg(target_os = "none")]
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
+    use crate::arch;
+    use crate::arch::ExitStatus;
+    use crate::cmdline;
+    use crate::cmdline::PanicAction;
+
     println!("kernel panic: {info}");
-    loop {}
+    crate::backtrace::print_backtrace();
+
+    #[cfg(feature = "ktest")]
+    crate::ktest::bail_out();
+
+    match cmdline::get().panic {
+        PanicAction::Halt => arch::halt(),
+        PanicAction::Reboot => arch::reboot(),
+        PanicAction::QemuExit => arch::semihosting_exit(ExitStatus::Failure),
+    }
 }
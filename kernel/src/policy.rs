@@ -0,0 +1,257 @@
+//! The boot-time policy.
+//!
+//! The policy file in initfs defines who can provide and use each service,
+//! and how the kernel handles crashed servers:
+//!
+//! ```text
+//! # service <name> <provider> <client>...
+//! service tcpip   tcpip   lx http
+//! service console console *
+//!
+//! # restart <server> [<max-restarts>]
+//! restart lx 3
+//!
+//! # quota <server> <bytes>[K|M|G]
+//! quota lx 512M
+//! ```
+//!
+//! `*` allows all servers to look up the service. Servers without `restart`
+//! are never restarted; `restart <server>` alone allows
+//! [`MAX_RESTARTS_DEFAULT`] restarts. Servers without `quota` have no memory
+//! budget.
+use alloc::collections::btree_map::BTreeMap;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+use ftl_utils::formatter::ByteSize;
+use ftl_utils::spinlock::SpinLock;
+
+/// The path to the policy file in initfs.
+pub const POLICY_PATH: &[u8] = b"policy.txt";
+
+/// The maximum number of restarts if `restart` omits it. Servers without
+/// `restart` are not restarted at all.
+const MAX_RESTARTS_DEFAULT: usize = 3;
+
+#[derive(Clone)]
+pub struct ServiceRule {
+    /// The server allowed to register the service.
+    pub provider: String,
+    clients: Vec<String>,
+    allow_all: bool,
+}
+
+impl ServiceRule {
+    /// Returns true if `client` is allowed to look up the service.
+    pub fn allows(&self, client: &str) -> bool {
+        self.allow_all || self.clients.iter().any(|c| c == client)
+    }
+}
+
+struct Policy {
+    services: BTreeMap<String, ServiceRule>,
+    /// The maximum number of restarts, indexed by server name.
+    restarts: BTreeMap<String, usize>,
+    /// The memory budgets in bytes, indexed by server name.
+    quotas: BTreeMap<String, usize>,
+}
+
+impl Policy {
+    const fn new() -> Self {
+        Self {
+            services: BTreeMap::new(),
+            restarts: BTreeMap::new(),
+            quotas: BTreeMap::new(),
+        }
+    }
+}
+
+static POLICY: SpinLock<Policy> = SpinLock::new(Policy::new());
+
+/// Parses the policy file.
+pub fn load(data: &[u8]) {
+    let Ok(text) = core::str::from_utf8(data) else {
+        error!("policy: not a UTF-8 text");
+        return;
+    };
+
+    parse(&mut POLICY.lock(), text);
+}
+
+/// Adds the rules in `text` to `policy`. Invalid lines are ignored with a
+/// warning.
+fn parse(policy: &mut Policy, text: &str) {
+    for (lineno, line) in text.lines().enumerate() {
+        let lineno = lineno + 1;
+        let line = match line.split_once('#') {
+            Some((line, _comment)) => line,
+            None => line,
+        };
+
+        let mut words = line.split_whitespace();
+        match words.next() {
+            None => {
+                // An empty line.
+            }
+            Some("service") => {
+                let (Some(service), Some(provider)) = (words.next(), words.next()) else {
+                    warn!(
+                        "policy:{}: usage: service <name> <provider> <client>...",
+                        lineno
+                    );
+                    continue;
+                };
+
+                let mut rule = ServiceRule {
+                    provider: provider.to_string(),
+                    clients: Vec::new(),
+                    allow_all: false,
+                };
+
+                for client in words {
+                    if client == "*" {
+                        rule.allow_all = true;
+                    } else {
+                        rule.clients.push(client.to_string());
+                    }
+                }
+
+                if policy.services.insert(service.to_string(), rule).is_some() {
+                    warn!("policy:{}: duplicated service \"{}\"", lineno, service);
+                }
+            }
+            Some("restart") => {
+                let Some(server) = words.next() else {
+                    warn!(
+                        "policy:{}: usage: restart <server> [<max-restarts>]",
+                        lineno
+                    );
+                    continue;
+                };
+
+                let max_restarts = match words.next().map(str::parse) {
+                    None => MAX_RESTARTS_DEFAULT,
+                    Some(Ok(max_restarts)) => max_restarts,
+                    Some(Err(_)) => {
+                        warn!("policy:{}: invalid max-restarts", lineno);
+                        continue;
+                    }
+                };
+
+                policy.restarts.insert(server.to_string(), max_restarts);
+            }
+            Some("quota") => {
+                let (Some(server), Some(size)) = (words.next(), words.next()) else {
+                    warn!("policy:{}: usage: quota <server> <bytes>[K|M|G]", lineno);
+                    continue;
+                };
+
+                let Some(size) = ByteSize::parse(size) else {
+                    warn!("policy:{}: invalid size \"{}\"", lineno, size);
+                    continue;
+                };
+
+                policy.quotas.insert(server.to_string(), size);
+            }
+            Some(directive) => {
+                warn!("policy:{}: unknown directive \"{}\"", lineno, directive);
+            }
+        }
+    }
+}
+
+/// Returns the rule for a service, if defined.
+pub fn service_rule(name: &str) -> Option<ServiceRule> {
+    POLICY.lock().services.get(name).cloned()
+}
+
+/// Returns how many times a crashed server can be restarted. Zero if the
+/// policy has no `restart` line for it.
+pub fn max_restarts(server: &str) -> usize {
+    POLICY.lock().restarts.get(server).copied().unwrap_or(0)
+}
+
+/// Returns the memory budget for a server, or `None` if unlimited.
+pub fn memory_quota(server: &str) -> Option<usize> {
+    POLICY.lock().quotas.get(server).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(text: &str) -> Policy {
+        let mut policy = Policy::new();
+        parse(&mut policy, text);
+        policy
+    }
+
+    #[test]
+    fn service() {
+        let policy = parse_str(
+            "service tcpip tcpip lx http  # comment\n\
+             service console console *\n\
+             service empty provider\n",
+        );
+
+        let tcpip = &policy.services["tcpip"];
+        assert_eq!(tcpip.provider, "tcpip");
+        assert!(tcpip.allows("lx"));
+        assert!(tcpip.allows("http"));
+        assert!(!tcpip.allows("tcpip"));
+        assert!(!tcpip.allows("l"));
+
+        let console = &policy.services["console"];
+        assert_eq!(console.provider, "console");
+        assert!(console.allows("lx"));
+
+        assert!(!policy.services["empty"].allows("provider"));
+    }
+
+    #[test]
+    fn service_later_line_overrides() {
+        let policy = parse_str("service tcpip a lx\nservice tcpip b http\n");
+        let tcpip = &policy.services["tcpip"];
+        assert_eq!(tcpip.provider, "b");
+        assert!(tcpip.allows("http"));
+        assert!(!tcpip.allows("lx"));
+    }
+
+    #[test]
+    fn restart() {
+        let policy = parse_str("restart lx 5\nrestart http\nrestart tcpip 0\n");
+        assert_eq!(policy.restarts["lx"], 5);
+        assert_eq!(policy.restarts["http"], MAX_RESTARTS_DEFAULT);
+        assert_eq!(policy.restarts["tcpip"], 0);
+        assert!(!policy.restarts.contains_key("console"));
+    }
+
+    #[test]
+    fn quota() {
+        let policy = parse_str("quota lx 512M\nquota http 4096\nquota tcpip 1G\n");
+        assert_eq!(policy.quotas["lx"], 512 * 1024 * 1024);
+        assert_eq!(policy.quotas["http"], 4096);
+        assert_eq!(policy.quotas["tcpip"], 1024 * 1024 * 1024);
+    }
+
+    #[test]
+    fn ignore_invalid_lines() {
+        let policy = parse_str(
+            "service\n\
+             service tcpip\n\
+             restart\n\
+             restart lx many\n\
+             restart lx -1\n\
+             quota lx\n\
+             quota lx 1T\n\
+             quota lx M\n\
+             allow lx\n\
+             # service commented out\n\
+             \n",
+        );
+        assert!(policy.services.is_empty());
+        assert!(policy.restarts.is_empty());
+        assert!(policy.quotas.is_empty());
+    }
+}
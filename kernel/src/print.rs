@@ -1,8 +1,33 @@
 use core::fmt;
+use core::sync::atomic::AtomicU8;
+use core::sync::atomic::Ordering;
+
+use ftl_api::print::LogLevel;
 
 use crate::arch;
 pub struct Printer;
 
+/// The minimum level of logs to print on the console, set by the `log`
+/// option. All logs are recorded in [`crate::log`] regardless of this.
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Trace as u8);
+
+pub fn set_log_level(level: LogLevel) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn log_level() -> LogLevel {
+    match LOG_LEVEL.load(Ordering::Relaxed) {
+        0 => LogLevel::Trace,
+        1 => LogLevel::Info,
+        2 => LogLevel::Warn,
+        _ => LogLevel::Error,
+    }
+}
+
+pub fn enabled(level: LogLevel) -> bool {
+    level >= log_level()
+}
+
 impl fmt::Write for Printer {
     fn write_str(&mut self, s: &str) -> fmt::Result {
         arch::console_write(s.as_bytes());
@@ -13,31 +38,28 @@ impl fmt::Write for Printer {
 #[macro_export]
 macro_rules! info {
     ($($arg:tt)+) => {{
-        $crate::println!("[kernel    ] INFO  {}", format_args!($($arg)+));
+        $crate::log::log(ftl_api::print::LogLevel::Info, format_args!($($arg)+));
     }};
 }
 
 #[macro_export]
 macro_rules! warn {
     ($($arg:tt)+) => {{
-        $crate::println!(
-            "[kernel    ] \x1b[33mWARN\x1b[0m  {}",
-            format_args!($($arg)+)
-        );
+        $crate::log::log(ftl_api::print::LogLevel::Warn, format_args!($($arg)+));
     }};
 }
 
 #[macro_export]
 macro_rules! error {
     ($($arg:tt)+) => {{
-        $crate::println!("[kernel    ] \x1b[31mERROR\x1b[0m  {}", format_args!($($arg)+));
+        $crate::log::log(ftl_api::print::LogLevel::Error, format_args!($($arg)+));
     }};
 }
 
 #[macro_export]
 macro_rules! trace {
     ($($arg:tt)+) => {{
-        $crate::println!("[kernel    ] {}", format_args!($($arg)+));
+        $crate::log::log(ftl_api::print::LogLevel::Trace, format_args!($($arg)+));
     }};
 }
 
@@ -0,0 +1,154 @@
+//! Memory quotas.
+//!
+//! Each server has a memory budget, and each VmSpace has its own budget
+//! nested in the budget of the server which created it. Memory charged to a
+//! quota is also charged to its ancestors, so that a server can't exceed its
+//! budget by creating VmSpaces.
+use core::ptr;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+
+use ftl_api::error::ErrorCode;
+use ftl_api::quota::MemoryUsage;
+
+use crate::address::PAddr;
+use crate::memory::PAGE_ALLOCATOR;
+use crate::memory::PageType;
+use crate::shared_ref::SharedRef;
+
+pub struct Quota {
+    /// The maximum number of bytes. `None` means unlimited.
+    limit: Option<usize>,
+    /// The number of bytes charged to this quota.
+    used: AtomicUsize,
+    parent: Option<SharedRef<Quota>>,
+}
+
+impl Quota {
+    pub fn new(
+        limit: Option<usize>,
+        parent: Option<SharedRef<Quota>>,
+    ) -> Result<SharedRef<Self>, ErrorCode> {
+        SharedRef::new(Self {
+            limit,
+            used: AtomicUsize::new(0),
+            parent,
+        })
+    }
+
+    fn try_charge(&self, len: usize) -> bool {
+        self.used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                let new_used = used.checked_add(len)?;
+                match self.limit {
+                    Some(limit) if new_used > limit => None,
+                    _ => Some(new_used),
+                }
+            })
+            .is_ok()
+    }
+
+    /// Charges `len` bytes to this quota and its ancestors.
+    pub fn charge(&self, len: usize) -> Result<(), ErrorCode> {
+        let mut quota = self;
+        loop {
+            if !quota.try_charge(len) {
+                // Undo the charges to the descendants of `quota`.
+                let mut charged = self;
+                while !ptr::eq(charged, quota) {
+                    charged.used.fetch_sub(len, Ordering::AcqRel);
+                    charged = charged.parent.as_ref().unwrap();
+                }
+
+                return Err(ErrorCode::OUT_OF_MEMORY);
+            }
+
+            match &quota.parent {
+                Some(parent) => quota = parent,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Returns `len` bytes charged by [`Quota::charge`].
+    pub fn uncharge(&self, len: usize) {
+        let mut quota = self;
+        loop {
+            let prev = quota.used.fetch_sub(len, Ordering::AcqRel);
+            debug_assert!(prev >= len);
+
+            match &quota.parent {
+                Some(parent) => quota = parent,
+                None => return,
+            }
+        }
+    }
+
+    /// Allocates a memory block from [`PAGE_ALLOCATOR`], charging it to this
+    /// quota.
+    pub fn alloc(&self, len: usize, page_type: PageType) -> Result<PAddr, ErrorCode> {
+        self.charge(len)?;
+        match PAGE_ALLOCATOR.alloc(len, page_type) {
+            Some(paddr) => Ok(paddr),
+            None => {
+                self.uncharge(len);
+                Err(ErrorCode::OUT_OF_MEMORY)
+            }
+        }
+    }
+
+    /// Frees a memory block allocated by [`Quota::alloc`].
+    pub fn free(&self, paddr: PAddr, len: usize) {
+        PAGE_ALLOCATOR.free(paddr, len);
+        self.uncharge(len);
+    }
+
+    pub fn usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            used: self.used.load(Ordering::Acquire),
+            limit: self.limit,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch;
+    use crate::arch::MIN_PAGE_SIZE;
+
+    #[test]
+    fn charge_ancestors() {
+        let parent = Quota::new(Some(2 * MIN_PAGE_SIZE), None).unwrap();
+        let child = Quota::new(None, Some(parent.clone())).unwrap();
+
+        child.charge(MIN_PAGE_SIZE).unwrap();
+        assert_eq!(parent.usage().used, MIN_PAGE_SIZE);
+
+        // The parent's limit applies to the child, and a failed charge is
+        // undone.
+        assert_eq!(
+            child.charge(2 * MIN_PAGE_SIZE),
+            Err(ErrorCode::OUT_OF_MEMORY)
+        );
+        assert_eq!(child.usage().used, MIN_PAGE_SIZE);
+
+        child.uncharge(MIN_PAGE_SIZE);
+        assert_eq!(parent.usage().used, 0);
+    }
+
+    #[test]
+    fn alloc_and_free() {
+        arch::init_for_test();
+        let quota = Quota::new(Some(MIN_PAGE_SIZE), None).unwrap();
+
+        let paddr = quota.alloc(MIN_PAGE_SIZE, PageType::Zeroed).unwrap();
+        assert_eq!(
+            quota.alloc(MIN_PAGE_SIZE, PageType::Zeroed),
+            Err(ErrorCode::OUT_OF_MEMORY)
+        );
+
+        quota.free(paddr, MIN_PAGE_SIZE);
+        assert_eq!(quota.usage().used, 0);
+    }
+}
@@ -6,6 +6,7 @@ use ftl_utils::spinlock::SpinLock;
 use crate::arch;
 use crate::shared_ref::SharedRef;
 use crate::thread::Thread;
+use crate::upcall;
 
 pub static SCHEDULER: Scheduler = Scheduler::new();
 
@@ -58,16 +59,16 @@ impl Scheduler {
 /// Unlike traditional operating systems, this function never returns because of
 /// the single kernel stack design.
 pub fn return_to_user() -> ! {
+    // Deliver upcalls deferred by kernel objects before picking a thread. They
+    // may make threads runnable.
+    upcall::deliver_pending();
+
     let cpuvar = arch::get_cpuvar();
     let current = &cpuvar.current_thread;
 
-    if let Some(current) = current.thread()
-        && current.is_runnable()
-    {
-        // The current thread is runnable. Push it back to the scheduler.
-        SCHEDULER
-            .push_front(current)
-            .expect("out of memory in runqueue"); // FIXME:
+    if let Some(current) = current.thread() {
+        // Push the current thread back to the scheduler if it's runnable.
+        current.requeue().expect("out of memory in runqueue"); // FIXME:
     }
 
     let next = loop {
@@ -81,9 +82,9 @@ pub fn return_to_user() -> ! {
             arch::idle();
         };
 
-        // The thread can be blocked while in the runqueue. Make sure it
-        // is still runnable.
-        if thread.is_runnable() {
+        // The thread can be blocked or suspended while in the runqueue. Make
+        // sure it is still runnable and no other CPU is running it.
+        if thread.mark_running() {
             break thread;
         }
     };
@@ -91,3 +92,15 @@ pub fn return_to_user() -> ! {
     // Switch to the new thread.
     current.enter(next);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idle_without_threads() {
+        arch::init_for_test();
+        arch::run_until_idle();
+        assert!(arch::get_cpuvar().current_thread.thread().is_none());
+    }
+}
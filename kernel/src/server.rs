@@ -1,27 +1,60 @@
+use alloc::borrow::Cow;
+use alloc::collections::btree_map::BTreeMap;
+use alloc::string::String;
+use alloc::string::ToString;
 use alloc::vec::Vec;
+use core::fmt;
+use core::mem;
 
+use ftl_api::Spec;
 use ftl_api::error::ErrorCode;
 use ftl_api::handle::HandleRight;
 use ftl_api::start::StartInfo;
+use ftl_utils::alignment::is_aligned;
 use ftl_utils::spinlock::SpinLock;
 
+use crate::address::PAddr;
 use crate::address::UAddr;
+use crate::address::VAddr;
 use crate::arch;
-use crate::boot::BootInfo;
+use crate::arch::MIN_PAGE_SIZE;
+use crate::backtrace::Symbol;
+use crate::channel::Channel;
+use crate::cmdline;
+use crate::console;
+use crate::console::Console;
+use crate::handle;
+use crate::handle::HandleTable;
 use crate::initfs;
+use crate::interceptor::Interceptor;
+use crate::loader::ElfSymbols;
+use crate::loader::EntryFn;
 use crate::loader::LoadedElf;
-use crate::memory::PAGE_ALLOCATOR;
 use crate::memory::PageType;
+use crate::policy;
+use crate::quota::Quota;
+use crate::scheduler;
+use crate::service;
 use crate::shared_ref::SharedRef;
+use crate::signature;
 use crate::thread::Thread;
+use crate::upcall;
+use crate::upcall::PendingUpcall;
 use crate::vmarea::VmArea;
 use crate::vmspace::VmSpace;
 
 const START_INFO: &StartInfo = &StartInfo {
     malloc: |size| {
-        let paddr = PAGE_ALLOCATOR
-            .alloc(size, PageType::Dirty)
-            .ok_or(ErrorCode::OUT_OF_MEMORY)?;
+        if size == 0 || !is_aligned(size, MIN_PAGE_SIZE) {
+            return Err(ErrorCode::INVALID_ARG);
+        }
+
+        let server = current();
+        let paddr = server.quota.alloc(size, PageType::Dirty)?;
+        if let Err(err) = server.add_pages(paddr, size) {
+            server.quota.free(paddr, size);
+            return Err(err);
+        }
 
         let ptr = arch::paddr2vaddr(paddr).as_mut_ptr();
         Ok(ptr)
@@ -29,17 +62,34 @@ const START_INFO: &StartInfo = &StartInfo {
     print: |bytes| {
         arch::console_write(bytes);
     },
-    panic: || {
-        panic!("server panicked");
-    },
-    vmspace_create: || {
-        let vmspace = VmSpace::new()?;
-        let handle = SharedRef::new(vmspace)?.into_handle();
+    log_level: crate::print::log_level,
+    log: crate::log::log_from_server,
+    log_read: crate::log::read,
+    log_set_console_level: crate::log::set_console_level,
+    boot_option: cmdline::option,
+    panic: |info| crash_current(info),
+    vmspace_create: |limit| {
+        let quota = Quota::new(limit, Some(current().quota.clone()))?;
+        let vmspace = VmSpace::new(quota)?;
+        let handle = SharedRef::new(vmspace)?.into_handle()?;
         Ok(handle)
     },
-    vmarea_allocate: |len| {
-        let vmarea = VmArea::new_anonymous(len)?;
-        let handle = vmarea.into_handle();
+    vmspace_usage: |vmspace| {
+        let vmspace = SharedRef::<VmSpace>::from_borrowed_handle(vmspace, HandleRight::READ)?;
+        Ok(vmspace.quota().usage())
+    },
+    vmarea_allocate: |len, vmspace| {
+        let quota = match vmspace {
+            Some(vmspace) => {
+                let vmspace =
+                    SharedRef::<VmSpace>::from_borrowed_handle(vmspace, HandleRight::MAP)?;
+                vmspace.quota().clone()
+            }
+            None => current().quota.clone(),
+        };
+
+        let vmarea = VmArea::new_anonymous(len, quota)?;
+        let handle = vmarea.into_handle()?;
         Ok(handle)
     },
     vmarea_write: |vmarea, offset, data| {
@@ -55,68 +105,821 @@ const START_INFO: &StartInfo = &StartInfo {
         let vmspace = SharedRef::<VmSpace>::from_borrowed_handle(vmspace, HandleRight::READ)?;
         vmspace.read_bytes(UAddr::new(uaddr), buf)
     },
+    vmspace_intercept: |vmspace, interceptor| {
+        let vmspace = SharedRef::<VmSpace>::from_borrowed_handle(vmspace, HandleRight::INTERCEPT)?;
+        let interceptor =
+            SharedRef::<Interceptor>::from_borrowed_handle(interceptor, HandleRight::READ)?;
+        vmspace.interceptors().attach(interceptor)
+    },
     thread_create: |vmspace, upcall| {
         let vmspace = SharedRef::<VmSpace>::from_borrowed_handle(vmspace, HandleRight::MAP)?;
         let thread = Thread::new(vmspace, upcall)?;
-        Ok(thread.into_handle())
+        thread.into_handle()
     },
     thread_get_context: |thread, kind, regs| {
         let thread = SharedRef::<Thread>::from_borrowed_handle(thread, HandleRight::READ)?;
         thread.read_context(kind, regs)
     },
     thread_set_context: |thread, kind, regs| {
-        let thread = SharedRef::<Thread>::from_borrowed_handle(thread, HandleRight::WRITE)?;
+        let thread = SharedRef::<Thread>::from_borrowed_handle(thread, HandleRight::SET_CONTEXT)?;
         thread.write_context(kind, regs)
     },
     thread_unblock: |thread| {
-        let thread = SharedRef::<Thread>::from_borrowed_handle(thread, HandleRight::WRITE)?;
+        let thread = SharedRef::<Thread>::from_borrowed_handle(thread, HandleRight::UNBLOCK)?;
         thread.unblock()
     },
     thread_terminate: |thread| {
-        let thread = SharedRef::<Thread>::from_borrowed_handle(thread, HandleRight::WRITE)?;
+        let thread = SharedRef::<Thread>::from_borrowed_handle(thread, HandleRight::TERMINATE)?;
         thread.terminate()
     },
-    thread_destroy: |thread| {
-        let sref = SharedRef::<Thread>::from_moved_handle(thread)?;
-        // Decrement the ref count.
-        drop(sref);
-        Ok(())
+    thread_suspend: |thread| {
+        let thread = SharedRef::<Thread>::from_borrowed_handle(thread, HandleRight::SUSPEND)?;
+        thread.suspend()
+    },
+    thread_resume: |thread| {
+        let thread = SharedRef::<Thread>::from_borrowed_handle(thread, HandleRight::SUSPEND)?;
+        thread.resume()
+    },
+    thread_intercept: |thread, interceptor| {
+        let thread = SharedRef::<Thread>::from_borrowed_handle(thread, HandleRight::INTERCEPT)?;
+        let interceptor =
+            SharedRef::<Interceptor>::from_borrowed_handle(interceptor, HandleRight::READ)?;
+        thread.interceptors().attach(interceptor)
+    },
+    channel_create: || {
+        let (ch0, ch1) = Channel::new_pair()?;
+        let handle0 = ch0.into_handle()?;
+        match ch1.into_handle() {
+            Ok(handle1) => Ok((handle0, handle1)),
+            Err(err) => {
+                handle::close(handle0)?;
+                Err(err)
+            }
+        }
+    },
+    channel_listen: |channel, upcall| {
+        let channel = SharedRef::<Channel>::from_borrowed_handle(channel, HandleRight::READ)?;
+        channel.listen(upcall)
+    },
+    channel_send: |channel, data, handles| {
+        let channel = SharedRef::<Channel>::from_borrowed_handle(channel, HandleRight::WRITE)?;
+        let server = current();
+        channel.send(&mut server.handles().lock(), data, handles)
+    },
+    channel_recv: |channel, data, handles| {
+        let channel = SharedRef::<Channel>::from_borrowed_handle(channel, HandleRight::READ)?;
+        let server = current();
+        channel.recv(&mut server.handles().lock(), data, handles)
+    },
+    channel_close: |channel| {
+        let channel = SharedRef::<Channel>::from_borrowed_handle(channel, HandleRight::WRITE)?;
+        channel.close()
+    },
+    handle_close: handle::close,
+    handle_duplicate: handle::duplicate,
+    handle_revoke: handle::revoke,
+    service_register: service::register,
+    service_lookup: service::lookup,
+    server_stop: |name| {
+        service::authorize_control(name)?;
+        let server = lookup(name).ok_or(ErrorCode::NOT_FOUND)?;
+        server.stop()
+    },
+    server_reload: |name, elf_file, signature| {
+        service::authorize_control(name)?;
+        reload(name, elf_file, signature)
+    },
+    server_usage: || current().quota.usage(),
+    initfs_stat: initfs::stat,
+    initfs_read: initfs::read,
+    initfs_readdir: initfs::readdir,
+    console_open: || {
+        let console = console::open()?;
+        console.into_handle()
+    },
+    console_listen: |console, upcall| {
+        let console = SharedRef::<Console>::from_borrowed_handle(console, HandleRight::READ)?;
+        console.listen(upcall)
+    },
+    console_read: |console, buf| {
+        let console = SharedRef::<Console>::from_borrowed_handle(console, HandleRight::READ)?;
+        console.read(buf)
+    },
+    interceptor_create: |upcall| {
+        let interceptor = Interceptor::new(upcall)?;
+        interceptor.into_handle()
+    },
+    interceptor_detach: |interceptor| {
+        let interceptor =
+            SharedRef::<Interceptor>::from_borrowed_handle(interceptor, HandleRight::TERMINATE)?;
+        interceptor.detach()
     },
 };
 
-static SERVERS: SpinLock<Vec<Server>> = SpinLock::new(Vec::new());
+/// Handles a panic in the current server, and abandons the kernel stack.
+fn crash_current(info: &dyn fmt::Display) -> ! {
+    let server = current();
+    error!("{}: server panicked: {}", server.name(), info);
+    crate::backtrace::print_backtrace();
+
+    // Restore the state of the upcalls which will never return, e.g.
+    // terminate the thread whose syscall the server was handling, and drop
+    // the references held in the abandoned stack frames.
+    upcall::unwind_all();
+    server.crash();
+    drop(server);
+
+    // Abandon the server's stack frames.
+    scheduler::return_to_user();
+}
+
+/// Loaded servers, indexed by name.
+static SERVERS: SpinLock<BTreeMap<String, SharedRef<Server>>> = SpinLock::new(BTreeMap::new());
+
+#[derive(Debug, PartialEq, Eq)]
+enum State {
+    Running,
+    /// The server will be stopped soon.
+    Stopping,
+    Stopped,
+}
+
+struct Mutable {
+    state: State,
+    /// The spec returned from the entry point. `None` until started.
+    spec: Option<&'static Spec>,
+    /// Memory blocks allocated for the server's heap.
+    pages: Vec<(PAddr, usize)>,
+    /// The server to start once this one is torn down, queued by [`reload`].
+    successor: Option<SharedRef<Server>>,
+}
 
 pub struct Server {
-    image: *const u8,
+    name: String,
+    /// The ELF file, kept to restart the server.
+    elf_file: Cow<'static, [u8]>,
+    /// How many times the server has been restarted after crashes.
+    restarts: usize,
+    /// The quota the server's heap and VM areas are charged to.
+    quota: SharedRef<Quota>,
+    image_vaddr: VAddr,
+    image_paddr: PAddr,
+    image_len: usize,
+    entry_fn: EntryFn,
+    symbols: Option<ElfSymbols>,
+    handles: SpinLock<HandleTable>,
+    mutable: SpinLock<Mutable>,
 }
 
 impl Server {
-    fn load(elf_file: &[u8]) -> Result<Self, crate::loader::Error> {
-        let LoadedElf { image, entry_fn } = crate::loader::load_elf(elf_file)?;
-        entry_fn(START_INFO);
-        Ok(Self { image })
+    /// Loads a server, and schedules it to start.
+    fn load(
+        name: &str,
+        elf_file: Cow<'static, [u8]>,
+        restarts: usize,
+    ) -> Result<SharedRef<Self>, ErrorCode> {
+        let server = Server::prepare(name, elf_file, restarts)?;
+        if let Err(err) = server.start(&mut SERVERS.lock()) {
+            server.discard();
+            return Err(err);
+        }
+
+        Ok(server)
+    }
+
+    /// Loads a server image without starting it.
+    fn prepare(
+        name: &str,
+        elf_file: Cow<'static, [u8]>,
+        restarts: usize,
+    ) -> Result<SharedRef<Self>, ErrorCode> {
+        trace!("loading {}...", name);
+        let LoadedElf {
+            image_vaddr,
+            image_paddr,
+            image_len,
+            entry_fn,
+            symbols,
+        } = crate::loader::load_elf(&elf_file).map_err(|err| {
+            error!("failed to load {}: {:?}", name, err);
+            match err {
+                crate::loader::Error::OutOfMemory => ErrorCode::OUT_OF_MEMORY,
+                _ => ErrorCode::INVALID_ARG,
+            }
+        })?;
+
+        let quota = match Quota::new(policy::memory_quota(name), None) {
+            Ok(quota) => quota,
+            Err(err) => {
+                crate::loader::unload_elf(image_vaddr, image_paddr, image_len);
+                return Err(err);
+            }
+        };
+
+        match SharedRef::new(Server {
+            name: name.to_string(),
+            elf_file,
+            restarts,
+            quota,
+            image_vaddr,
+            image_paddr,
+            image_len,
+            entry_fn,
+            symbols,
+            handles: SpinLock::new(HandleTable::new()),
+            mutable: SpinLock::new(Mutable {
+                state: State::Running,
+                spec: None,
+                pages: Vec::new(),
+                successor: None,
+            }),
+        }) {
+            Ok(server) => Ok(server),
+            Err(err) => {
+                crate::loader::unload_elf(image_vaddr, image_paddr, image_len);
+                Err(err)
+            }
+        }
+    }
+
+    /// Registers the server so that it can be found by name, and schedules it
+    /// to start.
+    fn start(
+        self: &SharedRef<Self>,
+        servers: &mut BTreeMap<String, SharedRef<Server>>,
+    ) -> Result<(), ErrorCode> {
+        // Start the server as a deferred upcall so that a panic in a server
+        // doesn't prevent other servers from starting.
+        let start = SharedRef::new(StartServer(self.clone()))?;
+        upcall::schedule(start)?;
+
+        servers.insert(self.name.clone(), self.clone());
+        Ok(())
+    }
+
+    /// Frees the image of a server which has not been started.
+    fn discard(&self) {
+        self.mutable.lock().state = State::Stopped;
+        crate::loader::unload_elf(self.image_vaddr, self.image_paddr, self.image_len);
+    }
+
+    /// Creates a server with an empty image, to run kernel code as the
+    /// server in host tests. It's not registered nor started.
+    #[cfg(test)]
+    pub fn new_for_test(name: &str) -> SharedRef<Self> {
+        extern "Rust" fn entry(_start_info: *const StartInfo) -> &'static Spec {
+            static SPEC: Spec = Spec {
+                name: b"test",
+                start: || {},
+                stop: || {},
+            };
+
+            &SPEC
+        }
+
+        let image_paddr = crate::memory::PAGE_ALLOCATOR
+            .alloc(MIN_PAGE_SIZE, PageType::Zeroed)
+            .unwrap();
+        let image_vaddr = arch::map_server_image(image_paddr, MIN_PAGE_SIZE).unwrap();
+        SharedRef::new(Server {
+            name: name.to_string(),
+            elf_file: Cow::Borrowed(&[]),
+            restarts: 0,
+            quota: Quota::new(None, None).unwrap(),
+            image_vaddr,
+            image_paddr,
+            image_len: MIN_PAGE_SIZE,
+            entry_fn: entry,
+            symbols: None,
+            handles: SpinLock::new(HandleTable::new()),
+            mutable: SpinLock::new(Mutable {
+                state: State::Running,
+                spec: None,
+                pages: Vec::new(),
+                successor: None,
+            }),
+        })
+        .unwrap()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn handles(&self) -> &SpinLock<HandleTable> {
+        &self.handles
+    }
+
+    /// Returns false if the server has been stopped. Upcalls to a stopped
+    /// server must not be delivered.
+    pub fn is_alive(&self) -> bool {
+        self.mutable.lock().state != State::Stopped
+    }
+
+    /// Records a memory block allocated for the server, to be freed when it
+    /// stops.
+    fn add_pages(&self, paddr: PAddr, len: usize) -> Result<(), ErrorCode> {
+        let mut mutable = self.mutable.lock();
+        if mutable.pages.try_reserve(1).is_err() {
+            return Err(ErrorCode::OUT_OF_MEMORY);
+        }
+
+        mutable.pages.push((paddr, len));
+        Ok(())
+    }
+
+    /// Stops the server, and frees its image and memory.
+    ///
+    /// The server is stopped asynchronously: [`Spec::stop`] is called as a
+    /// deferred upcall, and then its resources are released as in
+    /// [`Server::teardown`].
+    pub fn stop(self: &SharedRef<Self>) -> Result<(), ErrorCode> {
+        let mut mutable = self.mutable.lock();
+        if mutable.state != State::Running {
+            return Err(ErrorCode::INVALID_STATE);
+        }
+
+        upcall::schedule(SharedRef::new(StopServer(self.clone()))?)?;
+        mutable.state = State::Stopping;
+        Ok(())
+    }
+
+    /// Stops the server, and starts `successor` in its place once the server
+    /// is torn down. If the server is already stopping, `successor` waits for
+    /// it, superseding the one queued before if any.
+    ///
+    /// The caller must hold the [`SERVERS`] lock, and the server must be in
+    /// it: [`Server::teardown`] takes the successor while holding the lock.
+    fn replace(self: &SharedRef<Self>, successor: SharedRef<Server>) -> Result<(), ErrorCode> {
+        let prev = {
+            let mut mutable = self.mutable.lock();
+            if mutable.state == State::Running {
+                upcall::schedule(SharedRef::new(StopServer(self.clone()))?)?;
+                mutable.state = State::Stopping;
+            }
+
+            mutable.successor.replace(successor)
+        };
+
+        if let Some(prev) = prev {
+            prev.discard();
+        }
+
+        Ok(())
+    }
+
+    /// Handles a panic in the server's code.
+    ///
+    /// The caller must abandon the current kernel stack after this, by
+    /// [`scheduler::return_to_user`].
+    fn crash(self: &SharedRef<Self>) {
+        // The server's code on the stack won't return.
+        arch::get_cpuvar().current_server.take();
+
+        let was_stopping = self.mutable.lock().state == State::Stopping;
+        self.teardown();
+
+        if was_stopping {
+            return;
+        }
+
+        // A crashed test server fails, and is not restarted.
+        #[cfg(feature = "ktest")]
+        if crate::ktest::server_finished(&self.name, false) {
+            return;
+        }
+
+        if self.restarts >= policy::max_restarts(&self.name) {
+            warn!("{}: not restarting the crashed server", self.name);
+            return;
+        }
+
+        warn!(
+            "{}: restarting the crashed server ({}/{})",
+            self.name,
+            self.restarts + 1,
+            policy::max_restarts(&self.name)
+        );
+
+        if let Err(err) = Server::load(&self.name, self.elf_file.clone(), self.restarts + 1) {
+            error!("{}: failed to restart: {:?}", self.name, err);
+        }
+    }
+
+    /// Releases the server's resources.
+    ///
+    /// The server's threads are terminated, and its handles are closed. Upcalls
+    /// to the server are no longer delivered. Kernel objects still referenced
+    /// by other servers stay alive.
+    fn teardown(self: &SharedRef<Self>) {
+        let pages = {
+            let mut mutable = self.mutable.lock();
+            if mutable.state == State::Stopped {
+                return;
+            }
+
+            mutable.state = State::Stopped;
+            mem::take(&mut mutable.pages)
+        };
+
+        // Close the remaining handles. Threads are terminated explicitly
+        // because they can be alive while other servers reference them.
+        let handles = self.handles.lock().take_all();
+        for handle in handles {
+            if let Ok(thread) = handle.downcast::<Thread>(HandleRight::NONE) {
+                let _ = thread.terminate();
+            }
+        }
+
+        service::unregister_all(&self.name);
+
+        {
+            let mut servers = SERVERS.lock();
+            if servers
+                .get(&self.name)
+                .is_some_and(|server| SharedRef::eq(server, self))
+            {
+                servers.remove(&self.name);
+            }
+
+            // Start the new image queued by reload. It's started after the
+            // services above are unregistered.
+            let successor = self.mutable.lock().successor.take();
+            if let Some(successor) = successor
+                && let Err(err) = successor.start(&mut servers)
+            {
+                error!("{}: failed to start the new image: {:?}", self.name, err);
+                successor.discard();
+            }
+        }
+
+        // Nothing references the server's memory anymore.
+        for (paddr, len) in pages {
+            self.quota.free(paddr, len);
+        }
+
+        crate::loader::unload_elf(self.image_vaddr, self.image_paddr, self.image_len);
+        trace!("stopped {}", self.name);
     }
 }
 
 unsafe impl Send for Server {}
+unsafe impl Sync for Server {}
+
+/// A deferred upcall to start a server.
+struct StartServer(SharedRef<Server>);
+
+impl PendingUpcall for StartServer {
+    fn deliver(&self) {
+        let server = &self.0;
+        if server.mutable.lock().state != State::Running {
+            return;
+        }
+
+        let spec = run_as(server, || (server.entry_fn)(START_INFO));
+        server.mutable.lock().spec = Some(spec);
+        trace!("started {}", server.name);
+
+        #[cfg(feature = "ktest")]
+        crate::ktest::server_finished(&server.name, true);
+    }
+}
+
+/// A deferred upcall to stop a server.
+struct StopServer(SharedRef<Server>);
+
+impl PendingUpcall for StopServer {
+    fn deliver(&self) {
+        let server = &self.0;
+        if server.mutable.lock().state != State::Stopping {
+            // Crashed while stopping, and has been torn down.
+            return;
+        }
+
+        trace!("stopping {}...", server.name);
+
+        // Let the server release its objects.
+        let spec = server.mutable.lock().spec;
+        if let Some(spec) = spec {
+            run_as(server, || (spec.stop)());
+        }
+
+        server.teardown();
+    }
+}
+
+/// Returns the server running on this CPU, that is, the caller of the
+/// supercall.
+pub fn current() -> SharedRef<Server> {
+    let current = &arch::get_cpuvar().current_server;
+    let server = current.take().expect("not running a server");
+    let cloned = server.clone();
+    current.set(Some(server));
+    cloned
+}
+
+/// Runs `f` (the server's code) as `server`.
+pub fn run_as<R>(server: &SharedRef<Server>, f: impl FnOnce() -> R) -> R {
+    let current = &arch::get_cpuvar().current_server;
+    let prev = current.replace(Some(server.clone()));
+    let (prev, ret) = upcall::lend(prev, |_| f());
+    current.set(prev);
+    ret
+}
+
+/// Returns the loaded server with the given name.
+pub fn lookup(name: &str) -> Option<SharedRef<Server>> {
+    SERVERS.lock().get(name).cloned()
+}
+
+/// Symbolizes an address in a server image, and calls `callback` with the
+/// server name and the symbol. Returns false if it's not in any server.
+///
+/// Called on panic: it gives up if the server list is locked.
+pub fn symbolize(addr: usize, callback: impl FnOnce(&str, Option<Symbol<'_>>)) -> bool {
+    let Ok(servers) = SERVERS.try_lock() else {
+        return false;
+    };
+
+    for server in servers.values() {
+        let start = server.image_vaddr.as_usize();
+        if (start..start + server.image_len).contains(&addr) {
+            let symbol = server
+                .symbols
+                .as_ref()
+                .and_then(|symbols| symbols.lookup(&server.elf_file, addr - start));
+            callback(&server.name, symbol);
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Replaces the server with a new image, or loads it if it's not running.
+///
+/// The new image is loaded first so that a broken one leaves the old server
+/// running. The old server is stopped, or its pending stop is waited for,
+/// and the new one starts once the old one is torn down. The image must be
+/// signed as the ones in initfs.
+pub fn reload(name: &str, elf_file: &[u8], signature: &[u8]) -> Result<(), ErrorCode> {
+    signature::verify(elf_file, Some(signature))?;
+
+    let mut copied = Vec::new();
+    if copied.try_reserve_exact(elf_file.len()).is_err() {
+        return Err(ErrorCode::OUT_OF_MEMORY);
+    }
+    copied.extend_from_slice(elf_file);
+
+    let server = Server::prepare(name, Cow::Owned(copied), 0)?;
+    install(server)
+}
+
+/// Starts `server`, in place of the running one with the same name if any.
+fn install(server: SharedRef<Server>) -> Result<(), ErrorCode> {
+    let result = {
+        let mut servers = SERVERS.lock();
+        match servers.get(&server.name).cloned() {
+            Some(old) => old.replace(server.clone()),
+            None => server.start(&mut servers),
+        }
+    };
+
+    if result.is_err() {
+        server.discard();
+    }
+
+    result
+}
+
+/// Looks for the detached signature of a file in initfs.
+fn find_signature(path: &[u8]) -> Option<&'static [u8]> {
+    initfs::files()
+        .filter(|file| file.name.strip_suffix(signature::SIGNATURE_SUFFIX) == Some(path))
+        .last()
+        .map(|file| file.data)
+}
+
+pub fn init() {
+    // Load the service policy first: servers may register services while
+    // starting.
+    if let Some(file) = initfs::files()
+        .filter(|file| file.name == policy::POLICY_PATH)
+        .last()
+    {
+        policy::load(file.data);
+    }
 
-pub fn init(bootinfo: &BootInfo) {
-    for module in &bootinfo.modules {
-        let initfs = initfs::InitFsLoader::new(module);
-        for file in initfs {
-            if file.name.starts_with(b"servers/") && file.name.ends_with(b".elf") {
-                let name = core::str::from_utf8(file.name).unwrap();
-                trace!("loading {}...", name);
-                match Server::load(file.data) {
-                    Ok(server) => {
-                        SERVERS.lock().push(server);
-                    }
-                    Err(e) => {
-                        error!("failed to load server: {:?}", e);
-                    }
-                }
-                trace!("loaded {}", name);
+    for file in initfs::files().filter(initfs::is_latest) {
+        if let Some(name) = file.name.strip_prefix(b"servers/")
+            && let Some(name) = name.strip_suffix(b".elf")
+        {
+            let Ok(name) = core::str::from_utf8(name) else {
+                error!("invalid server name: {:?}", file.name);
+                continue;
+            };
+
+            if !cmdline::get().is_server_enabled(name) {
+                trace!("skipping {} (not in servers=)", name);
+                continue;
             }
+
+            load_from_initfs(name, file.name, file.data);
         }
+
+        #[cfg(feature = "ktest")]
+        if let Some(name) = file.name.strip_prefix(b"tests/")
+            && let Some(name) = name.strip_suffix(b".elf")
+        {
+            let Ok(name) = core::str::from_utf8(name) else {
+                error!("invalid test server name: {:?}", file.name);
+                continue;
+            };
+
+            crate::ktest::add_server(name);
+            load_from_initfs(name, file.name, file.data);
+        }
+    }
+}
+
+fn load_from_initfs(name: &str, path: &[u8], elf_file: &'static [u8]) {
+    if lookup(name).is_some() {
+        error!("server \"{}\" already exists", name);
+        return;
+    }
+
+    let signature = find_signature(path);
+    if let Err(err) = signature::verify(elf_file, signature) {
+        error!("refused to load {}: {:?}", name, err);
+        return;
+    }
+
+    // Servers start when the kernel first returns to the user.
+    let _ = Server::load(name, Cow::Borrowed(elf_file), 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use ftl_api::thread::ContextData;
+    use ftl_api::thread::ContextKind;
+    use ftl_api::thread::InitRegs;
+    use ftl_api::upcall::UpCallCtx;
+    use ftl_api::upcall::Upcall;
+
+    use super::*;
+    use crate::handle::AnyHandle;
+
+    /// Runs the pending upcalls until `done` returns true. Other tests may
+    /// deliver them on their host threads.
+    fn run_until(done: impl Fn() -> bool) {
+        while !done() {
+            arch::run_until_idle();
+            std::thread::yield_now();
+        }
+    }
+
+    /// Waits for `done` to return true, giving up after a while.
+    fn wait_for(done: impl Fn() -> bool) -> bool {
+        for _ in 0..10000 {
+            if done() {
+                return true;
+            }
+
+            arch::run_until_idle();
+            std::thread::yield_now();
+        }
+
+        done()
+    }
+
+    extern "Rust" fn crash_on_channel_event(_ctx: UpCallCtx, _arg: ftl_api::channel::UpcallArg) {
+        crash_current(&"test crash");
+    }
+
+    extern "Rust" fn crash_on_syscall(_ctx: UpCallCtx, arg: ftl_api::thread::UpcallArg) {
+        assert!(matches!(arg, ftl_api::thread::UpcallArg::Syscall));
+        crash_current(&"test crash");
+    }
+
+    fn make_syscall(thread: &mut arch::Thread) {
+        thread.n = 1;
+    }
+
+    #[test]
+    fn crash_in_channel_upcall() {
+        arch::init_for_test();
+        let crashing = Server::new_for_test("crash_in_channel_upcall");
+        crashing.start(&mut SERVERS.lock()).unwrap();
+        let bystander = Server::new_for_test("crash_in_channel_upcall_bystander");
+
+        let (ch0, ch1) = Channel::new_pair().unwrap();
+        run_as(&crashing, || {
+            ch1.listen(Upcall::from_fn(crash_on_channel_event)).unwrap();
+            let handle = AnyHandle::new(ch1.clone()).unwrap();
+            crashing.handles().lock().insert(handle).unwrap();
+        });
+        drop(ch1);
+
+        run_as(&bystander, || {
+            ch0.send(&mut HandleTable::new(), b"crash", &mut [])
+                .unwrap();
+        });
+        run_until(|| !crashing.is_alive());
+
+        // The server is torn down, including the channel endpoint and the
+        // listener, which was in the abandoned stack.
+        assert!(lookup("crash_in_channel_upcall").is_none());
+        assert!(arch::server_image_attrs(crashing.image_vaddr).is_none());
+        assert!(wait_for(|| SharedRef::ref_count(&crashing) == 1));
+        assert_eq!(
+            ch0.send(&mut HandleTable::new(), b"crash", &mut []),
+            Err(ErrorCode::PEER_CLOSED)
+        );
+        assert!(bystander.is_alive());
+    }
+
+    #[test]
+    fn crash_in_syscall_upcall() {
+        arch::init_for_test();
+        let crashing = Server::new_for_test("crash_in_syscall_upcall");
+        let thread = run_as(&crashing, || {
+            let quota = Quota::new(None, None).unwrap();
+            let vmspace = SharedRef::new(VmSpace::new(quota).unwrap()).unwrap();
+            Thread::new(vmspace, Upcall::from_fn(crash_on_syscall)).unwrap()
+        });
+
+        let program: fn(&mut arch::Thread) = make_syscall;
+        let init_regs = InitRegs {
+            pc: program as usize as u64,
+            sp: 0,
+        };
+        thread
+            .write_context(ContextKind::InitRegs, &ContextData { init_regs })
+            .unwrap();
+        thread.unblock().unwrap();
+        run_until(|| !crashing.is_alive());
+
+        // The thread is terminated, and released by the CPU which ran it.
+        assert!(!thread.is_runnable());
+        assert!(wait_for(|| SharedRef::ref_count(&thread) == 1));
+        drop(thread);
+        assert_eq!(SharedRef::ref_count(&crashing), 1);
+    }
+
+    #[test]
+    fn reload_waits_for_pending_stop() {
+        arch::init_for_test();
+        let name = "reload_waits_for_pending_stop";
+        let old = Server::new_for_test(name);
+        old.start(&mut SERVERS.lock()).unwrap();
+        run_until(|| old.mutable.lock().spec.is_some());
+
+        let heap_len = 2 * MIN_PAGE_SIZE;
+        let heap = old.quota.alloc(heap_len, PageType::Dirty).unwrap();
+        old.add_pages(heap, heap_len).unwrap();
+        assert_eq!(old.quota.usage().used, heap_len);
+
+        old.stop().unwrap();
+        assert_eq!(old.stop(), Err(ErrorCode::INVALID_STATE));
+
+        // The new image is queued behind the pending stop.
+        let new = Server::new_for_test(name);
+        install(new.clone()).unwrap();
+        assert!(lookup(name).is_some_and(|server| SharedRef::eq(&server, &old)));
+
+        run_until(|| new.mutable.lock().spec.is_some());
+        assert!(!old.is_alive());
+        assert!(arch::server_image_attrs(old.image_vaddr).is_none());
+        assert_eq!(old.quota.usage().used, 0);
+        assert!(lookup(name).is_some_and(|server| SharedRef::eq(&server, &new)));
+
+        new.stop().unwrap();
+        run_until(|| !new.is_alive());
+        assert!(arch::server_image_attrs(new.image_vaddr).is_none());
+        assert!(lookup(name).is_none());
+    }
+
+    #[test]
+    fn reload_supersedes_queued_image() {
+        arch::init_for_test();
+        let name = "reload_supersedes_queued_image";
+        let old = Server::new_for_test(name);
+        old.start(&mut SERVERS.lock()).unwrap();
+
+        // The running server is stopped by the first reload.
+        let first = Server::new_for_test(name);
+        install(first.clone()).unwrap();
+        let second = Server::new_for_test(name);
+        install(second.clone()).unwrap();
+        assert!(!first.is_alive());
+        assert!(arch::server_image_attrs(first.image_vaddr).is_none());
+
+        run_until(|| second.mutable.lock().spec.is_some());
+        assert!(!old.is_alive());
+        assert!(first.mutable.lock().spec.is_none());
+        assert!(lookup(name).is_some_and(|server| SharedRef::eq(&server, &second)));
+
+        second.stop().unwrap();
+        run_until(|| !second.is_alive());
     }
 }
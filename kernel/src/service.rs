@@ -0,0 +1,134 @@
+//! Named services.
+//!
+//! A server publishes a handle (typically a channel endpoint) under a service
+//! name, and other servers look it up by the name. Who can provide and use
+//! each service is defined by the boot-time policy (see [`crate::policy`]).
+//! Services not listed in the policy can be neither registered nor looked up.
+use alloc::collections::btree_map::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+use ftl_api::error::ErrorCode;
+use ftl_api::handle::Handle;
+use ftl_api::handle::HandleRight;
+use ftl_utils::spinlock::SpinLock;
+
+use crate::handle::AnyHandle;
+use crate::policy;
+use crate::server;
+
+/// The maximum length of a service name.
+const SERVICE_NAME_LEN_MAX: usize = 64;
+
+/// Registered services, indexed by service name.
+static SERVICES: SpinLock<BTreeMap<String, AnyHandle>> = SpinLock::new(BTreeMap::new());
+
+/// Checks if the current server is allowed to stop or reload the server
+/// `name`: it's allowed if the server can look up the service `server/<name>`.
+pub fn authorize_control(name: &str) -> Result<(), ErrorCode> {
+    let service = format!("server/{}", name);
+    validate_name(&service)?;
+
+    let server = server::current();
+    match policy::service_rule(&service) {
+        Some(rule) if rule.allows(server.name()) => Ok(()),
+        _ => Err(ErrorCode::NOT_ALLOWED),
+    }
+}
+
+/// Checks if the current server is allowed to use the kernel feature `name`:
+/// it's allowed if the server can look up the service `kernel/<name>`.
+pub fn authorize_kernel(name: &str) -> Result<(), ErrorCode> {
+    let service = format!("kernel/{}", name);
+    let server = server::current();
+    match policy::service_rule(&service) {
+        Some(rule) if rule.allows(server.name()) => Ok(()),
+        _ => Err(ErrorCode::NOT_ALLOWED),
+    }
+}
+
+/// Removes the services provided by a server.
+pub fn unregister_all(provider: &str) {
+    let removed: Vec<AnyHandle> = {
+        let mut services = SERVICES.lock();
+        let names: Vec<String> = services
+            .keys()
+            .filter(|name| policy::service_rule(name).is_some_and(|rule| rule.provider == provider))
+            .cloned()
+            .collect();
+
+        names
+            .iter()
+            .filter_map(|name| services.remove(name))
+            .collect()
+    };
+
+    // Drop the handles after releasing the locks.
+    drop(removed);
+}
+
+fn validate_name(name: &str) -> Result<(), ErrorCode> {
+    if name.is_empty() || name.len() > SERVICE_NAME_LEN_MAX {
+        return Err(ErrorCode::INVALID_ARG);
+    }
+
+    Ok(())
+}
+
+/// Publishes a duplicate of `handle` as a service.
+///
+/// The provider can withdraw the service by revoking `handle`: it also
+/// revokes the handles given to the clients.
+pub fn register(name: &str, handle: &Handle) -> Result<(), ErrorCode> {
+    validate_name(name)?;
+
+    let server = server::current();
+    match policy::service_rule(name) {
+        Some(rule) if rule.provider == server.name() => {}
+        _ => return Err(ErrorCode::NOT_ALLOWED),
+    }
+
+    let handle = server
+        .handles()
+        .lock()
+        .get(handle)?
+        .duplicate(HandleRight::ALL)?;
+
+    let mut services = SERVICES.lock();
+    if let Some(existing) = services.get(name)
+        && !existing.is_revoked()
+    {
+        return Err(ErrorCode::ALREADY_EXISTS);
+    }
+
+    services.insert(name.to_string(), handle);
+    Ok(())
+}
+
+/// Looks up a service, and returns a new handle to it.
+pub fn lookup(name: &str) -> Result<Handle, ErrorCode> {
+    validate_name(name)?;
+
+    let server = server::current();
+    match policy::service_rule(name) {
+        Some(rule) if rule.allows(server.name()) => {}
+        _ => return Err(ErrorCode::NOT_ALLOWED),
+    }
+
+    let handle = {
+        let services = SERVICES.lock();
+        let Some(service) = services.get(name) else {
+            return Err(ErrorCode::NOT_FOUND);
+        };
+
+        if service.is_revoked() {
+            return Err(ErrorCode::REVOKED);
+        }
+
+        service.duplicate(HandleRight::ALL)?
+    };
+
+    server.handles().lock().insert(handle)
+}
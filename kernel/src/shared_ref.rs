@@ -2,6 +2,8 @@
 use alloc::alloc::Layout;
 use alloc::alloc::alloc;
 use alloc::boxed::Box;
+use core::any::Any;
+use core::any::TypeId;
 use core::fmt;
 use core::marker::Unsize;
 use core::mem;
@@ -18,6 +20,9 @@ use ftl_api::error::ErrorCode;
 use ftl_api::handle::Handle;
 use ftl_api::handle::HandleRight;
 
+use crate::handle::AnyHandle;
+use crate::server;
+
 /// The storage for a reference-counted object.
 ///
 /// `SharedRef<T>`s store a pointer to this struct.
@@ -107,6 +112,12 @@ impl<T> SharedRef<T> {
         core::ptr::eq(a.ptr.as_ptr(), b.ptr.as_ptr())
     }
 
+    /// Returns the number of references to the object.
+    #[cfg(test)]
+    pub fn ref_count(this: &SharedRef<T>) -> usize {
+        this.inner().counter.load(Ordering::Relaxed)
+    }
+
     /// Consumes the SharedRef, returning the wrapped pointer.
     ///
     /// To avoid a memory leak the pointer must be converted back to a SharedRef
@@ -152,57 +163,46 @@ impl<T> SharedRef<T> {
         // ManuallyDrop.
         SharedRef::clone(&borrowed)
     }
+}
 
-    /// Unwraps a borrowed handle into a reference to the kernel object.
-    pub fn from_borrowed_handle(
-        handle: &Handle,
-        action: HandleRight,
-    ) -> Result<SharedRef<T>, ErrorCode>
-    where
-        T: 'static,
-    {
-        // TODO: Is it safe to skip this check, assuming that ftl_api
-        // guarantees that the handle has the correct type?
-        if !handle.is_type::<T>() {
-            return Err(ErrorCode::INVALID_TYPE);
-        }
-
-        if !handle.authorize(action) {
-            return Err(ErrorCode::NOT_ALLOWED);
-        }
-
-        // SAFETY: &Handle ensures that the object is still alive,
-        //         and we checked the handle has the correct type.
-        Ok(unsafe { Self::clone_from_raw(handle.raw() as *const T) })
-    }
-
-    /// Unwraps an owned handle into a reference to the kernel object.
-    pub fn from_moved_handle(handle: Handle) -> Result<SharedRef<T>, ErrorCode>
-    where
-        T: 'static,
-    {
-        if !handle.is_type::<T>() {
-            // TODO: Free the shared reference to the object.
-            //
-            // TODO: Is it safe to skip this check, assuming that ftl_api
-            // guarantees that the handle has the correct type?
-            return Err(ErrorCode::INVALID_TYPE);
+impl SharedRef<dyn Any + Send + Sync> {
+    /// Converts into a reference to the concrete type `T`.
+    ///
+    /// Returns `self` back if the object is not a `T`.
+    pub fn downcast<T: Any + Send + Sync>(self) -> Result<SharedRef<T>, Self> {
+        if (*self).type_id() != TypeId::of::<T>() {
+            return Err(self);
         }
 
-        let ptr = handle.raw() as *const T;
-        Ok(unsafe { Self::from_raw(ptr) })
+        let ptr = self.ptr.cast::<RefCounted<T>>();
+        // Move the reference count to the new SharedRef.
+        mem::forget(self);
+        Ok(SharedRef { ptr })
     }
 }
 
 /// A kernel object that can be exposed to servers as a [`Handle`].
-pub trait Handleable: Sized + 'static {
+pub trait Handleable: Send + Sync + Sized + 'static {
     const DEFAULT_RIGHT: HandleRight;
 }
 
 impl<T: Handleable> SharedRef<T> {
-    pub fn into_handle(self) -> Handle {
-        let raw = self.into_raw() as usize;
-        Handle::new::<T>(raw, T::DEFAULT_RIGHT)
+    /// Looks up a handle in the current server's handle table.
+    pub fn from_borrowed_handle(
+        handle: &Handle,
+        action: HandleRight,
+    ) -> Result<SharedRef<T>, ErrorCode> {
+        let server = server::current();
+        let table = server.handles().lock();
+        table.get(handle)?.downcast(action)
+    }
+
+    /// Adds the object to the current server's handle table.
+    pub fn into_handle(self) -> Result<Handle, ErrorCode> {
+        let handle = AnyHandle::new(self)?;
+        let server = server::current();
+        let mut table = server.handles().lock();
+        table.insert(handle)
     }
 }
 
@@ -277,3 +277,51 @@ unsafe impl<T: Sync + Send + ?Sized> Sync for SharedRef<T> {}
 unsafe impl<T: Sync + Send + ?Sized> Send for SharedRef<T> {}
 
 impl<T: ?Sized + Unsize<U>, U: ?Sized> CoerceUnsized<SharedRef<U>> for SharedRef<T> {}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::AtomicBool;
+
+    use super::*;
+
+    struct DropFlag<'a>(&'a AtomicBool);
+
+    impl Drop for DropFlag<'_> {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn drop_last_reference() {
+        let dropped = AtomicBool::new(false);
+        let ref0 = SharedRef::new(DropFlag(&dropped)).unwrap();
+        let ref1 = ref0.clone();
+        assert!(SharedRef::eq(&ref0, &ref1));
+
+        drop(ref0);
+        assert!(!dropped.load(Ordering::Relaxed));
+        drop(ref1);
+        assert!(dropped.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn raw_round_trip() {
+        let dropped = AtomicBool::new(false);
+        let ptr = SharedRef::new(DropFlag(&dropped)).unwrap().into_raw();
+
+        let cloned = unsafe { SharedRef::clone_from_raw(ptr) };
+        drop(cloned);
+        assert!(!dropped.load(Ordering::Relaxed));
+
+        drop(unsafe { SharedRef::from_raw(ptr) });
+        assert!(dropped.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn downcast() {
+        let object: SharedRef<dyn Any + Send + Sync> = SharedRef::new(42u32).unwrap();
+        let object = object.downcast::<u64>().unwrap_err();
+        assert_eq!(*object.downcast::<u32>().ok().unwrap(), 42);
+    }
+}
@@ -0,0 +1,55 @@
+//! Server image signatures.
+//!
+//! A server image `servers/<name>.elf` in initfs comes with a detached Ed25519
+//! signature `servers/<name>.elf.sig`, made by `tools/sign-server.sh` at build
+//! time. The kernel refuses images that are unsigned or not signed by the key
+//! given as `FTL_SERVER_PUBKEY` (32 bytes in hex) when building the kernel, or
+//! as `server_pubkey` on the kernel command line.
+use ed25519_compact::PublicKey;
+use ed25519_compact::Signature;
+use ftl_api::error::ErrorCode;
+
+use crate::cmdline;
+
+/// The public key to verify server images, in hex.
+const PUBLIC_KEY: Option<&str> = option_env!("FTL_SERVER_PUBKEY");
+
+/// The file name suffix of detached signatures.
+pub const SIGNATURE_SUFFIX: &[u8] = b".sig";
+
+fn decode_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    let hex = hex.trim().as_bytes();
+    if hex.len() != N * 2 {
+        return None;
+    }
+
+    let mut bytes = [0; N];
+    for (byte, pair) in bytes.iter_mut().zip(hex.chunks_exact(2)) {
+        let pair = core::str::from_utf8(pair).ok()?;
+        *byte = u8::from_str_radix(pair, 16).ok()?;
+    }
+
+    Some(bytes)
+}
+
+fn public_key() -> Option<PublicKey> {
+    let hex = cmdline::get().server_pubkey.or(PUBLIC_KEY)?;
+    let bytes = decode_hex::<{ PublicKey::BYTES }>(hex)?;
+    Some(PublicKey::new(bytes))
+}
+
+/// Checks that `signature` is a valid signature of `elf_file`.
+pub fn verify(elf_file: &[u8], signature: Option<&[u8]>) -> Result<(), ErrorCode> {
+    let Some(public_key) = public_key() else {
+        warn!("no valid server public key is built in");
+        return Err(ErrorCode::BAD_SIGNATURE);
+    };
+
+    let Some(signature) = signature.and_then(|sig| Signature::from_slice(sig).ok()) else {
+        return Err(ErrorCode::BAD_SIGNATURE);
+    };
+
+    public_key
+        .verify(elf_file, &signature)
+        .map_err(|_| ErrorCode::BAD_SIGNATURE)
+}
@@ -1,12 +1,31 @@
 use crate::arch::get_cpuvar;
 use crate::scheduler;
+use crate::upcall;
 
-pub extern "C" fn handle_syscall() -> ! {
+/// The syscall entry, jumped to from the arch's syscall handler.
+///
+/// It's `C-unwind` because the host backend enters the idle loop by
+/// unwinding: see `arch::host::run_until_idle`.
+pub extern "C-unwind" fn handle_syscall() -> ! {
     let cpuvar = get_cpuvar();
     let current = cpuvar.current_thread.thread().unwrap();
 
     cpuvar.current_thread.clear();
-    current.handle_syscall();
+    let (current, ()) = upcall::lend(current, |current| current.handle_syscall());
+    drop(current);
+
+    scheduler::return_to_user();
+}
+
+/// Forwards a CPU exception in the user mode to the server.
+pub fn handle_exception(vector: u8, error_code: u64) -> ! {
+    let cpuvar = get_cpuvar();
+    let current = cpuvar.current_thread.thread().unwrap();
+
+    cpuvar.current_thread.clear();
+    let (current, ()) = upcall::lend(current, |current| {
+        current.handle_exception(vector, error_code);
+    });
     drop(current);
 
     scheduler::return_to_user();
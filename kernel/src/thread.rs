@@ -1,22 +1,32 @@
 use core::cell::UnsafeCell;
+use core::mem;
 use core::mem::offset_of;
 
 use ftl_api::error::ErrorCode;
 use ftl_api::handle::HandleRight;
+use ftl_api::interceptor::Action;
 use ftl_api::thread::ContextData;
 use ftl_api::thread::ContextKind;
+use ftl_api::thread::SyscallArgs;
+use ftl_api::thread::Sysret;
 use ftl_api::thread::UpcallArg;
 use ftl_api::upcall::Upcall;
 use ftl_utils::spinlock::SpinLock;
 use ftl_utils::static_assert;
 
 use crate::arch;
+use crate::interceptor::Chain;
+use crate::interceptor::Interceptor;
+use crate::interceptor::InterceptorList;
 use crate::scheduler::SCHEDULER;
 use crate::shared_ref::Handleable;
 use crate::shared_ref::SharedRef;
+use crate::upcall;
+use crate::upcall::PendingUpcall;
+use crate::upcall::ServerUpcall;
 use crate::vmspace::VmSpace;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum State {
     Runnable,
     Blocked,
@@ -30,6 +40,40 @@ struct Mutable {
     /// While in_upcalls > 0, the thread must not be terminated since it frees
     /// the upcall's user data while it is being referenced.
     in_upcalls: usize,
+    /// The interceptors which have passed the current syscall. They will see
+    /// the return value.
+    intercepted: Chain,
+    /// Whether the return value is being passed back to `intercepted`. The
+    /// thread stays blocked until it's done.
+    returning: bool,
+    /// Whether the thread is stopped by [`Thread::suspend`]. Orthogonal to
+    /// `state`: a suspended thread does not run even if it's runnable.
+    suspended: bool,
+    /// The CPU which has the thread as the current thread. The CPU may be
+    /// running the kernel, e.g. an interrupt handler.
+    running_on: Option<usize>,
+    /// Whether the thread is in the runqueue. It's pushed at most once, and
+    /// the flag is cleared in [`Thread::mark_running`] after it's popped.
+    enqueued: bool,
+}
+
+impl Mutable {
+    /// Whether the thread won't run until the server resumes it, i.e. its
+    /// context can be accessed.
+    fn is_stopped(&self) -> bool {
+        match self.state {
+            State::Blocked => true,
+            // Another CPU might be still running the thread. This CPU is
+            // in the kernel, and has saved the context.
+            State::Runnable => {
+                self.suspended
+                    && self
+                        .running_on
+                        .is_none_or(|cpu_id| cpu_id == arch::get_cpuvar().cpu_id)
+            }
+            State::Terminated => false,
+        }
+    }
 }
 
 #[repr(C)]
@@ -39,8 +83,9 @@ pub struct Thread {
     /// This is an [`UnsafeCell`] because the interrupt handler updates this
     /// field directly.
     arch: UnsafeCell<arch::Thread>,
-    upcall: Upcall<UpcallArg>,
+    upcall: ServerUpcall<UpcallArg>,
     vmspace: SharedRef<VmSpace>,
+    interceptors: InterceptorList,
     mutable: SpinLock<Mutable>,
 }
 
@@ -60,12 +105,18 @@ impl Thread {
         let mutable = Mutable {
             state: State::Blocked,
             in_upcalls: 0,
+            intercepted: Chain::new(),
+            returning: false,
+            suspended: false,
+            running_on: None,
+            enqueued: false,
         };
 
         let thread = SharedRef::new(Thread {
             arch: UnsafeCell::new(arch::Thread::new()),
             vmspace,
-            upcall,
+            upcall: ServerUpcall::new(upcall),
+            interceptors: InterceptorList::new(),
             mutable: SpinLock::new(mutable),
         })?;
 
@@ -75,18 +126,91 @@ impl Thread {
     pub fn is_runnable(&self) -> bool {
         // TODO: Avoid locking the spin lock.
         let mutable = self.mutable.lock();
-        matches!(mutable.state, State::Runnable)
+        matches!(mutable.state, State::Runnable) && !mutable.suspended
+    }
+
+    /// Pushes the thread to the runqueue unless it's already there.
+    fn enqueue(self: &SharedRef<Self>, mutable: &mut Mutable) -> Result<(), ErrorCode> {
+        if !mutable.enqueued {
+            SCHEDULER.push_back(self.clone())?;
+            mutable.enqueued = true;
+        }
+
+        Ok(())
+    }
+
+    /// Pushes the current thread back to the front of the runqueue if it's
+    /// still runnable.
+    pub fn requeue(self: &SharedRef<Self>) -> Result<(), ErrorCode> {
+        let mut mutable = self.mutable.lock();
+        if mutable.state != State::Runnable || mutable.suspended || mutable.enqueued {
+            return Ok(());
+        }
+
+        SCHEDULER.push_front(self.clone())?;
+        mutable.enqueued = true;
+        Ok(())
+    }
+
+    /// Marks the thread as running on this CPU if it's runnable. Returns
+    /// false if it's not runnable, or is running on another CPU.
+    ///
+    /// Called right after the thread is popped from the runqueue. Clearing
+    /// `enqueued` here, with the state check under the same lock, makes sure
+    /// a thread made runnable in between is either picked or pushed again.
+    pub fn mark_running(&self) -> bool {
+        let cpu_id = arch::get_cpuvar().cpu_id;
+        let mut mutable = self.mutable.lock();
+        mutable.enqueued = false;
+        if mutable.state != State::Runnable
+            || mutable.suspended
+            || mutable.running_on.is_some_and(|id| id != cpu_id)
+        {
+            return false;
+        }
+
+        mutable.running_on = Some(cpu_id);
+        true
+    }
+
+    /// Called when this CPU switches away from the thread.
+    fn mark_not_running(&self) {
+        self.mutable.lock().running_on = None;
     }
 
     pub fn vmspace(&self) -> &SharedRef<VmSpace> {
         &self.vmspace
     }
 
+    pub fn interceptors(&self) -> &InterceptorList {
+        &self.interceptors
+    }
+
     /// Upcalls the syscall handler.
     ///
     /// System call registers are not passed to this method because they can be
     /// read by the [`Self::read_context`] method.
-    pub fn handle_syscall(&self) {
+    pub fn handle_syscall(self: &SharedRef<Self>) {
+        self.block_in_upcall(|| {
+            if self.intercept_syscall() {
+                self.upcall.invoke(UpcallArg::Syscall);
+            }
+        });
+    }
+
+    /// Upcalls the exception handler for a CPU exception in the user mode.
+    ///
+    /// Like syscalls, the thread is blocked until the server unblocks it.
+    /// Exceptions are not passed through interceptors.
+    pub fn handle_exception(self: &SharedRef<Self>, vector: u8, error_code: u64) {
+        self.block_in_upcall(|| {
+            self.upcall
+                .invoke(UpcallArg::Exception { vector, error_code });
+        });
+    }
+
+    /// Blocks the thread and calls `upcall`, which upcalls the server.
+    fn block_in_upcall(self: &SharedRef<Self>, upcall: impl FnOnce()) {
         // Mark the thread as blocked and mark it as being referenced
         // (in_upcalls > 0).
         {
@@ -100,14 +224,22 @@ impl Thread {
             mutable.in_upcalls += 1;
         }
 
-        self.upcall.invoke(UpcallArg::Syscall);
+        upcall::with_unwind(
+            || {
+                // The server panicked. The syscall is left unhandled.
+                let mut mutable = self.mutable.lock();
+                mutable.in_upcalls -= 1;
+                self.terminate_abandoned(&mut mutable);
+            },
+            upcall,
+        );
 
         // Check if the thread is safe to terminate.
         let terminate_now = {
             let mut mutable = self.mutable.lock();
             debug_assert!(mutable.in_upcalls > 0);
             mutable.in_upcalls -= 1;
-            mutable.in_upcalls == 0 && mutable.state == State::Terminated
+            mutable.in_upcalls == 0 && !mutable.returning && mutable.state == State::Terminated
         };
 
         if terminate_now {
@@ -115,38 +247,252 @@ impl Thread {
         }
     }
 
-    /// Asks the kernel to terminate a thread.
-    ///
-    /// When the thread becomes safe to terminate, the kernel will do an
-    /// upcall with [`UpcallArg::Terminated`].
-    pub fn terminate(&self) -> Result<(), ErrorCode> {
-        let terminate_now = {
-            let mut mutable = self.mutable.lock();
-            if mutable.state == State::Terminated {
-                return Err(ErrorCode::INVALID_STATE);
+    /// Passes the current syscall through the interceptors. Returns false if
+    /// an interceptor has completed it or killed the thread.
+    fn intercept_syscall(self: &SharedRef<Self>) -> bool {
+        let mut chain = Chain::new();
+        self.vmspace.interceptors().collect_into(&mut chain);
+        self.interceptors.collect_into(&mut chain);
+
+        // If an interceptor server panics, the chain is dropped.
+        let (chain, passed) = upcall::lend(chain, |chain| self.run_interceptors(chain));
+        if passed {
+            self.mutable.lock().intercepted = chain;
+        }
+
+        passed
+    }
+
+    /// Upcalls the interceptors in `chain` in order. Returns false if one of
+    /// them has completed the syscall or killed the thread.
+    fn run_interceptors(self: &SharedRef<Self>, chain: &Chain) -> bool {
+        for (i, interceptor) in chain.iter().enumerate() {
+            // The interceptors before this one have passed the syscall.
+            let passed = &chain.as_slice()[..i];
+            let Some(args) = self.syscall_args() else {
+                // Terminated by an interceptor.
+                return false;
+            };
+
+            match interceptor.syscall(args) {
+                Action::Continue => {}
+                Action::Modify(args) => {
+                    let regs = ContextData { syscall_args: args };
+                    if let Err(err) = self.write_context(ContextKind::SyscallArgs, &regs) {
+                        warn!("failed to modify syscall arguments: {:?}", err);
+                        self.mutable.lock().state = State::Terminated;
+                        return false;
+                    }
+                }
+                Action::Complete(sysret) => {
+                    let regs = ContextData { sysret };
+                    if let Err(err) = self.write_context(ContextKind::Sysret, &regs) {
+                        warn!("failed to complete a syscall: {:?}", err);
+                        self.mutable.lock().state = State::Terminated;
+                        return false;
+                    }
+
+                    self.return_through(passed);
+
+                    // Resume the thread without the personality server.
+                    let mut mutable = self.mutable.lock();
+                    if mutable.state == State::Blocked {
+                        mutable.state = match self.enqueue(&mut mutable) {
+                            Ok(()) => State::Runnable,
+                            Err(err) => {
+                                // `handle_syscall` notifies the personality
+                                // server.
+                                warn!("failed to resume a thread: {:?}", err);
+                                State::Terminated
+                            }
+                        };
+                    }
+
+                    return false;
+                }
+                Action::Kill => {
+                    // `handle_syscall` notifies the personality server.
+                    trace!("interceptor killed a thread (n={})", args.n);
+                    self.mutable.lock().state = State::Terminated;
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+
+    fn syscall_args(&self) -> Option<SyscallArgs> {
+        let mut regs = ContextData {
+            syscall_args: SyscallArgs::zeroed(),
+        };
+
+        self.read_context(ContextKind::SyscallArgs, &mut regs)
+            .ok()?;
+        Some(unsafe { regs.syscall_args })
+    }
+
+    /// Passes the return value back through `interceptors` in the reverse
+    /// order.
+    fn return_through(&self, interceptors: &[SharedRef<Interceptor>]) {
+        for interceptor in interceptors.iter().rev() {
+            let Some(args) = self.syscall_args() else {
+                // Terminated by an interceptor.
+                return;
+            };
+
+            let mut regs = ContextData {
+                sysret: Sysret::zeroed(),
+            };
+            if self.read_context(ContextKind::Sysret, &mut regs).is_err() {
+                return;
             }
 
-            mutable.state = State::Terminated;
+            let sysret = interceptor.sysret(args, unsafe { regs.sysret });
+            let _ = self.write_context(ContextKind::Sysret, &ContextData { sysret });
+        }
+    }
+
+    /// Resumes the thread after passing the return value back through the
+    /// interceptors. Scheduled by [`Thread::unblock`].
+    fn return_from_syscall(self: &SharedRef<Self>) {
+        let intercepted = mem::take(&mut self.mutable.lock().intercepted);
+        upcall::with_unwind(
+            || {
+                let mut mutable = self.mutable.lock();
+                mutable.returning = false;
+                self.terminate_abandoned(&mut mutable);
+            },
+            || {
+                upcall::lend(intercepted, |intercepted| {
+                    self.return_through(intercepted.as_slice());
+                })
+            },
+        );
 
-            // We can't terminate right now while it is being processed in
-            // an upcall handler.
-            mutable.in_upcalls == 0
+        let terminate_now = {
+            let mut mutable = self.mutable.lock();
+            mutable.returning = false;
+            match mutable.state {
+                State::Blocked => {
+                    match self.enqueue(&mut mutable) {
+                        Ok(()) => {
+                            mutable.state = State::Runnable;
+                            false
+                        }
+                        Err(err) => {
+                            warn!("failed to resume a thread: {:?}", err);
+                            mutable.state = State::Terminated;
+                            mutable.in_upcalls == 0
+                        }
+                    }
+                }
+                State::Terminated => mutable.in_upcalls == 0,
+                State::Runnable => unreachable!(),
+            }
         };
 
         if terminate_now {
             self.upcall.invoke(UpcallArg::Terminated);
         }
+    }
+
+    /// Terminates the thread after a server panicked in its upcall, which
+    /// the thread was blocked in.
+    fn terminate_abandoned(self: &SharedRef<Self>, mutable: &mut Mutable) {
+        mutable.state = State::Terminated;
+        if mutable.in_upcalls == 0
+            && !mutable.returning
+            && let Err(err) = upcall::schedule(self.clone())
+        {
+            warn!("failed to schedule a terminated thread: {:?}", err);
+        }
+    }
+
+    /// Asks the kernel to terminate a thread.
+    ///
+    /// When the thread becomes safe to terminate, the kernel will do an
+    /// upcall with [`UpcallArg::Terminated`]. The upcall is deferred until
+    /// the current supercall returns, and is not delivered if the server has
+    /// been stopped.
+    pub fn terminate(self: &SharedRef<Self>) -> Result<(), ErrorCode> {
+        let mut mutable = self.mutable.lock();
+        if mutable.state == State::Terminated {
+            return Err(ErrorCode::INVALID_STATE);
+        }
+
+        // We can't terminate right now while it is being processed in an
+        // upcall handler. `handle_syscall` does it when the handler returns,
+        // or `return_from_syscall` when the interceptors have seen the return
+        // value.
+        if mutable.in_upcalls == 0 && !mutable.returning {
+            upcall::schedule(self.clone())?;
+        }
+
+        mutable.state = State::Terminated;
+        Ok(())
+    }
+
+    /// Stops the thread until [`Thread::resume`] is called. Its context can
+    /// be accessed once the CPU running it switches away from the thread.
+    ///
+    /// Suspending a thread running on another CPU is not implemented: it
+    /// needs an IPI to make that CPU enter the scheduler. It doesn't happen
+    /// for now because only the BSP runs threads.
+    pub fn suspend(&self) -> Result<(), ErrorCode> {
+        let mut mutable = self.mutable.lock();
+        if mutable.state == State::Terminated || mutable.suspended {
+            return Err(ErrorCode::INVALID_STATE);
+        }
+
+        debug_assert!(
+            mutable
+                .running_on
+                .is_none_or(|cpu_id| cpu_id == arch::get_cpuvar().cpu_id),
+            "cross-CPU suspend is not implemented"
+        );
+
+        mutable.suspended = true;
+        Ok(())
+    }
+
+    /// Resumes the thread stopped by [`Thread::suspend`].
+    pub fn resume(self: &SharedRef<Self>) -> Result<(), ErrorCode> {
+        let mut mutable = self.mutable.lock();
+        if mutable.state == State::Terminated || !mutable.suspended {
+            return Err(ErrorCode::INVALID_STATE);
+        }
+
+        // If this CPU has the thread as the current thread, `return_to_user`
+        // pushes it back to the runqueue. A blocked thread is pushed when
+        // it's unblocked.
+        let is_current = mutable.running_on == Some(arch::get_cpuvar().cpu_id);
+        if mutable.state == State::Runnable && !is_current {
+            self.enqueue(&mut mutable)?;
+        }
+
+        mutable.suspended = false;
         Ok(())
     }
 
     /// Resumes the thread.
     pub fn unblock(self: &SharedRef<Self>) -> Result<(), ErrorCode> {
         let mut mutable = self.mutable.lock();
-        if mutable.state != State::Blocked {
+        if mutable.state != State::Blocked || mutable.returning {
             return Err(ErrorCode::INVALID_STATE);
         }
 
-        SCHEDULER.push_back(self.clone())?;
+        if !mutable.intercepted.is_empty() {
+            // Pass the return value back through the interceptors first.
+            let pending = SharedRef::new(PendingSysret {
+                thread: self.clone(),
+            })?;
+            upcall::schedule(pending)?;
+            mutable.returning = true;
+            return Ok(());
+        }
+
+        self.enqueue(&mut mutable)?;
         mutable.state = State::Runnable;
 
         Ok(())
@@ -155,11 +501,11 @@ impl Thread {
     /// Reads the thread's context such as general-purpose registers.
     pub fn read_context(&self, kind: ContextKind, regs: &mut ContextData) -> Result<(), ErrorCode> {
         let mutable = self.mutable.lock();
-        if mutable.state != State::Blocked {
+        if !mutable.is_stopped() {
             return Err(ErrorCode::INVALID_STATE);
         }
 
-        // SAFETY: The thread is blocked and we hold the `mutable` lock.
+        // SAFETY: The thread is stopped and we hold the `mutable` lock.
         unsafe {
             (*self.arch.get()).read_context(kind, regs);
         }
@@ -169,20 +515,42 @@ impl Thread {
     /// Writes the thread's context such as general-purpose registers.
     pub fn write_context(&self, kind: ContextKind, regs: &ContextData) -> Result<(), ErrorCode> {
         let mutable = self.mutable.lock();
-        if mutable.state != State::Blocked {
+        if !mutable.is_stopped() {
             return Err(ErrorCode::INVALID_STATE);
         }
 
-        // SAFETY: The thread is blocked and we hold the `mutable` lock.
-        unsafe {
-            (*self.arch.get()).write_context(kind, regs);
-        }
-        Ok(())
+        // SAFETY: The thread is stopped and we hold the `mutable` lock.
+        unsafe { (*self.arch.get()).write_context(kind, regs) }
+    }
+}
+
+impl PendingUpcall for Thread {
+    fn deliver(&self) {
+        self.upcall.invoke(UpcallArg::Terminated);
+    }
+}
+
+/// Passes the return value of an intercepted syscall back through the
+/// interceptors, and then resumes the thread.
+struct PendingSysret {
+    thread: SharedRef<Thread>,
+}
+
+impl PendingUpcall for PendingSysret {
+    fn deliver(&self) {
+        self.thread.return_from_syscall();
     }
 }
 
 impl Handleable for Thread {
-    const DEFAULT_RIGHT: HandleRight = HandleRight::READ.or(HandleRight::WRITE);
+    const DEFAULT_RIGHT: HandleRight = HandleRight::READ
+        .or(HandleRight::UNBLOCK)
+        .or(HandleRight::TERMINATE)
+        .or(HandleRight::SET_CONTEXT)
+        .or(HandleRight::INTERCEPT)
+        .or(HandleRight::SUSPEND)
+        .or(HandleRight::TRANSFER)
+        .or(HandleRight::DUPLICATE);
 }
 
 /// The current thread.
@@ -214,7 +582,8 @@ impl CurrentThread {
 
         // Release the ref count of the previous thread.
         if !old_ptr.is_null() {
-            drop(unsafe { SharedRef::from_raw(old_ptr) });
+            let old = unsafe { SharedRef::from_raw(old_ptr) };
+            old.mark_not_running();
         }
     }
 
@@ -258,7 +627,10 @@ impl CurrentThread {
 
         // Decrement the ref count of the current thread.
         if !old_ptr.is_null() {
-            drop(unsafe { SharedRef::from_raw(old_ptr) });
+            let old = unsafe { SharedRef::from_raw(old_ptr) };
+            if old_ptr != new_ptr {
+                old.mark_not_running();
+            }
         }
     }
 
@@ -280,3 +652,369 @@ impl CurrentThread {
         arch::Thread::enter(arch_thread);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::AtomicBool;
+    use core::sync::atomic::AtomicUsize;
+    use core::sync::atomic::Ordering;
+
+    use ftl_api::interceptor;
+    use ftl_api::thread::InitRegs;
+    use ftl_api::upcall::UpCallCtx;
+
+    use super::*;
+    use crate::quota::Quota;
+    use crate::server;
+    use crate::server::Server;
+
+    const SYSCALL_N: u64 = 42;
+
+    /// The thread under test. Upcalls without user data can't tell which
+    /// thread they are for.
+    static THREAD: SpinLock<Option<SharedRef<Thread>>> = SpinLock::new(None);
+    static NUM_SYSCALLS: AtomicUsize = AtomicUsize::new(0);
+    static TERMINATED: AtomicBool = AtomicBool::new(false);
+
+    /// Makes a syscall with the previous return value as the argument.
+    fn user_program(thread: &mut arch::Thread) {
+        thread.args[0] = thread.n;
+        thread.n = SYSCALL_N;
+    }
+
+    /// Returns the argument plus one, and terminates the thread once it
+    /// reaches 3.
+    extern "Rust" fn handle_upcall(_ctx: UpCallCtx, arg: UpcallArg) {
+        let thread = THREAD.lock().clone().unwrap();
+        match arg {
+            UpcallArg::Syscall => {
+                NUM_SYSCALLS.fetch_add(1, Ordering::SeqCst);
+                let args = thread.syscall_args().unwrap();
+                assert_eq!(args.n, SYSCALL_N);
+                if args.arg0 < 3 {
+                    let sysret = Sysret {
+                        retval: args.arg0 + 1,
+                    };
+                    thread
+                        .write_context(ContextKind::Sysret, &ContextData { sysret })
+                        .unwrap();
+                    thread.unblock().unwrap();
+                } else {
+                    thread.terminate().unwrap();
+                }
+            }
+            UpcallArg::Terminated => {
+                THREAD.lock().take();
+                TERMINATED.store(true, Ordering::SeqCst);
+            }
+            UpcallArg::Exception { vector, .. } => panic!("unexpected exception {}", vector),
+        }
+    }
+
+    #[test]
+    fn syscall_upcalls() {
+        arch::init_for_test();
+        let server = Server::new_for_test("test");
+        let thread = server::run_as(&server, || {
+            let quota = Quota::new(None, None).unwrap();
+            let vmspace = SharedRef::new(VmSpace::new(quota).unwrap()).unwrap();
+            Thread::new(vmspace, Upcall::from_fn(handle_upcall)).unwrap()
+        });
+
+        let program: fn(&mut arch::Thread) = user_program;
+        let init_regs = InitRegs {
+            pc: program as usize as u64,
+            sp: 0,
+        };
+        thread
+            .write_context(ContextKind::InitRegs, &ContextData { init_regs })
+            .unwrap();
+        *THREAD.lock() = Some(thread.clone());
+        thread.unblock().unwrap();
+        drop(thread);
+
+        // Tests share the runqueue: another test's CPU (host thread) might
+        // run the thread instead.
+        while !TERMINATED.load(Ordering::SeqCst) {
+            arch::run_until_idle();
+            std::thread::yield_now();
+        }
+
+        // Three syscalls returned 1, 2, and 3, and the last one terminated
+        // the thread.
+        assert_eq!(NUM_SYSCALLS.load(Ordering::SeqCst), 4);
+        assert!(THREAD.lock().is_none());
+    }
+
+    const N_CONTINUE: u64 = 1;
+    const N_MODIFY: u64 = 2;
+    const N_COMPLETE: u64 = 3;
+    const N_KILL: u64 = 4;
+
+    extern "Rust" fn ignore_upcall(_ctx: UpCallCtx, _arg: UpcallArg) {}
+
+    /// The first interceptor: increments `arg0` of [`N_MODIFY`], and doubles
+    /// return values.
+    extern "Rust" fn first_interceptor(_ctx: UpCallCtx, arg: interceptor::UpcallArg) -> Action {
+        match arg {
+            interceptor::UpcallArg::Syscall(mut args) if args.n == N_MODIFY => {
+                args.arg0 += 1;
+                Action::Modify(args)
+            }
+            interceptor::UpcallArg::Sysret(_, sysret) => {
+                Action::Complete(Sysret {
+                    retval: sysret.retval * 2,
+                })
+            }
+            _ => Action::Continue,
+        }
+    }
+
+    /// The second interceptor: completes [`N_COMPLETE`] with 5, kills the
+    /// thread on [`N_KILL`], and increments return values.
+    extern "Rust" fn second_interceptor(_ctx: UpCallCtx, arg: interceptor::UpcallArg) -> Action {
+        match arg {
+            interceptor::UpcallArg::Syscall(args) if args.n == N_COMPLETE => {
+                Action::Complete(Sysret { retval: 5 })
+            }
+            interceptor::UpcallArg::Syscall(args) if args.n == N_KILL => Action::Kill,
+            interceptor::UpcallArg::Sysret(_, sysret) => {
+                Action::Complete(Sysret {
+                    retval: sysret.retval + 1,
+                })
+            }
+            _ => Action::Continue,
+        }
+    }
+
+    /// Creates a thread with the first interceptor attached to its VmSpace,
+    /// and the second one attached to the thread itself.
+    fn intercepted_thread(server: &SharedRef<Server>) -> SharedRef<Thread> {
+        server::run_as(server, || {
+            let quota = Quota::new(None, None).unwrap();
+            let vmspace = SharedRef::new(VmSpace::new(quota).unwrap()).unwrap();
+            let thread = Thread::new(vmspace, Upcall::from_fn(ignore_upcall)).unwrap();
+            let first = Interceptor::new(Upcall::from_fn(first_interceptor)).unwrap();
+            let second = Interceptor::new(Upcall::from_fn(second_interceptor)).unwrap();
+            thread.vmspace().interceptors().attach(first).unwrap();
+            thread.interceptors().attach(second).unwrap();
+            thread
+        })
+    }
+
+    /// Makes a syscall on the current (host) thread. The thread is suspended
+    /// so that other tests' CPUs don't run it once it's resumed.
+    fn syscall(thread: &SharedRef<Thread>, n: u64, arg0: u64) {
+        let mut syscall_args = SyscallArgs::zeroed();
+        syscall_args.n = n;
+        syscall_args.arg0 = arg0;
+        thread
+            .write_context(ContextKind::SyscallArgs, &ContextData { syscall_args })
+            .unwrap();
+
+        {
+            let mut mutable = thread.mutable.lock();
+            mutable.state = State::Runnable;
+            mutable.suspended = true;
+        }
+
+        thread.handle_syscall();
+    }
+
+    /// Returns the thread state. It's blocked while the return value is
+    /// being passed back.
+    fn state(thread: &Thread) -> State {
+        let mutable = thread.mutable.lock();
+        if mutable.returning {
+            State::Blocked
+        } else {
+            mutable.state
+        }
+    }
+
+    fn sysret(thread: &Thread) -> u64 {
+        let mut regs = ContextData {
+            sysret: Sysret::zeroed(),
+        };
+        thread.read_context(ContextKind::Sysret, &mut regs).unwrap();
+        unsafe { regs.sysret.retval }
+    }
+
+    #[test]
+    fn intercept_modify_and_sysret() {
+        arch::init_for_test();
+        let server = Server::new_for_test("intercept_modify_and_sysret");
+        let thread = intercepted_thread(&server);
+
+        // The personality server sees the modified arguments.
+        syscall(&thread, N_MODIFY, 41);
+        assert_eq!(state(&thread), State::Blocked);
+        assert_eq!(thread.syscall_args().unwrap().arg0, 42);
+        assert_eq!(thread.mutable.lock().intercepted.len(), 2);
+
+        // The return value goes back through the second interceptor, and
+        // then the first one.
+        thread
+            .write_context(
+                ContextKind::Sysret,
+                &ContextData {
+                    sysret: Sysret { retval: 10 },
+                },
+            )
+            .unwrap();
+        thread.unblock().unwrap();
+        while state(&thread) != State::Runnable {
+            arch::run_until_idle();
+            std::thread::yield_now();
+        }
+
+        assert_eq!(sysret(&thread), (10 + 1) * 2);
+        assert!(thread.mutable.lock().intercepted.is_empty());
+    }
+
+    #[test]
+    fn intercept_complete() {
+        arch::init_for_test();
+        let server = Server::new_for_test("intercept_complete");
+        let thread = intercepted_thread(&server);
+
+        // The personality server doesn't see the syscall, and the return
+        // value goes back through the first interceptor only.
+        syscall(&thread, N_COMPLETE, 0);
+        assert_eq!(state(&thread), State::Runnable);
+        assert_eq!(sysret(&thread), 5 * 2);
+        assert!(thread.mutable.lock().intercepted.is_empty());
+    }
+
+    #[test]
+    fn intercept_kill() {
+        arch::init_for_test();
+        let server = Server::new_for_test("intercept_kill");
+        let thread = intercepted_thread(&server);
+
+        syscall(&thread, N_KILL, 0);
+        assert_eq!(state(&thread), State::Terminated);
+        assert!(thread.mutable.lock().intercepted.is_empty());
+    }
+
+    #[test]
+    fn stopped_interceptor_kills_thread() {
+        arch::init_for_test();
+        let server = Server::new_for_test("stopped_interceptor_kills_thread");
+        let interceptor_server = Server::new_for_test("stopped_interceptor_kills_thread_ic");
+        let thread = intercepted_thread(&server);
+        let interceptor = server::run_as(&interceptor_server, || {
+            Interceptor::new(Upcall::from_fn(first_interceptor)).unwrap()
+        });
+        thread.interceptors().attach(interceptor.clone()).unwrap();
+
+        interceptor_server.stop().unwrap();
+        while interceptor_server.is_alive() {
+            arch::run_until_idle();
+            std::thread::yield_now();
+        }
+
+        syscall(&thread, N_CONTINUE, 0);
+        assert_eq!(state(&thread), State::Terminated);
+
+        // A detached interceptor is skipped instead.
+        let thread = intercepted_thread(&server);
+        let detached = server::run_as(&server, || {
+            Interceptor::new(Upcall::from_fn(first_interceptor)).unwrap()
+        });
+        thread.interceptors().attach(detached.clone()).unwrap();
+        detached.detach().unwrap();
+        syscall(&thread, N_CONTINUE, 0);
+        assert_eq!(state(&thread), State::Blocked);
+        assert_eq!(thread.mutable.lock().intercepted.len(), 2);
+    }
+
+    /// Makes a syscall, which blocks the thread forever: its upcall handler
+    /// ignores it.
+    fn syscall_program(thread: &mut arch::Thread) {
+        thread.n = N_CONTINUE;
+    }
+
+    fn new_thread(server: &SharedRef<Server>) -> SharedRef<Thread> {
+        let thread = server::run_as(server, || {
+            let quota = Quota::new(None, None).unwrap();
+            let vmspace = SharedRef::new(VmSpace::new(quota).unwrap()).unwrap();
+            Thread::new(vmspace, Upcall::from_fn(ignore_upcall)).unwrap()
+        });
+
+        let program: fn(&mut arch::Thread) = syscall_program;
+        let init_regs = InitRegs {
+            pc: program as usize as u64,
+            sp: 0,
+        };
+        thread
+            .write_context(ContextKind::InitRegs, &ContextData { init_regs })
+            .unwrap();
+        thread
+    }
+
+    #[test]
+    fn suspend_and_resume() {
+        arch::init_for_test();
+        let server = Server::new_for_test("suspend_and_resume");
+        let thread = new_thread(&server);
+
+        assert_eq!(thread.resume(), Err(ErrorCode::INVALID_STATE));
+        thread.suspend().unwrap();
+        assert_eq!(thread.suspend(), Err(ErrorCode::INVALID_STATE));
+
+        // A suspended thread doesn't run even if it's unblocked, and its
+        // context is still accessible.
+        thread.unblock().unwrap();
+        assert!(!thread.is_runnable());
+        thread
+            .write_context(
+                ContextKind::Sysret,
+                &ContextData {
+                    sysret: Sysret { retval: 0 },
+                },
+            )
+            .unwrap();
+
+        // The thread runs once resumed, and blocks in the syscall.
+        thread.resume().unwrap();
+        assert_eq!(thread.resume(), Err(ErrorCode::INVALID_STATE));
+        while state(&thread) != State::Blocked {
+            arch::run_until_idle();
+            std::thread::yield_now();
+        }
+
+        thread.terminate().unwrap();
+        assert_eq!(thread.suspend(), Err(ErrorCode::INVALID_STATE));
+        assert_eq!(thread.resume(), Err(ErrorCode::INVALID_STATE));
+    }
+
+    /// A thread resumed and unblocked is pushed to the runqueue only once.
+    /// Otherwise, two CPUs could pick the same thread.
+    #[test]
+    fn enqueue_at_most_once() {
+        arch::init_for_test();
+        let server = Server::new_for_test("enqueue_at_most_once");
+        let thread = new_thread(&server);
+
+        // Each runqueue entry holds a reference. Other tests' CPUs may pop
+        // it meanwhile, but there must be never more than one.
+        let max_refs = 2;
+        thread.suspend().unwrap();
+        thread.unblock().unwrap();
+        assert!(SharedRef::ref_count(&thread) <= max_refs);
+        thread.resume().unwrap();
+        assert!(SharedRef::ref_count(&thread) <= max_refs);
+
+        while state(&thread) != State::Blocked {
+            arch::run_until_idle();
+            std::thread::yield_now();
+        }
+
+        // Resuming a blocked thread doesn't queue it.
+        thread.suspend().unwrap();
+        thread.resume().unwrap();
+        assert!(!thread.mutable.lock().enqueued);
+        thread.terminate().unwrap();
+    }
+}
@@ -0,0 +1,198 @@
+//! Deferred upcalls.
+//!
+//! Kernel objects such as channels don't invoke upcalls in the middle of a
+//! supercall: the caller might hold its own locks, and the upcall handler
+//! might call back into the same object. Instead, they enqueue themselves
+//! here, and the kernel delivers the pending upcalls right before returning
+//! to the user.
+use alloc::collections::vec_deque::VecDeque;
+use core::mem::ManuallyDrop;
+use core::ptr;
+
+use ftl_api::error::ErrorCode;
+use ftl_api::upcall::Upcall;
+use ftl_utils::spinlock::SpinLock;
+
+use crate::arch;
+use crate::server;
+use crate::server::Server;
+use crate::shared_ref::SharedRef;
+
+static PENDING: SpinLock<VecDeque<SharedRef<dyn PendingUpcall>>> = SpinLock::new(VecDeque::new());
+
+/// A kernel object with upcalls to be delivered.
+pub trait PendingUpcall: Send + Sync {
+    /// Invokes the pending upcalls.
+    ///
+    /// This is called without any kernel locks held.
+    fn deliver(&self);
+}
+
+/// Enqueues an object to deliver its pending upcalls later.
+///
+/// The caller is responsible for not enqueueing the same object twice.
+pub fn schedule(object: SharedRef<dyn PendingUpcall>) -> Result<(), ErrorCode> {
+    let mut pending = PENDING.lock();
+    if pending.try_reserve(1).is_err() {
+        return Err(ErrorCode::OUT_OF_MEMORY);
+    }
+
+    pending.push_back(object);
+    Ok(())
+}
+
+/// Delivers all pending upcalls, including ones enqueued during the delivery.
+pub fn deliver_pending() {
+    loop {
+        // Don't hold the lock while delivering.
+        let Some(object) = PENDING.lock().pop_front() else {
+            break;
+        };
+
+        lend(object, |object| object.deliver());
+    }
+}
+
+/// The state to be restored if a server panics during an upcall.
+pub struct Unwinder<'a> {
+    unwind: &'a dyn Fn(),
+    prev: *const Unwinder<'static>,
+}
+
+/// Calls `f`, which upcalls a server.
+///
+/// A panicking server never returns to the kernel: [`unwind_all`] calls
+/// `unwind` instead, to restore the state `f` would have restored on return.
+pub fn with_unwind<R>(unwind: impl Fn(), f: impl FnOnce() -> R) -> R {
+    let unwinders = &arch::get_cpuvar().unwinders;
+    let unwinder = Unwinder {
+        unwind: &unwind,
+        prev: unwinders.get(),
+    };
+
+    unwinders.set(ptr::from_ref(&unwinder).cast());
+    let ret = f();
+    unwinders.set(unwinder.prev);
+    ret
+}
+
+/// Calls `f` with `value`, which upcalls a server, and returns `value` back.
+///
+/// Stack frames abandoned by a panicking server are never dropped: references
+/// held in them would be leaked. Instead, [`unwind_all`] drops `value`.
+pub fn lend<T, R>(value: T, f: impl FnOnce(&T) -> R) -> (T, R) {
+    let value = ManuallyDrop::new(value);
+    let ret = with_unwind(
+        // SAFETY: `f` never returns in this case, and `value` is not used
+        //         anymore.
+        || drop(unsafe { ptr::read(&*value) }),
+        || f(&value),
+    );
+
+    (ManuallyDrop::into_inner(value), ret)
+}
+
+/// Restores the state of the upcalls in progress on this CPU, from the
+/// innermost one.
+///
+/// Called on a server panic, right before abandoning the kernel stack.
+pub fn unwind_all() {
+    let mut unwinder = arch::get_cpuvar().unwinders.replace(ptr::null());
+    while !unwinder.is_null() {
+        // SAFETY: The unwinder is in a stack frame of `with_unwind`, which
+        //         is not abandoned yet.
+        let current = unsafe { &*unwinder };
+        (current.unwind)();
+        unwinder = current.prev;
+    }
+}
+
+/// An upcall to the server which registered it.
+///
+/// Supercalls from the upcall handler are done as the server, that is, they
+/// use the server's handle table.
+pub struct ServerUpcall<T, R = ()> {
+    upcall: Upcall<T, R>,
+    server: SharedRef<Server>,
+}
+
+impl<T, R> ServerUpcall<T, R> {
+    /// Binds an upcall to the current server.
+    pub fn new(upcall: Upcall<T, R>) -> Self {
+        Self {
+            upcall,
+            server: server::current(),
+        }
+    }
+
+    /// Returns false if the server has been stopped: the upcall will never
+    /// be delivered.
+    pub fn is_alive(&self) -> bool {
+        self.server.is_alive()
+    }
+
+    /// Invokes the upcall, unless the server has been stopped. Returns `None`
+    /// in that case.
+    pub fn invoke(&self, arg: T) -> Option<R> {
+        if !self.server.is_alive() {
+            return None;
+        }
+
+        Some(server::run_as(&self.server, || self.upcall.invoke(arg)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::AtomicUsize;
+    use core::sync::atomic::Ordering;
+
+    use super::*;
+
+    struct DropCounter<'a>(&'a AtomicUsize);
+
+    impl Drop for DropCounter<'_> {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn lend_returns_value() {
+        arch::init_for_test();
+        let dropped = AtomicUsize::new(0);
+        let (value, ret) = lend(DropCounter(&dropped), |_| 42);
+        assert_eq!(ret, 42);
+        assert_eq!(dropped.load(Ordering::SeqCst), 0);
+        drop(value);
+        assert_eq!(dropped.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn lend_drops_value_on_unwind() {
+        arch::init_for_test();
+        let dropped = AtomicUsize::new(0);
+        let unwound = AtomicUsize::new(0);
+        let result = std::panic::catch_unwind(|| {
+            with_unwind(
+                || {
+                    unwound.fetch_add(1, Ordering::SeqCst);
+                },
+                || {
+                    lend(DropCounter(&dropped), |_| {
+                        // A server panicked: restore the state, and abandon
+                        // the stack as the host backend does.
+                        unwind_all();
+                        assert_eq!(dropped.load(Ordering::SeqCst), 1);
+                        std::panic::resume_unwind(Box::new(()));
+                    })
+                },
+            )
+        });
+
+        assert!(result.is_err());
+        assert_eq!(dropped.load(Ordering::SeqCst), 1);
+        assert_eq!(unwound.load(Ordering::SeqCst), 1);
+        assert!(arch::get_cpuvar().unwinders.get().is_null());
+    }
+}
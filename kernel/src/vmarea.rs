@@ -10,8 +10,8 @@ use ftl_utils::spinlock::SpinLock;
 use crate::address::PAddr;
 use crate::arch;
 use crate::arch::MIN_PAGE_SIZE;
-use crate::memory::PAGE_ALLOCATOR;
 use crate::memory::PageType;
+use crate::quota::Quota;
 use crate::shared_ref::Handleable;
 use crate::shared_ref::SharedRef;
 
@@ -31,12 +31,10 @@ struct Mutable {
 }
 
 impl Mutable {
-    fn get_or_fill(&mut self, index: usize) -> Result<&mut Page, ErrorCode> {
+    fn get_or_fill(&mut self, quota: &Quota, index: usize) -> Result<&mut Page, ErrorCode> {
         let page = &mut self.pages[index];
         if page.is_none() {
-            let paddr = PAGE_ALLOCATOR
-                .alloc(MIN_PAGE_SIZE, PageType::Zeroed)
-                .ok_or(ErrorCode::OUT_OF_MEMORY)?;
+            let paddr = quota.alloc(MIN_PAGE_SIZE, PageType::Zeroed)?;
             *page = Some(Page { paddr });
         }
 
@@ -45,23 +43,39 @@ impl Mutable {
     }
 }
 
+/// The bytes of kernel memory to track `num_pages` pages.
+fn metadata_len(num_pages: usize) -> usize {
+    num_pages * size_of::<Option<Page>>()
+}
+
 /// A virtually-contiguous memory area.
 pub struct VmArea {
     mutable: SpinLock<Mutable>,
     pager: Pager,
     len: usize,
+    /// The quota the pages are charged to.
+    quota: SharedRef<Quota>,
 }
 
 impl VmArea {
-    pub fn new_anonymous(len: usize) -> Result<SharedRef<Self>, ErrorCode> {
+    pub fn new_anonymous(
+        len: usize,
+        quota: SharedRef<Quota>,
+    ) -> Result<SharedRef<Self>, ErrorCode> {
         if len == 0 || !is_aligned(len, MIN_PAGE_SIZE) {
             return Err(ErrorCode::INVALID_ARG);
         }
 
+        // The page list is charged too: otherwise a huge area would consume
+        // kernel memory without using the quota.
+        let n = len / MIN_PAGE_SIZE;
+        let metadata_len = metadata_len(n);
+        quota.charge(metadata_len)?;
+
         //　Mark all pages as empty.
         let mut pages = Vec::new();
-        let n = len / MIN_PAGE_SIZE;
         if pages.try_reserve_exact(n).is_err() {
+            quota.uncharge(metadata_len);
             return Err(ErrorCode::OUT_OF_MEMORY);
         }
         pages.resize_with(n, Default::default);
@@ -69,8 +83,10 @@ impl VmArea {
         SharedRef::new(Self {
             pager: Pager::Anonymous,
             len,
+            quota: quota.clone(),
             mutable: SpinLock::new(Mutable { pages }),
         })
+        .inspect_err(|_| quota.uncharge(metadata_len))
     }
 
     pub fn len(&self) -> usize {
@@ -83,7 +99,7 @@ impl VmArea {
             return Err(ErrorCode::OUT_OF_BOUNDS);
         }
 
-        let page = mutable.get_or_fill(index)?;
+        let page = mutable.get_or_fill(&self.quota, index)?;
         Ok(page.paddr)
     }
 
@@ -101,7 +117,7 @@ impl VmArea {
             let page_offset = offset % MIN_PAGE_SIZE;
             let copy_len = min(data.len(), MIN_PAGE_SIZE - page_offset);
 
-            let page = mutable.get_or_fill(index)?;
+            let page = mutable.get_or_fill(&self.quota, index)?;
             let vaddr = arch::paddr2vaddr(page.paddr);
 
             unsafe {
@@ -133,7 +149,7 @@ impl VmArea {
             let index = offset / MIN_PAGE_SIZE;
             let offset_in_page = offset % MIN_PAGE_SIZE;
 
-            let page = mutable.get_or_fill(index)?;
+            let page = mutable.get_or_fill(&self.quota, index)?;
             let vaddr = arch::paddr2vaddr(page.paddr);
 
             let copy_len = min(buf.len(), MIN_PAGE_SIZE - offset_in_page);
@@ -150,8 +166,55 @@ impl VmArea {
     }
 }
 
+impl Drop for VmArea {
+    fn drop(&mut self) {
+        let mutable = self.mutable.lock();
+        for page in mutable.pages.iter().flatten() {
+            self.quota.free(page.paddr, MIN_PAGE_SIZE);
+        }
+
+        self.quota.uncharge(metadata_len(mutable.pages.len()));
+    }
+}
+
 impl Handleable for VmArea {
     const DEFAULT_RIGHT: HandleRight = HandleRight::READ
         .or(HandleRight::WRITE)
-        .or(HandleRight::MAP);
+        .or(HandleRight::MAP)
+        .or(HandleRight::TRANSFER)
+        .or(HandleRight::DUPLICATE);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn charge_page_list() {
+        arch::init_for_test();
+        let quota = Quota::new(None, None).unwrap();
+        let len = 16 * MIN_PAGE_SIZE;
+        let vmarea = VmArea::new_anonymous(len, quota.clone()).unwrap();
+        assert_eq!(quota.usage().used, metadata_len(16));
+
+        vmarea.write(0, b"hello").unwrap();
+        assert_eq!(quota.usage().used, metadata_len(16) + MIN_PAGE_SIZE);
+
+        drop(vmarea);
+        assert_eq!(quota.usage().used, 0);
+    }
+
+    #[test]
+    fn reject_page_list_over_quota() {
+        arch::init_for_test();
+        let quota = Quota::new(Some(MIN_PAGE_SIZE), None).unwrap();
+
+        // No pages are allocated, but the page list doesn't fit in the quota.
+        let len = (MIN_PAGE_SIZE / size_of::<Option<Page>>() + 1) * MIN_PAGE_SIZE;
+        assert_eq!(
+            VmArea::new_anonymous(len, quota.clone()).err(),
+            Some(ErrorCode::OUT_OF_MEMORY)
+        );
+        assert_eq!(quota.usage().used, 0);
+    }
 }
@@ -9,6 +9,8 @@ use ftl_utils::spinlock::SpinLock;
 use crate::address::UAddr;
 use crate::arch;
 use crate::arch::MIN_PAGE_SIZE;
+use crate::interceptor::InterceptorList;
+use crate::quota::Quota;
 use crate::shared_ref::Handleable;
 use crate::shared_ref::SharedRef;
 use crate::vmarea::VmArea;
@@ -34,20 +36,33 @@ struct Mutable {
 /// A virtual memory space.
 pub struct VmSpace {
     arch: arch::VmSpace,
+    /// The quota page tables and VM areas for this space are charged to.
+    quota: SharedRef<Quota>,
+    interceptors: InterceptorList,
     mutable: SpinLock<Mutable>,
 }
 
 impl VmSpace {
-    pub fn new() -> Result<Self, ErrorCode> {
-        let arch = arch::VmSpace::new()?;
+    pub fn new(quota: SharedRef<Quota>) -> Result<Self, ErrorCode> {
+        let arch = arch::VmSpace::new(quota.clone())?;
         Ok(Self {
             arch,
+            quota,
+            interceptors: InterceptorList::new(),
             mutable: SpinLock::new(Mutable {
                 mappings: Vec::new(),
             }),
         })
     }
 
+    pub fn quota(&self) -> &SharedRef<Quota> {
+        &self.quota
+    }
+
+    pub fn interceptors(&self) -> &InterceptorList {
+        &self.interceptors
+    }
+
     pub fn switch(&self) {
         self.arch.switch();
     }
@@ -151,5 +166,70 @@ impl VmSpace {
 impl Handleable for VmSpace {
     const DEFAULT_RIGHT: HandleRight = HandleRight::READ
         .or(HandleRight::WRITE)
-        .or(HandleRight::MAP);
+        .or(HandleRight::MAP)
+        .or(HandleRight::INTERCEPT)
+        .or(HandleRight::TRANSFER)
+        .or(HandleRight::DUPLICATE);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UADDR: usize = 0x10000;
+
+    fn new_vmspace() -> (VmSpace, SharedRef<Quota>) {
+        arch::init_for_test();
+        let quota = Quota::new(None, None).unwrap();
+        let vmspace = VmSpace::new(quota.clone()).unwrap();
+        (vmspace, quota)
+    }
+
+    #[test]
+    fn map_and_read() {
+        let (vmspace, quota) = new_vmspace();
+        let vmarea = VmArea::new_anonymous(2 * MIN_PAGE_SIZE, quota).unwrap();
+        vmarea.write(MIN_PAGE_SIZE - 2, b"hello").unwrap();
+        vmspace
+            .map(vmarea, UAddr::new(UADDR), PageAttrs::READ)
+            .unwrap();
+
+        let mut buf = [0; 5];
+        vmspace
+            .read_bytes(UAddr::new(UADDR + MIN_PAGE_SIZE - 2), &mut buf)
+            .unwrap();
+        assert_eq!(&buf, b"hello");
+
+        let mapping = vmspace.arch.lookup(UAddr::new(UADDR + 1)).unwrap();
+        assert!(mapping.attrs.contains(PageAttrs::READ));
+        assert!(!mapping.attrs.contains(PageAttrs::WRITE));
+    }
+
+    #[test]
+    fn reject_overlapping_map() {
+        let (vmspace, quota) = new_vmspace();
+        let vmarea0 = VmArea::new_anonymous(2 * MIN_PAGE_SIZE, quota.clone()).unwrap();
+        let vmarea1 = VmArea::new_anonymous(MIN_PAGE_SIZE, quota).unwrap();
+        vmspace
+            .map(vmarea0, UAddr::new(UADDR), PageAttrs::READ)
+            .unwrap();
+
+        let result = vmspace.map(
+            vmarea1.clone(),
+            UAddr::new(UADDR + MIN_PAGE_SIZE),
+            PageAttrs::READ,
+        );
+        assert_eq!(result, Err(ErrorCode::ALREADY_EXISTS));
+
+        let result = vmspace.map(vmarea1, UAddr::new(UADDR + 1), PageAttrs::READ);
+        assert_eq!(result, Err(ErrorCode::INVALID_ARG));
+    }
+
+    #[test]
+    fn read_unmapped() {
+        let (vmspace, _) = new_vmspace();
+        let mut buf = [0; 1];
+        let result = vmspace.read_bytes(UAddr::new(UADDR), &mut buf);
+        assert_eq!(result, Err(ErrorCode::OUT_OF_BOUNDS));
+    }
 }
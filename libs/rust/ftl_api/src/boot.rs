@@ -0,0 +1,9 @@
+//! Boot options.
+use crate::start::start_info;
+
+/// Returns the value of a kernel command line option (`key=value`), or `None`
+/// if it's not given.
+pub fn option(key: &str) -> Option<&'static str> {
+    let start_info = start_info();
+    (start_info.boot_option)(key)
+}
@@ -0,0 +1,163 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::error::ErrorCode;
+use crate::handle::Handle;
+use crate::start::start_info;
+use crate::upcall::UpCallCtx;
+use crate::upcall::Upcall;
+use crate::upcall::UserData;
+
+/// The maximum number of bytes in a message.
+pub const MESSAGE_DATA_LEN_MAX: usize = 4096;
+/// The maximum number of handles in a message.
+pub const MESSAGE_HANDLES_MAX: usize = 4;
+/// The maximum number of in-flight messages in each direction.
+///
+/// Once the peer's queue is full, [`Channel::send`] fails with
+/// [`ErrorCode::WOULD_BLOCK`] until the peer receives a message.
+pub const MESSAGE_QUEUE_LEN_MAX: usize = 32;
+
+/// Handles to be sent or received. `None` is an empty slot.
+pub type HandleSlots = [Option<Handle>];
+
+/// The size of a received message.
+#[derive(Debug, Clone, Copy)]
+pub struct MessageInfo {
+    pub data_len: usize,
+    pub num_handles: usize,
+}
+
+/// A received message.
+pub struct Message {
+    pub data: Vec<u8>,
+    pub handles: Vec<Handle>,
+}
+
+pub enum UpcallArg {
+    /// New messages have arrived.
+    Readable,
+    /// The peer's queue is no longer full after a send failed with
+    /// [`ErrorCode::WOULD_BLOCK`].
+    Writable,
+    /// The peer endpoint has been closed.
+    PeerClosed,
+    /// This endpoint has been closed. This is the last upcall.
+    Closed,
+}
+
+pub trait Handler: Send + Sync {
+    /// Called when new messages are available.
+    ///
+    /// Receive messages until [`Channel::recv`] returns
+    /// [`ErrorCode::WOULD_BLOCK`]; the kernel won't notify again for the
+    /// messages already queued.
+    fn readable(&self, channel: &Channel);
+
+    fn writable(&self, _channel: &Channel) {}
+
+    fn peer_closed(&self, _channel: &Channel) {}
+
+    fn closed(&self, _channel: &Channel) {}
+}
+
+fn upcall_entry<H: Handler + 'static>(ctx: UpCallCtx, arg: UpcallArg) {
+    match arg {
+        UpcallArg::Closed => {
+            let user_data = unsafe { UserData::<Arc<Channel>, H>::reclaim(ctx) };
+            user_data.handler.closed(&user_data.object);
+        }
+        arg => {
+            let user_data = unsafe { UserData::<Arc<Channel>, H>::borrow(ctx) };
+            match arg {
+                UpcallArg::Readable => user_data.handler.readable(&user_data.object),
+                UpcallArg::Writable => user_data.handler.writable(&user_data.object),
+                UpcallArg::PeerClosed => user_data.handler.peer_closed(&user_data.object),
+                UpcallArg::Closed => unreachable!(),
+            }
+        }
+    }
+}
+
+/// An endpoint of a bidirectional message channel.
+pub struct Channel {
+    handle: Handle,
+}
+
+impl Channel {
+    /// Creates a pair of connected endpoints.
+    pub fn create() -> crate::Result<(Channel, Channel)> {
+        let start_info = start_info();
+        let (handle0, handle1) = (start_info.channel_create)()?;
+        Ok((Channel { handle: handle0 }, Channel { handle: handle1 }))
+    }
+
+    pub fn from_handle(handle: Handle) -> Self {
+        Self { handle }
+    }
+
+    /// Starts receiving upcalls for this endpoint.
+    ///
+    /// Messages queued before this call are notified as well.
+    pub fn listen<H: Handler + 'static>(self, handler: H) -> crate::Result<Arc<Channel>> {
+        let start_info = start_info();
+
+        Upcall::new(upcall_entry::<H>, handler, |upcall| {
+            (start_info.channel_listen)(&self.handle, upcall)?;
+            Ok(Arc::new(self))
+        })
+    }
+
+    /// Sends a message to the peer.
+    ///
+    /// On success, the kernel takes all handles in `handles`, leaving `None`s.
+    /// On failure, `handles` are left untouched.
+    pub fn send(&self, data: &[u8], handles: &mut HandleSlots) -> crate::Result<()> {
+        let start_info = start_info();
+        (start_info.channel_send)(&self.handle, data, handles)
+    }
+
+    /// Receives a message without blocking.
+    ///
+    /// Returns [`ErrorCode::WOULD_BLOCK`] if no messages are queued.
+    pub fn recv(&self) -> crate::Result<Message> {
+        let start_info = start_info();
+        let mut data = alloc::vec![0; MESSAGE_DATA_LEN_MAX];
+        let mut handles: [Option<Handle>; MESSAGE_HANDLES_MAX] = Default::default();
+        let info = (start_info.channel_recv)(&self.handle, &mut data, &mut handles)?;
+
+        data.truncate(info.data_len);
+        let handles = handles
+            .into_iter()
+            .take(info.num_handles)
+            .map(|handle| handle.ok_or(ErrorCode::INVALID_STATE))
+            .collect::<crate::Result<Vec<_>>>()?;
+
+        Ok(Message { data, handles })
+    }
+
+    /// Closes this endpoint. Safe to call from its upcall handler.
+    ///
+    /// Queued messages are discarded, and the peer is notified. If listening,
+    /// [`Handler::closed`] is called afterwards.
+    pub fn close(&self) -> crate::Result<()> {
+        let start_info = start_info();
+        (start_info.channel_close)(&self.handle)
+    }
+
+    pub fn handle(&self) -> &Handle {
+        &self.handle
+    }
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        // SAFETY: Handle does not implement Drop, and we won't use it after
+        //         the close call below.
+        let handle = unsafe { core::ptr::read(&self.handle) };
+
+        if let Err(err) = handle.close() {
+            error!("failed to close channel: {:?}", err);
+        }
+    }
+}
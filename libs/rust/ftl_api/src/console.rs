@@ -0,0 +1,79 @@
+//! The system console input.
+//!
+//! Output goes through [`crate::println`] and logging macros. A server
+//! allowed to look up the service `kernel/console` can open the console to
+//! read input bytes.
+use alloc::sync::Arc;
+
+use crate::handle::Handle;
+use crate::start::start_info;
+use crate::upcall::UpCallCtx;
+use crate::upcall::Upcall;
+use crate::upcall::UserData;
+
+pub enum UpcallArg {
+    /// New input bytes have arrived.
+    Readable,
+}
+
+pub trait Handler: Send + Sync {
+    /// Called when new input is available.
+    ///
+    /// Read until [`Console::read`] returns [`ErrorCode::WOULD_BLOCK`]; the
+    /// kernel won't notify again for the bytes already buffered.
+    ///
+    /// [`ErrorCode::WOULD_BLOCK`]: crate::error::ErrorCode::WOULD_BLOCK
+    fn readable(&self, console: &Console);
+}
+
+fn upcall_entry<H: Handler + 'static>(ctx: UpCallCtx, arg: UpcallArg) {
+    let user_data = unsafe { UserData::<Arc<Console>, H>::borrow(ctx) };
+    match arg {
+        UpcallArg::Readable => user_data.handler.readable(&user_data.object),
+    }
+}
+
+pub struct Console {
+    handle: Handle,
+}
+
+impl Console {
+    /// Opens the system console.
+    pub fn open() -> crate::Result<Console> {
+        let start_info = start_info();
+        let handle = (start_info.console_open)()?;
+        Ok(Console { handle })
+    }
+
+    pub fn from_handle(handle: Handle) -> Self {
+        Self { handle }
+    }
+
+    /// Starts receiving upcalls on input. Only one listener is allowed in
+    /// the system.
+    ///
+    /// Input buffered before this call is notified as well.
+    pub fn listen<H: Handler + 'static>(self, handler: H) -> crate::Result<Arc<Console>> {
+        let start_info = start_info();
+
+        Upcall::new(upcall_entry::<H>, handler, |upcall| {
+            (start_info.console_listen)(&self.handle, upcall)?;
+            Ok(Arc::new(self))
+        })
+    }
+
+    /// Reads input bytes without blocking, and returns the number of bytes
+    /// read.
+    ///
+    /// Returns [`ErrorCode::WOULD_BLOCK`] if no input is buffered.
+    ///
+    /// [`ErrorCode::WOULD_BLOCK`]: crate::error::ErrorCode::WOULD_BLOCK
+    pub fn read(&self, buf: &mut [u8]) -> crate::Result<usize> {
+        let start_info = start_info();
+        (start_info.console_read)(&self.handle, buf)
+    }
+
+    pub fn handle(&self) -> &Handle {
+        &self.handle
+    }
+}
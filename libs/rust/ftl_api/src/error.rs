@@ -12,6 +12,13 @@ impl ErrorCode {
     pub const INVALID_TYPE: Self = Self::from_name(b"INVT");
     pub const OUT_OF_BOUNDS: Self = Self::from_name(b" OOB");
     pub const UNSUPPORTED: Self = Self::from_name(b"UNSP");
+    pub const WOULD_BLOCK: Self = Self::from_name(b"WBLK");
+    pub const PEER_CLOSED: Self = Self::from_name(b"PCLS");
+    pub const TOO_LARGE: Self = Self::from_name(b"2BIG");
+    pub const INVALID_HANDLE: Self = Self::from_name(b"HNDL");
+    pub const REVOKED: Self = Self::from_name(b"RVKD");
+    pub const NOT_FOUND: Self = Self::from_name(b"NFND");
+    pub const BAD_SIGNATURE: Self = Self::from_name(b"BSIG");
 
     const fn from_name(name: &'static [u8]) -> Self {
         if name.len() != 4 {
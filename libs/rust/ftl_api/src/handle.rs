@@ -1,40 +1,50 @@
-use core::any::TypeId;
+use core::ops::BitAnd;
 use core::ops::BitOr;
 
+use crate::start::start_info;
+
 /// A reference to a kernel object, with allowed actions.
+///
+/// This is an index into the server's handle table in the kernel. The kernel
+/// keeps the referenced object and the rights granted to the handle, and
+/// validates them on every supercall.
 pub struct Handle {
-    /// An opaque pointer to the kernel memory.
-    #[cfg_attr(not(feature = "kernel"), allow(unused))]
-    raw: usize,
-    /// The type of the kernel object.
-    #[cfg_attr(not(feature = "kernel"), allow(unused))]
-    type_id: TypeId,
-    /// The rights granted to the handle. Also known as *capability*.
-    right: HandleRight,
+    id: usize,
 }
 
 impl Handle {
-    #[cfg(feature = "kernel")]
-    pub fn new<T: 'static>(raw: usize, right: HandleRight) -> Self {
-        Self {
-            raw,
-            type_id: TypeId::of::<T>(),
-            right,
-        }
+    #[cfg(any(feature = "kernel", feature = "mock"))]
+    pub fn new(id: usize) -> Self {
+        Self { id }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
     }
 
-    #[cfg(feature = "kernel")]
-    pub fn raw(&self) -> usize {
-        self.raw
+    /// Creates a new handle to the same object with rights narrowed by
+    /// `mask`. Requires [`HandleRight::DUPLICATE`].
+    ///
+    /// Rights not granted to this handle are never added. The new handle is
+    /// revoked when [`Handle::revoke`] is called on this handle.
+    pub fn duplicate(&self, mask: HandleRight) -> crate::Result<Handle> {
+        let start_info = start_info();
+        (start_info.handle_duplicate)(self, mask)
     }
 
-    #[cfg(feature = "kernel")]
-    pub fn is_type<T: 'static>(&self) -> bool {
-        self.type_id == TypeId::of::<T>()
+    /// Invalidates all handles derived from this handle, that is, its
+    /// duplicates (recursively), including ones sent to other servers.
+    ///
+    /// This handle itself remains valid.
+    pub fn revoke(&self) -> crate::Result<()> {
+        let start_info = start_info();
+        (start_info.handle_revoke)(self)
     }
 
-    pub fn authorize(&self, action: HandleRight) -> bool {
-        self.right.contains(action)
+    /// Removes the handle from the server's handle table.
+    pub fn close(self) -> crate::Result<()> {
+        let start_info = start_info();
+        (start_info.handle_close)(self)
     }
 }
 
@@ -47,6 +57,21 @@ impl HandleRight {
     pub const READ: Self = Self(1 << 0);
     pub const WRITE: Self = Self(1 << 1);
     pub const MAP: Self = Self(1 << 2);
+    /// Resume a blocked thread.
+    pub const UNBLOCK: Self = Self(1 << 3);
+    /// Terminate a thread, or detach an interceptor.
+    pub const TERMINATE: Self = Self(1 << 4);
+    /// Modify a thread's context, e.g. registers.
+    pub const SET_CONTEXT: Self = Self(1 << 5);
+    /// Send the handle to another server through a channel.
+    pub const TRANSFER: Self = Self(1 << 6);
+    /// Create a new handle to the same object. See [`Handle::duplicate`].
+    pub const DUPLICATE: Self = Self(1 << 7);
+    /// Attach an interceptor to a thread or a VmSpace.
+    pub const INTERCEPT: Self = Self(1 << 8);
+    /// Suspend and resume a thread.
+    pub const SUSPEND: Self = Self(1 << 9);
+    pub const ALL: Self = Self(usize::MAX);
 
     pub const fn contains(&self, other: Self) -> bool {
         self.0 & other.0 == other.0
@@ -64,3 +89,11 @@ impl BitOr for HandleRight {
         Self(self.0 | rhs.0)
     }
 }
+
+impl BitAnd for HandleRight {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self::Output {
+        Self(self.0 & rhs.0)
+    }
+}
@@ -0,0 +1,71 @@
+//! The read-only file system in the boot image.
+//!
+//! Paths are absolute or relative to the root, e.g. `/bin/hello` and
+//! `bin/hello` are the same file.
+use crate::start::start_info;
+
+/// The file type bits in [`FileStat::mode`].
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFREG: u32 = 0o100000;
+
+/// File metadata, as recorded in the boot image.
+#[derive(Debug, Clone, Copy)]
+pub struct FileStat {
+    /// The file type and permissions, as in `st_mode`.
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// The size in bytes. Zero for directories.
+    pub size: usize,
+    /// The modification time in seconds since the Unix epoch.
+    pub mtime: u64,
+}
+
+impl FileStat {
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.mode & S_IFMT == S_IFREG
+    }
+}
+
+pub fn stat(path: &str) -> crate::Result<FileStat> {
+    let start_info = start_info();
+    (start_info.initfs_stat)(path)
+}
+
+/// Reads a file from `offset` into `buf`, and returns the number of bytes
+/// read. It's less than `buf.len()` at the end of the file.
+pub fn read(path: &str, offset: usize, buf: &mut [u8]) -> crate::Result<usize> {
+    let start_info = start_info();
+    (start_info.initfs_read)(path, offset, buf)
+}
+
+/// Returns an iterator over the names of the entries in a directory.
+pub fn read_dir(path: &str) -> ReadDir<'_> {
+    ReadDir { path, index: 0 }
+}
+
+pub struct ReadDir<'a> {
+    path: &'a str,
+    index: usize,
+}
+
+impl Iterator for ReadDir<'_> {
+    type Item = crate::Result<&'static str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start_info = start_info();
+        match (start_info.initfs_readdir)(self.path, self.index) {
+            Ok(Some(name)) => {
+                self.index += 1;
+                Some(Ok(name))
+            }
+            Ok(None) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
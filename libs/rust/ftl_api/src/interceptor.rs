@@ -0,0 +1,138 @@
+//! Syscall interceptors.
+//!
+//! An interceptor sees syscalls of a thread before its personality server
+//! (e.g. lx) does. Interceptors are attached to a [`Thread`] or to a
+//! [`VmSpace`] (i.e. all threads in it), and form a chain: ones attached to
+//! the VmSpace come first, then ones attached to the thread, each in the
+//! attached order.
+//!
+//! Each interceptor can pass the syscall to the next one as is, modify its
+//! arguments, complete it with a return value, or kill the thread. The
+//! return value goes back through the interceptors which have passed the
+//! syscall, in the reverse order.
+//!
+//! Interceptors fail closed: if the server of an attached interceptor stops,
+//! threads making syscalls through it are killed.
+//!
+//! An interceptor stays attached until [`Interceptor::detach`] is called,
+//! even if the server drops its [`Interceptor`]: the handler holds a
+//! reference to it.
+//!
+//! [`Thread`]: crate::thread::Thread
+//! [`VmSpace`]: crate::vmspace::VmSpace
+use alloc::sync::Arc;
+
+use crate::handle::Handle;
+use crate::start::start_info;
+use crate::thread::SyscallArgs;
+use crate::thread::Sysret;
+use crate::upcall::UpCallCtx;
+use crate::upcall::Upcall;
+use crate::upcall::UserData;
+
+/// The maximum number of interceptors attached to a thread or a VmSpace.
+pub const MAX_INTERCEPTORS: usize = 8;
+
+pub enum UpcallArg {
+    /// A syscall is on the way to the personality server.
+    Syscall(SyscallArgs),
+    /// A syscall is returning with the return value.
+    Sysret(SyscallArgs, Sysret),
+    /// The interceptor has been detached, or its kernel object has been
+    /// destroyed. This is the last upcall.
+    Detached,
+}
+
+/// What to do with an intercepted syscall.
+pub enum Action {
+    /// Passes the syscall to the next interceptor as is.
+    Continue,
+    /// Replaces the syscall arguments, and passes it to the next interceptor.
+    Modify(SyscallArgs),
+    /// Completes the syscall with the return value. The rest of the chain and
+    /// the personality server won't see the syscall.
+    ///
+    /// On [`UpcallArg::Sysret`], replaces the return value.
+    Complete(Sysret),
+    /// Terminates the thread. To fail the syscall instead, complete it with
+    /// an error return value.
+    Kill,
+}
+
+pub trait Handler: Send + Sync {
+    fn syscall(&self, args: &SyscallArgs) -> Action;
+
+    /// Called when a syscall passed by [`Handler::syscall`] returns. The
+    /// return value can be modified in place.
+    fn sysret(&self, _args: &SyscallArgs, _sysret: &mut Sysret) {}
+
+    /// Called when the interceptor no longer sees syscalls. The handler is
+    /// dropped after this.
+    fn detached(&self) {}
+}
+
+fn upcall_entry<H: Handler + 'static>(ctx: UpCallCtx, arg: UpcallArg) -> Action {
+    match arg {
+        UpcallArg::Syscall(args) => {
+            let user_data = unsafe { UserData::<Arc<Interceptor>, H>::borrow(ctx) };
+            user_data.handler.syscall(&args)
+        }
+        UpcallArg::Sysret(args, mut sysret) => {
+            let user_data = unsafe { UserData::<Arc<Interceptor>, H>::borrow(ctx) };
+            user_data.handler.sysret(&args, &mut sysret);
+            Action::Complete(sysret)
+        }
+        UpcallArg::Detached => {
+            let user_data = unsafe { UserData::<Arc<Interceptor>, H>::reclaim(ctx) };
+            user_data.handler.detached();
+            Action::Continue
+        }
+    }
+}
+
+pub struct Interceptor {
+    handle: Handle,
+}
+
+impl Interceptor {
+    /// Creates an interceptor. Attach it by [`Thread::intercept`] or
+    /// [`VmSpace::intercept`], or send it to the server which owns the
+    /// threads.
+    ///
+    /// [`Thread::intercept`]: crate::thread::Thread::intercept
+    /// [`VmSpace::intercept`]: crate::vmspace::VmSpace::intercept
+    pub fn create<H: Handler + 'static>(handler: H) -> crate::Result<Arc<Interceptor>> {
+        let start_info = start_info();
+
+        Upcall::new(upcall_entry::<H>, handler, |upcall| {
+            let handle = (start_info.interceptor_create)(upcall)?;
+            Ok(Arc::new(Interceptor { handle }))
+        })
+    }
+
+    /// Stops intercepting syscalls on all threads and VmSpaces it's attached
+    /// to. Safe to call from its handler.
+    ///
+    /// [`Handler::detached`] is called once no upcalls are in flight. The
+    /// interceptor is closed when the last reference is dropped after that.
+    pub fn detach(&self) -> crate::Result<()> {
+        let start_info = start_info();
+        (start_info.interceptor_detach)(&self.handle)
+    }
+
+    pub fn handle(&self) -> &Handle {
+        &self.handle
+    }
+}
+
+impl Drop for Interceptor {
+    fn drop(&mut self) {
+        // SAFETY: Handle does not implement Drop, and we won't use it after
+        //         the close call below.
+        let handle = unsafe { core::ptr::read(&self.handle) };
+
+        if let Err(err) = handle.close() {
+            error!("failed to close interceptor: {:?}", err);
+        }
+    }
+}
@@ -3,12 +3,24 @@
 extern crate alloc;
 
 use alloc::boxed::Box;
+use core::any::Any;
+
+use ftl_utils::spinlock::SpinLock;
 
 #[macro_use]
 pub mod print;
 
+pub mod boot;
+pub mod channel;
+pub mod console;
 pub mod error;
 pub mod handle;
+pub mod initfs;
+pub mod interceptor;
+pub mod log;
+pub mod quota;
+pub mod server;
+pub mod service;
 pub mod start;
 pub mod thread;
 pub mod upcall;
@@ -20,16 +32,30 @@ pub type Result<T> = core::result::Result<T, error::ErrorCode>;
 pub struct Spec {
     pub name: &'static [u8],
     pub start: fn(),
+    /// Called before the kernel unloads the server. The server should
+    /// release its objects here, typically by [`stop`].
+    pub stop: fn(),
+}
+
+/// The object created by the server's constructor.
+static SERVER: SpinLock<Option<Box<dyn Any + Send>>> = SpinLock::new(None);
+
+pub fn start<R: Send + 'static, F: Fn() -> R>(ctor: F) {
+    let server = Box::new(ctor());
+    *SERVER.lock() = Some(server);
 }
 
-pub fn start<R, F: Fn() -> R>(ctor: F) {
-    // FIXME: Free the box when the server is stopped.
-    // FIXME: Should we increment the server's ref count when registering a upcall?
-    Box::leak(Box::new(ctor()));
+/// Drops the object created in [`start`].
+pub fn stop() {
+    let server = SERVER.lock().take();
+    drop(server);
 }
 
 #[cfg(not(feature = "kernel"))]
 mod panic;
 
-#[cfg(not(feature = "kernel"))]
+#[cfg(not(any(feature = "kernel", feature = "mock")))]
 pub mod allocator;
+
+#[cfg(feature = "mock")]
+pub mod mock;
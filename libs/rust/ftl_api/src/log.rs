@@ -0,0 +1,135 @@
+//! The kernel log buffer.
+//!
+//! All logs from the kernel and servers are recorded in the kernel log
+//! buffer regardless of the console log level. A server allowed to look up
+//! the service `kernel/log` can read it by [`LogReader`], like `dmesg`.
+use crate::print::LogLevel;
+use crate::start::start_info;
+
+pub const LOG_SOURCE_LEN: usize = 16;
+pub const LOG_MESSAGE_LEN: usize = 200;
+
+/// A log message. Too long source names and messages are truncated.
+#[derive(Clone, Copy)]
+pub struct LogRecord {
+    /// The sequence number, incremented for each record.
+    pub seq: u64,
+    /// The time since boot in nanoseconds.
+    pub timestamp: u64,
+    pub cpu_id: u32,
+    pub level: LogLevel,
+    source_len: u8,
+    source: [u8; LOG_SOURCE_LEN],
+    message_len: u16,
+    message: [u8; LOG_MESSAGE_LEN],
+}
+
+impl LogRecord {
+    pub const fn new(seq: u64, timestamp: u64, cpu_id: u32, level: LogLevel) -> Self {
+        Self {
+            seq,
+            timestamp,
+            cpu_id,
+            level,
+            source_len: 0,
+            source: [0; LOG_SOURCE_LEN],
+            message_len: 0,
+            message: [0; LOG_MESSAGE_LEN],
+        }
+    }
+
+    /// The server name, or `kernel`.
+    pub fn source(&self) -> &str {
+        str_from_prefix(&self.source[..self.source_len as usize])
+    }
+
+    pub fn set_source(&mut self, source: &str) {
+        let len = copy_truncated(&mut self.source, 0, source);
+        self.source_len = len as u8;
+    }
+
+    pub fn message(&self) -> &str {
+        str_from_prefix(&self.message[..self.message_len as usize])
+    }
+
+    /// Appends a string to the message. It's truncated if the message gets
+    /// too long.
+    pub fn push_message(&mut self, s: &str) {
+        let len = copy_truncated(&mut self.message, self.message_len as usize, s);
+        self.message_len = len as u16;
+    }
+}
+
+impl core::fmt::Write for LogRecord {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.push_message(s);
+        Ok(())
+    }
+}
+
+/// Copies `s` into `buf` at `offset` without splitting a character. Returns
+/// the new length.
+fn copy_truncated(buf: &mut [u8], offset: usize, s: &str) -> usize {
+    let mut len = s.len().min(buf.len() - offset);
+    while !s.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    buf[offset..offset + len].copy_from_slice(&s.as_bytes()[..len]);
+    offset + len
+}
+
+fn str_from_prefix(bytes: &[u8]) -> &str {
+    match core::str::from_utf8(bytes) {
+        Ok(s) => s,
+        Err(err) => core::str::from_utf8(&bytes[..err.valid_up_to()]).unwrap(),
+    }
+}
+
+/// Reads the kernel log buffer in order.
+pub struct LogReader {
+    next_seq: u64,
+    lost: u64,
+}
+
+impl LogReader {
+    /// Creates a reader from the oldest record in the buffer.
+    pub fn new() -> Self {
+        Self {
+            next_seq: 0,
+            lost: 0,
+        }
+    }
+
+    /// Returns the next record, or `None` if there are no new records yet.
+    /// Call this again later to tail the buffer.
+    pub fn read_next(&mut self) -> crate::Result<Option<LogRecord>> {
+        let start_info = start_info();
+        let Some(record) = (start_info.log_read)(self.next_seq)? else {
+            return Ok(None);
+        };
+
+        // Records are overwritten if the reader is too slow.
+        self.lost += record.seq - self.next_seq;
+        self.next_seq = record.seq + 1;
+        Ok(Some(record))
+    }
+
+    /// The number of records overwritten before they were read.
+    pub fn lost(&self) -> u64 {
+        self.lost
+    }
+}
+
+impl Default for LogReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Changes the minimum level of logs to print on the console. Requires the
+/// same permission as reading the buffer.
+pub fn set_console_level(level: LogLevel) -> crate::Result<()> {
+    let start_info = start_info();
+    (start_info.log_set_console_level)(level)
+}
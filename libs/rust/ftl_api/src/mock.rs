@@ -0,0 +1,716 @@
+//! An in-memory kernel to unit-test servers on the host.
+//!
+//! With the `mock` feature, [`start_info`](crate::start::start_info) returns
+//! [`START_INFO`] instead of the one from the kernel. Each host thread (that
+//! is, each test) has its own kernel state.
+//!
+//! VmSpaces and VmAreas are backed by host memory. Threads are synthetic:
+//! they don't run any code, but a test can make a syscall from a thread by
+//! [`MockThread::syscall`], and see the return value set by the server.
+//!
+//! Channels, services, consoles, interceptors, and server management are not
+//! supported: they return [`ErrorCode::UNSUPPORTED`].
+extern crate std;
+
+use alloc::boxed::Box;
+use alloc::collections::btree_map::BTreeMap;
+use alloc::collections::vec_deque::VecDeque;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::cell::Cell;
+use core::cell::RefCell;
+use core::cmp::min;
+use std::thread_local;
+
+use crate::error::ErrorCode;
+use crate::handle::Handle;
+use crate::handle::HandleRight;
+use crate::initfs::FileStat;
+use crate::initfs::S_IFDIR;
+use crate::initfs::S_IFREG;
+use crate::print::LogLevel;
+use crate::quota::MemoryUsage;
+use crate::start::StartInfo;
+use crate::thread::ContextData;
+use crate::thread::ContextKind;
+use crate::thread::FpState;
+use crate::thread::FsBase;
+use crate::thread::GsBase;
+use crate::thread::InitRegs;
+use crate::thread::Regs;
+use crate::thread::SyscallArgs;
+use crate::thread::Sysret;
+use crate::thread::UpcallArg;
+use crate::upcall::Upcall;
+use crate::vmspace::PageAttrs;
+
+const PAGE_SIZE: usize = 4096;
+
+struct Mapping {
+    uaddr: usize,
+    vmarea: Rc<VmAreaObject>,
+    #[allow(unused)]
+    attrs: PageAttrs,
+}
+
+struct VmSpaceObject {
+    limit: Option<usize>,
+    used: Cell<usize>,
+    /// Sorted by the user address.
+    mappings: RefCell<Vec<Mapping>>,
+}
+
+impl VmSpaceObject {
+    fn charge(&self, len: usize) -> Result<(), ErrorCode> {
+        let used = self.used.get() + len;
+        if self.limit.is_some_and(|limit| used > limit) {
+            return Err(ErrorCode::OUT_OF_MEMORY);
+        }
+
+        self.used.set(used);
+        Ok(())
+    }
+}
+
+struct VmAreaObject {
+    data: RefCell<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ThreadState {
+    Runnable,
+    Blocked,
+    Terminated,
+}
+
+struct ThreadObject {
+    upcall: Upcall<UpcallArg>,
+    state: Cell<ThreadState>,
+    /// Whether the syscall upcall is running.
+    in_upcall: Cell<bool>,
+    /// Whether the thread is stopped by `thread_suspend`.
+    suspended: Cell<bool>,
+    syscall_args: Cell<SyscallArgs>,
+    sysret: Cell<Sysret>,
+    init_regs: Cell<InitRegs>,
+    fsbase: Cell<u64>,
+    gsbase: Cell<u64>,
+    fp_state: Cell<FpState>,
+    /// Registers set by [`ContextKind::Regs`], except the ones above.
+    regs: Cell<Regs>,
+}
+
+impl ThreadObject {
+    /// Whether the context can be accessed.
+    fn is_stopped(&self) -> bool {
+        self.state.get() == ThreadState::Blocked || self.suspended.get()
+    }
+}
+
+#[derive(Clone)]
+enum Object {
+    VmSpace(Rc<VmSpaceObject>),
+    VmArea(Rc<VmAreaObject>),
+    Thread(Rc<ThreadObject>),
+}
+
+struct Kernel {
+    handles: BTreeMap<usize, (Object, HandleRight)>,
+    next_handle_id: usize,
+    /// Threads in the created order.
+    threads: Vec<Rc<ThreadObject>>,
+    /// Threads to deliver the terminated upcall.
+    terminated: VecDeque<Rc<ThreadObject>>,
+    files: BTreeMap<String, &'static [u8]>,
+    options: BTreeMap<String, &'static str>,
+}
+
+impl Kernel {
+    const fn new() -> Self {
+        Self {
+            handles: BTreeMap::new(),
+            // Zero is not a valid handle ID in the kernel.
+            next_handle_id: 1,
+            threads: Vec::new(),
+            terminated: VecDeque::new(),
+            files: BTreeMap::new(),
+            options: BTreeMap::new(),
+        }
+    }
+}
+
+thread_local! {
+    static KERNEL: RefCell<Kernel> = const { RefCell::new(Kernel::new()) };
+}
+
+/// Accesses the kernel state. Don't invoke upcalls in `f`: they may call back
+/// into the kernel.
+fn with_kernel<R>(f: impl FnOnce(&mut Kernel) -> R) -> R {
+    KERNEL.with_borrow_mut(f)
+}
+
+fn insert(object: Object, right: HandleRight) -> Handle {
+    with_kernel(|kernel| {
+        let id = kernel.next_handle_id;
+        kernel.next_handle_id += 1;
+        kernel.handles.insert(id, (object, right));
+        Handle::new(id)
+    })
+}
+
+fn get(handle: &Handle, action: HandleRight) -> Result<Object, ErrorCode> {
+    with_kernel(|kernel| {
+        let (object, right) = kernel
+            .handles
+            .get(&handle.id())
+            .ok_or(ErrorCode::INVALID_HANDLE)?;
+        if !right.contains(action) {
+            return Err(ErrorCode::NOT_ALLOWED);
+        }
+
+        Ok(object.clone())
+    })
+}
+
+fn get_vmspace(handle: &Handle, action: HandleRight) -> Result<Rc<VmSpaceObject>, ErrorCode> {
+    match get(handle, action)? {
+        Object::VmSpace(vmspace) => Ok(vmspace),
+        _ => Err(ErrorCode::INVALID_TYPE),
+    }
+}
+
+fn get_vmarea(handle: &Handle, action: HandleRight) -> Result<Rc<VmAreaObject>, ErrorCode> {
+    match get(handle, action)? {
+        Object::VmArea(vmarea) => Ok(vmarea),
+        _ => Err(ErrorCode::INVALID_TYPE),
+    }
+}
+
+fn get_thread(handle: &Handle, action: HandleRight) -> Result<Rc<ThreadObject>, ErrorCode> {
+    match get(handle, action)? {
+        Object::Thread(thread) => Ok(thread),
+        _ => Err(ErrorCode::INVALID_TYPE),
+    }
+}
+
+fn vmspace_create(limit: Option<usize>) -> crate::Result<Handle> {
+    let vmspace = Rc::new(VmSpaceObject {
+        limit,
+        used: Cell::new(0),
+        mappings: RefCell::new(Vec::new()),
+    });
+
+    let right = HandleRight::READ
+        .or(HandleRight::WRITE)
+        .or(HandleRight::MAP)
+        .or(HandleRight::INTERCEPT)
+        .or(HandleRight::TRANSFER)
+        .or(HandleRight::DUPLICATE);
+    Ok(insert(Object::VmSpace(vmspace), right))
+}
+
+fn vmarea_allocate(len: usize, vmspace: Option<&Handle>) -> crate::Result<Handle> {
+    if len == 0 || !len.is_multiple_of(PAGE_SIZE) {
+        return Err(ErrorCode::INVALID_ARG);
+    }
+
+    if let Some(vmspace) = vmspace {
+        get_vmspace(vmspace, HandleRight::MAP)?.charge(len)?;
+    }
+
+    let vmarea = Rc::new(VmAreaObject {
+        data: RefCell::new(alloc::vec![0; len]),
+    });
+
+    let right = HandleRight::READ
+        .or(HandleRight::WRITE)
+        .or(HandleRight::MAP)
+        .or(HandleRight::TRANSFER)
+        .or(HandleRight::DUPLICATE);
+    Ok(insert(Object::VmArea(vmarea), right))
+}
+
+fn vmarea_write(vmarea: &Handle, offset: usize, data: &[u8]) -> crate::Result<()> {
+    let vmarea = get_vmarea(vmarea, HandleRight::WRITE)?;
+    let mut buf = vmarea.data.borrow_mut();
+    let end = offset
+        .checked_add(data.len())
+        .filter(|end| *end <= buf.len())
+        .ok_or(ErrorCode::OUT_OF_BOUNDS)?;
+
+    buf[offset..end].copy_from_slice(data);
+    Ok(())
+}
+
+fn vmspace_map(
+    vmspace: &Handle,
+    vmarea: &Handle,
+    uaddr: usize,
+    attrs: PageAttrs,
+) -> crate::Result<()> {
+    let vmspace = get_vmspace(vmspace, HandleRight::MAP)?;
+    let vmarea = get_vmarea(vmarea, HandleRight::MAP)?;
+    if !uaddr.is_multiple_of(PAGE_SIZE) {
+        return Err(ErrorCode::INVALID_ARG);
+    }
+
+    let len = vmarea.data.borrow().len();
+    let end = uaddr.checked_add(len).ok_or(ErrorCode::OUT_OF_BOUNDS)?;
+    let mut mappings = vmspace.mappings.borrow_mut();
+    if mappings.iter().any(|mapping| {
+        let mapping_end = mapping.uaddr + mapping.vmarea.data.borrow().len();
+        uaddr < mapping_end && mapping.uaddr < end
+    }) {
+        return Err(ErrorCode::ALREADY_EXISTS);
+    }
+
+    let index = mappings.partition_point(|mapping| mapping.uaddr < uaddr);
+    mappings.insert(
+        index,
+        Mapping {
+            uaddr,
+            vmarea,
+            attrs,
+        },
+    );
+    Ok(())
+}
+
+fn vmspace_read(vmspace: &Handle, mut uaddr: usize, mut buf: &mut [u8]) -> crate::Result<()> {
+    let vmspace = get_vmspace(vmspace, HandleRight::READ)?;
+    let mappings = vmspace.mappings.borrow();
+    while !buf.is_empty() {
+        let mapping = mappings
+            .iter()
+            .find(|mapping| {
+                mapping.uaddr <= uaddr && uaddr < mapping.uaddr + mapping.vmarea.data.borrow().len()
+            })
+            .ok_or(ErrorCode::OUT_OF_BOUNDS)?;
+
+        let data = mapping.vmarea.data.borrow();
+        let offset = uaddr - mapping.uaddr;
+        let copy_len = min(buf.len(), data.len() - offset);
+        let (chunk, rest) = buf.split_at_mut(copy_len);
+        chunk.copy_from_slice(&data[offset..offset + copy_len]);
+        uaddr += copy_len;
+        buf = rest;
+    }
+
+    Ok(())
+}
+
+fn thread_create(vmspace: &Handle, upcall: Upcall<UpcallArg>) -> crate::Result<Handle> {
+    get_vmspace(vmspace, HandleRight::MAP)?;
+    let thread = Rc::new(ThreadObject {
+        upcall,
+        state: Cell::new(ThreadState::Blocked),
+        in_upcall: Cell::new(false),
+        suspended: Cell::new(false),
+        syscall_args: Cell::new(SyscallArgs::zeroed()),
+        sysret: Cell::new(Sysret::zeroed()),
+        init_regs: Cell::new(InitRegs { pc: 0, sp: 0 }),
+        fsbase: Cell::new(0),
+        gsbase: Cell::new(0),
+        fp_state: Cell::new(FpState::initial()),
+        regs: Cell::new(Regs::zeroed()),
+    });
+
+    with_kernel(|kernel| kernel.threads.push(thread.clone()));
+    let right = HandleRight::READ
+        .or(HandleRight::UNBLOCK)
+        .or(HandleRight::TERMINATE)
+        .or(HandleRight::SET_CONTEXT)
+        .or(HandleRight::INTERCEPT)
+        .or(HandleRight::SUSPEND)
+        .or(HandleRight::TRANSFER)
+        .or(HandleRight::DUPLICATE);
+    Ok(insert(Object::Thread(thread), right))
+}
+
+fn thread_get_context(
+    thread: &Handle,
+    kind: ContextKind,
+    regs: &mut ContextData,
+) -> crate::Result<()> {
+    let thread = get_thread(thread, HandleRight::READ)?;
+    if !thread.is_stopped() {
+        return Err(ErrorCode::INVALID_STATE);
+    }
+
+    match kind {
+        ContextKind::SyscallArgs => regs.syscall_args = thread.syscall_args.get(),
+        ContextKind::Sysret => regs.sysret = thread.sysret.get(),
+        ContextKind::InitRegs => regs.init_regs = thread.init_regs.get(),
+        ContextKind::Fsbase => {
+            regs.fsbase = FsBase {
+                base: thread.fsbase.get(),
+            }
+        }
+        ContextKind::FpState => regs.fp_state = thread.fp_state.get(),
+        ContextKind::Gsbase => {
+            regs.gsbase = GsBase {
+                base: thread.gsbase.get(),
+            }
+        }
+        ContextKind::Regs => {
+            let args = thread.syscall_args.get();
+            let init_regs = thread.init_regs.get();
+            regs.regs = Regs {
+                rax: args.n,
+                rdi: args.arg0,
+                rsi: args.arg1,
+                rdx: args.arg2,
+                r10: args.arg3,
+                r8: args.arg4,
+                r9: args.arg5,
+                rip: init_regs.pc,
+                rsp: init_regs.sp,
+                fsbase: thread.fsbase.get(),
+                gsbase: thread.gsbase.get(),
+                ..thread.regs.get()
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn thread_set_context(thread: &Handle, kind: ContextKind, regs: &ContextData) -> crate::Result<()> {
+    let thread = get_thread(thread, HandleRight::SET_CONTEXT)?;
+    if !thread.is_stopped() {
+        return Err(ErrorCode::INVALID_STATE);
+    }
+
+    // SAFETY: `kind` tells which field is valid.
+    unsafe {
+        match kind {
+            ContextKind::SyscallArgs => thread.syscall_args.set(regs.syscall_args),
+            ContextKind::Sysret => thread.sysret.set(regs.sysret),
+            ContextKind::InitRegs => thread.init_regs.set(regs.init_regs),
+            ContextKind::Fsbase => thread.fsbase.set(regs.fsbase.base),
+            ContextKind::FpState => thread.fp_state.set(regs.fp_state),
+            ContextKind::Gsbase => thread.gsbase.set(regs.gsbase.base),
+            ContextKind::Regs => {
+                let regs = regs.regs;
+                thread.syscall_args.set(SyscallArgs {
+                    n: regs.rax,
+                    arg0: regs.rdi,
+                    arg1: regs.rsi,
+                    arg2: regs.rdx,
+                    arg3: regs.r10,
+                    arg4: regs.r8,
+                    arg5: regs.r9,
+                });
+                thread.sysret.set(Sysret { retval: regs.rax });
+                thread.init_regs.set(InitRegs {
+                    pc: regs.rip,
+                    sp: regs.rsp,
+                });
+                thread.fsbase.set(regs.fsbase);
+                thread.gsbase.set(regs.gsbase);
+                thread.regs.set(regs);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn thread_unblock(thread: &Handle) -> crate::Result<()> {
+    let thread = get_thread(thread, HandleRight::UNBLOCK)?;
+    if thread.state.get() != ThreadState::Blocked {
+        return Err(ErrorCode::INVALID_STATE);
+    }
+
+    thread.state.set(ThreadState::Runnable);
+    Ok(())
+}
+
+fn thread_terminate(thread: &Handle) -> crate::Result<()> {
+    let thread = get_thread(thread, HandleRight::TERMINATE)?;
+    if thread.state.get() == ThreadState::Terminated {
+        return Err(ErrorCode::INVALID_STATE);
+    }
+
+    thread.state.set(ThreadState::Terminated);
+
+    // The upcall is deferred as in the kernel. MockThread::syscall does it
+    // when the syscall upcall returns.
+    if !thread.in_upcall.get() {
+        with_kernel(|kernel| kernel.terminated.push_back(thread));
+    }
+
+    Ok(())
+}
+
+fn thread_suspend(thread: &Handle) -> crate::Result<()> {
+    let thread = get_thread(thread, HandleRight::SUSPEND)?;
+    if thread.state.get() == ThreadState::Terminated || thread.suspended.get() {
+        return Err(ErrorCode::INVALID_STATE);
+    }
+
+    thread.suspended.set(true);
+    Ok(())
+}
+
+fn thread_resume(thread: &Handle) -> crate::Result<()> {
+    let thread = get_thread(thread, HandleRight::SUSPEND)?;
+    if thread.state.get() == ThreadState::Terminated || !thread.suspended.get() {
+        return Err(ErrorCode::INVALID_STATE);
+    }
+
+    thread.suspended.set(false);
+    Ok(())
+}
+
+fn handle_close(handle: Handle) -> crate::Result<()> {
+    let object = with_kernel(|kernel| kernel.handles.remove(&handle.id()));
+    // Drop the object outside the kernel state.
+    object.map(|_| ()).ok_or(ErrorCode::INVALID_HANDLE)
+}
+
+fn handle_duplicate(handle: &Handle, mask: HandleRight) -> crate::Result<Handle> {
+    let (object, right) = with_kernel(|kernel| {
+        kernel
+            .handles
+            .get(&handle.id())
+            .cloned()
+            .ok_or(ErrorCode::INVALID_HANDLE)
+    })?;
+
+    if !right.contains(HandleRight::DUPLICATE) {
+        return Err(ErrorCode::NOT_ALLOWED);
+    }
+
+    Ok(insert(object, right & mask))
+}
+
+fn log(level: LogLevel, args: core::fmt::Arguments<'_>) {
+    std::println!("[mock] {:?}: {}", level, args);
+}
+
+fn normalize(path: &str) -> &str {
+    path.trim_matches('/')
+}
+
+fn initfs_stat(path: &str) -> crate::Result<FileStat> {
+    let path = normalize(path);
+    with_kernel(|kernel| {
+        let (mode, size) = match kernel.files.get(path) {
+            Some(data) => (S_IFREG | 0o644, data.len()),
+            None if path.is_empty()
+                || kernel.files.keys().any(|name| {
+                    name.strip_prefix(path)
+                        .is_some_and(|rest| rest.starts_with('/'))
+                }) =>
+            {
+                (S_IFDIR | 0o755, 0)
+            }
+            None => return Err(ErrorCode::NOT_FOUND),
+        };
+
+        Ok(FileStat {
+            mode,
+            uid: 0,
+            gid: 0,
+            size,
+            mtime: 0,
+        })
+    })
+}
+
+fn initfs_read(path: &str, offset: usize, buf: &mut [u8]) -> crate::Result<usize> {
+    let path = normalize(path);
+    let data = with_kernel(|kernel| kernel.files.get(path).copied()).ok_or(ErrorCode::NOT_FOUND)?;
+    let data = data.get(offset..).ok_or(ErrorCode::OUT_OF_BOUNDS)?;
+    let len = min(buf.len(), data.len());
+    buf[..len].copy_from_slice(&data[..len]);
+    Ok(len)
+}
+
+fn initfs_readdir(path: &str, index: usize) -> crate::Result<Option<&'static str>> {
+    if !initfs_stat(path)?.is_dir() {
+        return Err(ErrorCode::INVALID_ARG);
+    }
+
+    let path = normalize(path);
+    let children = with_kernel(|kernel| {
+        let mut children: Vec<String> = Vec::new();
+        for name in kernel.files.keys() {
+            let rest = if path.is_empty() {
+                name.as_str()
+            } else {
+                match name.strip_prefix(path).and_then(|s| s.strip_prefix('/')) {
+                    Some(rest) => rest,
+                    None => continue,
+                }
+            };
+
+            let child = rest.split('/').next().unwrap();
+            if !children.iter().any(|c| c == child) {
+                children.push(child.to_string());
+            }
+        }
+
+        children
+    });
+
+    // Leak the name as the kernel returns a name in the boot image.
+    Ok(children
+        .into_iter()
+        .nth(index)
+        .map(|name| &*Box::leak(name.into_boxed_str())))
+}
+
+fn unsupported<T>() -> crate::Result<T> {
+    Err(ErrorCode::UNSUPPORTED)
+}
+
+pub static START_INFO: StartInfo = StartInfo {
+    // The host's allocator is used instead.
+    malloc: |_| unsupported(),
+    print: |bytes| std::print!("{}", String::from_utf8_lossy(bytes)),
+    log_level: || LogLevel::Trace,
+    log,
+    log_read: |_| Ok(None),
+    log_set_console_level: |_| Ok(()),
+    boot_option: |key| with_kernel(|kernel| kernel.options.get(key).copied()),
+    panic: |info| panic!("{}", info),
+    vmspace_create,
+    vmspace_usage: |vmspace| {
+        let vmspace = get_vmspace(vmspace, HandleRight::READ)?;
+        Ok(MemoryUsage {
+            used: vmspace.used.get(),
+            limit: vmspace.limit,
+        })
+    },
+    vmarea_allocate,
+    vmarea_write,
+    vmspace_map,
+    vmspace_read,
+    vmspace_intercept: |_, _| unsupported(),
+    thread_create,
+    thread_get_context,
+    thread_set_context,
+    thread_unblock,
+    thread_terminate,
+    thread_suspend,
+    thread_resume,
+    thread_intercept: |_, _| unsupported(),
+    channel_create: unsupported,
+    channel_listen: |_, _| unsupported(),
+    channel_send: |_, _, _| unsupported(),
+    channel_recv: |_, _, _| unsupported(),
+    channel_close: |_| unsupported(),
+    handle_close,
+    handle_duplicate,
+    handle_revoke: |_| unsupported(),
+    service_register: |_, _| unsupported(),
+    service_lookup: |_| unsupported(),
+    server_stop: |_| unsupported(),
+    server_reload: |_, _, _| unsupported(),
+    server_usage: || {
+        MemoryUsage {
+            used: 0,
+            limit: None,
+        }
+    },
+    initfs_stat,
+    initfs_read,
+    initfs_readdir,
+    console_open: unsupported,
+    console_listen: |_, _| unsupported(),
+    console_read: |_, _| unsupported(),
+    interceptor_create: |_| unsupported(),
+    interceptor_detach: |_| unsupported(),
+};
+
+/// Adds a file to initfs.
+pub fn add_file(path: &str, data: &'static [u8]) {
+    let path = normalize(path).to_string();
+    with_kernel(|kernel| kernel.files.insert(path, data));
+}
+
+/// Sets a boot option, as `key=value` in the kernel command line.
+pub fn set_boot_option(key: &str, value: &'static str) {
+    with_kernel(|kernel| kernel.options.insert(key.to_string(), value));
+}
+
+/// Delivers deferred upcalls, that is, the terminated upcalls.
+pub fn deliver_pending() {
+    while let Some(thread) = with_kernel(|kernel| kernel.terminated.pop_front()) {
+        thread.upcall.invoke(UpcallArg::Terminated);
+    }
+}
+
+/// Returns the threads created by the server, in the created order.
+pub fn threads() -> Vec<MockThread> {
+    with_kernel(|kernel| kernel.threads.iter().cloned().map(MockThread).collect())
+}
+
+/// A synthetic thread created by [`Thread::create`](crate::thread::Thread::create).
+pub struct MockThread(Rc<ThreadObject>);
+
+impl MockThread {
+    pub fn is_runnable(&self) -> bool {
+        self.0.state.get() == ThreadState::Runnable && !self.0.suspended.get()
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        self.0.state.get() == ThreadState::Terminated
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.0.suspended.get()
+    }
+
+    pub fn init_regs(&self) -> InitRegs {
+        self.0.init_regs.get()
+    }
+
+    pub fn fsbase(&self) -> u64 {
+        self.0.fsbase.get()
+    }
+
+    /// Makes a syscall from the thread, and returns the return value if the
+    /// server unblocks the thread in the syscall handler.
+    ///
+    /// Returns `None` if the thread is still blocked or has been terminated.
+    /// Panics if the thread is not runnable.
+    pub fn syscall(&self, args: SyscallArgs) -> Option<Sysret> {
+        let thread = &self.0;
+        assert!(self.is_runnable(), "the thread is not runnable");
+
+        thread.syscall_args.set(args);
+        self.upcall(UpcallArg::Syscall);
+        self.is_runnable().then(|| thread.sysret.get())
+    }
+
+    /// Raises a CPU exception in the thread. Returns true if the server
+    /// unblocks the thread in the exception handler.
+    ///
+    /// Panics if the thread is not runnable.
+    pub fn exception(&self, vector: u8, error_code: u64) -> bool {
+        assert!(self.is_runnable(), "the thread is not runnable");
+
+        self.upcall(UpcallArg::Exception { vector, error_code });
+        self.is_runnable()
+    }
+
+    /// Blocks the thread and upcalls the server, as the kernel does.
+    fn upcall(&self, arg: UpcallArg) {
+        let thread = &self.0;
+        thread.state.set(ThreadState::Blocked);
+        thread.in_upcall.set(true);
+        thread.upcall.invoke(arg);
+        thread.in_upcall.set(false);
+
+        if thread.state.get() == ThreadState::Terminated {
+            with_kernel(|kernel| kernel.terminated.push_back(thread.clone()));
+        }
+
+        deliver_pending();
+    }
+}
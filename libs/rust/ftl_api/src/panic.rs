@@ -1,9 +1,10 @@
+#[cfg(target_os = "none")]
 use crate::start::start_info;
 
 #[cfg(target_os = "none")]
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
-    error!("server panic: {info}");
-    (start_info().panic)();
-    loop {}
+    // The kernel logs the panic with a backtrace, and stops (or restarts) the
+    // server.
+    (start_info().panic)(info)
 }
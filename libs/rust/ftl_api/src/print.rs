@@ -2,6 +2,30 @@ use core::fmt;
 
 pub struct Printer;
 
+/// The severity of a log message, from the least severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum LogLevel {
+    Trace,
+    Info,
+    Warn,
+    Error,
+}
+
+/// Returns true if logs at `level` should be printed. The level is set by the
+/// kernel command line.
+pub fn enabled(level: LogLevel) -> bool {
+    let info = crate::start::start_info();
+    level >= (info.log_level)()
+}
+
+/// Records a log message in the kernel log buffer. It's also printed on the
+/// console if `level` is [`enabled`].
+pub fn log(level: LogLevel, args: fmt::Arguments<'_>) {
+    let info = crate::start::start_info();
+    (info.log)(level, args);
+}
+
 pub fn print_str(s: &str) {
     let info = crate::start::start_info();
     (info.print)(s.as_bytes());
@@ -17,31 +41,28 @@ impl fmt::Write for Printer {
 #[macro_export]
 macro_rules! info {
     ($($arg:tt)+) => {{
-        $crate::println!("{}", format_args!($($arg)+));
+        $crate::print::log($crate::print::LogLevel::Info, format_args!($($arg)+));
     }};
 }
 
 #[macro_export]
 macro_rules! warn {
     ($($arg:tt)+) => {{
-        $crate::println!(
-            "\x1b[33mWARN\x1b[0m {}",
-            format_args!($($arg)+)
-        );
+        $crate::print::log($crate::print::LogLevel::Warn, format_args!($($arg)+));
     }};
 }
 
 #[macro_export]
 macro_rules! error {
     ($($arg:tt)+) => {{
-        $crate::println!("\x1b[31mERROR\x1b[0m {}", format_args!($($arg)+));
+        $crate::print::log($crate::print::LogLevel::Error, format_args!($($arg)+));
     }};
 }
 
 #[macro_export]
 macro_rules! trace {
     ($($arg:tt)+) => {{
-        $crate::println!("{}", format_args!($($arg)+));
+        $crate::print::log($crate::print::LogLevel::Trace, format_args!($($arg)+));
     }};
 }
 
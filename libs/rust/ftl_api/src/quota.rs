@@ -0,0 +1,17 @@
+//! Memory quotas.
+use crate::start::start_info;
+
+/// Memory charged to a quota, in bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryUsage {
+    pub used: usize,
+    /// The budget. `None` means unlimited.
+    pub limit: Option<usize>,
+}
+
+/// Returns the memory usage of the current server, including the VmSpaces it
+/// created.
+pub fn usage() -> MemoryUsage {
+    let start_info = start_info();
+    (start_info.server_usage)()
+}
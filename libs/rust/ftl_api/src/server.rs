@@ -0,0 +1,23 @@
+//! Server lifecycle management.
+use crate::start::start_info;
+
+/// Stops a server. Its threads are terminated and its memory is freed.
+///
+/// The server is stopped after the caller returns to the kernel, so a server
+/// can stop itself.
+pub fn stop(name: &str) -> crate::Result<()> {
+    let start_info = start_info();
+    (start_info.server_stop)(name)
+}
+
+/// Replaces a server with a new image without rebooting the system.
+///
+/// If the server is running, it's stopped first. Processes and objects
+/// owned by the old server are not carried over.
+///
+/// `signature` is the detached Ed25519 signature of `elf_file`, made by
+/// `tools/sign-server.sh`.
+pub fn reload(name: &str, elf_file: &[u8], signature: &[u8]) -> crate::Result<()> {
+    let start_info = start_info();
+    (start_info.server_reload)(name, elf_file, signature)
+}
@@ -0,0 +1,22 @@
+//! Named services.
+//!
+//! Servers can publish handles, typically channel endpoints, under names so
+//! that other servers can find them. Who can provide and use each service is
+//! defined by the kernel's boot-time policy.
+use crate::handle::Handle;
+use crate::start::start_info;
+
+/// Publishes a handle as a service.
+///
+/// The kernel keeps a duplicate of `handle`. Revoke `handle` to withdraw the
+/// service from the clients.
+pub fn register(name: &str, handle: &Handle) -> crate::Result<()> {
+    let start_info = start_info();
+    (start_info.service_register)(name, handle)
+}
+
+/// Looks up a service by name.
+pub fn lookup(name: &str) -> crate::Result<Handle> {
+    let start_info = start_info();
+    (start_info.service_lookup)(name)
+}
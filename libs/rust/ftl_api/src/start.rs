@@ -1,25 +1,41 @@
+#[cfg(not(feature = "mock"))]
 use core::sync::atomic::AtomicUsize;
+#[cfg(not(feature = "mock"))]
 use core::sync::atomic::Ordering;
 
+use crate::channel::HandleSlots;
+use crate::channel::MessageInfo;
 use crate::handle::Handle;
+use crate::handle::HandleRight;
+use crate::initfs::FileStat;
+use crate::log::LogRecord;
+use crate::quota::MemoryUsage;
 use crate::thread::ContextData;
 use crate::thread::ContextKind;
 use crate::thread::UpcallArg;
 use crate::upcall::Upcall;
 use crate::vmspace::PageAttrs;
 
+#[cfg(not(feature = "mock"))]
 static START_INFO: AtomicUsize = AtomicUsize::new(0);
 
 pub struct StartInfo {
     pub malloc: fn(size: usize) -> crate::Result<*mut u8>,
     pub print: fn(bytes: &[u8]),
-    pub panic: fn(),
-    pub vmspace_create: fn() -> crate::Result<Handle>,
-    pub vmarea_allocate: fn(len: usize) -> crate::Result<Handle>,
+    pub log_level: fn() -> crate::print::LogLevel,
+    pub log: fn(level: crate::print::LogLevel, args: core::fmt::Arguments<'_>),
+    pub log_read: fn(seq: u64) -> crate::Result<Option<LogRecord>>,
+    pub log_set_console_level: fn(level: crate::print::LogLevel) -> crate::Result<()>,
+    pub boot_option: fn(key: &str) -> Option<&'static str>,
+    pub panic: fn(info: &core::panic::PanicInfo) -> !,
+    pub vmspace_create: fn(quota: Option<usize>) -> crate::Result<Handle>,
+    pub vmspace_usage: fn(vmspace: &Handle) -> crate::Result<MemoryUsage>,
+    pub vmarea_allocate: fn(len: usize, vmspace: Option<&Handle>) -> crate::Result<Handle>,
     pub vmarea_write: fn(vmarea: &Handle, offset: usize, data: &[u8]) -> crate::Result<()>,
     pub vmspace_map:
         fn(vmspace: &Handle, vmarea: &Handle, uaddr: usize, attrs: PageAttrs) -> crate::Result<()>,
     pub vmspace_read: fn(vmspace: &Handle, uaddr: usize, buf: &mut [u8]) -> crate::Result<()>,
+    pub vmspace_intercept: fn(vmspace: &Handle, interceptor: &Handle) -> crate::Result<()>,
     pub thread_create: fn(vmspace: &Handle, upcall: Upcall<UpcallArg>) -> crate::Result<Handle>,
     pub thread_get_context:
         fn(thread: &Handle, kind: ContextKind, regs: &mut ContextData) -> crate::Result<()>,
@@ -27,21 +43,66 @@ pub struct StartInfo {
         fn(thread: &Handle, kind: ContextKind, regs: &ContextData) -> crate::Result<()>,
     pub thread_unblock: fn(thread: &Handle) -> crate::Result<()>,
     pub thread_terminate: fn(thread: &Handle) -> crate::Result<()>,
-    pub thread_destroy: fn(thread: Handle) -> crate::Result<()>,
+    pub thread_suspend: fn(thread: &Handle) -> crate::Result<()>,
+    pub thread_resume: fn(thread: &Handle) -> crate::Result<()>,
+    pub thread_intercept: fn(thread: &Handle, interceptor: &Handle) -> crate::Result<()>,
+    pub channel_create: fn() -> crate::Result<(Handle, Handle)>,
+    pub channel_listen:
+        fn(channel: &Handle, upcall: Upcall<crate::channel::UpcallArg>) -> crate::Result<()>,
+    pub channel_send:
+        fn(channel: &Handle, data: &[u8], handles: &mut HandleSlots) -> crate::Result<()>,
+    pub channel_recv: fn(
+        channel: &Handle,
+        data: &mut [u8],
+        handles: &mut HandleSlots,
+    ) -> crate::Result<MessageInfo>,
+    pub channel_close: fn(channel: &Handle) -> crate::Result<()>,
+    pub handle_close: fn(handle: Handle) -> crate::Result<()>,
+    pub handle_duplicate: fn(handle: &Handle, mask: HandleRight) -> crate::Result<Handle>,
+    pub handle_revoke: fn(handle: &Handle) -> crate::Result<()>,
+    pub service_register: fn(name: &str, handle: &Handle) -> crate::Result<()>,
+    pub service_lookup: fn(name: &str) -> crate::Result<Handle>,
+    pub server_stop: fn(name: &str) -> crate::Result<()>,
+    pub server_reload: fn(name: &str, elf_file: &[u8], signature: &[u8]) -> crate::Result<()>,
+    pub server_usage: fn() -> MemoryUsage,
+    pub initfs_stat: fn(path: &str) -> crate::Result<FileStat>,
+    pub initfs_read: fn(path: &str, offset: usize, buf: &mut [u8]) -> crate::Result<usize>,
+    pub initfs_readdir: fn(path: &str, index: usize) -> crate::Result<Option<&'static str>>,
+    pub console_open: fn() -> crate::Result<Handle>,
+    pub console_listen:
+        fn(console: &Handle, upcall: Upcall<crate::console::UpcallArg>) -> crate::Result<()>,
+    pub console_read: fn(console: &Handle, buf: &mut [u8]) -> crate::Result<usize>,
+    pub interceptor_create: fn(
+        upcall: Upcall<crate::interceptor::UpcallArg, crate::interceptor::Action>,
+    ) -> crate::Result<Handle>,
+    pub interceptor_detach: fn(interceptor: &Handle) -> crate::Result<()>,
 }
 
+#[cfg(not(feature = "mock"))]
 pub fn start_info() -> &'static StartInfo {
     let ptr = START_INFO.load(Ordering::Relaxed);
     debug_assert!(ptr != 0);
     unsafe { &*(ptr as *const StartInfo) }
 }
 
+#[cfg(feature = "mock")]
+pub fn start_info() -> &'static StartInfo {
+    &crate::mock::START_INFO
+}
+
+#[cfg(not(feature = "mock"))]
 unsafe extern "Rust" {
     static SPEC: crate::Spec;
 }
 
+/// The entry point of the server. Returns the spec to the kernel so that it
+/// can stop the server later.
+#[cfg(not(feature = "mock"))]
 #[unsafe(no_mangle)]
-pub fn server_start(start_info_ptr: *const StartInfo) {
+pub fn server_start(start_info_ptr: *const StartInfo) -> &'static crate::Spec {
     START_INFO.store(start_info_ptr as usize, Ordering::Relaxed);
-    unsafe { (SPEC.start)() };
+    unsafe {
+        (SPEC.start)();
+        &SPEC
+    }
 }
@@ -1,6 +1,7 @@
 use alloc::sync::Arc;
 
 use crate::handle::Handle;
+use crate::interceptor::Interceptor;
 use crate::start::start_info;
 use crate::upcall::UpCallCtx;
 use crate::upcall::Upcall;
@@ -10,7 +11,9 @@ use crate::vmspace::VmSpace;
 /// The kind of thread context.
 ///
 /// Instead of defining a single struct for all general-purpose registers,
-/// we prefer to define minimal variants, designed for each use case.
+/// we prefer to define minimal variants, designed for each use case. The
+/// exception is [`ContextKind::Regs`] for debuggers and signal delivery,
+/// which need all of them.
 #[repr(usize)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ContextKind {
@@ -18,6 +21,9 @@ pub enum ContextKind {
     Sysret = 1,
     InitRegs = 2,
     Fsbase = 3,
+    FpState = 4,
+    Gsbase = 5,
+    Regs = 6,
 }
 
 #[repr(C)]
@@ -27,6 +33,9 @@ pub union ContextData {
     pub sysret: Sysret,
     pub init_regs: InitRegs,
     pub fsbase: FsBase,
+    pub fp_state: FpState,
+    pub gsbase: GsBase,
+    pub regs: Regs,
 }
 
 /// The initial registers for a thread.
@@ -86,13 +95,184 @@ pub struct FsBase {
     pub base: u64,
 }
 
+/// The x86-64 gsbase.
+///
+/// In Linux, `arch_prctl(ARCH_(SET|GET)_GS)` is the equivalent.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct GsBase {
+    pub base: u64,
+}
+
+/// All user registers except the FPU state, which is in [`FpState`].
+///
+/// The kernel rejects non-canonical `rip`, `fsbase`, and `gsbase`, and
+/// privileged bits in `rflags` such as IOPL. In Linux, `user_regs_struct`
+/// is the equivalent.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Regs {
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub rsp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rip: u64,
+    pub rflags: u64,
+    pub fsbase: u64,
+    pub gsbase: u64,
+}
+
+impl Regs {
+    pub const fn zeroed() -> Self {
+        Self {
+            rax: 0,
+            rbx: 0,
+            rcx: 0,
+            rdx: 0,
+            rsi: 0,
+            rdi: 0,
+            rbp: 0,
+            rsp: 0,
+            r8: 0,
+            r9: 0,
+            r10: 0,
+            r11: 0,
+            r12: 0,
+            r13: 0,
+            r14: 0,
+            r15: 0,
+            rip: 0,
+            rflags: 0,
+            fsbase: 0,
+            gsbase: 0,
+        }
+    }
+}
+
+/// The x87 FPU, SSE, and AVX registers.
+///
+/// The first 512 bytes are in the FXSAVE format. In Linux, `user_fpregs_struct`
+/// is the equivalent. Writing an `mxcsr` with bits the CPU doesn't support
+/// (see `mxcsr_mask` read from a thread) fails with `INVALID_ARG`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct FpState {
+    pub fcw: u16,
+    pub fsw: u16,
+    /// The abridged x87 tag word: a bit per register, set if it's valid.
+    pub ftw: u8,
+    reserved0: u8,
+    pub fop: u16,
+    pub fip: u64,
+    pub fdp: u64,
+    pub mxcsr: u32,
+    pub mxcsr_mask: u32,
+    /// ST0-ST7 (or MM0-MM7), in the lower 10 bytes of each.
+    pub st: [[u8; 16]; 8],
+    pub xmm: [[u8; 16]; 16],
+    reserved1: [u8; 96],
+    /// The upper halves of YMM0-YMM15. Zero if AVX is not available.
+    pub ymm_hi: [[u8; 16]; 16],
+}
+
+impl FpState {
+    pub const fn zeroed() -> Self {
+        Self {
+            fcw: 0,
+            fsw: 0,
+            ftw: 0,
+            reserved0: 0,
+            fop: 0,
+            fip: 0,
+            fdp: 0,
+            mxcsr: 0,
+            mxcsr_mask: 0,
+            st: [[0; 16]; 8],
+            xmm: [[0; 16]; 16],
+            reserved1: [0; 96],
+            ymm_hi: [[0; 16]; 16],
+        }
+    }
+
+    /// The state of a new thread: all registers are zero, and exceptions
+    /// are masked.
+    pub const fn initial() -> Self {
+        let mut state = Self::zeroed();
+        state.fcw = 0x37f;
+        state.mxcsr = 0x1f80;
+        state
+    }
+}
+
+/// x86-64 exception vectors in [`UpcallArg::Exception`].
+pub mod vector {
+    /// Divide Error (#DE).
+    pub const DE: u8 = 0;
+    /// Debug (#DB).
+    pub const DB: u8 = 1;
+    /// Breakpoint (#BP).
+    pub const BP: u8 = 3;
+    /// Overflow (#OF).
+    pub const OF: u8 = 4;
+    /// BOUND Range Exceeded (#BR).
+    pub const BR: u8 = 5;
+    /// Invalid Opcode (#UD).
+    pub const UD: u8 = 6;
+    /// Device Not Available (#NM).
+    pub const NM: u8 = 7;
+    /// Invalid TSS (#TS).
+    pub const TS: u8 = 10;
+    /// Segment Not Present (#NP).
+    pub const NP: u8 = 11;
+    /// Stack-Segment Fault (#SS).
+    pub const SS: u8 = 12;
+    /// General Protection (#GP).
+    pub const GP: u8 = 13;
+    /// x87 Floating-Point Error (#MF).
+    pub const MF: u8 = 16;
+    /// Alignment Check (#AC).
+    pub const AC: u8 = 17;
+    /// SIMD Floating-Point Exception (#XM).
+    pub const XM: u8 = 19;
+}
+
 pub enum UpcallArg {
     Syscall,
+    /// A CPU exception in the user mode. See [`vector`] for `vector`.
+    Exception {
+        vector: u8,
+        error_code: u64,
+    },
     Terminated,
 }
 
 pub trait Handler: Send + Sync {
     fn syscall(&self, thread: &Thread);
+
+    /// Called when the thread raises a CPU exception, such as an invalid
+    /// opcode or a breakpoint. The thread is blocked until it's unblocked
+    /// or terminated.
+    ///
+    /// By default, the thread is terminated.
+    fn exception(&self, thread: &Thread, vector: u8, error_code: u64) {
+        warn!("unhandled exception ({vector}), error_code={error_code:#x}");
+        if let Err(err) = thread.terminate() {
+            error!("failed to terminate thread: {:?}", err);
+        }
+    }
+
     fn terminated(&self, thread: &Thread);
 }
 
@@ -102,6 +282,12 @@ fn upcall_entry<H: Handler + 'static>(ctx: UpCallCtx, arg: UpcallArg) {
             let user_data = unsafe { UserData::<Arc<Thread>, H>::borrow(ctx) };
             user_data.handler.syscall(&user_data.object);
         }
+        UpcallArg::Exception { vector, error_code } => {
+            let user_data = unsafe { UserData::<Arc<Thread>, H>::borrow(ctx) };
+            user_data
+                .handler
+                .exception(&user_data.object, vector, error_code);
+        }
         UpcallArg::Terminated => {
             let user_data = unsafe { UserData::<Arc<Thread>, H>::reclaim(ctx) };
             user_data.handler.terminated(&user_data.object);
@@ -141,22 +327,46 @@ impl Thread {
         (start_info.thread_unblock)(&self.handle)
     }
 
+    /// Attaches an interceptor to the thread. See [`crate::interceptor`].
+    pub fn intercept(&self, interceptor: &Interceptor) -> crate::Result<()> {
+        let start_info = start_info();
+        (start_info.thread_intercept)(&self.handle, interceptor.handle())
+    }
+
     /// Stops the thread permanently. Safe to call from its syscall handler.
     pub fn terminate(&self) -> crate::Result<()> {
         let start_info = start_info();
         (start_info.thread_terminate)(&self.handle)
     }
+
+    /// Stops the thread until [`Thread::resume`] is called.
+    ///
+    /// Stopping a thread running on another CPU is not implemented yet. It
+    /// doesn't matter for now because only the boot CPU runs threads.
+    ///
+    /// The context of a suspended thread can be read and written. If the
+    /// thread is blocked in a syscall, it stays suspended after being
+    /// unblocked.
+    pub fn suspend(&self) -> crate::Result<()> {
+        let start_info = start_info();
+        (start_info.thread_suspend)(&self.handle)
+    }
+
+    /// Resumes a thread stopped by [`Thread::suspend`].
+    pub fn resume(&self) -> crate::Result<()> {
+        let start_info = start_info();
+        (start_info.thread_resume)(&self.handle)
+    }
 }
 
 impl Drop for Thread {
     fn drop(&mut self) {
         // SAFETY: Handle does not implement Drop, and we won't use it after
-        //         the thread_destroy call below.
+        //         the close call below.
         let handle = unsafe { core::ptr::read(&self.handle) };
 
-        let start_info = start_info();
-        if let Err(err) = (start_info.thread_destroy)(handle) {
-            error!("failed to destroy thread: {:?}", err);
+        if let Err(err) = handle.close() {
+            error!("failed to close thread: {:?}", err);
         }
     }
 }
@@ -23,20 +23,21 @@ impl<C, H> UserData<C, H> {
 #[derive(Clone, Copy)]
 pub struct UpCallCtx(usize);
 
-type Dispatcher<T> = extern "Rust" fn(ctx: UpCallCtx, arg: T);
+type Dispatcher<T, R> = extern "Rust" fn(ctx: UpCallCtx, arg: T) -> R;
 
-pub struct Upcall<T> {
-    dispatch: Dispatcher<T>,
+/// An upcall with an argument `T`, returning `R` to the kernel.
+pub struct Upcall<T, R = ()> {
+    dispatch: Dispatcher<T, R>,
     ctx: UpCallCtx,
 }
 
-unsafe impl<T> Send for Upcall<T> {}
-unsafe impl<T> Sync for Upcall<T> {}
+unsafe impl<T, R> Send for Upcall<T, R> {}
+unsafe impl<T, R> Sync for Upcall<T, R> {}
 
-impl<T> Upcall<T> {
-    pub(crate) fn new<F, H, C, E>(dispatch: Dispatcher<T>, handler: H, ctor: F) -> Result<C, E>
+impl<T, R> Upcall<T, R> {
+    pub(crate) fn new<F, H, C, E>(dispatch: Dispatcher<T, R>, handler: H, ctor: F) -> Result<C, E>
     where
-        F: FnOnce(Upcall<T>) -> Result<C, E>,
+        F: FnOnce(Upcall<T, R>) -> Result<C, E>,
         H: Send + Sync + 'static,
         C: Clone + Send + Sync + 'static,
     {
@@ -73,8 +74,20 @@ impl<T> Upcall<T> {
     }
 }
 
-impl<T> Upcall<T> {
-    pub fn invoke(&self, arg: T) {
+/// Upcalls without user data, for the kernel's own tests: they have no
+/// server to register a handler.
+#[cfg(feature = "kernel")]
+impl<T, R> Upcall<T, R> {
+    pub fn from_fn(dispatch: extern "Rust" fn(ctx: UpCallCtx, arg: T) -> R) -> Self {
+        Self {
+            dispatch,
+            ctx: UpCallCtx(0),
+        }
+    }
+}
+
+impl<T, R> Upcall<T, R> {
+    pub fn invoke(&self, arg: T) -> R {
         (self.dispatch)(self.ctx, arg)
     }
 }
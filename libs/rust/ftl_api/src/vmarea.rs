@@ -1,5 +1,6 @@
 use crate::handle::Handle;
 use crate::start::start_info;
+use crate::vmspace::VmSpace;
 
 pub struct VmArea {
     handle: Handle,
@@ -8,7 +9,14 @@ pub struct VmArea {
 impl VmArea {
     pub fn allocate(len: usize) -> crate::Result<Self> {
         let start_info = start_info();
-        let handle = (start_info.vmarea_allocate)(len)?;
+        let handle = (start_info.vmarea_allocate)(len, None)?;
+        Ok(Self { handle })
+    }
+
+    /// Allocates a VM area whose pages are charged to `vmspace`'s quota.
+    pub fn allocate_for(vmspace: &VmSpace, len: usize) -> crate::Result<Self> {
+        let start_info = start_info();
+        let handle = (start_info.vmarea_allocate)(len, Some(vmspace.handle()))?;
         Ok(Self { handle })
     }
 
@@ -24,6 +32,12 @@ impl VmArea {
 
 impl Drop for VmArea {
     fn drop(&mut self) {
-        // TODO: Add vmarea_destroy supercall
+        // SAFETY: Handle does not implement Drop, and we won't use it after
+        //         the close call below.
+        let handle = unsafe { core::ptr::read(&self.handle) };
+
+        if let Err(err) = handle.close() {
+            error!("failed to close vmarea: {:?}", err);
+        }
     }
 }
@@ -1,6 +1,8 @@
 use core::ops::BitOr;
 
 use crate::handle::Handle;
+use crate::interceptor::Interceptor;
+use crate::quota::MemoryUsage;
 use crate::start::start_info;
 use crate::vmarea::VmArea;
 
@@ -11,10 +13,26 @@ pub struct VmSpace {
 impl VmSpace {
     pub fn create() -> crate::Result<Self> {
         let start_info = start_info();
-        let handle = (start_info.vmspace_create)()?;
+        let handle = (start_info.vmspace_create)(None)?;
         Ok(Self { handle })
     }
 
+    /// Creates a VmSpace with a memory budget of `quota` bytes.
+    ///
+    /// Page tables and VM areas allocated for the VmSpace (see
+    /// [`VmArea::allocate_for`]) are charged to the budget, as well as to the
+    /// current server's.
+    pub fn create_with_quota(quota: usize) -> crate::Result<Self> {
+        let start_info = start_info();
+        let handle = (start_info.vmspace_create)(Some(quota))?;
+        Ok(Self { handle })
+    }
+
+    pub fn memory_usage(&self) -> crate::Result<MemoryUsage> {
+        let start_info = start_info();
+        (start_info.vmspace_usage)(&self.handle)
+    }
+
     pub fn map(&self, vmarea: &VmArea, uaddr: usize, attrs: PageAttrs) -> crate::Result<()> {
         let start_info = start_info();
         (start_info.vmspace_map)(&self.handle, vmarea.handle(), uaddr, attrs)
@@ -25,6 +43,13 @@ impl VmSpace {
         (start_info.vmspace_read)(&self.handle, uaddr, buf)
     }
 
+    /// Attaches an interceptor to all threads in the VmSpace. See
+    /// [`crate::interceptor`].
+    pub fn intercept(&self, interceptor: &Interceptor) -> crate::Result<()> {
+        let start_info = start_info();
+        (start_info.vmspace_intercept)(&self.handle, interceptor.handle())
+    }
+
     pub fn handle(&self) -> &Handle {
         &self.handle
     }
@@ -32,7 +57,13 @@ impl VmSpace {
 
 impl Drop for VmSpace {
     fn drop(&mut self) {
-        // TODO: Add vmspace_destroy supercall
+        // SAFETY: Handle does not implement Drop, and we won't use it after
+        //         the close call below.
+        let handle = unsafe { core::ptr::read(&self.handle) };
+
+        if let Err(err) = handle.close() {
+            error!("failed to close vmspace: {:?}", err);
+        }
     }
 }
 
@@ -41,11 +72,11 @@ pub struct PageAttrs(usize);
 
 impl PageAttrs {
     // X64 PTE flags.
-    #[cfg(target_arch = "x86_64")]
+    #[cfg(all(target_arch = "x86_64", target_os = "none"))]
     pub const READ: Self = Self(1 << 0); // TODO: This is P bit actually. Should we use 0?
-    #[cfg(target_arch = "x86_64")]
+    #[cfg(all(target_arch = "x86_64", target_os = "none"))]
     pub const WRITE: Self = Self(1 << 1);
-    #[cfg(target_arch = "x86_64")]
+    #[cfg(all(target_arch = "x86_64", target_os = "none"))]
     pub const EXEC: Self = Self(1 << 2);
 
     // Host environment page attributes.
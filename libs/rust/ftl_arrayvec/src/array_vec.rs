@@ -107,6 +107,28 @@ impl<T, const N: usize> ArrayVec<T, N> {
         self.len = 0;
     }
 
+    /// Keeps only the elements for which `f` returns true, in the order.
+    pub fn retain(&mut self, mut f: impl FnMut(&T) -> bool) {
+        let mut kept = 0;
+        for i in 0..self.len {
+            // SAFETY: i < self.len guarantees that the slot is initialized.
+            if f(unsafe { self.elems[i].assume_init_ref() }) {
+                self.elems.swap(i, kept);
+                kept += 1;
+            }
+        }
+
+        let len = self.len;
+        self.len = kept;
+        for elem in &mut self.elems[kept..len] {
+            // SAFETY: The slots were initialized, and won't be read again
+            // since they're beyond self.len.
+            unsafe {
+                elem.assume_init_drop();
+            }
+        }
+    }
+
     pub fn iter(&self) -> slice::Iter<'_, T> {
         self.as_slice().iter()
     }
@@ -203,6 +225,17 @@ mod tests {
         assert_eq!(vec.as_ref(), &['A', 'B']);
     }
 
+    #[test]
+    fn test_retain() {
+        let mut vec = ArrayVec::<u32, 5>::new();
+        for i in 0..5 {
+            vec.try_push(i).unwrap();
+        }
+
+        vec.retain(|&i| i % 2 == 0);
+        assert_eq!(vec.as_slice(), &[0, 2, 4]);
+    }
+
     #[test]
     fn test_drop() {
         #[derive(Debug)]
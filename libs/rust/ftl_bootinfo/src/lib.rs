@@ -0,0 +1,21 @@
+//! Parsers for boot information passed by bootloaders.
+//!
+//! The parsers take byte slices instead of physical addresses, and never
+//! read outside of them: the information is written by the bootloader (or
+//! the hypervisor), and we don't trust it blindly.
+#![no_std]
+
+pub mod multiboot2;
+pub mod pvh;
+
+/// Reads a little-endian `u32` at `offset`.
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// Reads a little-endian `u64` at `offset`.
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    let bytes = bytes.get(offset..offset.checked_add(8)?)?;
+    Some(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
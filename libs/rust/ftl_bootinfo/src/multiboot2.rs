@@ -0,0 +1,214 @@
+//! Multiboot2 boot information.
+//!
+//! <https://www.gnu.org/software/grub/manual/multiboot2/multiboot.html#Boot-information-format>
+use crate::read_u32;
+use crate::read_u64;
+
+const TAG_END: u32 = 0;
+const TAG_CMDLINE: u32 = 1;
+const TAG_MODULE: u32 = 3;
+const TAG_MEMORY_MAP: u32 = 6;
+
+/// The size of the fixed part of the boot information.
+const INFO_HEADER_SIZE: usize = 8;
+/// The size of `type` and `size` fields in each tag.
+const TAG_HEADER_SIZE: usize = 8;
+/// The size of `entry_size` and `entry_version` fields in the memory map tag.
+const MEMORY_MAP_HEADER_SIZE: usize = 8;
+/// The size of the fields we read in a memory map entry.
+const MEMORY_MAP_ENTRY_SIZE: usize = 24;
+
+pub const MEMORY_AVAILABLE: u32 = 1;
+
+/// Returns the `total_size` field, that is, the size of the boot
+/// information including the field itself.
+pub fn total_size(header: &[u8; INFO_HEADER_SIZE]) -> usize {
+    u32::from_le_bytes(header[..4].try_into().unwrap()) as usize
+}
+
+pub enum Tag<'a> {
+    /// The kernel command line, without the trailing null character.
+    Cmdline(&'a [u8]),
+    Module {
+        start: u32,
+        end: u32,
+    },
+    MemoryMap(MemoryMap<'a>),
+    Unknown(u32),
+}
+
+/// Iterates over the tags in the boot information `info`, whose length is
+/// the `total_size` field.
+///
+/// The iteration stops at the end tag, or at a malformed tag.
+pub struct Tags<'a> {
+    info: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Tags<'a> {
+    pub fn new(info: &'a [u8]) -> Self {
+        Self {
+            info,
+            offset: INFO_HEADER_SIZE,
+        }
+    }
+}
+
+impl<'a> Iterator for Tags<'a> {
+    type Item = Tag<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let type_ = read_u32(self.info, self.offset)?;
+        let size = read_u32(self.info, self.offset + 4)? as usize;
+        if type_ == TAG_END || size < TAG_HEADER_SIZE {
+            return None;
+        }
+
+        let body_start = self.offset + TAG_HEADER_SIZE;
+        let body = self.info.get(body_start..self.offset.checked_add(size)?)?;
+
+        // Tags are 8-byte aligned.
+        self.offset = self.offset.checked_add(size.checked_next_multiple_of(8)?)?;
+
+        let tag = match type_ {
+            TAG_CMDLINE => {
+                let len = body.iter().position(|b| *b == 0).unwrap_or(body.len());
+                Tag::Cmdline(&body[..len])
+            }
+            TAG_MODULE => {
+                Tag::Module {
+                    start: read_u32(body, 0)?,
+                    end: read_u32(body, 4)?,
+                }
+            }
+            TAG_MEMORY_MAP => {
+                let entry_size = read_u32(body, 0)? as usize;
+                let entries = body.get(MEMORY_MAP_HEADER_SIZE..)?;
+                Tag::MemoryMap(MemoryMap::new(entries, entry_size))
+            }
+            _ => Tag::Unknown(type_),
+        };
+
+        Some(tag)
+    }
+}
+
+pub struct MemoryMapEntry {
+    pub base_addr: u64,
+    pub length: u64,
+    pub type_: u32,
+}
+
+/// Iterates over the entries in a memory map tag.
+pub struct MemoryMap<'a> {
+    entries: &'a [u8],
+    entry_size: usize,
+}
+
+impl<'a> MemoryMap<'a> {
+    fn new(entries: &'a [u8], entry_size: usize) -> Self {
+        // Ignore the entries if the size is too small for the fields.
+        let entries = if entry_size < MEMORY_MAP_ENTRY_SIZE {
+            &[]
+        } else {
+            entries
+        };
+
+        Self {
+            entries,
+            entry_size,
+        }
+    }
+}
+
+impl Iterator for MemoryMap<'_> {
+    type Item = MemoryMapEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.entries.get(..self.entry_size)?;
+        self.entries = &self.entries[self.entry_size..];
+        Some(MemoryMapEntry {
+            base_addr: read_u64(entry, 0)?,
+            length: read_u64(entry, 8)?,
+            type_: read_u32(entry, 16)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+
+    fn push_tag(info: &mut Vec<u8>, type_: u32, body: &[u8]) {
+        info.extend_from_slice(&type_.to_le_bytes());
+        info.extend_from_slice(&((TAG_HEADER_SIZE + body.len()) as u32).to_le_bytes());
+        info.extend_from_slice(body);
+        info.resize(info.len().next_multiple_of(8), 0);
+    }
+
+    fn info(tags: impl FnOnce(&mut Vec<u8>)) -> Vec<u8> {
+        let mut info = std::vec![0; INFO_HEADER_SIZE];
+        tags(&mut info);
+        push_tag(&mut info, TAG_END, &[]);
+        let total_size = info.len() as u32;
+        info[..4].copy_from_slice(&total_size.to_le_bytes());
+        info
+    }
+
+    #[test]
+    fn parse_tags() {
+        let info = info(|info| {
+            push_tag(info, TAG_CMDLINE, b"log=info\0");
+            let mut mmap = Vec::new();
+            mmap.extend_from_slice(&24u32.to_le_bytes()); // entry_size
+            mmap.extend_from_slice(&0u32.to_le_bytes()); // entry_version
+            mmap.extend_from_slice(&0x100000u64.to_le_bytes());
+            mmap.extend_from_slice(&0x200000u64.to_le_bytes());
+            mmap.extend_from_slice(&MEMORY_AVAILABLE.to_le_bytes());
+            mmap.extend_from_slice(&0u32.to_le_bytes());
+            push_tag(info, TAG_MEMORY_MAP, &mmap);
+        });
+
+        assert_eq!(total_size(info[..8].try_into().unwrap()), info.len());
+        let mut tags = Tags::new(&info);
+        assert!(matches!(tags.next(), Some(Tag::Cmdline(b"log=info"))));
+        let Some(Tag::MemoryMap(mut mmap)) = tags.next() else {
+            panic!("expected a memory map");
+        };
+        let entry = mmap.next().unwrap();
+        assert_eq!(entry.base_addr, 0x100000);
+        assert_eq!(entry.length, 0x200000);
+        assert!(mmap.next().is_none());
+        assert!(tags.next().is_none());
+    }
+
+    #[test]
+    fn malformed_sizes() {
+        // A zero-sized tag used to loop forever.
+        let mut info = std::vec![0; INFO_HEADER_SIZE];
+        info.extend_from_slice(&TAG_CMDLINE.to_le_bytes());
+        info.extend_from_slice(&0u32.to_le_bytes());
+        assert!(Tags::new(&info).next().is_none());
+
+        // A zero entry size used to divide by zero.
+        let info = info_with_mmap_entry_size(0);
+        let Some(Tag::MemoryMap(mut mmap)) = Tags::new(&info).next() else {
+            panic!("expected a memory map");
+        };
+        assert!(mmap.next().is_none());
+    }
+
+    fn info_with_mmap_entry_size(entry_size: u32) -> Vec<u8> {
+        info(|info| {
+            let mut mmap = Vec::new();
+            mmap.extend_from_slice(&entry_size.to_le_bytes());
+            mmap.extend_from_slice(&[0; 28]);
+            push_tag(info, TAG_MEMORY_MAP, &mmap);
+        })
+    }
+}
@@ -0,0 +1,118 @@
+//! PVH boot protocol.
+//!
+//! <https://xenbits.xen.org/docs/unstable/hypercall/x86_64/include,public,arch-x86,hvm,start_info.h.html>
+use crate::read_u32;
+use crate::read_u64;
+
+pub const MAGIC: [u8; 4] = [b'x', b'E' | 0x80, b'n', b'3'];
+/// The size of `hvm_start_info`.
+pub const START_INFO_SIZE: usize = 56;
+/// The size of `hvm_modlist_entry`.
+pub const MODULE_ENTRY_SIZE: usize = 32;
+/// The size of `hvm_memmap_table_entry`.
+pub const MEMORY_MAP_ENTRY_SIZE: usize = 24;
+
+pub const MEMMAP_TYPE_RAM: u32 = 1;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseError {
+    TooShort,
+    InvalidMagic([u8; 4]),
+}
+
+/// The fields of `hvm_start_info` we use.
+pub struct StartInfo {
+    pub nr_modules: u32,
+    pub modlist_paddr: u64,
+    pub cmdline_paddr: u64,
+    pub memmap_paddr: u64,
+    pub memmap_entries: u32,
+}
+
+impl StartInfo {
+    pub fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
+        let bytes = bytes.get(..START_INFO_SIZE).ok_or(ParseError::TooShort)?;
+        let magic: [u8; 4] = bytes[..4].try_into().unwrap();
+        if magic != MAGIC {
+            return Err(ParseError::InvalidMagic(magic));
+        }
+
+        // The length is checked above.
+        Ok(Self {
+            nr_modules: read_u32(bytes, 12).unwrap(),
+            modlist_paddr: read_u64(bytes, 16).unwrap(),
+            cmdline_paddr: read_u64(bytes, 24).unwrap(),
+            memmap_paddr: read_u64(bytes, 40).unwrap(),
+            memmap_entries: read_u32(bytes, 48).unwrap(),
+        })
+    }
+}
+
+pub struct ModuleEntry {
+    pub paddr: u64,
+    pub size: u64,
+}
+
+pub struct MemoryMapEntry {
+    pub addr: u64,
+    pub size: u64,
+    pub type_: u32,
+}
+
+/// Iterates over the module list. A trailing partial entry is ignored.
+pub fn modules(modlist: &[u8]) -> impl Iterator<Item = ModuleEntry> + '_ {
+    modlist.chunks_exact(MODULE_ENTRY_SIZE).map(|entry| {
+        ModuleEntry {
+            paddr: read_u64(entry, 0).unwrap(),
+            size: read_u64(entry, 8).unwrap(),
+        }
+    })
+}
+
+/// Iterates over the memory map. A trailing partial entry is ignored.
+pub fn memory_map(memmap: &[u8]) -> impl Iterator<Item = MemoryMapEntry> + '_ {
+    memmap.chunks_exact(MEMORY_MAP_ENTRY_SIZE).map(|entry| {
+        MemoryMapEntry {
+            addr: read_u64(entry, 0).unwrap(),
+            size: read_u64(entry, 8).unwrap(),
+            type_: read_u32(entry, 16).unwrap(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_start_info() {
+        let mut bytes = [0; START_INFO_SIZE];
+        bytes[..4].copy_from_slice(&MAGIC);
+        bytes[12..16].copy_from_slice(&2u32.to_le_bytes());
+        bytes[48..52].copy_from_slice(&3u32.to_le_bytes());
+
+        let start_info = StartInfo::parse(&bytes).unwrap();
+        assert_eq!(start_info.nr_modules, 2);
+        assert_eq!(start_info.memmap_entries, 3);
+
+        assert_eq!(
+            StartInfo::parse(&bytes[..START_INFO_SIZE - 1]).err(),
+            Some(ParseError::TooShort)
+        );
+        bytes[0] = 0;
+        assert!(matches!(
+            StartInfo::parse(&bytes),
+            Err(ParseError::InvalidMagic(_))
+        ));
+    }
+
+    #[test]
+    fn partial_entries() {
+        let mut memmap = [0; MEMORY_MAP_ENTRY_SIZE + 8];
+        memmap[16..20].copy_from_slice(&MEMMAP_TYPE_RAM.to_le_bytes());
+        let mut entries = memory_map(&memmap);
+        assert_eq!(entries.next().unwrap().type_, MEMMAP_TYPE_RAM);
+        assert!(entries.next().is_none());
+        assert_eq!(modules(&[0; MODULE_ENTRY_SIZE - 1]).count(), 0);
+    }
+}
@@ -0,0 +1,202 @@
+//! A reader for newc CPIO archives.
+//!
+//! <https://man.archlinux.org/man/cpio.5.en#New_ASCII_Format>
+#![no_std]
+
+use core::mem::offset_of;
+
+use ftl_utils::alignment::align_up;
+
+pub const MAGIC: &[u8] = b"070701";
+
+/// The name of the last entry in an archive.
+const TRAILER: &[u8] = b"TRAILER!!!";
+
+/// New CPIO header format.
+#[repr(C, packed)]
+struct Header {
+    magic: [u8; 6],
+    inode: [u8; 8],
+    mode: [u8; 8],
+    uid: [u8; 8],
+    gid: [u8; 8],
+    nlink: [u8; 8],
+    mtime: [u8; 8],
+    filesize: [u8; 8],
+    dev_major: [u8; 8],
+    dev_minor: [u8; 8],
+    rdev_major: [u8; 8],
+    rdev_minor: [u8; 8],
+    namesize: [u8; 8],
+    checksum: [u8; 8],
+}
+
+fn parse_hex(s: &[u8]) -> Option<usize> {
+    let mut value = 0;
+    for &byte in s {
+        let digit = match byte {
+            b'0'..=b'9' => byte - b'0',
+            b'a'..=b'f' => byte - b'a' + 10,
+            b'A'..=b'F' => byte - b'A' + 10,
+            _ => return None,
+        };
+
+        value = value * 16 + (digit as usize);
+    }
+
+    Some(value)
+}
+
+pub struct Entry<'a> {
+    /// The path as in the archive, without the trailing null byte.
+    pub name: &'a [u8],
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub mtime: u64,
+    pub data: &'a [u8],
+}
+
+/// Iterates over the entries in the archive at the beginning of `file`.
+///
+/// The iteration stops at the trailer, or at a malformed entry.
+pub struct Reader<'a> {
+    file: &'a [u8],
+    offset: usize,
+    /// Whether the trailer has been reached.
+    finished: bool,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(file: &'a [u8]) -> Self {
+        Self {
+            file,
+            offset: 0,
+            finished: false,
+        }
+    }
+
+    /// Returns the length of the archive at the beginning of `file`,
+    /// including the trailer. Zero if the first entry is malformed.
+    pub fn archive_len(file: &'a [u8]) -> usize {
+        let mut reader = Self::new(file);
+        for _ in reader.by_ref() {}
+        reader.offset
+    }
+
+    fn header_field(&self, field_offset: usize) -> Option<usize> {
+        let offset = self.offset + field_offset;
+        parse_hex(self.file.get(offset..offset + 8)?)
+    }
+}
+
+impl<'a> Iterator for Reader<'a> {
+    type Item = Entry<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        let magic = self.file.get(self.offset..self.offset + MAGIC.len())?;
+        if magic != MAGIC {
+            return None;
+        }
+
+        let namesize = self.header_field(offset_of!(Header, namesize))?;
+        let filesize = self.header_field(offset_of!(Header, filesize))?;
+        let mode = self.header_field(offset_of!(Header, mode))?;
+        let uid = self.header_field(offset_of!(Header, uid))?;
+        let gid = self.header_field(offset_of!(Header, gid))?;
+        let mtime = self.header_field(offset_of!(Header, mtime))?;
+
+        let name_offset = self.offset + size_of::<Header>();
+        let name_end = name_offset.checked_add(namesize)?;
+        let mut name = self.file.get(name_offset..name_end)?;
+
+        // Remove the trailing null byte.
+        if name.ends_with(b"\0") {
+            name = &name[..name.len() - 1];
+        }
+
+        // The offsets are within the file, and won't overflow when aligned.
+        let data_offset = align_up(name_end, 4);
+        let data_end = data_offset.checked_add(filesize)?;
+        let data = self.file.get(data_offset..data_end)?;
+
+        self.offset = align_up(data_end, 4);
+        if name == TRAILER {
+            self.finished = true;
+            return None;
+        }
+
+        Some(Entry {
+            name,
+            mode: mode as u32,
+            uid: uid as u32,
+            gid: gid as u32,
+            mtime: mtime as u64,
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+
+    fn push_entry(archive: &mut Vec<u8>, name: &[u8], data: &[u8]) {
+        let header = std::format!(
+            "070701{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}",
+            0,
+            0o100644,
+            0,
+            0,
+            1,
+            0,
+            data.len(),
+            0,
+            0,
+            0,
+            0,
+            name.len() + 1,
+            0
+        );
+        archive.extend_from_slice(header.as_bytes());
+        archive.extend_from_slice(name);
+        archive.push(0);
+        archive.resize(align_up(archive.len(), 4), 0);
+        archive.extend_from_slice(data);
+        archive.resize(align_up(archive.len(), 4), 0);
+    }
+
+    #[test]
+    fn read_entries() {
+        let mut archive = Vec::new();
+        push_entry(&mut archive, b"hello.txt", b"hello");
+        push_entry(&mut archive, TRAILER, b"");
+        let len = archive.len();
+        archive.extend_from_slice(b"garbage");
+
+        let entries: Vec<_> = Reader::new(&archive).collect();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, b"hello.txt");
+        assert_eq!(entries[0].mode, 0o100644);
+        assert_eq!(entries[0].data, b"hello");
+        assert_eq!(Reader::archive_len(&archive), len);
+    }
+
+    #[test]
+    fn truncated() {
+        let mut archive = Vec::new();
+        push_entry(&mut archive, b"hello.txt", b"hello");
+        archive.truncate(archive.len() - 4);
+
+        assert_eq!(Reader::new(&archive).count(), 0);
+        assert_eq!(Reader::archive_len(&archive), 0);
+    }
+}
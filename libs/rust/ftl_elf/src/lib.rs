@@ -1,5 +1,7 @@
 #![no_std]
 
+use core::ops::Range;
+
 #[cfg(target_pointer_width = "64")]
 pub type Addr = u64;
 #[cfg(target_pointer_width = "64")]
@@ -15,7 +17,7 @@ const EM_NATIVE: u16 = 62;
 #[cfg(target_arch = "aarch64")]
 const EM_NATIVE: u16 = 183;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct Ehdr {
     pub e_ident: [u8; 16],
@@ -49,6 +51,8 @@ pub enum PhdrType {
     ShLib = 5,
     Phdr = 6,
     Tls = 7,
+    /// The range to be made read-only after relocation.
+    GnuRelro = 0x6474e552,
 }
 
 /// Executable segment.
@@ -69,8 +73,100 @@ pub struct Dyn64 {
 pub type Dyn = Dyn64;
 
 pub const DT_NULL: i64 = 0;
+pub const DT_PLTRELSZ: i64 = 2;
+pub const DT_HASH: i64 = 4;
+pub const DT_STRTAB: i64 = 5;
+pub const DT_SYMTAB: i64 = 6;
 pub const DT_RELA: i64 = 7;
 pub const DT_RELASZ: i64 = 8;
+pub const DT_RELAENT: i64 = 9;
+pub const DT_STRSZ: i64 = 10;
+pub const DT_SYMENT: i64 = 11;
+pub const DT_PLTREL: i64 = 20;
+pub const DT_JMPREL: i64 = 23;
+pub const DT_GNU_HASH: i64 = 0x6fff_fef5;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct Sym64 {
+    pub st_name: u32,
+    pub st_info: u8,
+    pub st_other: u8,
+    pub st_shndx: u16,
+    pub st_value: u64,
+    pub st_size: u64,
+}
+
+#[cfg(target_pointer_width = "64")]
+pub type Sym = Sym64;
+
+/// The section index of undefined symbols.
+pub const SHN_UNDEF: u16 = 0;
+
+pub const STB_LOCAL: u8 = 0;
+pub const STB_GLOBAL: u8 = 1;
+pub const STB_WEAK: u8 = 2;
+
+pub const STT_FUNC: u8 = 2;
+
+impl Sym {
+    pub fn binding(&self) -> u8 {
+        self.st_info >> 4
+    }
+
+    pub fn sym_type(&self) -> u8 {
+        self.st_info & 0xf
+    }
+
+    pub fn is_defined(&self) -> bool {
+        self.st_shndx != SHN_UNDEF
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct Shdr64 {
+    pub sh_name: u32,
+    pub sh_type: u32,
+    pub sh_flags: u64,
+    pub sh_addr: u64,
+    pub sh_offset: u64,
+    pub sh_size: u64,
+    pub sh_link: u32,
+    pub sh_info: u32,
+    pub sh_addralign: u64,
+    pub sh_entsize: u64,
+}
+
+#[cfg(target_pointer_width = "64")]
+pub type Shdr = Shdr64;
+
+pub const SHT_NULL: u32 = 0;
+/// The static symbol table (`.symtab`).
+pub const SHT_SYMTAB: u32 = 2;
+pub const SHT_STRTAB: u32 = 3;
+pub const SHT_DYNAMIC: u32 = 6;
+pub const SHT_NOTE: u32 = 7;
+/// A section which occupies no space in the file, such as `.bss`.
+pub const SHT_NOBITS: u32 = 8;
+/// The dynamic symbol table (`.dynsym`).
+pub const SHT_DYNSYM: u32 = 11;
+
+impl Shdr {
+    /// The range of the section's contents in the file. `None` if it
+    /// overflows. Empty for `SHT_NOBITS` sections.
+    pub fn file_range(&self) -> Option<Range<usize>> {
+        let start = self.sh_offset as usize;
+        if self.sh_type == SHT_NOBITS {
+            return Some(start..start);
+        }
+
+        Some(start..start.checked_add(self.sh_size as usize)?)
+    }
+}
+
+/// The note type of `.note.gnu.build-id`.
+pub const NT_GNU_BUILD_ID: u32 = 3;
 
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
@@ -93,10 +189,18 @@ impl Rela {
     }
 }
 
+#[cfg(target_arch = "x86_64")]
+pub const R_X86_64_NONE: u32 = 0;
+#[cfg(target_arch = "x86_64")]
+pub const R_X86_64_64: u32 = 1;
+#[cfg(target_arch = "x86_64")]
+pub const R_X86_64_GLOB_DAT: u32 = 6;
+#[cfg(target_arch = "x86_64")]
+pub const R_X86_64_JUMP_SLOT: u32 = 7;
 #[cfg(target_arch = "x86_64")]
 pub const R_X86_64_RELATIVE: u32 = 8;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct Phdr64 {
     pub p_type: u32,
@@ -124,6 +228,24 @@ impl Phdr {
     pub fn executable(&self) -> bool {
         self.p_flags & 0x1 != 0
     }
+
+    /// The range of the segment's contents in the file. `None` if it
+    /// overflows.
+    pub fn file_range(&self) -> Option<Range<usize>> {
+        let start = self.p_offset as usize;
+        Some(start..start.checked_add(self.p_filesz as usize)?)
+    }
+
+    /// The range of the segment in memory. `None` if it overflows, or if the
+    /// file contents don't fit in it.
+    pub fn vaddr_range(&self) -> Option<Range<usize>> {
+        if self.p_filesz > self.p_memsz {
+            return None;
+        }
+
+        let start = self.p_vaddr as usize;
+        Some(start..start.checked_add(self.p_memsz as usize)?)
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -134,20 +256,134 @@ pub enum ParseError {
     InvalidMachine,
     InvalidEhdrSize,
     InvalidPhdrSize,
+    InvalidShdrSize,
+    /// The buffer or a header table is not aligned to 8 bytes.
+    Unaligned,
+    /// A segment out of the file, larger in the file than in memory, or
+    /// wrapping around the address space.
+    BadSegment,
+    /// `p_align` of a `PT_LOAD` segment is not a power of two, or
+    /// `p_offset` and `p_vaddr` are not congruent modulo it.
+    BadAlignment,
+    /// `PT_LOAD` segments overlap, or are not sorted by `p_vaddr`.
+    OverlappingSegments,
+    /// A section out of the file, or a bad section name table index.
+    BadSection,
 }
 
+/// Reads a `T` from the beginning of `bytes`, which may not be aligned.
+///
+/// `T` must be one of the plain old data types in this crate, and `bytes`
+/// must be long enough.
+fn read<T: Copy>(bytes: &[u8]) -> T {
+    assert!(bytes.len() >= size_of::<T>());
+    // SAFETY: The length is checked above, and any bit pattern is valid for
+    // the types in this crate.
+    unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const T) }
+}
+
+/// Returns the null-terminated string at `offset`, without the null byte.
+fn cstr(bytes: &[u8], offset: usize) -> Option<&[u8]> {
+    let rest = bytes.get(offset..)?;
+    let len = rest.iter().position(|&b| b == 0)?;
+    Some(&rest[..len])
+}
+
+/// Returns the `count` headers at `offset` in `buf`, in place.
+fn table<T>(buf: &[u8], offset: usize, count: usize) -> Result<&[T], ParseError> {
+    if !offset.is_multiple_of(align_of::<T>()) {
+        return Err(ParseError::Unaligned);
+    }
+
+    let size = count * size_of::<T>();
+    if offset.checked_add(size).is_none_or(|end| end > buf.len()) {
+        return Err(ParseError::BufferTooShort);
+    }
+
+    // SAFETY: The range is in the buffer, and it's aligned because `buf` is.
+    Ok(unsafe { core::slice::from_raw_parts(buf.as_ptr().add(offset) as *const T, count) })
+}
+
+fn validate_segments(buf: &[u8], phdrs: &[Phdr]) -> Result<(), ParseError> {
+    let mut prev_end = None;
+    for phdr in phdrs {
+        if phdr.file_range().is_none_or(|range| range.end > buf.len()) {
+            return Err(ParseError::BadSegment);
+        }
+
+        let vaddr_range = phdr.vaddr_range().ok_or(ParseError::BadSegment)?;
+        if phdr.p_type != PhdrType::Load as u32 {
+            continue;
+        }
+
+        let align = phdr.p_align;
+        if align > 1 && (!align.is_power_of_two() || phdr.p_vaddr % align != phdr.p_offset % align)
+        {
+            return Err(ParseError::BadAlignment);
+        }
+
+        if prev_end.is_some_and(|end| vaddr_range.start < end) {
+            return Err(ParseError::OverlappingSegments);
+        }
+
+        prev_end = Some(vaddr_range.end);
+    }
+
+    Ok(())
+}
+
+fn validate_sections(buf: &[u8], shdrs: &[Shdr], shstrndx: u16) -> Result<(), ParseError> {
+    for shdr in shdrs {
+        if shdr.sh_type == SHT_NULL {
+            continue;
+        }
+
+        if shdr.file_range().is_none_or(|range| range.end > buf.len()) {
+            return Err(ParseError::BadSection);
+        }
+    }
+
+    if !shdrs.is_empty() && shstrndx as usize >= shdrs.len() {
+        return Err(ParseError::BadSection);
+    }
+
+    Ok(())
+}
+
+/// A parsed ELF file.
+///
+/// [`Elf::parse`] validates the headers, so that the accessors below never
+/// read out of the file.
 #[derive(Debug)]
 pub struct Elf<'a> {
     pub ehdr: &'a Ehdr,
     pub phdrs: &'a [Phdr],
+    /// The section headers. Empty if the file has none.
+    pub shdrs: &'a [Shdr],
+    file: &'a [u8],
+}
+
+/// A segment in an ELF file.
+pub struct Segment<'a> {
+    pub phdr: &'a Phdr,
+    /// The contents in the file (`p_filesz` bytes). The rest of the segment
+    /// in memory is zero-filled.
+    pub data: &'a [u8],
+    pub vaddr_range: Range<usize>,
 }
 
 impl<'a> Elf<'a> {
-    pub fn parse(buf: &[u8], expected_type: u16) -> Result<Elf<'a>, ParseError> {
+    /// Parses and validates the headers in place. `buf` must be aligned to
+    /// 8 bytes.
+    pub fn parse(buf: &'a [u8], expected_type: u16) -> Result<Elf<'a>, ParseError> {
         if buf.len() < size_of::<Ehdr>() {
             return Err(ParseError::BufferTooShort);
         }
 
+        if !(buf.as_ptr() as usize).is_multiple_of(align_of::<Ehdr>()) {
+            return Err(ParseError::Unaligned);
+        }
+
         let ehdr = unsafe { &*(buf.as_ptr() as *const Ehdr) };
         if ehdr.e_ident[0..4] != [0x7f, b'E', b'L', b'F'] {
             return Err(ParseError::InvalidMagic);
@@ -169,18 +405,485 @@ impl<'a> Elf<'a> {
             return Err(ParseError::InvalidPhdrSize);
         }
 
-        let phdrs_size = ehdr.e_phnum as usize * size_of::<Phdr>();
-        if buf.len() < ehdr.e_phoff as usize + phdrs_size {
-            return Err(ParseError::BufferTooShort);
+        let phdrs = table::<Phdr>(buf, ehdr.e_phoff as usize, ehdr.e_phnum as usize)?;
+        validate_segments(buf, phdrs)?;
+
+        let shdrs = if ehdr.e_shnum == 0 {
+            &[]
+        } else {
+            if ehdr.e_shentsize as usize != size_of::<Shdr>() {
+                return Err(ParseError::InvalidShdrSize);
+            }
+
+            table::<Shdr>(buf, ehdr.e_shoff as usize, ehdr.e_shnum as usize)?
+        };
+        validate_sections(buf, shdrs, ehdr.e_shstrndx)?;
+
+        Ok(Elf {
+            ehdr,
+            phdrs,
+            shdrs,
+            file: buf,
+        })
+    }
+
+    /// Returns the segments, in the order of the program headers.
+    pub fn segments(&self) -> impl Iterator<Item = Segment<'a>> + use<'a> {
+        let file = self.file;
+        self.phdrs.iter().map(move |phdr| {
+            Segment {
+                phdr,
+                // The ranges are validated in parse.
+                data: &file[phdr.file_range().unwrap()],
+                vaddr_range: phdr.vaddr_range().unwrap(),
+            }
+        })
+    }
+
+    /// Returns the `PT_LOAD` segments, sorted by the address.
+    pub fn load_segments(&self) -> impl Iterator<Item = Segment<'a>> + use<'a> {
+        self.segments()
+            .filter(|segment| segment.phdr.p_type == PhdrType::Load as u32)
+    }
+
+    fn segment_data(&self, p_type: PhdrType) -> Option<&'a [u8]> {
+        self.segments()
+            .find(|segment| segment.phdr.p_type == p_type as u32)
+            .map(|segment| segment.data)
+    }
+
+    /// Returns the path of the program interpreter (`PT_INTERP`), without
+    /// the trailing null byte.
+    pub fn interp(&self) -> Option<&'a [u8]> {
+        cstr(self.segment_data(PhdrType::Interp)?, 0)
+    }
+
+    /// Returns the entries in the dynamic segment (`PT_DYNAMIC`) up to
+    /// `DT_NULL`. Empty if the file is statically linked.
+    pub fn dynamic(&self) -> impl Iterator<Item = Dyn> + use<'a> {
+        self.segment_data(PhdrType::Dynamic)
+            .unwrap_or_default()
+            .chunks_exact(size_of::<Dyn>())
+            .map(read::<Dyn>)
+            .take_while(|entry| entry.d_tag != DT_NULL)
+    }
+
+    /// Returns the notes in the `PT_NOTE` segments.
+    pub fn notes(&self) -> impl Iterator<Item = Note<'a>> + use<'a> {
+        self.segments()
+            .filter(|segment| segment.phdr.p_type == PhdrType::Note as u32)
+            .flat_map(|segment| {
+                // GNU property notes are 8-byte aligned, and others are 4-byte
+                // aligned.
+                let align = if segment.phdr.p_align == 8 { 8 } else { 4 };
+                Notes {
+                    data: segment.data,
+                    align,
+                }
+            })
+    }
+
+    /// Returns the contents of a section in the file. Empty for
+    /// `SHT_NOBITS` sections.
+    pub fn section_data(&self, shdr: &Shdr) -> &'a [u8] {
+        // The ranges of our section headers are validated in parse.
+        shdr.file_range()
+            .and_then(|range| self.file.get(range))
+            .unwrap_or_default()
+    }
+
+    /// Returns the name of a section, without the trailing null byte.
+    pub fn section_name(&self, shdr: &Shdr) -> Option<&'a [u8]> {
+        let shstrtab = self.shdrs.get(self.ehdr.e_shstrndx as usize)?;
+        cstr(self.section_data(shstrtab), shdr.sh_name as usize)
+    }
+
+    /// Looks for a section by name, such as `.text`.
+    pub fn section_by_name(&self, name: &[u8]) -> Option<&'a Shdr> {
+        self.shdrs
+            .iter()
+            .find(|shdr| self.section_name(shdr) == Some(name))
+    }
+
+    /// Returns the first symbol table of `sh_type` (`SHT_SYMTAB` or
+    /// `SHT_DYNSYM`).
+    pub fn symbol_table(&self, sh_type: u32) -> Option<SymbolTable<'a>> {
+        let symtab = self.shdrs.iter().find(|shdr| shdr.sh_type == sh_type)?;
+        if symtab.sh_entsize as usize != size_of::<Sym>() {
+            return None;
+        }
+
+        let strtab = self.shdrs.get(symtab.sh_link as usize)?;
+        Some(SymbolTable::new(
+            self.section_data(symtab),
+            self.section_data(strtab),
+        ))
+    }
+}
+
+/// A symbol table (`.symtab` or `.dynsym`) and its string table.
+pub struct SymbolTable<'a> {
+    symbols: &'a [u8],
+    strtab: &'a [u8],
+}
+
+impl<'a> SymbolTable<'a> {
+    /// Creates a symbol table from the contents of the sections. A trailing
+    /// partial entry is ignored.
+    pub fn new(symbols: &'a [u8], strtab: &'a [u8]) -> Self {
+        Self { symbols, strtab }
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len() / size_of::<Sym>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<Sym> {
+        let offset = index.checked_mul(size_of::<Sym>())?;
+        let end = offset.checked_add(size_of::<Sym>())?;
+        Some(read(self.symbols.get(offset..end)?))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Sym> + use<'a> {
+        self.symbols.chunks_exact(size_of::<Sym>()).map(read::<Sym>)
+    }
+
+    /// Returns the name of a symbol, without the trailing null byte.
+    pub fn name(&self, sym: &Sym) -> Option<&'a [u8]> {
+        cstr(self.strtab, sym.st_name as usize)
+    }
+}
+
+/// An entry in a note segment or section.
+pub struct Note<'a> {
+    pub n_type: u32,
+    /// The owner, such as `GNU`, without the trailing null byte.
+    pub name: &'a [u8],
+    pub desc: &'a [u8],
+}
+
+/// Iterates over notes. The iteration stops at a malformed note.
+struct Notes<'a> {
+    data: &'a [u8],
+    align: usize,
+}
+
+impl<'a> Notes<'a> {
+    fn parse(&self) -> Option<(Note<'a>, usize)> {
+        let header = self.data.get(..12)?;
+        let namesz = u32::from_ne_bytes(header[0..4].try_into().unwrap()) as usize;
+        let descsz = u32::from_ne_bytes(header[4..8].try_into().unwrap()) as usize;
+        let n_type = u32::from_ne_bytes(header[8..12].try_into().unwrap());
+
+        let name_end = 12usize.checked_add(namesz)?;
+        let mut name = self.data.get(12..name_end)?;
+        if let [rest @ .., 0] = name {
+            name = rest;
+        }
+
+        let desc_start = name_end.checked_next_multiple_of(self.align)?;
+        let desc_end = desc_start.checked_add(descsz)?;
+        let desc = self.data.get(desc_start..desc_end)?;
+        let next = desc_end.checked_next_multiple_of(self.align)?;
+        Some((Note { n_type, name, desc }, next))
+    }
+}
+
+impl<'a> Iterator for Notes<'a> {
+    type Item = Note<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Some((note, next)) = self.parse() else {
+            self.data = &[];
+            return None;
+        };
+
+        self.data = self.data.get(next..).unwrap_or_default();
+        Some(note)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+
+    const INTERP: &[u8] = b"/lib/ld.so\0";
+    const SHSTRTAB: &[u8] = b"\0.symtab\0.strtab\0.shstrtab\0";
+
+    fn push<T>(bytes: &mut Vec<u8>, value: &T) {
+        // SAFETY: `T` is a plain old data type.
+        let value =
+            unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+        bytes.extend_from_slice(value);
+    }
+
+    /// Appends `data` aligned to 8 bytes, and returns its offset.
+    fn push_data(bytes: &mut Vec<u8>, data: &[u8]) -> u64 {
+        bytes.resize(bytes.len().next_multiple_of(8), 0);
+        let offset = bytes.len();
+        bytes.extend_from_slice(data);
+        offset as u64
+    }
+
+    fn phdr(p_type: PhdrType, p_offset: u64, p_filesz: u64) -> Phdr {
+        Phdr {
+            p_type: p_type as u32,
+            p_flags: PF_R,
+            p_offset,
+            p_vaddr: p_offset,
+            p_paddr: 0,
+            p_filesz,
+            p_memsz: p_filesz,
+            p_align: 8,
         }
+    }
 
-        let phdrs = unsafe {
-            core::slice::from_raw_parts(
-                buf.as_ptr().add(ehdr.e_phoff as usize) as *const Phdr,
-                ehdr.e_phnum as usize,
-            )
+    fn shdr(sh_name: u32, sh_type: u32, sh_offset: u64, sh_size: usize) -> Shdr {
+        Shdr {
+            sh_name,
+            sh_type,
+            sh_flags: 0,
+            sh_addr: 0,
+            sh_offset,
+            sh_size: sh_size as u64,
+            sh_link: 0,
+            sh_info: 0,
+            sh_addralign: 8,
+            sh_entsize: 0,
+        }
+    }
+
+    /// Builds a shared object with a `PT_LOAD` segment covering the whole
+    /// file, `PT_INTERP`, `PT_NOTE`, `PT_DYNAMIC`, and a symbol table.
+    /// `edit` modifies the headers before they are written.
+    fn build(edit: impl FnOnce(&mut Ehdr, &mut Vec<Phdr>)) -> Vec<u64> {
+        const NUM_PHDRS: usize = 4;
+        let mut bytes = std::vec![0; size_of::<Ehdr>() + NUM_PHDRS * size_of::<Phdr>()];
+
+        let interp = push_data(&mut bytes, INTERP);
+
+        let mut note = Vec::new();
+        note.extend_from_slice(&4u32.to_ne_bytes()); // namesz
+        note.extend_from_slice(&2u32.to_ne_bytes()); // descsz
+        note.extend_from_slice(&NT_GNU_BUILD_ID.to_ne_bytes());
+        note.extend_from_slice(b"GNU\0\xab\xcd\0\0");
+        let note_offset = push_data(&mut bytes, &note);
+
+        let dynamic_offset = push_data(&mut bytes, &[]);
+        for (d_tag, d_val) in [(DT_STRSZ, 6), (DT_NULL, 0), (DT_STRSZ, 7)] {
+            push(&mut bytes, &Dyn { d_tag, d_val });
+        }
+
+        let strtab = push_data(&mut bytes, b"\0main\0");
+        let symtab = push_data(&mut bytes, &[]);
+        push(&mut bytes, &[0u8; size_of::<Sym>()]);
+        push(
+            &mut bytes,
+            &Sym {
+                st_name: 1,
+                st_info: (STB_GLOBAL << 4) | STT_FUNC,
+                st_other: 0,
+                st_shndx: 1,
+                st_value: 0x1000,
+                st_size: 0x10,
+            },
+        );
+        let shstrtab = push_data(&mut bytes, SHSTRTAB);
+
+        let shoff = push_data(&mut bytes, &[]);
+        let mut symtab = shdr(1, SHT_SYMTAB, symtab, 2 * size_of::<Sym>());
+        symtab.sh_link = 2;
+        symtab.sh_entsize = size_of::<Sym>() as u64;
+        push(&mut bytes, &shdr(0, SHT_NULL, 0, 0));
+        push(&mut bytes, &symtab);
+        push(&mut bytes, &shdr(9, SHT_STRTAB, strtab, 6));
+        push(&mut bytes, &shdr(17, SHT_STRTAB, shstrtab, SHSTRTAB.len()));
+
+        let file_len = bytes.len() as u64;
+        let mut load = phdr(PhdrType::Load, 0, file_len);
+        load.p_memsz = file_len + 0x100;
+        load.p_align = 0x1000;
+        let mut phdrs = std::vec![
+            load,
+            phdr(PhdrType::Interp, interp, INTERP.len() as u64),
+            phdr(PhdrType::Note, note_offset, note.len() as u64),
+            phdr(
+                PhdrType::Dynamic,
+                dynamic_offset,
+                3 * size_of::<Dyn>() as u64
+            ),
+        ];
+
+        let mut e_ident = [0; 16];
+        e_ident[..4].copy_from_slice(b"\x7fELF");
+        let mut ehdr = Ehdr {
+            e_ident,
+            e_type: ET_DYN,
+            e_machine: EM_NATIVE,
+            e_version: 1,
+            e_entry: 0,
+            e_phoff: size_of::<Ehdr>() as u64,
+            e_shoff: shoff,
+            e_flags: 0,
+            e_ehsize: size_of::<Ehdr>() as u16,
+            e_phentsize: size_of::<Phdr>() as u16,
+            e_phnum: NUM_PHDRS as u16,
+            e_shentsize: size_of::<Shdr>() as u16,
+            e_shnum: 4,
+            e_shstrndx: 3,
         };
+        edit(&mut ehdr, &mut phdrs);
+
+        let mut headers = Vec::new();
+        push(&mut headers, &ehdr);
+        for phdr in &phdrs {
+            push(&mut headers, phdr);
+        }
+        let len = headers.len().min(bytes.len());
+        bytes[..len].copy_from_slice(&headers[..len]);
+
+        let mut words = std::vec![0u64; bytes.len().div_ceil(8)];
+        as_bytes_mut(&mut words)[..bytes.len()].copy_from_slice(&bytes);
+        words
+    }
+
+    fn as_bytes_mut(words: &mut [u64]) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, words.len() * 8) }
+    }
+
+    fn as_bytes(words: &[u64]) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(words.as_ptr() as *const u8, words.len() * 8) }
+    }
+
+    fn parse_error(edit: impl FnOnce(&mut Ehdr, &mut Vec<Phdr>)) -> Option<ParseError> {
+        Elf::parse(as_bytes(&build(edit)), ET_DYN).err()
+    }
+
+    #[test]
+    fn parse() {
+        let file = build(|_, _| {});
+        let elf = Elf::parse(as_bytes(&file), ET_DYN).unwrap();
+
+        let segments: Vec<_> = elf.load_segments().collect();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].data.len(), as_bytes(&file).len());
+        assert_eq!(segments[0].vaddr_range.end - segments[0].data.len(), 0x100);
+
+        assert_eq!(elf.interp(), Some(&b"/lib/ld.so"[..]));
+
+        let dynamic: Vec<_> = elf.dynamic().collect();
+        assert_eq!(dynamic.len(), 1);
+        assert_eq!((dynamic[0].d_tag, dynamic[0].d_val), (DT_STRSZ, 6));
+
+        let notes: Vec<_> = elf.notes().collect();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].n_type, NT_GNU_BUILD_ID);
+        assert_eq!(notes[0].name, b"GNU");
+        assert_eq!(notes[0].desc, b"\xab\xcd");
+
+        let symtab = elf.section_by_name(b".symtab").unwrap();
+        assert_eq!(symtab.sh_type, SHT_SYMTAB);
+        let symbols = elf.symbol_table(SHT_SYMTAB).unwrap();
+        assert_eq!(symbols.len(), 2);
+        let main = symbols.get(1).unwrap();
+        assert_eq!(symbols.name(&main), Some(&b"main"[..]));
+        assert_eq!(main.sym_type(), STT_FUNC);
+        assert!(symbols.get(2).is_none());
+        assert!(elf.symbol_table(SHT_DYNSYM).is_none());
+    }
+
+    #[test]
+    fn reject_out_of_bounds_phdrs() {
+        assert_eq!(
+            parse_error(|ehdr, _| ehdr.e_phoff = u64::MAX - 7),
+            Some(ParseError::BufferTooShort)
+        );
+    }
+
+    #[test]
+    fn reject_unaligned() {
+        assert_eq!(
+            parse_error(|ehdr, _| ehdr.e_phoff += 4),
+            Some(ParseError::Unaligned)
+        );
+    }
+
+    #[test]
+    fn reject_bad_segments() {
+        // Out of the file.
+        assert_eq!(
+            parse_error(|_, phdrs| phdrs[1].p_offset = u64::MAX),
+            Some(ParseError::BadSegment)
+        );
+        assert_eq!(
+            parse_error(|_, phdrs| phdrs[0].p_filesz += 1),
+            Some(ParseError::BadSegment)
+        );
+        // Larger in the file than in memory.
+        assert_eq!(
+            parse_error(|_, phdrs| phdrs[3].p_memsz = 0),
+            Some(ParseError::BadSegment)
+        );
+        // Wrapping around the address space.
+        assert_eq!(
+            parse_error(|_, phdrs| {
+                phdrs[0].p_vaddr = 0x1000;
+                phdrs[0].p_memsz = u64::MAX;
+            }),
+            Some(ParseError::BadSegment)
+        );
+        assert_eq!(
+            parse_error(|_, phdrs| phdrs[0].p_vaddr = 0x800),
+            Some(ParseError::BadAlignment)
+        );
+        assert_eq!(
+            parse_error(|_, phdrs| phdrs[0].p_align = 0x1800),
+            Some(ParseError::BadAlignment)
+        );
+    }
+
+    #[test]
+    fn reject_overlapping_segments() {
+        assert_eq!(
+            parse_error(|ehdr, phdrs| {
+                let mut second = phdrs[0];
+                second.p_filesz = 0;
+                second.p_vaddr = 0x1000;
+                phdrs[1] = second;
+                ehdr.e_phnum = 2;
+            }),
+            None
+        );
+        assert_eq!(
+            parse_error(|ehdr, phdrs| {
+                phdrs[1] = phdrs[0];
+                ehdr.e_phnum = 2;
+            }),
+            Some(ParseError::OverlappingSegments)
+        );
+    }
 
-        Ok(Elf { ehdr, phdrs })
+    #[test]
+    fn reject_bad_sections() {
+        assert_eq!(
+            parse_error(|ehdr, _| ehdr.e_shstrndx = 4),
+            Some(ParseError::BadSection)
+        );
+        assert_eq!(
+            parse_error(|ehdr, _| ehdr.e_shentsize = 0),
+            Some(ParseError::InvalidShdrSize)
+        );
+        assert_eq!(
+            parse_error(|ehdr, _| ehdr.e_shnum = u16::MAX),
+            Some(ParseError::BufferTooShort)
+        );
     }
 }
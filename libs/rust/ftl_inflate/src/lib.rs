@@ -0,0 +1,654 @@
+//! A DEFLATE (RFC 1951) and gzip (RFC 1952) decompressor for `no_std`.
+//!
+//! The decompressor is streaming: [`Inflater`] and [`GzipReader`] write the
+//! output into the caller's buffer chunk by chunk, keeping only the last
+//! 32 KiB of the output as the sliding window.
+#![no_std]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const GZIP_CM_DEFLATE: u8 = 8;
+const GZIP_FHCRC: u8 = 1 << 1;
+const GZIP_FEXTRA: u8 = 1 << 2;
+const GZIP_FNAME: u8 = 1 << 3;
+const GZIP_FCOMMENT: u8 = 1 << 4;
+const GZIP_FRESERVED: u8 = 0xe0;
+
+/// The maximum distance of back-references.
+const WINDOW_SIZE: usize = 32 * 1024;
+
+const MAX_CODE_LEN: usize = 15;
+const NUM_LITLEN_CODES: usize = 288;
+const NUM_DIST_CODES: usize = 30;
+
+/// The base lengths for length codes 257..285.
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; NUM_DIST_CODES] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; NUM_DIST_CODES] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// The order of code length code lengths in dynamic blocks.
+const CODE_LEN_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    UnexpectedEof,
+    InvalidMagic,
+    UnsupportedMethod,
+    InvalidHeader,
+    InvalidBlockType,
+    InvalidStoredLen,
+    InvalidCode,
+    InvalidDistance,
+    ChecksumMismatch,
+    LengthMismatch,
+    OutOfMemory,
+}
+
+/// Reads bits from the least significant bit of each byte.
+struct BitReader<'a> {
+    input: &'a [u8],
+    pos: usize,
+    bitbuf: u32,
+    bitcnt: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(input: &'a [u8]) -> Self {
+        Self {
+            input,
+            pos: 0,
+            bitbuf: 0,
+            bitcnt: 0,
+        }
+    }
+
+    fn bits(&mut self, n: u32) -> Result<u32, Error> {
+        debug_assert!(n <= 16);
+        while self.bitcnt < n {
+            let byte = *self.input.get(self.pos).ok_or(Error::UnexpectedEof)?;
+            self.bitbuf |= (byte as u32) << self.bitcnt;
+            self.pos += 1;
+            self.bitcnt += 8;
+        }
+
+        let value = self.bitbuf & ((1 << n) - 1);
+        self.bitbuf >>= n;
+        self.bitcnt -= n;
+        Ok(value)
+    }
+
+    /// Discards the remaining bits in the current byte. Since bytes are read
+    /// only on demand, fewer than 8 bits are buffered.
+    fn align_to_byte(&mut self) {
+        self.bitbuf = 0;
+        self.bitcnt = 0;
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        debug_assert_eq!(self.bitcnt, 0);
+        let bytes = self
+            .input
+            .get(self.pos..self.pos + len)
+            .ok_or(Error::UnexpectedEof)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+}
+
+/// A canonical Huffman code.
+struct Huffman {
+    /// The number of codes of each length.
+    counts: [u16; MAX_CODE_LEN + 1],
+    /// Symbols ordered by their codes.
+    symbols: [u16; NUM_LITLEN_CODES],
+}
+
+impl Huffman {
+    /// A code with no symbols.
+    const fn empty() -> Self {
+        Self {
+            counts: [0; MAX_CODE_LEN + 1],
+            symbols: [0; NUM_LITLEN_CODES],
+        }
+    }
+
+    fn new(lengths: &[u8]) -> Result<Self, Error> {
+        let mut counts = [0u16; MAX_CODE_LEN + 1];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+
+        // Reject over-subscribed codes. Incomplete ones are allowed, e.g. a
+        // distance code with only one symbol.
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(Error::InvalidCode);
+            }
+        }
+
+        let mut offsets = [0u16; MAX_CODE_LEN + 2];
+        for len in 1..=MAX_CODE_LEN {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+
+        let mut symbols = [0u16; NUM_LITLEN_CODES];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+
+        Ok(Self { counts, symbols })
+    }
+
+    fn decode(&self, reader: &mut BitReader<'_>) -> Result<u16, Error> {
+        // The first code and the index of its symbol, of the current length.
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for &count in &self.counts[1..] {
+            code |= reader.bits(1)? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(Error::InvalidCode)
+    }
+}
+
+fn fixed_codes() -> Result<(Huffman, Huffman), Error> {
+    let mut lengths = [0u8; NUM_LITLEN_CODES];
+    lengths[0..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..288].fill(8);
+    let litlen = Huffman::new(&lengths)?;
+    let dist = Huffman::new(&[5; NUM_DIST_CODES])?;
+    Ok((litlen, dist))
+}
+
+fn dynamic_codes(reader: &mut BitReader<'_>) -> Result<(Huffman, Huffman), Error> {
+    let num_litlen = reader.bits(5)? as usize + 257;
+    let num_dist = reader.bits(5)? as usize + 1;
+    let num_code_len = reader.bits(4)? as usize + 4;
+    if num_litlen > 286 || num_dist > NUM_DIST_CODES {
+        return Err(Error::InvalidCode);
+    }
+
+    let mut code_len_lengths = [0u8; 19];
+    for &i in &CODE_LEN_ORDER[..num_code_len] {
+        code_len_lengths[i] = reader.bits(3)? as u8;
+    }
+    let code_len = Huffman::new(&code_len_lengths)?;
+
+    // Literal/length and distance code lengths, in a single sequence.
+    let mut lengths = [0u8; 286 + NUM_DIST_CODES];
+    let total = num_litlen + num_dist;
+    let mut i = 0;
+    while i < total {
+        let symbol = code_len.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let prev = *lengths[..i].last().ok_or(Error::InvalidCode)?;
+                (prev, 3 + reader.bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.bits(3)? as usize),
+            18 => (0, 11 + reader.bits(7)? as usize),
+            _ => return Err(Error::InvalidCode),
+        };
+
+        if i + repeat > total {
+            return Err(Error::InvalidCode);
+        }
+
+        lengths[i..i + repeat].fill(value);
+        i += repeat;
+    }
+
+    // The end-of-block code must exist.
+    if lengths[256] == 0 {
+        return Err(Error::InvalidCode);
+    }
+
+    let litlen = Huffman::new(&lengths[..num_litlen])?;
+    let dist = Huffman::new(&lengths[num_litlen..total])?;
+    Ok((litlen, dist))
+}
+
+enum State {
+    /// Reading the next block header.
+    BlockHeader,
+    Stored {
+        remaining: usize,
+    },
+    /// Decoding a block with [`Inflater::litlen`] and [`Inflater::dist`].
+    Codes,
+    Done,
+}
+
+/// A streaming decompressor of a raw DEFLATE stream.
+pub struct Inflater<'a> {
+    reader: BitReader<'a>,
+    state: State,
+    /// The codes of the current block, if it's compressed.
+    litlen: Huffman,
+    dist: Huffman,
+    /// Whether the current block is the last one.
+    is_final: bool,
+    /// The last [`WINDOW_SIZE`] bytes of the output, indexed by the output
+    /// position modulo its size.
+    window: Vec<u8>,
+    /// The number of bytes written so far.
+    total_out: usize,
+    /// The remaining length and the distance of the back-reference being
+    /// copied.
+    copy_len: usize,
+    copy_distance: usize,
+}
+
+impl<'a> Inflater<'a> {
+    pub fn new(input: &'a [u8]) -> Result<Self, Error> {
+        let mut window = Vec::new();
+        window
+            .try_reserve_exact(WINDOW_SIZE)
+            .map_err(|_| Error::OutOfMemory)?;
+        window.resize(WINDOW_SIZE, 0);
+
+        Ok(Self {
+            reader: BitReader::new(input),
+            state: State::BlockHeader,
+            litlen: Huffman::empty(),
+            dist: Huffman::empty(),
+            is_final: false,
+            window,
+            total_out: 0,
+            copy_len: 0,
+            copy_distance: 0,
+        })
+    }
+
+    /// Returns true if the end of the stream has been reached.
+    pub fn is_done(&self) -> bool {
+        matches!(self.state, State::Done)
+    }
+
+    /// The number of bytes consumed from the input. It's the length of the
+    /// stream once [`Inflater::is_done`] returns true.
+    pub fn consumed(&self) -> usize {
+        self.reader.pos
+    }
+
+    fn emit(&mut self, buf: &mut [u8], written: &mut usize, byte: u8) {
+        buf[*written] = byte;
+        *written += 1;
+        self.window[self.total_out % WINDOW_SIZE] = byte;
+        self.total_out += 1;
+    }
+
+    /// Decompresses the next bytes into `buf`. Returns the number of bytes
+    /// written, which is less than `buf.len()` only at the end of the stream.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut written = 0;
+        while written < buf.len() {
+            if self.copy_len > 0 {
+                // The source may overlap with the bytes being written, e.g.
+                // a run of the same byte.
+                let byte = self.window[(self.total_out - self.copy_distance) % WINDOW_SIZE];
+                self.emit(buf, &mut written, byte);
+                self.copy_len -= 1;
+                continue;
+            }
+
+            match &mut self.state {
+                State::BlockHeader => {
+                    if self.is_final {
+                        self.reader.align_to_byte();
+                        self.state = State::Done;
+                        continue;
+                    }
+
+                    self.is_final = self.reader.bits(1)? == 1;
+                    let codes = match self.reader.bits(2)? {
+                        0 => {
+                            let remaining = stored_len(&mut self.reader)?;
+                            self.state = State::Stored { remaining };
+                            continue;
+                        }
+                        1 => fixed_codes()?,
+                        2 => dynamic_codes(&mut self.reader)?,
+                        _ => return Err(Error::InvalidBlockType),
+                    };
+
+                    (self.litlen, self.dist) = codes;
+                    self.state = State::Codes;
+                }
+                State::Stored { remaining } => {
+                    if *remaining == 0 {
+                        self.state = State::BlockHeader;
+                        continue;
+                    }
+
+                    let len = (*remaining).min(buf.len() - written);
+                    *remaining -= len;
+                    for &byte in self.reader.bytes(len)? {
+                        self.emit(buf, &mut written, byte);
+                    }
+                }
+                State::Codes => {
+                    let symbol = self.litlen.decode(&mut self.reader)? as usize;
+                    match symbol {
+                        0..=255 => self.emit(buf, &mut written, symbol as u8),
+                        256 => self.state = State::BlockHeader,
+                        _ => {
+                            let index = symbol - 257;
+                            let base = *LENGTH_BASE.get(index).ok_or(Error::InvalidCode)?;
+                            let len = base as usize
+                                + self.reader.bits(LENGTH_EXTRA[index] as u32)? as usize;
+
+                            let index = self.dist.decode(&mut self.reader)? as usize;
+                            let base = *DIST_BASE.get(index).ok_or(Error::InvalidDistance)?;
+                            let distance = base as usize
+                                + self.reader.bits(DIST_EXTRA[index] as u32)? as usize;
+                            if distance > self.total_out {
+                                return Err(Error::InvalidDistance);
+                            }
+
+                            self.copy_len = len;
+                            self.copy_distance = distance;
+                        }
+                    }
+                }
+                State::Done => break,
+            }
+        }
+
+        Ok(written)
+    }
+}
+
+/// Reads the header of a stored block, and returns its length.
+fn stored_len(reader: &mut BitReader<'_>) -> Result<usize, Error> {
+    reader.align_to_byte();
+    let header = reader.bytes(4)?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    let nlen = u16::from_le_bytes([header[2], header[3]]);
+    if len != !nlen {
+        return Err(Error::InvalidStoredLen);
+    }
+
+    Ok(len as usize)
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                0xedb88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Computes the CRC-32 used in gzip.
+pub fn crc32(data: &[u8]) -> u32 {
+    !crc32_update(!0, data)
+}
+
+/// Updates a CRC-32 (before the final inversion) with `data`.
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc = CRC32_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+/// Returns true if `input` starts with a gzip member.
+pub fn is_gzip(input: &[u8]) -> bool {
+    input.starts_with(&GZIP_MAGIC)
+}
+
+/// A streaming decompressor of a gzip member.
+pub struct GzipReader<'a> {
+    input: &'a [u8],
+    header_len: usize,
+    inflater: Inflater<'a>,
+    /// The CRC-32 of the output so far, before the final inversion.
+    crc: u32,
+    len: usize,
+    /// The length of the member in `input`, once the trailer is checked.
+    member_len: Option<usize>,
+}
+
+impl<'a> GzipReader<'a> {
+    /// Parses the header of the gzip member at the beginning of `input`.
+    pub fn new(input: &'a [u8]) -> Result<Self, Error> {
+        if !is_gzip(input) {
+            return Err(Error::InvalidMagic);
+        }
+
+        let mut reader = BitReader::new(input);
+        let header = reader.bytes(10)?;
+
+        if header[2] != GZIP_CM_DEFLATE {
+            return Err(Error::UnsupportedMethod);
+        }
+
+        let flags = header[3];
+        if flags & GZIP_FRESERVED != 0 {
+            return Err(Error::InvalidHeader);
+        }
+
+        if flags & GZIP_FEXTRA != 0 {
+            let xlen = reader.bytes(2)?;
+            reader.bytes(u16::from_le_bytes([xlen[0], xlen[1]]) as usize)?;
+        }
+
+        // Skip the zero-terminated file name and comment.
+        for flag in [GZIP_FNAME, GZIP_FCOMMENT] {
+            if flags & flag != 0 {
+                while reader.bytes(1)?[0] != 0 {}
+            }
+        }
+
+        if flags & GZIP_FHCRC != 0 {
+            reader.bytes(2)?;
+        }
+
+        let header_len = reader.pos;
+        Ok(Self {
+            input,
+            header_len,
+            inflater: Inflater::new(&input[header_len..])?,
+            crc: !0,
+            len: 0,
+            member_len: None,
+        })
+    }
+
+    /// Returns the length of the member in the input, to find the next
+    /// member or data following it. `None` until all data has been read.
+    pub fn member_len(&self) -> Option<usize> {
+        self.member_len
+    }
+
+    /// Decompresses the next bytes into `buf`. Returns the number of bytes
+    /// written, which is less than `buf.len()` only at the end of the member.
+    ///
+    /// The checksum and the length in the trailer are checked when the end
+    /// is reached.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let written = self.inflater.read(buf)?;
+        self.crc = crc32_update(self.crc, &buf[..written]);
+        self.len += written;
+
+        if self.inflater.is_done() && self.member_len.is_none() {
+            let trailer_start = self.header_len + self.inflater.consumed();
+            let trailer = self
+                .input
+                .get(trailer_start..trailer_start + 8)
+                .ok_or(Error::UnexpectedEof)?;
+            let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+            let isize = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+            if crc != !self.crc {
+                return Err(Error::ChecksumMismatch);
+            }
+
+            // ISIZE is the length modulo 2^32.
+            if isize != self.len as u32 {
+                return Err(Error::LengthMismatch);
+            }
+
+            self.member_len = Some(trailer_start + 8);
+        }
+
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `gzip -n` of "hello\n", with the file name "hello.txt".
+    const HELLO_GZ: &[u8] = &[
+        0x1f, 0x8b, 0x08, 0x08, 0x00, 0x00, 0x00, 0x00, 0x02, 0xff, 0x68, 0x65, 0x6c, 0x6c, 0x6f,
+        0x2e, 0x74, 0x78, 0x74, 0x00, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0xe7, 0x02, 0x00, 0x20, 0x30,
+        0x3a, 0x36, 0x06, 0x00, 0x00, 0x00,
+    ];
+
+    /// Reads all data in small chunks, to test resuming in the middle of
+    /// blocks and back-references.
+    fn read_to_end(
+        mut read: impl FnMut(&mut [u8]) -> Result<usize, Error>,
+    ) -> Result<Vec<u8>, Error> {
+        let mut output = Vec::new();
+        let mut buf = [0; 7];
+        loop {
+            let len = read(&mut buf)?;
+            output.extend_from_slice(&buf[..len]);
+            if len < buf.len() {
+                return Ok(output);
+            }
+        }
+    }
+
+    fn inflate(input: &[u8]) -> Result<(Vec<u8>, usize), Error> {
+        let mut inflater = Inflater::new(input)?;
+        let output = read_to_end(|buf| inflater.read(buf))?;
+        assert!(inflater.is_done());
+        Ok((output, inflater.consumed()))
+    }
+
+    fn gunzip(input: &[u8]) -> Result<(Vec<u8>, usize), Error> {
+        let mut reader = GzipReader::new(input)?;
+        let output = read_to_end(|buf| reader.read(buf))?;
+        Ok((output, reader.member_len().unwrap()))
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn test_stored() {
+        let input = [
+            0x01, 0x06, 0x00, 0xf9, 0xff, 0x73, 0x74, 0x6f, 0x72, 0x65, 0x64,
+        ];
+        assert_eq!(inflate(&input), Ok((b"stored".to_vec(), input.len())));
+    }
+
+    #[test]
+    fn test_fixed() {
+        // "abcabcabc"
+        let input = [0x4b, 0x4c, 0x4a, 0x4e, 0x04, 0x23, 0x00];
+        assert_eq!(inflate(&input), Ok((b"abcabcabc".to_vec(), input.len())));
+    }
+
+    #[test]
+    fn test_dynamic() {
+        // "99 bottles of beer on the wall, 99 bottles of beer.\n" down to 90.
+        let input = [
+            0x85, 0xcb, 0xcb, 0x09, 0x80, 0x30, 0x10, 0x05, 0xc0, 0xbb, 0x55, 0x6c, 0x01, 0x22,
+            0x89, 0xf9, 0x97, 0x63, 0x60, 0xc5, 0x43, 0x30, 0xa0, 0x01, 0xdb, 0xb7, 0x80, 0x3c,
+            0x78, 0xe7, 0x61, 0x4a, 0x91, 0xda, 0xc7, 0x68, 0xfa, 0x4a, 0x3f, 0xa5, 0xaa, 0x3e,
+            0xd2, 0x6f, 0x19, 0x97, 0xca, 0x77, 0xb4, 0xb6, 0x4a, 0x99, 0x7c, 0x5b, 0x4a, 0x26,
+            0x27, 0x83, 0x93, 0xc8, 0x49, 0xe0, 0x44, 0x72, 0x22, 0x38, 0x81, 0x9c, 0x00, 0x8e,
+            0x27, 0xc7, 0x83, 0xe3, 0xc8, 0x71, 0xe0, 0xec, 0xe4, 0xec, 0xe0, 0x58, 0x72, 0x2c,
+            0x38, 0x86, 0x1c, 0x33, 0x9f, 0x1f,
+        ];
+        let (output, len) = inflate(&input).unwrap();
+        assert_eq!(len, input.len());
+        assert_eq!(output.len(), 520);
+        assert!(output.starts_with(b"99 bottles of beer on the wall, 99 bottles of beer.\n"));
+        assert_eq!(crc32(&output), 0x3bcdeaf4);
+    }
+
+    #[test]
+    fn test_gunzip() {
+        let (output, len) = gunzip(HELLO_GZ).unwrap();
+        assert_eq!(output, b"hello\n");
+        assert_eq!(len, HELLO_GZ.len());
+    }
+
+    #[test]
+    fn test_gunzip_concatenated() {
+        let mut input = Vec::new();
+        input.extend_from_slice(HELLO_GZ);
+        input.extend_from_slice(HELLO_GZ);
+        let (_, len) = gunzip(&input).unwrap();
+        assert_eq!(len, HELLO_GZ.len());
+        let (output, _) = gunzip(&input[len..]).unwrap();
+        assert_eq!(output, b"hello\n");
+    }
+
+    #[test]
+    fn test_gunzip_corrupted() {
+        let mut input = Vec::new();
+        input.extend_from_slice(HELLO_GZ);
+        let crc_offset = input.len() - 8;
+        input[crc_offset] ^= 1;
+        assert_eq!(gunzip(&input), Err(Error::ChecksumMismatch));
+        assert_eq!(
+            gunzip(&HELLO_GZ[..HELLO_GZ.len() - 1]),
+            Err(Error::UnexpectedEof)
+        );
+        assert_eq!(gunzip(b"070701"), Err(Error::InvalidMagic));
+    }
+}
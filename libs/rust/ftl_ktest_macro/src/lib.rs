@@ -0,0 +1,75 @@
+//! The `#[ktest]` attribute for in-kernel tests.
+//!
+//! ```ignore
+//! #[ktest]
+//! fn alloc_pages() {
+//!     // ...
+//! }
+//! ```
+//!
+//! The function is compiled only with the kernel's `ktest` feature, and
+//! registered in the `.ktests` section, which the kernel walks in the test
+//! mode.
+use proc_macro::Delimiter;
+use proc_macro::TokenStream;
+use proc_macro::TokenTree;
+
+#[proc_macro_attribute]
+pub fn ktest(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return compile_error("#[ktest] takes no arguments");
+    }
+
+    let Some(name) = fn_name(item.clone()) else {
+        return compile_error("#[ktest] must be on a function without parameters");
+    };
+
+    let registration = format!(
+        r#"
+        #[cfg(feature = "ktest")]
+        const _: () = {{
+            #[used]
+            #[unsafe(link_section = ".ktests")]
+            static KTEST: crate::ktest::KTest = crate::ktest::KTest {{
+                name: concat!(module_path!(), "::", "{name}"),
+                func: {name},
+            }};
+        }};
+        "#
+    );
+
+    let mut output: TokenStream = "#[cfg(feature = \"ktest\")]".parse().unwrap();
+    output.extend(item);
+    output.extend(registration.parse::<TokenStream>().unwrap());
+    output
+}
+
+/// Returns the name of `fn name() { ... }`.
+fn fn_name(item: TokenStream) -> Option<String> {
+    let mut tokens = item.into_iter();
+    let name = loop {
+        match tokens.next()? {
+            TokenTree::Ident(ident) if ident.to_string() == "fn" => {
+                let TokenTree::Ident(name) = tokens.next()? else {
+                    return None;
+                };
+                break name.to_string();
+            }
+            _ => continue,
+        }
+    };
+
+    // Generic functions and parameters are not supported.
+    match tokens.next()? {
+        TokenTree::Group(group)
+            if group.delimiter() == Delimiter::Parenthesis && group.stream().is_empty() =>
+        {
+            Some(name)
+        }
+        _ => None,
+    }
+}
+
+fn compile_error(message: &str) -> TokenStream {
+    format!("compile_error!({message:?});").parse().unwrap()
+}
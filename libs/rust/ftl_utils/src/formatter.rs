@@ -18,6 +18,28 @@ impl ByteSize {
     #[allow(non_upper_case_globals)]
     pub const KiB: usize = 1024;
 
+    /// Parses a size with an optional `K`, `M`, or `G` suffix.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ftl_utils::formatter::ByteSize;
+    ///
+    /// assert_eq!(ByteSize::parse("4096"), Some(4096));
+    /// assert_eq!(ByteSize::parse("512M"), Some(512 * 1024 * 1024));
+    /// assert_eq!(ByteSize::parse("1.5G"), None);
+    /// ```
+    pub fn parse(s: &str) -> Option<usize> {
+        let (digits, unit) = match s.as_bytes().last()? {
+            b'K' => (&s[..s.len() - 1], 1024),
+            b'M' => (&s[..s.len() - 1], 1024 * 1024),
+            b'G' => (&s[..s.len() - 1], 1024 * 1024 * 1024),
+            _ => (s, 1),
+        };
+
+        digits.parse::<usize>().ok()?.checked_mul(unit)
+    }
+
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let units = &["B", "KiB", "MiB", "GiB", "TiB"];
         let mut value = self.0;
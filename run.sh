@@ -3,10 +3,36 @@ set -eu
 
 ./build.sh
 
+# Run the kernel's test mode headlessly, and exit with its result. See
+# kernel/src/ktest.rs. A hung kernel is killed after KTEST_TIMEOUT.
+if [[ -n "${KTEST:-}" ]]; then
+  set +e
+  timeout --foreground "${KTEST_TIMEOUT:-120s}" qemu-system-x86_64 \
+    -m 128 -cpu qemu64,+fsgsbase -kernel ftl.elf \
+    -initrd initfs.cpio -append "${CMDLINE:-}" \
+    -nographic -serial mon:stdio --no-reboot \
+    -device isa-debug-exit,iobase=0x501,iosize=0x04
+  status=$?
+  set -e
+
+  # isa-debug-exit exits with 33 on success.
+  if [[ $status -eq 33 ]]; then
+    exit 0
+  fi
+
+  if [[ $status -eq 124 ]]; then
+    echo "ktest: timed out after ${KTEST_TIMEOUT:-120s}"
+    exit 1
+  fi
+
+  echo "ktest: failed (exit code $status)"
+  exit 1
+fi
+
 set +e
 qemu-system-x86_64 \
   -m 128 -cpu qemu64,+fsgsbase -kernel ftl.elf \
-  -initrd initfs.cpio \
+  -initrd initfs.cpio -append "${CMDLINE:-}" \
   -nographic -serial mon:stdio --no-reboot -gdb tcp::7778 \
   -d cpu_reset,unimp,guest_errors,int -D qemu.log \
   -device isa-debug-exit,iobase=0x501,iosize=0x04 \
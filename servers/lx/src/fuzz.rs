@@ -0,0 +1,16 @@
+//! Entry points for the fuzz targets in `/fuzz`.
+use alloc::vec::Vec;
+use core::slice;
+
+use crate::process::Process;
+
+/// Creates a process from `elf_file` and drops it.
+pub fn process_create(elf_file: &[u8]) {
+    // Copy into an 8-byte aligned buffer, as InitFsFile does.
+    let mut words: Vec<u64> = alloc::vec![0; elf_file.len().div_ceil(size_of::<u64>())];
+    // SAFETY: `words` has at least `elf_file.len()` bytes.
+    let buf = unsafe { slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, elf_file.len()) };
+    buf.copy_from_slice(elf_file);
+
+    let _ = Process::create(buf);
+}
@@ -2,14 +2,20 @@
 
 mod elf;
 mod errno;
+#[cfg(feature = "fuzzing")]
+pub mod fuzz;
 mod process;
+mod signal;
 mod syscall;
 extern crate alloc;
 
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::slice;
 
 use ftl_api::Spec;
+use ftl_api::error::ErrorCode;
+use ftl_api::initfs;
 use process::Process;
 
 struct Server {
@@ -17,11 +23,17 @@ struct Server {
     processes: Vec<Arc<Process>>,
 }
 
+/// The first program to run if `init` is not given in the kernel command line.
+const DEFAULT_INIT: &str = "/bin/hello";
+
 impl Server {
     fn new() -> Self {
-        // TODO: Get initfs from environment
-        let elf_file = HELLO_ELF.0.as_slice();
-        let (process, init_regs) = Process::create(elf_file).expect("failed to create process");
+        let init = ftl_api::boot::option("init").unwrap_or(DEFAULT_INIT);
+        let elf_file = InitFsFile::read(init)
+            .unwrap_or_else(|err| panic!("failed to read {}: {:?}", init, err));
+
+        let (process, init_regs) =
+            Process::create(elf_file.as_bytes()).expect("failed to create process");
         process.start(init_regs).expect("failed to start process");
         Self {
             processes: alloc::vec![process],
@@ -29,15 +41,49 @@ impl Server {
     }
 }
 
-#[repr(align(8))]
-pub struct AlignedBytes<const N: usize>([u8; N]);
+/// A file read from initfs, aligned to 8 bytes to parse ELF headers in place.
+struct InitFsFile {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl InitFsFile {
+    fn read(path: &str) -> ftl_api::Result<Self> {
+        let stat = initfs::stat(path)?;
+        if !stat.is_file() {
+            return Err(ErrorCode::INVALID_ARG);
+        }
+
+        let mut file = Self {
+            words: alloc::vec![0; stat.size.div_ceil(size_of::<u64>())],
+            len: stat.size,
+        };
+
+        // SAFETY: `words` has at least `len` bytes.
+        let buf =
+            unsafe { slice::from_raw_parts_mut(file.words.as_mut_ptr() as *mut u8, file.len) };
+        let mut offset = 0;
+        while offset < file.len {
+            let read_len = initfs::read(path, offset, &mut buf[offset..])?;
+            if read_len == 0 {
+                return Err(ErrorCode::INVALID_ARG);
+            }
 
-pub static HELLO_ELF: AlignedBytes<{ include_bytes!("../../../initfs/bin/hello").len() }> =
-    AlignedBytes(*include_bytes!("../../../initfs/bin/hello"));
+            offset += read_len;
+        }
+
+        Ok(file)
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        // SAFETY: `words` has at least `len` bytes.
+        unsafe { slice::from_raw_parts(self.words.as_ptr() as *const u8, self.len) }
+    }
+}
 
 #[unsafe(no_mangle)]
 pub static SPEC: Spec = Spec {
     name: b"lx",
-    // TODO: register the server
     start: || ftl_api::start(Server::new),
+    stop: ftl_api::stop,
 };
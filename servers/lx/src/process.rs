@@ -3,6 +3,7 @@ use alloc::sync::Weak;
 use alloc::vec::Vec;
 
 use ftl_api::error::ErrorCode;
+use ftl_api::info;
 use ftl_api::thread::ContextData;
 use ftl_api::thread::ContextKind;
 use ftl_api::thread::InitRegs;
@@ -14,20 +15,23 @@ use ftl_api::vmspace::PageAttrs;
 use ftl_api::vmspace::VmSpace;
 use ftl_elf::ET_EXEC;
 use ftl_elf::Elf;
-use ftl_elf::PhdrType;
 use ftl_utils::alignment::align_down;
-use ftl_utils::alignment::align_up;
 use ftl_utils::spinlock::SpinLock;
 
+use crate::elf;
 use crate::elf::STACK_BASE;
 use crate::elf::STACK_SIZE;
 use crate::elf::build_initial_stack;
 use crate::errno::Errno;
+use crate::signal::Signal;
 use crate::syscall::SyscallOutput;
 
 // TODO: should we use MIN_PAGE_SIZE in FTL?
 pub const PAGE_SIZE: usize = 4096;
 
+/// The memory budget for each process.
+const MEMORY_QUOTA: usize = 256 * 1024 * 1024; // 256 MiB
+
 struct Mutable {
     threads: Vec<Weak<Thread>>,
 }
@@ -44,36 +48,32 @@ impl Process {
 
     pub fn create(elf_file: &[u8]) -> ftl_api::Result<(Arc<Self>, InitRegs)> {
         let elf = Elf::parse(elf_file, ET_EXEC).map_err(|_| ErrorCode::INVALID_ARG)?;
-        let vmspace = VmSpace::create()?;
+        let vmspace = VmSpace::create_with_quota(MEMORY_QUOTA)?;
 
         // Copy the ELF segments into vmareas and map them.
         let e_phoff = elf.ehdr.e_phoff;
         let mut phdr_uaddr = None;
-        for phdr in elf.phdrs {
-            if phdr.p_type != PhdrType::Load as u32 {
-                continue;
-            }
+        for segment in elf.load_segments() {
+            let phdr = segment.phdr;
 
             // If the segment contains the program header table, store its uaddr
-            // for AT_PHDR.
-            if phdr_uaddr.is_none()
-                && phdr.p_offset <= e_phoff
-                && e_phoff < phdr.p_offset + phdr.p_filesz
-            {
+            // for AT_PHDR. The range is validated by Elf::parse.
+            let file_range = phdr.p_offset..(phdr.p_offset + phdr.p_filesz);
+            if phdr_uaddr.is_none() && file_range.contains(&e_phoff) {
                 let offset_in_segment = e_phoff - phdr.p_offset;
                 phdr_uaddr = Some(phdr.p_vaddr + offset_in_segment);
             }
 
-            let vaddr = phdr.p_vaddr as usize;
+            let vaddr = segment.vaddr_range.start;
             let mapped_vaddr = align_down(vaddr, PAGE_SIZE);
             let vaddr_offset = vaddr - mapped_vaddr;
-            let len = align_up(vaddr_offset + phdr.p_memsz as usize, PAGE_SIZE);
-
-            let vmarea = VmArea::allocate(len)?;
+            let len = (vaddr_offset + segment.vaddr_range.len())
+                .checked_next_multiple_of(PAGE_SIZE)
+                .filter(|len| *len <= MEMORY_QUOTA)
+                .ok_or(ErrorCode::INVALID_ARG)?;
 
-            let file_off = phdr.p_offset as usize;
-            let file_size = phdr.p_filesz as usize;
-            vmarea.write(vaddr_offset, &elf_file[file_off..file_off + file_size])?;
+            let vmarea = VmArea::allocate_for(&vmspace, len)?;
+            vmarea.write(vaddr_offset, segment.data)?;
 
             let mut attrs = PageAttrs::READ;
             if phdr.writable() {
@@ -93,9 +93,15 @@ impl Process {
         let env: &[&[u8]] = &[];
 
         // Prepare the initial stack.
-        let stack = VmArea::allocate(STACK_SIZE)?;
-        let sp = build_initial_stack(&stack, &elf, argv, env, phdr_uaddr, random)
-            .expect("TODO: proper error handling");
+        let stack = VmArea::allocate_for(&vmspace, STACK_SIZE)?;
+        let sp =
+            build_initial_stack(&stack, &elf, argv, env, phdr_uaddr, random).map_err(|err| {
+                match err {
+                    // The kernel ran out of memory or the quota.
+                    elf::Error::VmAreaWrite(err) => err,
+                    _ => ErrorCode::INVALID_ARG,
+                }
+            })?;
         vmspace.map(&stack, STACK_BASE, PageAttrs::READ | PageAttrs::WRITE)?;
 
         let process = Arc::new(Process {
@@ -172,5 +178,153 @@ impl ftl_api::thread::Handler for ThreadContext {
         }
     }
 
+    fn exception(&self, thread: &Thread, vector: u8, error_code: u64) {
+        // Signal handlers are not supported yet. Take the default action
+        // for these signals: terminate.
+        let signal = Signal::from_exception(vector);
+        info!(
+            "killed by signal {} (exception {vector}, error_code={error_code:#x})",
+            signal.as_u8()
+        );
+        thread.terminate().expect("terminate failed");
+    }
+
     fn terminated(&self, _thread: &Thread) {}
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use ftl_api::mock;
+    use ftl_api::mock::MockThread;
+    use ftl_elf::PhdrType;
+
+    use super::*;
+
+    const ENTRY: u64 = 0x400000;
+    /// The offset of the string in the test ELF file.
+    pub const HELLO_OFFSET: usize = size_of::<ftl_elf::Ehdr>() + size_of::<ftl_elf::Phdr>();
+    pub const HELLO_UADDR: u64 = ENTRY + HELLO_OFFSET as u64;
+    pub const HELLO: &[u8] = b"hello\n";
+
+    fn push<T>(buf: &mut Vec<u8>, value: &T) {
+        // SAFETY: `T` is a plain old data type.
+        let bytes =
+            unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+        buf.extend_from_slice(bytes);
+    }
+
+    /// Builds an executable with a single segment containing [`HELLO`], in a
+    /// buffer aligned to 8 bytes.
+    fn build_elf() -> (Vec<u64>, usize) {
+        let len = HELLO_OFFSET + HELLO.len();
+        let mut ident = [0; 16];
+        ident[..4].copy_from_slice(b"\x7fELF");
+        ident[4] = 2; // ELFCLASS64
+        ident[5] = 1; // ELFDATA2LSB
+        ident[6] = 1; // EV_CURRENT
+
+        let mut bytes = Vec::new();
+        push(
+            &mut bytes,
+            &ftl_elf::Ehdr {
+                e_ident: ident,
+                e_type: ET_EXEC,
+                e_machine: 62, // EM_X86_64
+                e_version: 1,
+                e_entry: ENTRY,
+                e_phoff: size_of::<ftl_elf::Ehdr>() as u64,
+                e_shoff: 0,
+                e_flags: 0,
+                e_ehsize: size_of::<ftl_elf::Ehdr>() as u16,
+                e_phentsize: size_of::<ftl_elf::Phdr>() as u16,
+                e_phnum: 1,
+                e_shentsize: 0,
+                e_shnum: 0,
+                e_shstrndx: 0,
+            },
+        );
+        push(
+            &mut bytes,
+            &ftl_elf::Phdr {
+                p_type: PhdrType::Load as u32,
+                p_flags: ftl_elf::PF_R | ftl_elf::PF_X,
+                p_offset: 0,
+                p_vaddr: ENTRY,
+                p_paddr: ENTRY,
+                p_filesz: len as u64,
+                p_memsz: len as u64,
+                p_align: PAGE_SIZE as u64,
+            },
+        );
+        bytes.extend_from_slice(HELLO);
+
+        let mut words = alloc::vec![0u64; len.div_ceil(size_of::<u64>())];
+        // SAFETY: `words` has at least `len` bytes.
+        unsafe {
+            core::ptr::copy_nonoverlapping(bytes.as_ptr(), words.as_mut_ptr() as *mut u8, len);
+        }
+
+        (words, len)
+    }
+
+    /// Creates and starts a process from the test ELF file.
+    pub fn spawn() -> (Arc<Process>, MockThread) {
+        let (words, len) = build_elf();
+        // SAFETY: `words` has at least `len` bytes.
+        let elf_file = unsafe { core::slice::from_raw_parts(words.as_ptr() as *const u8, len) };
+
+        let (process, init_regs) = Process::create(elf_file).unwrap();
+        process.start(init_regs).unwrap();
+
+        let mut threads = mock::threads();
+        assert_eq!(threads.len(), 1);
+        (process, threads.pop().unwrap())
+    }
+
+    #[test]
+    fn create_maps_segments() {
+        let (words, len) = build_elf();
+        // SAFETY: `words` has at least `len` bytes.
+        let elf_file = unsafe { core::slice::from_raw_parts(words.as_ptr() as *const u8, len) };
+        let (process, init_regs) = Process::create(elf_file).unwrap();
+        assert_eq!(init_regs.pc, ENTRY);
+        assert!((STACK_BASE..STACK_BASE + STACK_SIZE).contains(&(init_regs.sp as usize)));
+
+        let mut buf = [0; HELLO.len()];
+        process
+            .vmspace()
+            .read_bytes(HELLO_UADDR as usize, &mut buf)
+            .unwrap();
+        assert_eq!(buf, HELLO);
+
+        // argc.
+        let mut argc = [0; size_of::<usize>()];
+        process
+            .vmspace()
+            .read_bytes(init_regs.sp as usize, &mut argc)
+            .unwrap();
+        assert_eq!(usize::from_ne_bytes(argc), 1);
+    }
+
+    #[test]
+    fn create_rejects_non_elf() {
+        let words = [0u64; 16];
+        // SAFETY: `words` is 128 bytes long.
+        let file = unsafe { core::slice::from_raw_parts(words.as_ptr() as *const u8, 128) };
+        assert_eq!(Process::create(file).err(), Some(ErrorCode::INVALID_ARG));
+    }
+
+    #[test]
+    fn start_unblocks_thread() {
+        let (_process, thread) = spawn();
+        assert!(thread.is_runnable());
+        assert_eq!(thread.init_regs().pc, ENTRY);
+    }
+
+    #[test]
+    fn exception_terminates_thread() {
+        let (_process, thread) = spawn();
+        assert!(!thread.exception(ftl_api::thread::vector::UD, 0));
+        assert!(thread.is_terminated());
+    }
+}
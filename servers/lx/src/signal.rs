@@ -0,0 +1,27 @@
+use ftl_api::thread::vector;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signal(u8);
+
+impl Signal {
+    pub const SIGILL: Signal = Signal(4);
+    pub const SIGTRAP: Signal = Signal(5);
+    pub const SIGBUS: Signal = Signal(7);
+    pub const SIGFPE: Signal = Signal(8);
+    pub const SIGSEGV: Signal = Signal(11);
+
+    /// Returns the signal for a CPU exception, as Linux does.
+    pub const fn from_exception(vector: u8) -> Signal {
+        match vector {
+            vector::DE | vector::MF | vector::XM => Signal::SIGFPE,
+            vector::DB | vector::BP => Signal::SIGTRAP,
+            vector::UD => Signal::SIGILL,
+            vector::NP | vector::SS | vector::AC => Signal::SIGBUS,
+            _ => Signal::SIGSEGV,
+        }
+    }
+
+    pub const fn as_u8(self) -> u8 {
+        self.0
+    }
+}
@@ -130,3 +130,65 @@ fn sys_arch_prctl(thread: &Thread, code: isize, addr: isize) -> Result<isize, Er
         _ => Err(Errno::ENOSYS),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::tests::HELLO;
+    use crate::process::tests::HELLO_UADDR;
+    use crate::process::tests::spawn;
+
+    fn args(n: u64, arg0: u64, arg1: u64, arg2: u64) -> SyscallArgs {
+        SyscallArgs {
+            n,
+            arg0,
+            arg1,
+            arg2,
+            ..SyscallArgs::zeroed()
+        }
+    }
+
+    #[test]
+    fn write() {
+        let (_process, thread) = spawn();
+        let len = HELLO.len() as u64;
+        let sysret = thread
+            .syscall(args(SYS_WRITE, 1, HELLO_UADDR, len))
+            .unwrap();
+        assert_eq!(sysret.retval, len);
+    }
+
+    #[test]
+    fn write_errors() {
+        let (_process, thread) = spawn();
+        let sysret = thread.syscall(args(SYS_WRITE, 3, HELLO_UADDR, 1)).unwrap();
+        assert_eq!(sysret.retval as isize, Errno::EBADF.to_retval());
+
+        let sysret = thread.syscall(args(SYS_WRITE, 1, 0xdead_0000, 1)).unwrap();
+        assert_eq!(sysret.retval as isize, Errno::EFAULT.to_retval());
+    }
+
+    #[test]
+    fn arch_prctl_set_fs() {
+        let (_process, thread) = spawn();
+        let sysret = thread
+            .syscall(args(SYS_ARCH_PRCTL, ARCH_SET_FS as u64, 0x1234_5000, 0))
+            .unwrap();
+        assert_eq!(sysret.retval, 0);
+        assert_eq!(thread.fsbase(), 0x1234_5000);
+    }
+
+    #[test]
+    fn unimplemented() {
+        let (_process, thread) = spawn();
+        let sysret = thread.syscall(args(0xffff, 0, 0, 0)).unwrap();
+        assert_eq!(sysret.retval as isize, Errno::ENOSYS.to_retval());
+    }
+
+    #[test]
+    fn exit() {
+        let (_process, thread) = spawn();
+        assert!(thread.syscall(args(SYS_EXIT, 0, 0, 0)).is_none());
+        assert!(thread.is_terminated());
+    }
+}
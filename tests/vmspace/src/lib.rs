@@ -0,0 +1,63 @@
+//! A test server for VmSpace and VmArea supercalls. See kernel/src/ktest.rs.
+#![cfg_attr(target_os = "none", no_std)]
+
+use ftl_api::Spec;
+use ftl_api::error::ErrorCode;
+use ftl_api::handle::HandleRight;
+use ftl_api::info;
+use ftl_api::vmarea::VmArea;
+use ftl_api::vmspace::PageAttrs;
+use ftl_api::vmspace::VmSpace;
+
+const PAGE_SIZE: usize = 4096;
+const UADDR: usize = 0x10000;
+
+/// Unlike the mock kernel in host tests, the kernel charges page tables to
+/// the VmSpace's quota.
+fn map_charges_page_tables() {
+    let vmspace = VmSpace::create().unwrap();
+    let vmarea = VmArea::allocate_for(&vmspace, 2 * PAGE_SIZE).unwrap();
+    // Allocate the pages before mapping them.
+    vmarea.write(PAGE_SIZE - 2, b"hello").unwrap();
+    let used = vmspace.memory_usage().unwrap().used;
+
+    // A PDPT, a page directory, and a page table.
+    vmspace.map(&vmarea, UADDR, PageAttrs::READ).unwrap();
+    assert_eq!(vmspace.memory_usage().unwrap().used, used + 3 * PAGE_SIZE);
+
+    let mut buf = [0; 5];
+    vmspace.read_bytes(UADDR + PAGE_SIZE - 2, &mut buf).unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+fn quota() {
+    let vmspace = VmSpace::create_with_quota(PAGE_SIZE).unwrap();
+    assert!(matches!(
+        VmArea::allocate_for(&vmspace, 2 * PAGE_SIZE),
+        Err(ErrorCode::OUT_OF_MEMORY)
+    ));
+}
+
+fn duplicate_narrows_rights() {
+    let vmspace = VmSpace::create().unwrap();
+    let read_only = vmspace.handle().duplicate(HandleRight::READ).unwrap();
+    assert_eq!(
+        read_only.duplicate(HandleRight::ALL).err(),
+        Some(ErrorCode::NOT_ALLOWED)
+    );
+    read_only.close().unwrap();
+}
+
+fn main() {
+    map_charges_page_tables();
+    quota();
+    duplicate_narrows_rights();
+    info!("all tests passed");
+}
+
+#[unsafe(no_mangle)]
+pub static SPEC: Spec = Spec {
+    name: b"test_vmspace",
+    start: main,
+    stop: || {},
+};
@@ -0,0 +1,112 @@
+#!/usr/bin/env python3
+"""Embeds the kernel symbol table into the kernel image for backtraces.
+
+Usage: tools/embed-symbols.py [--size] <kernel.elf>
+
+The kernel reserves the table (SYMBOL_TABLE in kernel/src/backtrace.rs) in
+the .symbol_table section. This script fills it in place. With --size, it
+prints the table size needed instead, to build the kernel with
+FTL_SYMBOL_TABLE_SIZE.
+"""
+import os
+import re
+import struct
+import subprocess
+import sys
+
+SECTION_NAME = b".symbol_table"
+# The size of num_symbols.
+HEADER_SIZE = 8
+# The address, and the offset and length of the name.
+ENTRY_SIZE = 16
+# Round up the size so that the table fits even if the second build has a
+# few more symbols.
+SIZE_ALIGN = 4096
+
+
+def read_symbols(elf_path):
+    nm = os.environ.get("NM", "nm")
+    output = subprocess.check_output(
+        [nm, "--defined-only", "--demangle", elf_path], text=True
+    )
+
+    symbols = {}
+    for line in output.splitlines():
+        parts = line.split(" ", 2)
+        if len(parts) != 3 or parts[1] not in "TtWw":
+            continue
+        addr = int(parts[0], 16)
+        # Remove the hash of the legacy Rust mangling.
+        name = re.sub(r"::h[0-9a-f]{16}$", "", parts[2])
+        symbols.setdefault(addr, name)
+
+    return sorted(symbols.items())
+
+
+def build_table(symbols):
+    """Returns the table data after num_symbols."""
+    entries = b""
+    names = b""
+    names_offset = len(symbols) * ENTRY_SIZE
+    for addr, name in symbols:
+        name = name.encode("utf-8")
+        entries += struct.pack("<QII", addr, names_offset + len(names), len(name))
+        names += name
+    return entries + names
+
+
+def find_section(image, name):
+    """Returns the file offset and the size of an ELF64 section."""
+    if image[:4] != b"\x7fELF" or image[4] != 2:
+        sys.exit("not an ELF64 file")
+
+    (shoff,) = struct.unpack_from("<Q", image, 0x28)
+    shentsize, shnum, shstrndx = struct.unpack_from("<HHH", image, 0x3A)
+
+    def section(index):
+        # sh_name, sh_offset, and sh_size.
+        base = shoff + index * shentsize
+        (sh_name,) = struct.unpack_from("<I", image, base)
+        sh_offset, sh_size = struct.unpack_from("<QQ", image, base + 0x18)
+        return sh_name, sh_offset, sh_size
+
+    _, strtab_offset, _ = section(shstrndx)
+    for i in range(shnum):
+        sh_name, sh_offset, sh_size = section(i)
+        start = strtab_offset + sh_name
+        if image[start : image.index(b"\0", start)] == name:
+            return sh_offset, sh_size
+
+    sys.exit(f"section {name.decode()} is not found")
+
+
+def main():
+    print_size = sys.argv[1] == "--size"
+    elf_path = sys.argv[-1]
+    symbols = read_symbols(elf_path)
+    table = build_table(symbols)
+    if print_size:
+        print((len(table) + SIZE_ALIGN - 1) // SIZE_ALIGN * SIZE_ALIGN)
+        return
+
+    with open(elf_path, "rb") as f:
+        image = bytearray(f.read())
+
+    offset, size = find_section(image, SECTION_NAME)
+    if HEADER_SIZE + len(table) > size:
+        sys.exit(
+            f"the symbol table is too small: {size} bytes"
+            f" (needs {HEADER_SIZE + len(table)})"
+        )
+
+    image[offset : offset + HEADER_SIZE + len(table)] = (
+        struct.pack("<Q", len(symbols)) + table
+    )
+    with open(elf_path, "wb") as f:
+        f.write(image)
+
+    print(f"embedded {len(symbols)} symbols")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,27 @@
+#!/bin/bash
+# Signs server images for the kernel.
+#
+# Usage:
+#   tools/sign-server.sh keygen <key.pem>         Generate a signing key.
+#   tools/sign-server.sh pubkey <key.pem>         Print the public key in hex,
+#                                                 for FTL_SERVER_PUBKEY.
+#   tools/sign-server.sh sign <key.pem> <file>    Write <file>.sig.
+set -eu
+
+case "${1:-}" in
+    keygen)
+        openssl genpkey -algorithm ed25519 -out "$2"
+        ;;
+    pubkey)
+        # The raw key is the last 32 bytes of the DER-encoded public key.
+        openssl pkey -in "$2" -pubout -outform DER | tail -c 32 | od -An -tx1 | tr -d ' \n'
+        echo
+        ;;
+    sign)
+        openssl pkeyutl -sign -rawin -inkey "$2" -in "$3" -out "$3.sig"
+        ;;
+    *)
+        echo "usage: $0 keygen|pubkey|sign <key.pem> [<file>]" >&2
+        exit 1
+        ;;
+esac